@@ -3,11 +3,10 @@ use std::sync::Arc;
 
 #[cfg(not(target_arch = "wasm32"))]
 use arboard::Clipboard;
-use eframe::egui::load::SizedTexture;
 use eframe::egui::style::ScrollStyle;
 use eframe::egui::{
-    CentralPanel, Color32, ComboBox, Context, FontData, FontDefinitions, Frame, Margin, ScrollArea,
-    SidePanel, Slider, TopBottomPanel, Vec2, Widget, WidgetText, Window,
+    CentralPanel, Color32, ComboBox, Context, FontData, FontDefinitions, Frame, Margin, Pos2,
+    ScrollArea, SidePanel, Slider, TopBottomPanel, Vec2, Widget, WidgetText, Window,
 };
 use eframe::epaint::FontFamily;
 #[cfg(not(target_arch = "wasm32"))]
@@ -23,11 +22,13 @@ use egui_cosmic_text::atlas::TextureAtlas;
 use egui_cosmic_text::cosmic_text;
 use egui_cosmic_text::cosmic_text::fontdb::Source;
 use egui_cosmic_text::cosmic_text::{
-    Attrs, Family, FontSystem, Metrics, Shaping, SwashCache, Weight,
+    Attrs, Family, FeatureTag, FontFeatures, FontSystem, Metrics, Shaping, SwashCache, Weight,
 };
+use egui_cosmic_text::debug::atlas_inspector;
+use egui_cosmic_text::units::Logical;
 use egui_cosmic_text::widget::{
-    CosmicEdit, DefaultContextMenu, FillWidth, FillWidthAndHeight, HoverStrategy, Interactivity,
-    LayoutMode, LineHeight, PureBoundingBox, ShrinkToFit,
+    CaretLayer, CosmicEdit, DefaultContextMenu, FillWidth, FillWidthAndHeight, HoverStrategy,
+    Interactivity, LayoutMode, LineHeight, PureBoundingBox, ShrinkToFit, TabBehavior,
 };
 
 #[derive(Debug, PartialEq, Default, Copy, Clone)]
@@ -55,6 +56,7 @@ struct DemoApp {
     swash_cache: SwashCache,
     texture_atlas: TextureAtlas<BuildHasherDefault<FxHasher>>,
     editor: CosmicEdit<Box<dyn LayoutMode>>,
+    editor_min_pos: Pos2,
     bottom_text: CosmicEdit<PureBoundingBox>,
     #[cfg(not(target_arch = "wasm32"))]
     clipboard: Clipboard,
@@ -63,6 +65,8 @@ struct DemoApp {
     selected_layout_mode: SelectedLayoutMode,
     updated_max_texture_side: bool,
     show_texture_atlas: bool,
+    tabular_numbers: bool,
+    ligatures: bool,
 }
 
 impl App for DemoApp {
@@ -93,18 +97,18 @@ impl App for DemoApp {
                         .open(&mut self.show_texture_atlas)
                         .collapsible(false)
                         .show(ui.ctx(), |ui| {
-                            let size @ Vec2 { x, y } = self.texture_atlas.atlas_texture_size();
+                            let Vec2 { x, y } = self.texture_atlas.atlas_texture_size();
                             let max_texture_side = ui.input(|i| i.max_texture_side);
                             ui.label(format!("Atlas size: {x} x {y} • Max texture side: {max_texture_side}"));
+                            ui.label("The glyph after the editor's cursor is outlined.");
+
+                            let cursor_glyph = self
+                                .editor
+                                .cursor_glyph(Logical(self.editor_min_pos), ui.ctx().pixels_per_point());
 
                             ScrollArea::both()
                                 .show(ui, |ui| {
-                                    ui.image(
-                                        SizedTexture::new(
-                                            self.texture_atlas.atlas_texture(),
-                                            size
-                                        )
-                                    );
+                                    atlas_inspector(ui, &self.texture_atlas, cursor_glyph);
                                 });
                         });
                 }
@@ -117,6 +121,9 @@ impl App for DemoApp {
                 Slider::new(&mut self.rel_line_height, 1.0..=3.0)
                     .ui(ui);
 
+                ui.checkbox(&mut self.tabular_numbers, "Tabular numbers (tnum)");
+                ui.checkbox(&mut self.ligatures, "Ligatures (liga)");
+
                 ui.label("Interactivity");
 
                 let interactivity = self.editor.interactivity_mut();
@@ -141,6 +148,30 @@ impl App for DemoApp {
                         }
                     });
 
+                ui.label("Caret Layer");
+
+                let caret_layer = self.editor.caret_layer_mut();
+
+                ComboBox::from_id_source("caret_layer")
+                    .selected_text(format!("{caret_layer:?}"))
+                    .show_ui(ui, |ui| {
+                        for (name, variant) in CaretLayer::variants() {
+                            ui.selectable_value(caret_layer, *variant, &**name);
+                        }
+                    });
+
+                ui.label("Tab Behavior");
+
+                let tab_behavior = self.editor.tab_behavior_mut();
+
+                ComboBox::from_id_source("tab_behavior")
+                    .selected_text(format!("{tab_behavior:?}"))
+                    .show_ui(ui, |ui| {
+                        for (name, variant) in TabBehavior::variants() {
+                            ui.selectable_value(tab_behavior, *variant, &**name);
+                        }
+                    });
+
                 ui.label("Layout Mode");
 
                 ComboBox::from_id_source("layout_mode")
@@ -205,29 +236,42 @@ impl App for DemoApp {
                                 &mut self.font_system,
                             );
 
+                            let mut font_features = FontFeatures::new();
+                            if self.tabular_numbers {
+                                font_features.enable(FeatureTag::new(b"tnum"));
+                            }
+                            if !self.ligatures {
+                                font_features.disable(FeatureTag::STANDARD_LIGATURES);
+                            }
+                            self.editor
+                                .set_font_features(font_features, &mut self.font_system);
+
                             if self.selected_layout_mode != curr_layout_mode {
                                 self.selected_layout_mode = curr_layout_mode;
                                 self.editor
                                     .set_layout_mode(curr_layout_mode.into_layout_mode());
                             }
 
-                            self.editor.ui(
-                                ui,
-                                &mut self.font_system,
-                                &mut self.swash_cache,
-                                &mut self.texture_atlas,
-                                DefaultContextMenu {
-                                    #[cfg(not(target_arch = "wasm32"))]
-                                    read_clipboard_text: || self.clipboard.get_text().ok(),
-                                    #[cfg(target_arch = "wasm32")]
-                                    read_clipboard_text: || None,
-                                },
-                            );
+                            self.editor_min_pos = self
+                                .editor
+                                .ui(
+                                    ui,
+                                    &mut self.font_system,
+                                    &mut self.swash_cache,
+                                    &mut self.texture_atlas,
+                                    DefaultContextMenu {
+                                        #[cfg(not(target_arch = "wasm32"))]
+                                        read_clipboard_text: || self.clipboard.get_text().ok(),
+                                        #[cfg(target_arch = "wasm32")]
+                                        read_clipboard_text: || None,
+                                    },
+                                )
+                                .rect
+                                .min;
                         });
                 });
             });
 
-        self.texture_atlas.trim();
         self.font_system.shape_run_cache.trim(1024);
     }
 }
@@ -262,10 +306,6 @@ fn app_creator() -> AppCreator {
     Box::new(|cc| {
         cc.egui_ctx.set_fonts(font_definitions);
 
-        // Selection is very bugged...
-        cc.egui_ctx
-            .style_mut(|s| s.interaction.selectable_labels = false);
-
         let texture_atlas = TextureAtlas::new(cc.egui_ctx.clone(), Color32::WHITE);
 
         let layout_mode = SelectedLayoutMode::FillWidthAndHeight;
@@ -283,11 +323,11 @@ fn app_creator() -> AppCreator {
             .weight(Weight::LIGHT);
         editor.set_text(
             [
-                ("This text is editable!\n\n🦀🦀🦀🦀🚀🚀🚀🚀\n\nThese emojis come from the Twitter Emoji project", attrs),
+                ("This text is editable!\n\n🦀🦀🦀🦀🚀🚀🚀🚀\n\nThese emojis come from the Twitter Emoji project\n\n1111 fi\n0000 fl", attrs.clone()),
                 #[cfg(target_arch = "wasm32")]
-                ("\n\nNote: Pasting via context menu isn't supported in this WASM demo.", attrs)
+                ("\n\nNote: Pasting via context menu isn't supported in this WASM demo.", attrs.clone())
             ],
-            attrs,
+            attrs.clone(),
             Shaping::Advanced,
             &mut font_system,
         );
@@ -303,14 +343,15 @@ fn app_creator() -> AppCreator {
 
         bottom_text.set_text(
             [
-                ("You can also use it as a label! Try ", attrs),
+                ("You can also use it as a label! Try ", attrs.clone()),
                 (
                     "selecting this ",
                     attrs
+                        .clone()
                         .metrics(Metrics::new(20.0, 20.0 * 1.5))
                         .color(cosmic_text::Color::rgb(137, 207, 240)),
                 ),
-                ("text!", attrs)
+                ("text!", attrs.clone()),
             ],
             attrs,
             Shaping::Advanced,
@@ -322,6 +363,7 @@ fn app_creator() -> AppCreator {
             swash_cache,
             texture_atlas,
             editor,
+            editor_min_pos: Pos2::ZERO,
             bottom_text,
             #[cfg(not(target_arch = "wasm32"))]
             clipboard: Clipboard::new().expect("expected clipboard"),
@@ -330,6 +372,8 @@ fn app_creator() -> AppCreator {
             selected_layout_mode: layout_mode,
             updated_max_texture_side: false,
             show_texture_atlas: false,
+            tabular_numbers: false,
+            ligatures: true,
         }))
     })
 }
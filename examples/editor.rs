@@ -71,10 +71,7 @@ fn main() -> eframe::Result<()> {
 
         CentralPanel::default().show(ctx, |ui| {
             if let Some(frame_time) = frame_times.average() {
-                ui.monospace(format!(
-                    "{:.2} ms",
-                    frame_time * 1000.0
-                ));
+                ui.monospace(format!("{:.2} ms", frame_time * 1000.0));
             }
             ui.add(Slider::new(&mut font_size, 10.0..=200.0).text("Font size"));
             ui.label("This is a native egui label 👋👋👋");
@@ -104,7 +101,6 @@ fn main() -> eframe::Result<()> {
             });
         });
 
-        atlas.trim();
         ctx.request_repaint();
     })
 }
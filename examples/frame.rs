@@ -1,6 +1,6 @@
 use cosmic_text::{Attrs, FontSystem, Shaping, SwashCache};
 use eframe::NativeOptions;
-use egui::{CentralPanel, Color32, Frame, Margin, ScrollArea};
+use egui::{Align2, CentralPanel, Color32, Frame, Margin, ScrollArea};
 use egui_cosmic_text::atlas::TextureAtlas;
 use egui_cosmic_text::widget::{
     CosmicEdit, FillWidth, HoverStrategy, Interactivity, LineHeight, NoContextMenu,
@@ -21,7 +21,9 @@ fn main() -> eframe::Result<()> {
         HoverStrategy::Widget,
         FillWidth::default(),
         &mut font_system,
-    );
+    )
+    // `centered_and_justified` gives the editor the whole frame, keep the text in the middle of it
+    .with_content_align(Align2::CENTER_CENTER);
     editor.set_text([], Attrs::new(), Shaping::Advanced, &mut font_system);
 
     eframe::run_simple_native("", NativeOptions::default(), move |ctx, _| {
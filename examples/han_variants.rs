@@ -0,0 +1,76 @@
+use cosmic_text::{Attrs, FontSystem, Shaping};
+use eframe::NativeOptions;
+use egui::{CentralPanel, Color32};
+use rustc_hash::FxHasher;
+use std::hash::BuildHasherDefault;
+
+use egui_cosmic_text::resources::TextResources;
+use egui_cosmic_text::widget::{
+    CosmicEdit, DefaultContextMenu, HoverStrategy, Interactivity, LineHeight, PureBoundingBox,
+};
+
+// The same characters have different regional glyphs, e.g. the strokes on the left of 直
+const TEXT: &str = "直 值 今 骨 角";
+
+// Needs the Noto CJK fonts, any other regional CJK fonts work the same way
+const FAMILIES: [(&str, &str); 2] = [
+    ("Japanese", "Noto Sans CJK JP"),
+    ("Simplified Chinese", "Noto Sans CJK SC"),
+];
+
+fn main() -> eframe::Result<()> {
+    let mut font_system = FontSystem::new();
+
+    let mut labels = FAMILIES
+        .into_iter()
+        .map(|(locale, family)| {
+            let mut label = CosmicEdit::new(
+                48.0,
+                LineHeight::Relative(1.2),
+                Interactivity::Selection,
+                HoverStrategy::BoundingBox,
+                PureBoundingBox::default(),
+                &mut font_system,
+            );
+            // The font system's locale only picks one fallback for all widgets
+            label.set_font_family(Some(family.to_string()), &mut font_system);
+            label.set_text(
+                [(TEXT, Attrs::new())],
+                Attrs::new(),
+                Shaping::Advanced,
+                &mut font_system,
+            );
+            (locale, label)
+        })
+        .collect::<Vec<_>>();
+
+    // The atlas needs the egui context, which only exists once the app runs
+    let mut font_system = Some(font_system);
+    let mut resources = None::<TextResources<BuildHasherDefault<FxHasher>>>;
+
+    eframe::run_simple_native("", NativeOptions::default(), move |ctx, _| {
+        let resources = resources.get_or_insert_with(|| {
+            TextResources::from_font_system(
+                font_system.take().unwrap(),
+                ctx.clone(),
+                Color32::WHITE,
+            )
+        });
+
+        CentralPanel::default().show(ctx, |ui| {
+            for (locale, label) in &mut labels {
+                ui.label(*locale);
+                label.ui_with(
+                    ui,
+                    resources,
+                    DefaultContextMenu {
+                        read_clipboard_text: || None,
+                    },
+                );
+                ui.separator();
+            }
+        });
+
+        resources.end_frame();
+    })
+}
@@ -0,0 +1,70 @@
+use cosmic_text::{Attrs, FontSystem, Shaping};
+use eframe::NativeOptions;
+use egui::{CentralPanel, Color32, ScrollArea};
+use rustc_hash::FxHasher;
+use std::hash::BuildHasherDefault;
+
+use egui_cosmic_text::resources::TextResources;
+use egui_cosmic_text::widget::{
+    CosmicEdit, DefaultContextMenu, FillWidth, HoverStrategy, Interactivity, LineHeight,
+};
+
+const EDITORS: usize = 200;
+
+fn main() -> eframe::Result<()> {
+    let mut font_system = FontSystem::new();
+
+    let mut editors = (0..EDITORS)
+        .map(|i| {
+            let mut editor = CosmicEdit::new(
+                14.0,
+                LineHeight::Relative(1.5),
+                Interactivity::Enabled,
+                HoverStrategy::Widget,
+                FillWidth::default(),
+                &mut font_system,
+            );
+            editor.set_text(
+                [(
+                    format!("Editor #{i}, all of them share one atlas").as_str(),
+                    Attrs::new(),
+                )],
+                Attrs::new(),
+                Shaping::Advanced,
+                &mut font_system,
+            );
+            editor
+        })
+        .collect::<Vec<_>>();
+
+    // The atlas needs the egui context, which only exists once the app runs
+    let mut font_system = Some(font_system);
+    let mut resources = None::<TextResources<BuildHasherDefault<FxHasher>>>;
+
+    eframe::run_simple_native("", NativeOptions::default(), move |ctx, _| {
+        let resources = resources.get_or_insert_with(|| {
+            TextResources::from_font_system(
+                font_system.take().unwrap(),
+                ctx.clone(),
+                Color32::WHITE,
+            )
+        });
+
+        CentralPanel::default().show(ctx, |ui| {
+            ScrollArea::vertical().show(ui, |ui| {
+                for editor in &mut editors {
+                    editor.ui_with(
+                        ui,
+                        resources,
+                        DefaultContextMenu {
+                            read_clipboard_text: || None,
+                        },
+                    );
+                    ui.separator();
+                }
+            });
+        });
+
+        resources.end_frame();
+    })
+}
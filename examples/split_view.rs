@@ -0,0 +1,67 @@
+use cosmic_text::{Attrs, FontSystem, Shaping, SwashCache};
+use eframe::NativeOptions;
+use egui::{CentralPanel, Color32, ScrollArea, SidePanel};
+use rustc_hash::FxHasher;
+use std::hash::BuildHasherDefault;
+
+use egui_cosmic_text::atlas::TextureAtlas;
+use egui_cosmic_text::widget::{
+    CosmicEdit, CosmicEditView, DefaultContextMenu, FillWidth, HoverStrategy, Interactivity,
+    LineHeight,
+};
+
+fn main() -> eframe::Result<()> {
+    let mut font_system = FontSystem::new();
+    let mut swash_cache = SwashCache::new();
+
+    let mut atlas = None::<TextureAtlas<BuildHasherDefault<FxHasher>>>;
+
+    let mut cosmic_edit = CosmicEdit::new(
+        14.0,
+        LineHeight::Relative(1.5),
+        Interactivity::Enabled,
+        HoverStrategy::Widget,
+        FillWidth::default(),
+        &mut font_system,
+    );
+    cosmic_edit.set_text(
+        [(
+            include_str!("../misc/Decently sized lorem ipsum.txt"),
+            Attrs::new(),
+        )],
+        Attrs::new(),
+        Shaping::Advanced,
+        &mut font_system,
+    );
+
+    // Second pane onto the same buffer, with its own scroll position, caret and selection
+    let mut view = CosmicEditView::default();
+
+    eframe::run_simple_native("", NativeOptions::default(), move |ctx, _| {
+        let atlas = atlas.get_or_insert_with(|| TextureAtlas::new(ctx.clone(), Color32::WHITE));
+
+        SidePanel::left("editor")
+            .exact_width(ctx.screen_rect().width() / 2.0)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().id_source("editor").show(ui, |ui| {
+                    cosmic_edit.ui(
+                        ui,
+                        &mut font_system,
+                        &mut swash_cache,
+                        atlas,
+                        DefaultContextMenu {
+                            read_clipboard_text: || None,
+                        },
+                    );
+                });
+            });
+
+        CentralPanel::default().show(ctx, |ui| {
+            ScrollArea::vertical().id_source("view").show(ui, |ui| {
+                cosmic_edit.view_ui(&mut view, ui, &mut font_system, &mut swash_cache, atlas);
+            });
+        });
+
+        ctx.request_repaint();
+    })
+}
@@ -1,9 +1,9 @@
 use cosmic_text::{
-    CacheKey, FontSystem, LayoutGlyph, LayoutRun, PhysicalGlyph, Placement, SwashCache,
-    SwashContent, SwashImage,
+    CacheKey, FontSystem, LayoutGlyph, LayoutRun, PhysicalGlyph, Placement, SubpixelBin,
+    SwashCache, SwashContent, SwashImage,
 };
 use egui::{
-    pos2, vec2, Color32, ColorImage, NumExt, Painter, Rect, TextureHandle, TextureId,
+    pos2, vec2, Color32, Mesh, NumExt, Painter, Pos2, Rect, TextureHandle, TextureId,
     TextureOptions, Vec2,
 };
 use etagere::{size2, Allocation, BucketedAtlasAllocator, Size};
@@ -11,7 +11,11 @@ use imgref::{Img, ImgRefMut};
 use lru::LruCache;
 use std::collections::hash_map::RandomState;
 use std::collections::HashSet;
-use std::hash::BuildHasher;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::compat;
+use crate::units::{Logical, Phys};
+use crate::util::snap_to_logical;
 
 #[derive(Clone)]
 struct GlyphState {
@@ -54,6 +58,161 @@ fn write_glyph_image(image: SwashImage, default_color: Color32, mut sub_image: I
     };
 }
 
+/// A [`BuildHasher`] with a fixed seed, so hashing behaves the same across runs, machines and
+/// Rust releases.
+///
+/// Useful for [`TextureAtlas::new_deterministic`] in snapshot tests.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SeededState<const SEED: u64 = 0>;
+
+impl<const SEED: u64> BuildHasher for SeededState<SEED> {
+    type Hasher = SeededHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        let mut hasher = SeededHasher(SeededHasher::OFFSET_BASIS);
+        hasher.write_u64(SEED);
+        hasher
+    }
+}
+
+/// 64-bit FNV-1a, with integers hashed as little endian so the result doesn't depend on the
+/// platform, see [`SeededState`].
+///
+/// Unlike [`DefaultHasher`](std::collections::hash_map::DefaultHasher), the algorithm is fixed.
+/// It's not resistant to collision attacks, which doesn't matter for glyph keys.
+#[derive(Debug, Copy, Clone)]
+pub struct SeededHasher(u64);
+
+impl SeededHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+}
+
+impl Hasher for SeededHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    // Same width everywhere
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// The fields of a [`CacheKey`], spelled out so they can be serialized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GlyphKey {
+    /// The font's id in the [`FontSystem`]'s database, the same as long as the fonts are loaded in
+    /// the same order
+    pub font_id: u64,
+    pub glyph_id: u16,
+    /// `f32` bits of the font size
+    pub font_size_bits: u32,
+    /// Subpixel bins of the x and y offsets, 0 to 3
+    pub x_bin: u8,
+    pub y_bin: u8,
+    /// [`CacheKeyFlags`](cosmic_text::CacheKeyFlags) bits
+    pub flags: u32,
+}
+
+impl From<CacheKey> for GlyphKey {
+    fn from(cache_key: CacheKey) -> Self {
+        let bin = |bin: SubpixelBin| match bin {
+            SubpixelBin::Zero => 0,
+            SubpixelBin::One => 1,
+            SubpixelBin::Two => 2,
+            SubpixelBin::Three => 3,
+        };
+        Self {
+            // fontdb only exposes its ids through `Display`
+            font_id: cache_key.font_id.to_string().parse().unwrap(),
+            glyph_id: cache_key.glyph_id,
+            font_size_bits: cache_key.font_size_bits,
+            x_bin: bin(cache_key.x_bin),
+            y_bin: bin(cache_key.y_bin),
+            flags: cache_key.flags.bits(),
+        }
+    }
+}
+
+/// Where a glyph ended up inside the atlas.
+///
+/// **In physical pixels.**
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GlyphPlacement {
+    pub key: GlyphKey,
+    /// Top left corner of the glyph inside the atlas
+    pub min: [usize; 2],
+    pub size: [usize; 2],
+    pub left: i32,
+    pub top: i32,
+}
+
+/// A copy of the atlas contents, for comparing against golden snapshots.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AtlasSnapshot {
+    pub size: [usize; 2],
+    /// Row major, `size[0] * size[1]` pixels
+    pub pixels: Vec<Color32>,
+    /// Sorted by [`CacheKey`]
+    pub glyphs: Vec<GlyphPlacement>,
+}
+
+/// The color a glyph is drawn with, its own from the attributes or `default_color`.
+pub fn glyph_color(glyph: &LayoutGlyph, default_color: Color32) -> Color32 {
+    glyph
+        .color_opt
+        // Is this right?
+        .map_or(default_color, |x| {
+            Color32::from_rgba_premultiplied(x.r(), x.g(), x.b(), x.a())
+        })
+}
+
 pub struct GlyphImage {
     atlas_texture_id: TextureId,
     uv_rect: Rect,
@@ -92,36 +251,75 @@ impl GlyphImage {
         }
     }
 
-    pub fn paint(
-        self,
+    /// Tints glyphs without a color of their own with `color` instead of the atlas's default color.
+    ///
+    /// Like [`Attrs::color`](cosmic_text::Attrs::color), it tints the atlas's glyphs, which are
+    /// only drawn in exactly `color` with a white default color.
+    pub fn with_default_color(mut self, color: Color32) -> Self {
+        self.default_color = color;
+        self
+    }
+
+    /// Logical rect relative to the buffer's origin and the tint to draw the glyph with.
+    fn rect_and_tint(
+        &self,
         layout_glyph: &LayoutGlyph,
         physical_glyph: PhysicalGlyph,
         run: &LayoutRun,
-        painter: &mut Painter,
-    ) {
+        pixels_per_point: f32,
+    ) -> (Rect, Color32) {
         let x = physical_glyph.x + self.left;
         let y = run.line_y as i32 + physical_glyph.y - self.top;
 
-        let color_override = layout_glyph
-            .color_opt
-            // Is this right?
-            .map(|x| Color32::from_rgba_premultiplied(x.r(), x.g(), x.b(), x.a()));
-
         // Note: this isn't exactly working
         let tint = match self.colorable {
-            true => color_override.unwrap_or(self.default_color),
+            true => glyph_color(layout_glyph, self.default_color),
             false => Color32::WHITE,
         };
 
+        // Convert from physical -> logical
+        let rect = *snap_to_logical(
+            Phys(Rect::from_min_size(
+                pos2(x as f32, y as f32),
+                vec2(self.width, self.height),
+            )),
+            Logical(Pos2::ZERO),
+            pixels_per_point,
+        );
+
+        (rect, tint)
+    }
+
+    pub fn paint(
+        self,
+        layout_glyph: &LayoutGlyph,
+        physical_glyph: PhysicalGlyph,
+        run: &LayoutRun,
+        painter: &mut Painter,
+    ) {
         let pixels_per_point = painter.ctx().pixels_per_point();
 
-        painter.image(
-            self.atlas_texture_id,
-            Rect::from_min_size(pos2(x as f32, y as f32), vec2(self.width, self.height))
-                / pixels_per_point, // Convert from physical -> logical
-            self.uv_rect,
-            tint,
-        );
+        let (rect, tint) = self.rect_and_tint(layout_glyph, physical_glyph, run, pixels_per_point);
+
+        painter.image(self.atlas_texture_id, rect, self.uv_rect, tint);
+    }
+
+    /// Adds the glyph to `mesh` instead of painting it as its own shape.
+    ///
+    /// The mesh has to use the [atlas texture](TextureAtlas::atlas_texture) the glyph was allocated in.
+    pub fn add_to_mesh(
+        self,
+        layout_glyph: &LayoutGlyph,
+        physical_glyph: PhysicalGlyph,
+        run: &LayoutRun,
+        mesh: &mut Mesh,
+        pixels_per_point: f32,
+    ) {
+        debug_assert_eq!(mesh.texture_id, self.atlas_texture_id);
+
+        let (rect, tint) = self.rect_and_tint(layout_glyph, physical_glyph, run, pixels_per_point);
+
+        mesh.add_rect_with_uv(rect, self.uv_rect, tint);
     }
 }
 
@@ -129,12 +327,18 @@ impl GlyphImage {
 pub struct TextureAtlas<S: BuildHasher + Default = RandomState> {
     packer: BucketedAtlasAllocator,
     cache: LruCache<CacheKey, Option<GlyphState>, S>,
+    /// Glyphs used during `in_use_frame`, these can't be evicted
     in_use: HashSet<CacheKey, S>,
+    in_use_frame: u64,
     atlas_side: usize,
     max_texture_side: usize,
     texture: TextureHandle,
     ctx: egui::Context,
     default_color: Color32,
+    /// CPU side copy of the texture, only kept around in deterministic mode
+    mirror: Option<Img<Vec<Color32>>>,
+    /// Glyphs were allocated since the last time they were [settled](Self::settle)
+    unsettled: bool,
 }
 
 impl<S: BuildHasher + Default> TextureAtlas<S> {
@@ -145,21 +349,40 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
         let packer = BucketedAtlasAllocator::new(Size::splat(atlas_side as i32));
         let texture = ctx.load_texture(
             Self::ATLAS_TEXTURE_NAME,
-            ColorImage::new([atlas_side, atlas_side], Color32::TRANSPARENT),
+            compat::filled_color_image([atlas_side, atlas_side], Color32::TRANSPARENT),
             TextureOptions::NEAREST,
         );
         Self {
             packer,
             cache: LruCache::unbounded_with_hasher(S::default()),
             in_use: HashSet::with_hasher(S::default()),
+            in_use_frame: compat::frame_nr(&ctx),
             atlas_side,
             max_texture_side: ctx.input(|i| i.max_texture_side),
             texture,
             ctx,
             default_color,
+            mirror: None,
+            unsettled: false,
         }
     }
 
+    /// Creates an atlas meant for reproducible output, e.g. golden image tests.
+    ///
+    /// A CPU copy of the texture is kept so it can be [snapshotted](Self::snapshot). At the start
+    /// of every frame after new glyphs were allocated, all cached glyphs are packed again in
+    /// [`CacheKey`] order, so the layout doesn't depend on the order glyphs were drawn in. Pair
+    /// this with [`SeededState`] so it doesn't depend on hashing either.
+    pub fn new_deterministic(ctx: egui::Context, default_color: Color32) -> Self {
+        let mut atlas = Self::new(ctx, default_color);
+        atlas.mirror = Some(Img::new(
+            vec![Color32::TRANSPARENT; atlas.atlas_side * atlas.atlas_side],
+            atlas.atlas_side,
+            atlas.atlas_side,
+        ));
+        atlas
+    }
+
     fn grow(&mut self, font_system: &mut FontSystem, swash_cache: &mut SwashCache) {
         assert!(self.atlas_side < self.max_texture_side);
 
@@ -168,39 +391,86 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
 
         self.packer.grow(Size::splat(new_side_size as i32));
 
+        self.reupload(font_system, swash_cache);
+    }
+
+    /// Rebuilds the texture at the current atlas size and loads it into `self.ctx`.
+    fn reupload(&mut self, font_system: &mut FontSystem, swash_cache: &mut SwashCache) {
+        let side_size = self.atlas_side;
+
         let mut new_atlas_image = Img::new(
-            vec![Color32::TRANSPARENT; new_side_size * new_side_size],
-            new_side_size,
-            new_side_size,
+            vec![Color32::TRANSPARENT; side_size * side_size],
+            side_size,
+            side_size,
         );
 
-        self.cache
-            .iter()
-            .filter_map(|(cache_key, state)| state.as_ref().map(|state| (cache_key, state.clone())))
-            .for_each(|(&cache_key, cached_glyph_state)| {
-                let image = swash_cache
-                    .get_image_uncached(font_system, cache_key)
-                    .unwrap();
-                let rect = cached_glyph_state.allocation.rectangle;
-                let region = new_atlas_image.sub_image_mut(
-                    rect.min.x as usize,
-                    rect.min.y as usize,
-                    image.placement.width as usize,
-                    image.placement.height as usize,
-                );
-                write_glyph_image(image, self.default_color, region);
-            });
+        match self.mirror {
+            Some(ref mirror) => {
+                // The packer keeps existing allocations in place when growing
+                mirror.rows().enumerate().for_each(|(y, row)| {
+                    let start = y * side_size;
+                    new_atlas_image.buf_mut()[start..start + row.len()].copy_from_slice(row);
+                });
+            }
+            None => {
+                self.cache
+                    .iter()
+                    .filter_map(|(cache_key, state)| {
+                        state.as_ref().map(|state| (cache_key, state.clone()))
+                    })
+                    .for_each(|(&cache_key, cached_glyph_state)| {
+                        let image = swash_cache
+                            .get_image_uncached(font_system, cache_key)
+                            .unwrap();
+                        let rect = cached_glyph_state.allocation.rectangle;
+                        let region = new_atlas_image.sub_image_mut(
+                            rect.min.x as usize,
+                            rect.min.y as usize,
+                            image.placement.width as usize,
+                            image.placement.height as usize,
+                        );
+                        write_glyph_image(image, self.default_color, region);
+                    });
+            }
+        }
+
+        if self.mirror.is_some() {
+            self.mirror = Some(new_atlas_image.clone());
+        }
 
         self.texture = self.ctx.load_texture(
             Self::ATLAS_TEXTURE_NAME,
-            ColorImage {
-                size: [new_atlas_image.width(), new_atlas_image.height()],
-                pixels: new_atlas_image.into_buf(),
-            },
+            compat::color_image(
+                [new_atlas_image.width(), new_atlas_image.height()],
+                new_atlas_image.into_buf(),
+            ),
             TextureOptions::NEAREST,
         );
     }
 
+    /// Moves the atlas over to `ctx` if it isn't the context it was created with,
+    /// e.g. after the app recreated its [`egui::Context`].
+    ///
+    /// Without this, the texture keeps being updated in a context nothing renders anymore.
+    /// Cached glyphs are kept: the texture is re-uploaded from the CPU copy in deterministic mode
+    /// and rasterized again otherwise. [`draw_run`](crate::draw::draw_run) calls this itself.
+    ///
+    /// Returns whether the atlas was moved.
+    pub fn rehome(
+        &mut self,
+        ctx: &egui::Context,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+    ) -> bool {
+        if self.ctx == *ctx {
+            return false;
+        }
+        self.ctx = ctx.clone();
+        self.update_max_texture_side();
+        self.reupload(font_system, swash_cache);
+        true
+    }
+
     fn alloc_packer(&mut self, width: u32, height: u32) -> Option<Allocation> {
         let size = size2(width as i32, height as i32);
         // Will keep freeing up unused glyphs until it can be allocated or
@@ -231,6 +501,122 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
         }
     }
 
+    /// Glyphs are only pinned for the frame they're used in, so every widget drawn during a frame
+    /// is covered no matter the order they're drawn in.
+    fn sync_frame(&mut self) {
+        let frame_nr = compat::frame_nr(&self.ctx);
+        if self.in_use_frame != frame_nr {
+            self.in_use_frame = frame_nr;
+            self.in_use.clear();
+            self.settle();
+        }
+    }
+
+    /// Where every cached glyph goes when they're packed in [`CacheKey`] order into an empty
+    /// atlas of the current size, which is grown until they fit. If they don't fit into the max
+    /// texture size, the least recently used glyphs are left out until they do.
+    fn sorted_packing(&self) -> (BucketedAtlasAllocator, Vec<(CacheKey, Allocation)>) {
+        // Most recently used first
+        let mut glyphs = self
+            .cache
+            .iter()
+            .filter_map(|(&cache_key, state)| Some((cache_key, state.as_ref()?.placement)))
+            .collect::<Vec<_>>();
+        loop {
+            if let Some(packing) = self.pack_sorted(&glyphs) {
+                return packing;
+            }
+            glyphs.pop();
+        }
+    }
+
+    /// `glyphs` packed in [`CacheKey`] order, `None` if they don't fit into the max texture size.
+    fn pack_sorted(
+        &self,
+        glyphs: &[(CacheKey, Placement)],
+    ) -> Option<(BucketedAtlasAllocator, Vec<(CacheKey, Allocation)>)> {
+        let mut glyphs = glyphs.to_vec();
+        glyphs.sort_unstable_by_key(|&(cache_key, _)| cache_key);
+        let mut side = self.atlas_side;
+        loop {
+            let mut packer = BucketedAtlasAllocator::new(Size::splat(side as i32));
+            let allocations = glyphs
+                .iter()
+                .map(|&(cache_key, placement)| {
+                    let size = size2(placement.width as i32, placement.height as i32);
+                    Some((cache_key, packer.allocate(size)?))
+                })
+                .collect::<Option<Vec<_>>>();
+            match allocations {
+                Some(allocations) => return Some((packer, allocations)),
+                None if side >= self.max_texture_side => return None,
+                None => side = (side * 2).at_most(self.max_texture_side),
+            }
+        }
+    }
+
+    /// The CPU copy with the glyphs moved to `allocations`.
+    fn repacked_mirror(
+        &self,
+        mirror: &Img<Vec<Color32>>,
+        side: usize,
+        allocations: &[(CacheKey, Allocation)],
+    ) -> Img<Vec<Color32>> {
+        let mut repacked = Img::new(vec![Color32::TRANSPARENT; side * side], side, side);
+        for (cache_key, allocation) in allocations {
+            let Some(Some(state)) = self.cache.peek(cache_key) else {
+                continue;
+            };
+            let [width, height] =
+                [state.placement.width, state.placement.height].map(|x| x as usize);
+            let [from, to] = [state.allocation, *allocation]
+                .map(|x| x.rectangle.min.to_array().map(|x| x as usize));
+            let glyph = mirror.as_ref().sub_image(from[0], from[1], width, height);
+            repacked
+                .sub_image_mut(to[0], to[1], width, height)
+                .pixels_mut()
+                .zip(glyph.pixels())
+                .for_each(|(slot, pixel)| *slot = pixel);
+        }
+        repacked
+    }
+
+    /// Packs the glyphs again in [`CacheKey`] order in deterministic mode, if any were allocated
+    /// since the last time.
+    ///
+    /// Only done between frames, as glyphs drawn during a frame have to stay where they are.
+    fn settle(&mut self) {
+        let Some(mirror) = self.mirror.as_ref().filter(|_| self.unsettled) else {
+            return;
+        };
+        self.unsettled = false;
+        let (packer, allocations) = self.sorted_packing();
+        let side = packer.size().width as usize;
+        let mirror = self.repacked_mirror(mirror, side, &allocations);
+        let mut evicted = self
+            .cache
+            .iter()
+            .filter_map(|(&cache_key, state)| state.as_ref().map(|_| cache_key))
+            .collect::<HashSet<_>>();
+        for (cache_key, allocation) in allocations {
+            evicted.remove(&cache_key);
+            if let Some(Some(state)) = self.cache.peek_mut(&cache_key) {
+                state.allocation = allocation;
+            }
+        }
+        // Left out to fit into the max texture size
+        for cache_key in evicted {
+            self.cache.pop(&cache_key);
+        }
+        self.packer = packer;
+        self.atlas_side = side;
+        self.texture.set(
+            compat::color_image([side, side], mirror.buf().clone()),
+            TextureOptions::NEAREST,
+        );
+        self.mirror = Some(mirror);
+    }
+
     fn promote(&mut self, cache_key: CacheKey) {
         self.cache.promote(&cache_key);
         self.in_use.insert(cache_key);
@@ -249,6 +635,8 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
         font_system: &mut FontSystem,
         swash_cache: &mut SwashCache,
     ) -> Option<GlyphImage> {
+        self.sync_frame();
+
         let glyph_state = (match self.cache.get(&cache_key) {
             None => {
                 let image = swash_cache.get_image_uncached(font_system, cache_key)?;
@@ -269,6 +657,7 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
                             });
 
                             self.put(cache_key, glyph_state.clone());
+                            self.unsettled = true;
 
                             let [width, height] = [
                                 image.placement.width as usize,
@@ -281,12 +670,20 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
                                 Img::new(&mut pixels, width, height),
                             );
 
+                            let min = x.rectangle.min.to_array().map(|x| x as usize);
+
+                            if let Some(ref mut mirror) = self.mirror {
+                                let mut region =
+                                    mirror.sub_image_mut(min[0], min[1], width, height);
+                                region
+                                    .pixels_mut()
+                                    .zip(pixels.iter())
+                                    .for_each(|(slot, pixel)| *slot = *pixel);
+                            }
+
                             self.texture.set_partial(
-                                x.rectangle.min.to_array().map(|x| x as usize),
-                                ColorImage {
-                                    size: [width, height],
-                                    pixels,
-                                },
+                                min,
+                                compat::color_image([width, height], pixels),
                                 TextureOptions::NEAREST,
                             );
 
@@ -311,10 +708,76 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
         ))
     }
 
+    /// Allocates every glyph that isn't cached yet, in [`CacheKey`] order.
+    ///
+    /// Useful to allocate a frame's glyphs before drawing it, so the atlas grows before anything
+    /// is drawn with it rather than in the middle of drawing.
+    pub fn preload(
+        &mut self,
+        cache_keys: impl IntoIterator<Item = CacheKey>,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+    ) {
+        let mut cache_keys = cache_keys
+            .into_iter()
+            .filter(|x| !self.cache.contains(x))
+            .collect::<Vec<_>>();
+        cache_keys.sort_unstable();
+        cache_keys.dedup();
+        cache_keys.into_iter().for_each(|cache_key| {
+            self.alloc(cache_key, font_system, swash_cache);
+        });
+    }
+
+    /// Copies the atlas contents and glyph placements, with glyphs allocated during the current
+    /// frame already packed like they will be in the next one.
+    ///
+    /// Returns `None` unless the atlas was created with [`Self::new_deterministic`].
+    pub fn snapshot(&self) -> Option<AtlasSnapshot> {
+        let mirror = self.mirror.as_ref()?;
+        let (packer, allocations) = self.sorted_packing();
+        let side = packer.size().width as usize;
+        let glyphs = allocations
+            .iter()
+            .filter_map(|(cache_key, allocation)| {
+                let placement = self.cache.peek(cache_key)?.as_ref()?.placement;
+                Some(GlyphPlacement {
+                    key: GlyphKey::from(*cache_key),
+                    min: allocation.rectangle.min.to_array().map(|x| x as usize),
+                    size: [placement.width, placement.height].map(|x| x as usize),
+                    left: placement.left,
+                    top: placement.top,
+                })
+            })
+            .collect();
+        Some(AtlasSnapshot {
+            size: [side, side],
+            pixels: self.repacked_mirror(mirror, side, &allocations).into_buf(),
+            glyphs,
+        })
+    }
+
+    /// Where the glyph is inside the atlas texture, if it's allocated.
+    ///
+    /// **In physical pixels**, divide by [`Self::atlas_texture_size`] for UV coordinates.
+    pub fn locate(&self, cache_key: CacheKey) -> Option<Rect> {
+        let state = self.cache.peek(&cache_key)?.as_ref()?;
+        let min = state.allocation.rectangle.min;
+        Some(Rect::from_min_size(
+            pos2(min.x as f32, min.y as f32),
+            vec2(state.placement.width as f32, state.placement.height as f32),
+        ))
+    }
+
     pub fn atlas_texture(&self) -> TextureId {
         self.texture.id()
     }
 
+    /// The color glyphs without one of their own are drawn with.
+    pub fn default_color(&self) -> Color32 {
+        self.default_color
+    }
+
     pub fn atlas_texture_size(&self) -> Vec2 {
         self.texture.size_vec2()
     }
@@ -323,16 +786,31 @@ impl<S: BuildHasher + Default> TextureAtlas<S> {
         self.max_texture_side = self.ctx.input(|i| i.max_texture_side)
     }
 
-    pub fn trim(&mut self) {
-        self.in_use.clear()
-    }
+    /// Glyphs in use are now tracked per [frame](egui::Context::frame_nr), this does nothing.
+    #[deprecated(note = "the atlas tracks which glyphs are in use per frame by itself")]
+    pub fn trim(&mut self) {}
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::atlas::{GlyphImage, GlyphState};
-    use cosmic_text::{CacheKey, Placement};
+    use crate::atlas::{GlyphImage, GlyphKey, GlyphState, SeededHasher, SeededState, TextureAtlas};
+    use crate::test_util;
+    use cosmic_text::{CacheKey, FontSystem, Placement, SwashCache};
+    use egui::Color32;
     use etagere::Allocation;
+    use std::hash::{BuildHasher, Hasher};
+
+    fn cache_keys(font_system: &mut FontSystem, text: &str) -> Vec<CacheKey> {
+        let buf = test_util::buffer(font_system, text, None);
+        buf.layout_runs()
+            .flat_map(|run| {
+                run.glyphs
+                    .iter()
+                    .map(|glyph| glyph.physical((0.0, 0.0), 1.0).cache_key)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
 
     #[test]
     fn test() {
@@ -341,4 +819,133 @@ mod tests {
         dbg!(std::mem::size_of::<CacheKey>());
         dbg!(std::mem::size_of::<GlyphImage>());
     }
+
+    #[test]
+    fn seeded_hasher_is_fnv1a() {
+        let mut hasher = SeededHasher(SeededHasher::OFFSET_BASIS);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let hash = |x: u32| SeededState::<0>.hash_one(x);
+        assert_eq!(hash(1), hash(1));
+        assert_ne!(hash(1), SeededState::<1>.hash_one(1u32));
+    }
+
+    #[test]
+    fn deterministic_snapshot_ignores_draw_order() {
+        let mut font_system = test_util::font_system();
+        let mut swash_cache = SwashCache::new();
+        let cache_keys = cache_keys(&mut font_system, "The quick brown fox 0123456789");
+
+        let ctx = egui::Context::default();
+        let mut draw = |keys: &mut dyn Iterator<Item = &CacheKey>| {
+            let mut atlas =
+                TextureAtlas::<SeededState>::new_deterministic(ctx.clone(), Color32::WHITE);
+            keys.for_each(|&cache_key| {
+                atlas.alloc(cache_key, &mut font_system, &mut swash_cache);
+            });
+            atlas
+        };
+        let mut forward = draw(&mut cache_keys.iter());
+        let mut backward = draw(&mut cache_keys.iter().rev());
+
+        let snapshot = forward.snapshot().unwrap();
+        assert!(!snapshot.glyphs.is_empty());
+        assert_eq!(snapshot, backward.snapshot().unwrap());
+
+        // The next frame packs them like the snapshot
+        let _ = ctx.run(Default::default(), |_| {});
+        for atlas in [&mut forward, &mut backward] {
+            atlas.alloc(cache_keys[0], &mut font_system, &mut swash_cache);
+            assert_eq!(atlas.snapshot().unwrap(), snapshot);
+        }
+        for glyph in &snapshot.glyphs {
+            let cache_key = *cache_keys
+                .iter()
+                .find(|&&x| GlyphKey::from(x) == glyph.key)
+                .unwrap();
+            let rect = forward.locate(cache_key).unwrap();
+            assert_eq!(
+                rect.min.to_vec2(),
+                egui::vec2(glyph.min[0] as f32, glyph.min[1] as f32)
+            );
+            assert_eq!(backward.locate(cache_key), Some(rect));
+        }
+    }
+
+    #[test]
+    fn settling_leaves_out_least_recently_used_glyphs_over_the_max_size() {
+        let mut font_system = test_util::font_system();
+        let mut swash_cache = SwashCache::new();
+        let cache_keys = cache_keys(&mut font_system, "The quick brown fox 0123456789");
+
+        let ctx = egui::Context::default();
+        let mut atlas = TextureAtlas::<SeededState>::new_deterministic(ctx.clone(), Color32::WHITE);
+        let sized = cache_keys
+            .iter()
+            .copied()
+            .filter(|&x| atlas.alloc(x, &mut font_system, &mut swash_cache).is_some())
+            .collect::<Vec<_>>();
+        // As if the GPU only allowed tiny textures
+        atlas.atlas_side = 32;
+        atlas.max_texture_side = 32;
+
+        let snapshot = atlas.snapshot().unwrap();
+        assert_eq!(snapshot.size, [32, 32]);
+        assert!(snapshot.glyphs.len() < sized.len());
+
+        let _ = ctx.run(Default::default(), |_| {});
+        let last = *sized.last().unwrap();
+        atlas.alloc(last, &mut font_system, &mut swash_cache);
+        assert_eq!(atlas.snapshot().unwrap(), snapshot);
+        assert!(atlas.locate(last).is_some());
+        assert!(atlas.locate(sized[0]).is_none());
+    }
+
+    #[test]
+    fn rehome_reuploads_into_the_new_context() {
+        let mut font_system = test_util::font_system();
+        let mut swash_cache = SwashCache::new();
+        let cache_keys = cache_keys(&mut font_system, "Recreated");
+
+        let old_ctx = egui::Context::default();
+        let mut atlas =
+            TextureAtlas::<SeededState>::new_deterministic(old_ctx.clone(), Color32::WHITE);
+        atlas.preload(cache_keys, &mut font_system, &mut swash_cache);
+        let before = atlas.snapshot().unwrap();
+
+        assert!(!atlas.rehome(&old_ctx, &mut font_system, &mut swash_cache));
+
+        let new_ctx = egui::Context::default();
+        assert!(atlas.rehome(&new_ctx, &mut font_system, &mut swash_cache));
+        let name = new_ctx
+            .tex_manager()
+            .read()
+            .meta(atlas.atlas_texture())
+            .map(|meta| meta.name.clone());
+        assert_eq!(
+            name.as_deref(),
+            Some(TextureAtlas::<SeededState>::ATLAS_TEXTURE_NAME)
+        );
+        assert_eq!(atlas.snapshot().unwrap(), before);
+    }
+
+    #[test]
+    fn in_use_resets_every_frame() {
+        let mut font_system = test_util::font_system();
+        let mut swash_cache = SwashCache::new();
+        let buf = test_util::buffer(&mut font_system, "ab", None);
+        let run = buf.layout_runs().next().unwrap();
+        let [a, b] = [0, 1].map(|i| run.glyphs[i].physical((0.0, 0.0), 1.0).cache_key);
+
+        let ctx = egui::Context::default();
+        let mut atlas = TextureAtlas::<SeededState>::new(ctx.clone(), Color32::WHITE);
+        atlas.alloc(a, &mut font_system, &mut swash_cache);
+        assert!(atlas.in_use.contains(&a));
+
+        let _ = ctx.run(Default::default(), |_| {});
+        atlas.alloc(b, &mut font_system, &mut swash_cache);
+        assert!(!atlas.in_use.contains(&a));
+        assert!(atlas.in_use.contains(&b));
+    }
 }
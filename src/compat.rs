@@ -0,0 +1,136 @@
+//! The egui APIs that have changed across recent egui releases, kept in one place so
+//! supporting another egui version only has to touch this module.
+//!
+//! The version is picked by the `egui_028`, `egui_031` and `egui_032` features. 0.31 and 0.32
+//! share most of their APIs, so only the places where they differ are split further.
+
+use egui::{Color32, ColorImage};
+
+/// `ColorImage::new` takes a fill color up to 0.31, 0.32 constructs it from pixels and adds
+/// `ColorImage::filled` instead.
+#[cfg(not(feature = "egui_032"))]
+pub(crate) fn filled_color_image(size: [usize; 2], color: Color32) -> ColorImage {
+    ColorImage::new(size, color)
+}
+
+#[cfg(feature = "egui_032")]
+pub(crate) fn filled_color_image(size: [usize; 2], color: Color32) -> ColorImage {
+    ColorImage::filled(size, color)
+}
+
+/// Struct literals break once new fields are added, e.g. `source_size` in 0.32.
+#[cfg(not(feature = "egui_032"))]
+pub(crate) fn color_image(size: [usize; 2], pixels: Vec<Color32>) -> ColorImage {
+    ColorImage { size, pixels }
+}
+
+#[cfg(feature = "egui_032")]
+pub(crate) fn color_image(size: [usize; 2], pixels: Vec<Color32>) -> ColorImage {
+    ColorImage::new(size, pixels)
+}
+
+/// `frame_nr` was renamed to `cumulative_pass_nr` in 0.29.
+#[cfg(feature = "egui_028")]
+pub(crate) fn frame_nr(ctx: &egui::Context) -> u64 {
+    ctx.frame_nr()
+}
+
+#[cfg(not(feature = "egui_028"))]
+pub(crate) fn frame_nr(ctx: &egui::Context) -> u64 {
+    ctx.cumulative_pass_nr()
+}
+
+/// Strokes `rect` centered on its edge, which newer versions need spelled out as a `StrokeKind`.
+#[cfg(feature = "widget")]
+pub(crate) fn rect_stroke(painter: &egui::Painter, rect: egui::Rect, stroke: egui::Stroke) {
+    #[cfg(feature = "egui_028")]
+    painter.rect_stroke(rect, 0.0, stroke);
+    #[cfg(not(feature = "egui_028"))]
+    painter.rect_stroke(rect, 0.0, stroke, egui::StrokeKind::Middle);
+}
+
+/// Writing `copied_text` is deprecated in favor of output commands in 0.31+, `copy_text`
+/// covers both.
+#[cfg(feature = "widget")]
+pub(crate) fn copy_text(ctx: &egui::Context, text: String) {
+    ctx.copy_text(text);
+}
+
+/// The text the last frame put on the clipboard, empty if nothing was copied.
+#[cfg(all(test, feature = "egui_028"))]
+pub(crate) fn copied_text(output: &egui::PlatformOutput) -> String {
+    output.copied_text.clone()
+}
+
+#[cfg(all(test, not(feature = "egui_028")))]
+pub(crate) fn copied_text(output: &egui::PlatformOutput) -> String {
+    output
+        .commands
+        .iter()
+        .rev()
+        .find_map(|command| match command {
+            egui::OutputCommand::CopyText(text) => Some(text.clone()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// `close_menu` is deprecated in favor of `Ui::close` in 0.32.
+#[cfg(all(feature = "widget", not(feature = "egui_032")))]
+pub(crate) fn close_menu(ui: &mut egui::Ui) {
+    ui.close_menu();
+}
+
+#[cfg(all(feature = "widget", feature = "egui_032"))]
+pub(crate) fn close_menu(ui: &mut egui::Ui) {
+    ui.close();
+}
+
+/// Opens `response`'s context menu at `pos` as if it was secondary clicked there.
+///
+/// Up to 0.31 it's only opened on clicks, so this writes the state `Response::context_menu`
+/// keeps under its fixed id.
+#[cfg(all(feature = "widget", not(feature = "egui_032")))]
+pub(crate) fn open_context_menu(response: &egui::Response, pos: egui::Pos2) {
+    // https://github.com/emilk/egui/blob/0.28.1/crates/egui/src/menu.rs#L244
+    let id = egui::Id::new("__egui::context_menu");
+    let mut state = egui::menu::BarState::load(&response.ctx, id);
+    **state = Some(egui::menu::MenuRoot::new(pos, response.id));
+    state.store(&response.ctx, id);
+}
+
+/// 0.32 keeps context menus as popups, their position is only settable through the deprecated
+/// `open_popup_at`, which is what `Popup::context_menu` itself uses.
+#[cfg(all(feature = "widget", feature = "egui_032"))]
+pub(crate) fn open_context_menu(response: &egui::Response, pos: egui::Pos2) {
+    let id = egui::Popup::default_response_id(response);
+    #[allow(deprecated)]
+    response.ctx.memory_mut(|mem| mem.open_popup_at(id, pos));
+}
+
+/// Max seconds between the clicks of a double or triple click.
+///
+/// egui 0.28 keeps its own value private, newer versions expose it in `InputOptions`.
+#[cfg(all(feature = "widget", feature = "egui_028"))]
+pub(crate) fn max_multi_click_interval(_ctx: &egui::Context) -> f64 {
+    // https://github.com/emilk/egui/blob/114f8201709aa822a3f620404a20de2e695725ad/crates/egui/src/input_state.rs#L12
+    0.5
+}
+
+#[cfg(all(feature = "widget", not(feature = "egui_028")))]
+pub(crate) fn max_multi_click_interval(ctx: &egui::Context) -> f64 {
+    ctx.options(|options| options.input_options.max_double_click_delay)
+}
+
+/// Max distance in points between a press and release for it to still count as a click.
+///
+/// egui 0.28 keeps its own value private, newer versions expose `InputOptions::max_click_dist`.
+#[cfg(all(feature = "widget", feature = "egui_028"))]
+pub(crate) fn max_click_dist(_ctx: &egui::Context) -> f32 {
+    6.0
+}
+
+#[cfg(all(feature = "widget", not(feature = "egui_028")))]
+pub(crate) fn max_click_dist(ctx: &egui::Context) -> f32 {
+    ctx.options(|options| options.input_options.max_click_dist)
+}
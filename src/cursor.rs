@@ -1,11 +1,14 @@
 use cosmic_text::{Affinity, Buffer, Cursor, LayoutLine, LayoutRun};
 use egui::{pos2, vec2, Rect};
+use std::ops::Range;
+
+use crate::spacing::Spacing;
+use crate::units::Phys;
 
 // There's an issue here where if the first line is only spaces, it can get to a certain point where the cursor is invalid.
 // I believe this happens in cosmic-edit too so it might be a cosmic-text bug.
 // The editor gets into a state where the cursor goes past all the glyphs. Presumably this is where the buffer should've wrapped.
-/// **In physical pixels.**
-pub fn cursor_pos(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
+pub fn cursor_pos(buf: &Buffer, cursor: Cursor, spacing: Spacing) -> Option<Phys<Rect>> {
     let base_line_height = buf.metrics().line_height;
 
     let height_before_cursor_line = buf
@@ -13,7 +16,7 @@ pub fn cursor_pos(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
         .iter()
         .enumerate()
         .take(cursor.line)
-        .filter_map(|(_, line)| line.layout_opt().as_ref())
+        .filter_map(|(_, line)| line.layout_opt())
         .flatten()
         .map(|x| x.line_height_opt.unwrap_or(base_line_height))
         .sum();
@@ -22,18 +25,19 @@ pub fn cursor_pos(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
         let line_height = buf
             .lines
             .get(cursor.line)
-            .and_then(|x| x.layout_opt().as_ref())
+            .and_then(|x| x.layout_opt())
             .and_then(|x| x.first())
             .map(|x| x.line_height_opt.unwrap_or(base_line_height))?;
 
-        return Some(Rect::from_min_size(
+        return Some(Phys(Rect::from_min_size(
             pos2(0.0, height_before_cursor_line),
             vec2(1.0, line_height),
-        ));
+        )));
     }
 
     let line = buf.lines.get(cursor.line)?;
-    let layout_lines_vec = line.layout_opt().as_ref()?;
+    let text = line.text();
+    let layout_lines_vec = line.layout_opt()?;
 
     let mut last_line = None::<(&LayoutLine, f32)>;
 
@@ -57,22 +61,34 @@ pub fn cursor_pos(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
 
         if is_cursor_before_start {
             return last_line.map(|(line, line_top)| {
-                Rect::from_min_size(
-                    pos2(line.w, line_top),
+                Phys(Rect::from_min_size(
+                    pos2(line.w + spacing.extra_width(&line.glyphs, text), line_top),
                     vec2(1.0, line.line_height_opt.unwrap_or(base_line_height)),
-                )
+                ))
             });
         } else if is_cursor_before_end {
-            let offset = layout_line
-                .glyphs
-                .iter()
-                .take_while(|glyph| cursor.index > glyph.start)
-                .map(|glyph| glyph.w)
-                .sum();
-            return Some(Rect::from_min_size(
+            // Glyph positions aren't necessarily contiguous (alignment, tabs, shaping adjustments),
+            // so the caret is placed on the glyph edges rather than at the sum of their widths.
+            let glyphs = || {
+                layout_line
+                    .glyphs
+                    .iter()
+                    .zip(spacing.offsets(&layout_line.glyphs, text))
+            };
+            let offset = glyphs()
+                .find(|(glyph, _)| cursor.index <= glyph.start)
+                .map(|(glyph, spacing)| glyph.x + spacing)
+                .or_else(|| {
+                    glyphs()
+                        .filter(|(glyph, _)| glyph.start < cursor.index)
+                        .last()
+                        .map(|(glyph, spacing)| glyph.x + glyph.w + spacing)
+                })
+                .unwrap_or_default();
+            return Some(Phys(Rect::from_min_size(
                 pos2(offset, line_top),
                 vec2(1.0, layout_line.line_height_opt.unwrap_or(base_line_height)),
-            ));
+            )));
         }
 
         last_line = Some((layout_line, line_top));
@@ -80,15 +96,19 @@ pub fn cursor_pos(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
         line_top += layout_line.line_height_opt.unwrap_or(base_line_height);
     }
 
-    let last_glyph = layout_lines_vec.last().and_then(|x| x.glyphs.last());
+    let last_layout_line = layout_lines_vec.last();
+    let last_glyph = last_layout_line.and_then(|x| x.glyphs.last());
     if let Some(last_glyph) = last_glyph {
         let last_glyph_index = last_glyph.end;
         if last_glyph_index == cursor.index {
             let (line, line_top) = last_line?;
-            return Some(Rect::from_min_size(
-                pos2(last_glyph.x + last_glyph.w, line_top),
+            let extra_width = last_layout_line
+                .map(|x| spacing.extra_width(&x.glyphs, text))
+                .unwrap_or_default();
+            return Some(Phys(Rect::from_min_size(
+                pos2(last_glyph.x + last_glyph.w + extra_width, line_top),
                 vec2(1.0, line.line_height_opt.unwrap_or(base_line_height)),
-            ));
+            )));
         }
     }
 
@@ -112,6 +132,25 @@ fn end_cursor(run: &LayoutRun) -> Option<Cursor> {
     }
 }
 
+/// The selection geometry of every laid out row `bounds` touches, in order.
+///
+/// `visible_rows` limits it to the layout runs at those indices, e.g. the ones on screen.
+pub fn line_selections(
+    buf: &Buffer,
+    (start, end): (Cursor, Cursor),
+    visible_rows: Option<Range<usize>>,
+    spacing: Spacing,
+) -> impl Iterator<Item = LineSelection> + '_ {
+    let visible_rows = visible_rows.unwrap_or(0..usize::MAX);
+    buf.layout_runs()
+        .enumerate()
+        .skip(visible_rows.start)
+        .take(visible_rows.len())
+        // Runs after the end can't be selected
+        .take_while(move |(_, run)| run.line_i <= end.line)
+        .filter_map(move |(_, run)| LineSelection::new(&run, (start, end), spacing))
+}
+
 #[derive(Debug)]
 pub struct LineSelection {
     x_left: f32,
@@ -122,14 +161,29 @@ pub struct LineSelection {
 }
 
 impl LineSelection {
-    pub fn new(run: &LayoutRun, (start, end): (Cursor, Cursor)) -> Option<Self> {
+    pub fn new(run: &LayoutRun, (start, end): (Cursor, Cursor), spacing: Spacing) -> Option<Self> {
         run.highlight(start, end)
+            .map(|(x_left, x_width)| {
+                if spacing.is_zero() {
+                    return (x_left, x_width);
+                }
+                let left = spacing.space_x(run.glyphs, run.text, x_left, true);
+                let right = spacing.space_x(run.glyphs, run.text, x_left + x_width, false);
+                (left, right - left)
+            })
             .or_else(|| {
                 // Highlight function is based on glyphs, so it won't return anything even if
                 // it's within the selection.
                 // Affinity messes up with 0 indexes sometimes
                 let buffer_line_range = start.line..=end.line;
-                if run.glyphs.is_empty() && buffer_line_range.contains(&run.line_i) {
+                // A selection ending at the start of a line takes the line break before it,
+                // the row having a selection keeps that break's highlight from being dropped
+                let ends_on_row = run.line_i == end.line
+                    && end.index == 0
+                    && start.line < end.line
+                    && run.glyphs.iter().any(|glyph| glyph.start == 0);
+                if (run.glyphs.is_empty() && buffer_line_range.contains(&run.line_i)) || ends_on_row
+                {
                     (0.0, 0.0).into()
                 } else {
                     None
@@ -154,18 +208,96 @@ impl LineSelection {
         self.end_of_line_included
     }
 
-    /// In **physical pixels**
-    pub fn x_left_and_width(&self) -> (f32, f32) {
-        (self.x_left, self.x_width)
+    pub fn x_left_and_width(&self) -> Phys<(f32, f32)> {
+        Phys((self.x_left, self.x_width))
+    }
+
+    pub fn line_top(&self) -> Phys<f32> {
+        Phys(self.line_top)
     }
 
-    /// In **physical pixels**
-    pub fn line_top(&self) -> f32 {
-        self.line_top
+    pub fn line_height(&self) -> Phys<f32> {
+        Phys(self.line_height)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cursor::line_selections;
+    use crate::spacing::Spacing;
+    use crate::test_util;
+    use cosmic_text::Cursor;
+
+    #[test]
+    fn single_row() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "one row", None);
+        let bounds = (Cursor::new(0, 0), Cursor::new(0, 3));
+
+        let selections =
+            line_selections(&buf, bounds, None, Spacing::default()).collect::<Vec<_>>();
+        assert_eq!(selections.len(), 1);
+        let (x_left, x_width) = *selections[0].x_left_and_width();
+        assert_eq!(x_left, 0.0);
+        assert!(x_width > 0.0);
+        assert!(!selections[0].end_of_line_included());
+    }
+
+    #[test]
+    fn multiple_rows_and_visible_rows() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "first\nsecond\nthird", None);
+        let bounds = (Cursor::new(0, 2), Cursor::new(2, 2));
+
+        let selections =
+            line_selections(&buf, bounds, None, Spacing::default()).collect::<Vec<_>>();
+        assert_eq!(selections.len(), 3);
+        assert!(selections[0].end_of_line_included());
+        assert!(selections[1].end_of_line_included());
+        assert!(!selections[2].end_of_line_included());
+        assert!(*selections[0].line_top() < *selections[1].line_top());
+
+        let visible = line_selections(&buf, bounds, Some(1..2), Spacing::default())
+            .map(|x| *x.line_top())
+            .collect::<Vec<_>>();
+        assert_eq!(visible, [*selections[1].line_top()]);
+    }
+
+    #[test]
+    fn empty_line_is_still_selected() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "above\n\nbelow", None);
+        let bounds = (Cursor::new(0, 0), Cursor::new(2, 5));
+
+        let selections =
+            line_selections(&buf, bounds, None, Spacing::default()).collect::<Vec<_>>();
+        assert_eq!(selections.len(), 3);
+        assert_eq!(*selections[1].x_left_and_width(), (0.0, 0.0));
+        assert!(selections[1].end_of_line_included());
+    }
+
+    #[test]
+    fn right_to_left() {
+        let mut font_system = test_util::font_system();
+        let text = "שלום עולם";
+        let buf = test_util::buffer(&mut font_system, text, None);
+        let run = buf.layout_runs().next().unwrap();
+        assert!(run.rtl);
+
+        // The first word sits on the right
+        let first_word = (Cursor::new(0, 0), Cursor::new(0, "שלום".len()));
+        let selections =
+            line_selections(&buf, first_word, None, Spacing::default()).collect::<Vec<_>>();
+        assert_eq!(selections.len(), 1);
+        let (x_left, x_width) = *selections[0].x_left_and_width();
+        assert!(x_left > 0.0 && x_width > 0.0);
+        assert!((x_left + x_width - run.line_w).abs() < 0.01);
 
-    /// In **physical pixels**
-    pub fn line_height(&self) -> f32 {
-        self.line_height
+        let everything = (Cursor::new(0, 0), Cursor::new(0, text.len()));
+        let selections =
+            line_selections(&buf, everything, None, Spacing::default()).collect::<Vec<_>>();
+        let (x_left, x_width) = *selections[0].x_left_and_width();
+        assert!(x_left.abs() < 0.01 && (x_width - run.line_w).abs() < 0.01);
+        assert!(selections[0].end_of_line_included());
     }
 }
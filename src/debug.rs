@@ -0,0 +1,27 @@
+//! Helpers for inspecting what the crate is doing, behind the `debug-tools` feature.
+
+use crate::atlas::TextureAtlas;
+use cosmic_text::CacheKey;
+use egui::load::SizedTexture;
+use egui::{Color32, Response, Stroke, Ui};
+use std::hash::BuildHasher;
+
+/// Shows the atlas texture and outlines where `highlight` is, if it's allocated.
+///
+/// The texture is shown at one logical pixel per atlas pixel.
+pub fn atlas_inspector<S: BuildHasher + Default>(
+    ui: &mut Ui,
+    atlas: &TextureAtlas<S>,
+    highlight: Option<CacheKey>,
+) -> Response {
+    let size = atlas.atlas_texture_size();
+    let resp = ui.image(SizedTexture::new(atlas.atlas_texture(), size));
+    if let Some(rect) = highlight.and_then(|x| atlas.locate(x)) {
+        ui.painter().rect_stroke(
+            rect.translate(resp.rect.min.to_vec2()).expand(1.0),
+            0.0,
+            Stroke::new(1.0, Color32::RED),
+        );
+    }
+    resp
+}
@@ -1,8 +1,31 @@
-use crate::atlas::TextureAtlas;
-use crate::util::cursor_rect;
-use cosmic_text::{Buffer, Cursor, FontSystem, LayoutGlyph, LayoutRun, SwashCache};
-use egui::{Painter, Pos2, Rangef, Rect};
+use crate::atlas::{glyph_color, TextureAtlas};
+use crate::cursor::LineSelection;
+use crate::spacing::Spacing;
+use crate::units::{Logical, Phys};
+use crate::util::{
+    cursor_rect, selection_rect, snap_pos, snap_to_logical, to_logical, to_physical,
+};
+use cosmic_text::{Buffer, Cursor, FontSystem, LayoutRun, SwashCache};
+use egui::emath::Rot2;
+use egui::{pos2, vec2, Color32, CursorIcon, Mesh, Painter, Pos2, Rangef, Rect, Vec2};
 use std::hash::BuildHasher;
+use std::ops::Range;
+
+/// A quarter turn, see [`draw_buf_rotated`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rot90 {
+    /// Clockwise, the text reads from top to bottom.
+    Cw,
+    /// Counter-clockwise, the text reads from bottom to top, e.g. y axis titles.
+    Ccw,
+}
+
+impl Rot90 {
+    /// The size of the rect the text takes up once rotated.
+    pub fn transpose(size: Vec2) -> Vec2 {
+        vec2(size.y, size.x)
+    }
+}
 
 enum PeekedLine<H> {
     Peeked(Option<H>),
@@ -10,19 +33,16 @@ enum PeekedLine<H> {
 }
 
 /// Draws a buffer.
-///
-/// `min_pos`, `clip_rect`, `hover_pos` is expected to be in **logical pixels**
-///
-/// `measure_hover_box_width` is expected to be in *physical pixels*
+#[allow(clippy::too_many_arguments)]
 pub fn draw_buf<H>(
     buf: &Buffer,
-    min_pos: Pos2,
-    clip_rect: Rect,
-    hover_pos: Option<Pos2>,
+    min_pos: Logical<Pos2>,
+    clip_rect: Logical<Rect>,
+    hover_pos: Option<Logical<Pos2>>,
     selection_end: Option<Cursor>,
     painter: &mut Painter,
-    measure_hover_box_width: impl Fn(&[LayoutGlyph]) -> Option<f32>,
-    mut on_hover: impl FnMut(),
+    hover_icon: impl Fn(&LayoutRun, Phys<Pos2>) -> Option<CursorIcon>,
+    mut on_hover: impl FnMut(CursorIcon),
     highlight_single_line: impl Fn(&LayoutRun) -> Option<H>,
     mut draw_line_highlight: impl FnMut(H, bool, &mut Painter),
     mut draw_run: impl FnMut(&LayoutRun, &mut Painter),
@@ -33,16 +53,16 @@ pub fn draw_buf<H>(
 
     let line_y_range = |run: &LayoutRun| {
         Rangef::new(
-            min_pos.y + (run.line_top / pixels_per_point),
-            min_pos.y + ((run.line_top + run.line_height) / pixels_per_point),
+            min_pos.y + *to_logical(Phys(run.line_top), pixels_per_point),
+            min_pos.y + *to_logical(Phys(run.line_top + run.line_height), pixels_per_point),
         )
     };
 
+    // Only the y range is used, which spacing doesn't affect
     let selection_end_cursor_rect = selection_end
-        .and_then(|x| cursor_rect(buf, x))
+        .and_then(|x| cursor_rect(buf, x, Spacing::default()))
         // convert from physical pixels to logical points
-        .map(|x| x / pixels_per_point)
-        .map(|rect| rect.translate(min_pos.to_vec2()));
+        .map(|rect| *snap_to_logical(rect, min_pos, pixels_per_point));
 
     let mut peeked_highlighted_line: PeekedLine<H> = PeekedLine::Peeked(None);
 
@@ -61,18 +81,11 @@ pub fn draw_buf<H>(
         let line_y_range = line_y_range(&run);
 
         if let Some(hover_pos) = hover_pos {
-            if !hovered_already {
-                let hover_box_width = measure_hover_box_width(run.glyphs);
-                if let Some(hover_box_width) = hover_box_width {
-                    let bounding_box = Rect::from_x_y_ranges(
-                        min_pos.x..=min_pos.x + (hover_box_width / pixels_per_point),
-                        line_y_range,
-                    );
-                    let hover = bounding_box.contains(hover_pos);
-                    if hover {
-                        on_hover();
-                    }
-                    hovered_already |= hover;
+            if !hovered_already && line_y_range.contains(hover_pos.y) {
+                let pos = to_physical(Logical((*hover_pos - *min_pos).to_pos2()), pixels_per_point);
+                if let Some(icon) = hover_icon(&run, pos) {
+                    on_hover(icon);
+                    hovered_already = true;
                 }
             }
         }
@@ -100,22 +113,260 @@ pub fn draw_buf<H>(
     }
 }
 
-/// `rect` is expected to be in **logical pixels**
+/// Collects the selection rects of the runs within `visible_y_range`, matching the highlights
+/// [`draw_buf`] would paint, so they can be cached and painted up front.
+///
+/// `visible_y_range` is relative to the buffer.
+pub fn selection_rects(
+    buf: &Buffer,
+    visible_y_range: Phys<Rangef>,
+    bounds: (Cursor, Cursor),
+    spacing: Spacing,
+    rects: &mut Vec<Phys<Rect>>,
+) {
+    let line_y_range = |run: &LayoutRun| Rangef::new(run.line_top, run.line_top + run.line_height);
+
+    let is_end_visible = cursor_rect(buf, bounds.1, spacing)
+        .is_some_and(|rect| visible_y_range.intersects(rect.y_range()));
+
+    let mut selections = buf
+        .layout_runs()
+        .skip_while(|run| !visible_y_range.intersects(line_y_range(run)))
+        .take_while(|run| visible_y_range.intersects(line_y_range(run)))
+        .map(|run| LineSelection::new(&run, bounds, spacing))
+        .peekable();
+
+    while let Some(selection) = selections.next() {
+        if let Some(selection) = selection {
+            let last = is_end_visible && selections.peek().is_none_or(Option::is_none);
+            rects.push(selection_rect(selection, last));
+            if last {
+                break;
+            }
+        }
+    }
+}
+
+/// Draws the glyphs of `layout_run` within `rect`.
+///
+/// `text_color` tints the glyphs without a color of their own instead of the atlas's default
+/// color, see [`GlyphImage::with_default_color`](crate::atlas::GlyphImage::with_default_color).
+#[allow(clippy::too_many_arguments)]
 pub fn draw_run<S: BuildHasher + Default>(
     layout_run: &LayoutRun,
     font_system: &mut FontSystem,
     swash_cache: &mut SwashCache,
     atlas: &mut TextureAtlas<S>,
     painter: &mut Painter,
-    rect: Rect,
+    rect: Logical<Rect>,
+    spacing: Spacing,
+    text_color: Option<Color32>,
+) {
+    let pixels_per_point = painter.ctx().pixels_per_point();
+
+    atlas.rehome(painter.ctx(), font_system, swash_cache);
+
+    let min = to_physical(Logical(rect.min), pixels_per_point);
+    // Glyphs can reach past their advance, the margin keeps overhangs from being cut off
+    let visible_x_range = Rangef::new(
+        *to_physical(Logical(painter.clip_rect().left()), pixels_per_point) - min.x,
+        *to_physical(Logical(painter.clip_rect().right()), pixels_per_point) - min.x,
+    )
+    .expand(layout_run.line_height);
+
+    let offsets = spacing.offsets(layout_run.glyphs, layout_run.text);
+    layout_run
+        .glyphs
+        .iter()
+        .zip(offsets)
+        .filter(|(glyph, spacing)| {
+            // Off to the side, e.g. most of a long unwrapped line
+            visible_x_range.intersects(Rangef::new(glyph.x + spacing, glyph.x + spacing + glyph.w))
+        })
+        .for_each(|(glyph, spacing)| {
+            let physical_glyph = glyph.physical((min.x + spacing, min.y), 1.0);
+            if let Some(mut glyph_img) =
+                atlas.alloc(physical_glyph.cache_key, font_system, swash_cache)
+            {
+                if let Some(color) = text_color {
+                    glyph_img = glyph_img.with_default_color(color);
+                }
+                glyph_img.paint(glyph, physical_glyph, layout_run, painter)
+            }
+        })
+}
+
+/// A glyph as [`draw_run`] placed it, see [`run_glyphs`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphInfo {
+    /// The buffer line the glyph is on
+    pub line: usize,
+    /// The bytes of the line the glyph's cluster covers
+    pub range: Range<usize>,
+    /// The glyph's advance over the height of its row, in logical pixels
+    pub rect: Rect,
+    pub color: Color32,
+}
+
+/// Calls `f` with every glyph of `layout_run` where [`draw_run`] draws it within `rect`.
+///
+/// Glyphs without a color of their own get `default_color`, see [`TextureAtlas::default_color`].
+pub fn run_glyphs(
+    layout_run: &LayoutRun,
+    rect: Logical<Rect>,
+    pixels_per_point: f32,
+    spacing: Spacing,
+    default_color: Color32,
+    mut f: impl FnMut(GlyphInfo),
+) {
+    let offsets = spacing.offsets(layout_run.glyphs, layout_run.text);
+    layout_run
+        .glyphs
+        .iter()
+        .zip(offsets)
+        .for_each(|(glyph, spacing)| {
+            let min = pos2(glyph.x + spacing, layout_run.line_top);
+            let size = vec2(glyph.w, layout_run.line_height);
+            f(GlyphInfo {
+                line: layout_run.line_i,
+                range: glyph.start..glyph.end,
+                rect: *snap_to_logical(
+                    Phys(Rect::from_min_size(min, size)),
+                    Logical(rect.min),
+                    pixels_per_point,
+                ),
+                color: glyph_color(glyph, default_color),
+            })
+        })
+}
+
+/// Allocates the glyphs of every laid out run ahead of [`draw_run`], see [`TextureAtlas::preload`].
+///
+/// `rect` and `spacing` have to match the ones passed to [`draw_run`]
+pub fn preload_buf<S: BuildHasher + Default>(
+    buf: &Buffer,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    atlas: &mut TextureAtlas<S>,
+    pixels_per_point: f32,
+    rect: Logical<Rect>,
+    spacing: Spacing,
+) {
+    let min = to_physical(Logical(rect.min), pixels_per_point);
+    let cache_keys = buf
+        .layout_runs()
+        .flat_map(|run| {
+            let offsets = spacing.offsets(run.glyphs, run.text);
+            run.glyphs.iter().zip(offsets).map(move |(glyph, spacing)| {
+                glyph.physical((min.x + spacing, min.y), 1.0).cache_key
+            })
+        })
+        .collect::<Vec<_>>();
+    atlas.preload(cache_keys, font_system, swash_cache);
+}
+
+/// Draws a buffer turned by a quarter into `rect`, which is the laid out size [transposed](Rot90::transpose).
+///
+/// The glyphs are batched into a single mesh that's rotated as a whole.
+/// Unlike [`draw_buf`], there's no selection, hover or clipping of runs. `text_color` is the same
+/// as for [`draw_run`].
+#[allow(clippy::too_many_arguments)]
+pub fn draw_buf_rotated<S: BuildHasher + Default>(
+    buf: &Buffer,
+    font_system: &mut FontSystem,
+    swash_cache: &mut SwashCache,
+    atlas: &mut TextureAtlas<S>,
+    painter: &mut Painter,
+    rect: Logical<Rect>,
+    rotation: Rot90,
+    spacing: Spacing,
+    text_color: Option<Color32>,
 ) {
     let pixels_per_point = painter.ctx().pixels_per_point();
 
-    layout_run.glyphs.iter().for_each(|glyph| {
-        // convert from logical pixels to physical pixels
-        let physical_glyph = glyph.physical((rect.min * pixels_per_point).into(), 1.0);
-        if let Some(glyph_img) = atlas.alloc(physical_glyph.cache_key, font_system, swash_cache) {
-            glyph_img.paint(glyph, physical_glyph, layout_run, painter)
+    atlas.rehome(painter.ctx(), font_system, swash_cache);
+
+    // Growing the atlas replaces its texture and invalidates the UVs handed out before,
+    // so everything is allocated first and the mesh is built from the cached glyphs.
+    buf.layout_runs().for_each(|run| {
+        let offsets = spacing.offsets(run.glyphs, run.text);
+        run.glyphs.iter().zip(offsets).for_each(|(glyph, spacing)| {
+            let physical_glyph = glyph.physical((spacing, 0.0), 1.0);
+            atlas.alloc(physical_glyph.cache_key, font_system, swash_cache);
+        })
+    });
+
+    let mut mesh = Mesh::with_texture(atlas.atlas_texture());
+    buf.layout_runs().for_each(|run| {
+        let offsets = spacing.offsets(run.glyphs, run.text);
+        run.glyphs.iter().zip(offsets).for_each(|(glyph, spacing)| {
+            let physical_glyph = glyph.physical((spacing, 0.0), 1.0);
+            if let Some(mut glyph_img) =
+                atlas.alloc(physical_glyph.cache_key, font_system, swash_cache)
+            {
+                if let Some(color) = text_color {
+                    glyph_img = glyph_img.with_default_color(color);
+                }
+                glyph_img.add_to_mesh(glyph, physical_glyph, &run, &mut mesh, pixels_per_point);
+            }
+        })
+    });
+
+    // Keep the glyphs on the physical pixel grid
+    let min = snap_pos(Logical(rect.min), pixels_per_point).to_vec2();
+    let (rot, offset) = match rotation {
+        Rot90::Cw => (
+            Rot2::from_angle(std::f32::consts::FRAC_PI_2),
+            vec2(rect.width(), 0.0),
+        ),
+        Rot90::Ccw => (
+            Rot2::from_angle(-std::f32::consts::FRAC_PI_2),
+            vec2(0.0, rect.height()),
+        ),
+    };
+    mesh.rotate(rot, Pos2::ZERO);
+    mesh.translate(min + offset);
+
+    painter.add(mesh);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atlas::{SeededState, TextureAtlas};
+    use crate::draw::preload_buf;
+    use crate::spacing::Spacing;
+    use crate::test_util;
+    use crate::units::Logical;
+    use cosmic_text::SwashCache;
+    use egui::{vec2, Color32, Pos2, Rect};
+
+    #[test]
+    fn preloads_the_spaced_glyphs_draw_run_draws() {
+        let mut font_system = test_util::font_system();
+        let mut swash_cache = SwashCache::new();
+        let buf = test_util::buffer(&mut font_system, "abc abc", None);
+        let mut atlas = TextureAtlas::<SeededState>::new(egui::Context::default(), Color32::WHITE);
+        let spacing = Spacing {
+            letter: 0.3,
+            word: 1.1,
+        };
+        let rect = Logical(Rect::from_min_size(Pos2::ZERO, vec2(200.0, 50.0)));
+        preload_buf(
+            &buf,
+            &mut font_system,
+            &mut swash_cache,
+            &mut atlas,
+            1.0,
+            rect,
+            spacing,
+        );
+
+        let run = buf.layout_runs().next().unwrap();
+        let offsets = spacing.offsets(run.glyphs, run.text);
+        for (glyph, offset) in run.glyphs.iter().zip(offsets) {
+            let cache_key = glyph.physical((offset, 0.0), 1.0).cache_key;
+            let visible = !run.text[glyph.start..glyph.end].trim().is_empty();
+            assert_eq!(atlas.locate(cache_key).is_some(), visible);
         }
-    })
+    }
 }
@@ -0,0 +1,245 @@
+//! Rebasing recorded changes over changes made elsewhere, e.g. by other users in a collaborative
+//! session, so undo can skip those.
+//!
+//! Changes are transformed as lists of [`ChangeItem`]s, each one applying to the text the one
+//! before it left behind.
+
+use cosmic_text::{ChangeItem, Cursor};
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// An edit as byte offsets into the text with `\n` line breaks, e.g. to mirror it into a rope
+/// or a collaborative document, see
+/// [`CosmicEdit::take_changes`](crate::widget::CosmicEdit::take_changes).
+///
+/// Every line break is one `\n` byte, in `range` and in `inserted`, even where
+/// [`CosmicEdit::text`](crate::widget::CosmicEdit::text) writes a CRLF or a soft break.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChange {
+    /// What's replaced, in the text as the changes before this one left it
+    pub range: Range<usize>,
+    pub inserted: String,
+}
+
+fn key(cursor: Cursor) -> (usize, usize) {
+    (cursor.line, cursor.index)
+}
+
+/// Where the cursor ends up after inserting `text` at `start`.
+pub fn insert_end(start: Cursor, text: &str) -> Cursor {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => {
+            Cursor::new(start.line + before.matches('\n').count() + 1, last.len())
+        }
+        None => Cursor::new(start.line, start.index + text.len()),
+    }
+}
+
+/// Where `cursor` ends up after `item` is applied, a cursor right where text is inserted stays
+/// in front of it.
+pub fn transform_cursor(cursor: Cursor, item: &ChangeItem) -> Cursor {
+    match item.insert {
+        true => shift_by_insert(cursor, item.start, &item.text, false),
+        false => shift_by_delete(cursor, item.start, item.end),
+    }
+}
+
+/// Where the text between two cursors ends up after `item` is applied, it doesn't grow by text
+/// inserted right at its ends. Once it's deleted, both ends are where it was.
+pub fn transform_range(range: Range<Cursor>, item: &ChangeItem) -> Range<Cursor> {
+    match item.insert {
+        true => {
+            shift_by_insert(range.start, item.start, &item.text, true)
+                ..shift_by_insert(range.end, item.start, &item.text, false)
+        }
+        false => {
+            shift_by_delete(range.start, item.start, item.end)
+                ..shift_by_delete(range.end, item.start, item.end)
+        }
+    }
+}
+
+fn shift_by_insert(cursor: Cursor, at: Cursor, text: &str, after_ties: bool) -> Cursor {
+    match key(cursor).cmp(&key(at)) {
+        Ordering::Less => cursor,
+        Ordering::Equal if !after_ties => cursor,
+        _ if cursor.line == at.line => {
+            let end = insert_end(at, text);
+            Cursor::new(end.line, end.index + cursor.index - at.index)
+        }
+        _ => Cursor::new(cursor.line + text.matches('\n').count(), cursor.index),
+    }
+}
+
+fn shift_by_delete(cursor: Cursor, start: Cursor, end: Cursor) -> Cursor {
+    if key(cursor) <= key(start) {
+        cursor
+    } else if key(cursor) < key(end) {
+        start
+    } else if cursor.line == end.line {
+        Cursor::new(start.line, start.index + cursor.index - end.index)
+    } else {
+        Cursor::new(cursor.line - (end.line - start.line), cursor.index)
+    }
+}
+
+/// The byte offset of `cursor` into `text` that starts at `start`.
+fn text_offset(start: Cursor, text: &str, cursor: Cursor) -> usize {
+    match cursor.line - start.line {
+        0 => cursor.index - start.index,
+        lines => {
+            let line_start = text
+                .match_indices('\n')
+                .nth(lines - 1)
+                .map_or(text.len(), |(i, _)| i + 1);
+            line_start + cursor.index
+        }
+    }
+}
+
+fn item(start: Cursor, text: String, insert: bool) -> ChangeItem {
+    ChangeItem {
+        start,
+        end: insert_end(start, &text),
+        text,
+        insert,
+    }
+}
+
+/// `del` after `ins`, split in two if the text was inserted inside of what it deletes.
+fn delete_around_insert(del: &ChangeItem, ins: &ChangeItem) -> Vec<ChangeItem> {
+    if key(ins.start) <= key(del.start) {
+        let start = shift_by_insert(del.start, ins.start, &ins.text, true);
+        vec![item(start, del.text.clone(), false)]
+    } else if key(ins.start) >= key(del.end) {
+        vec![del.clone()]
+    } else {
+        let (before, after) = del
+            .text
+            .split_at(text_offset(del.start, &del.text, ins.start));
+        // The later part goes first so the earlier one's position still holds
+        vec![
+            item(insert_end(ins.start, &ins.text), after.to_string(), false),
+            item(del.start, before.to_string(), false),
+        ]
+    }
+}
+
+/// `del` after `other`, without what `other` already deleted.
+fn delete_after_delete(del: &ChangeItem, other: &ChangeItem) -> Vec<ChangeItem> {
+    let overlap_start = key(del.start).max(key(other.start));
+    let overlap_end = key(del.end).min(key(other.end));
+    let mut text = del.text.clone();
+    if overlap_start < overlap_end {
+        let cursor = |(line, index)| Cursor::new(line, index);
+        let from = text_offset(del.start, &del.text, cursor(overlap_start));
+        let to = text_offset(del.start, &del.text, cursor(overlap_end));
+        text.replace_range(from..to, "");
+    }
+    match text.is_empty() {
+        true => Vec::new(),
+        false => {
+            let start = shift_by_delete(del.start, other.start, other.end);
+            vec![item(start, text, false)]
+        }
+    }
+}
+
+fn transform_item(
+    x: &ChangeItem,
+    y: &ChangeItem,
+    x_first: bool,
+) -> (Vec<ChangeItem>, Vec<ChangeItem>) {
+    match (x.insert, y.insert) {
+        (true, true) => (
+            vec![item(
+                shift_by_insert(x.start, y.start, &y.text, !x_first),
+                x.text.clone(),
+                true,
+            )],
+            vec![item(
+                shift_by_insert(y.start, x.start, &x.text, x_first),
+                y.text.clone(),
+                true,
+            )],
+        ),
+        (true, false) => (
+            vec![item(
+                shift_by_delete(x.start, y.start, y.end),
+                x.text.clone(),
+                true,
+            )],
+            delete_around_insert(y, x),
+        ),
+        (false, true) => (
+            delete_around_insert(x, y),
+            vec![item(
+                shift_by_delete(y.start, x.start, x.end),
+                y.text.clone(),
+                true,
+            )],
+        ),
+        (false, false) => (delete_after_delete(x, y), delete_after_delete(y, x)),
+    }
+}
+
+/// Transforms `xs` and `ys`, both made for the same text, so they can be applied one after the
+/// other. The first list returned applies after `ys`, the second one after `xs`.
+///
+/// Text inserted by both at the same place puts what `xs` inserted first if `xs_first`. Neither
+/// deletes text the other one inserted, a deletion around an insertion is split in two.
+pub fn transform(
+    xs: &[ChangeItem],
+    ys: &[ChangeItem],
+    xs_first: bool,
+) -> (Vec<ChangeItem>, Vec<ChangeItem>) {
+    match (xs, ys) {
+        ([], _) | (_, []) => (xs.to_vec(), ys.to_vec()),
+        ([x], [y, rest @ ..]) => {
+            let (x, y) = transform_item(x, y, xs_first);
+            let (x, rest) = transform(&x, rest, xs_first);
+            (x, [y, rest].concat())
+        }
+        ([x, rest @ ..], _) => {
+            let (x, ys) = transform(std::slice::from_ref(x), ys, xs_first);
+            let (rest, ys) = transform(rest, &ys, xs_first);
+            ([x, rest].concat(), ys)
+        }
+    }
+}
+
+/// `items` as [`TextChange`]s, a deletion followed by an insertion where it started becomes one
+/// replacement. `line_lens` are the byte lengths of the lines before the first item and are kept
+/// up to date with the items.
+pub fn text_changes(line_lens: &mut Vec<usize>, items: &[ChangeItem]) -> Vec<TextChange> {
+    let mut changes = Vec::<TextChange>::new();
+    for item in items {
+        let start = item.start;
+        let offset = line_lens[..start.line].iter().sum::<usize>() + start.line + start.index;
+        if item.insert {
+            let mut parts = item.text.split('\n').map(str::len).collect::<Vec<_>>();
+            let rest = line_lens[start.line] - start.index;
+            parts[0] += start.index;
+            *parts.last_mut().unwrap() += rest;
+            line_lens.splice(start.line..=start.line, parts);
+            match changes.last_mut() {
+                Some(last) if last.range.start == offset && last.inserted.is_empty() => {
+                    last.inserted.clone_from(&item.text);
+                }
+                _ => changes.push(TextChange {
+                    range: offset..offset,
+                    inserted: item.text.clone(),
+                }),
+            }
+        } else {
+            let end = item.end;
+            let joined = start.index + line_lens[end.line] - end.index;
+            line_lens.splice(start.line..=end.line, [joined]);
+            changes.push(TextChange {
+                range: offset..offset + item.text.len(),
+                inserted: String::new(),
+            });
+        }
+    }
+    changes
+}
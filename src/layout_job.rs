@@ -0,0 +1,227 @@
+//! Converting styled text between cosmic-text and egui's [`LayoutJob`], so short strings that
+//! have to stay on egui's own text, e.g. in existing widgets or plot labels, can look like the
+//! text drawn by this crate, and both can be styled from one description.
+//!
+//! Only the family, size, line height, color, italics and letter spacing carry over, so it's an
+//! approximation:
+//! - egui has no weights, stretches or font features, bold text comes out regular.
+//! - egui rasterizes glyphs itself, they can differ slightly in hinting and gamma.
+//! - Named families have to be registered with egui's `FontDefinitions` under the same name, egui
+//!   panics on unknown ones, see [`egui_family`].
+//! - Backgrounds, underlines, strikethroughs and vertical alignment have no counterpart in
+//!   cosmic-text and are dropped.
+//! - Lines are joined with `\n`, their alignment isn't kept.
+//!
+//! Sizes are in physical pixels on the cosmic-text side and in points on egui's.
+
+use cosmic_text::{Attrs, Buffer, BufferLine, Color, Family, LetterSpacing, Metrics, Style, Wrap};
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontFamily, FontId};
+use std::ops::Range;
+
+/// egui's family for `family`. The generic families become egui's proportional or monospace
+/// ones, named families keep their name.
+pub fn egui_family(family: Family) -> FontFamily {
+    match family {
+        Family::Name(name) => FontFamily::Name(name.into()),
+        Family::Monospace => FontFamily::Monospace,
+        _ => FontFamily::Proportional,
+    }
+}
+
+/// cosmic-text's family for `family`, the reverse of [`egui_family`].
+pub fn cosmic_family(family: &FontFamily) -> Family<'_> {
+    match family {
+        FontFamily::Proportional => Family::SansSerif,
+        FontFamily::Monospace => Family::Monospace,
+        FontFamily::Name(name) => Family::Name(name),
+    }
+}
+
+/// The spans of `line` with their attributes, the gaps between spans get the line's defaults.
+fn line_spans(line: &BufferLine) -> Vec<(Range<usize>, Attrs<'_>)> {
+    let len = line.text().len();
+    let list = line.attrs_list();
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for (range, attrs) in list.spans_iter() {
+        let range = range.start.min(len)..range.end.min(len);
+        if range.start > end {
+            spans.push((end..range.start, list.defaults()));
+        }
+        end = range.end;
+        spans.push((range, attrs.as_attrs()));
+    }
+    if end < len {
+        spans.push((end..len, list.defaults()));
+    }
+    spans
+}
+
+/// A [`LayoutJob`] approximating how `buf` looks, see the [module docs](self) for what's lost.
+///
+/// Text without a color of its own gets `default_color`. `family` maps the families, e.g.
+/// [`egui_family`] or one that falls back for families egui doesn't have.
+pub fn buffer_to_layout_job(
+    buf: &Buffer,
+    default_color: Color32,
+    pixels_per_point: f32,
+    family: impl Fn(Family) -> FontFamily,
+) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    if buf.wrap() != Wrap::None {
+        if let Some(width) = buf.size().0 {
+            job.wrap.max_width = width / pixels_per_point;
+        }
+    }
+    let format = |attrs: Attrs| {
+        let metrics = attrs.metrics_opt.map_or(buf.metrics(), Metrics::from);
+        let font_size = metrics.font_size / pixels_per_point;
+        TextFormat {
+            font_id: FontId::new(font_size, family(attrs.family)),
+            extra_letter_spacing: attrs
+                .letter_spacing_opt
+                .map_or(0.0, |LetterSpacing(em)| em * font_size),
+            line_height: Some(metrics.line_height / pixels_per_point),
+            color: attrs.color_opt.map_or(default_color, |x| {
+                Color32::from_rgba_premultiplied(x.r(), x.g(), x.b(), x.a())
+            }),
+            italics: matches!(attrs.style, Style::Italic | Style::Oblique),
+            ..Default::default()
+        }
+    };
+    for (i, line) in buf.lines.iter().enumerate() {
+        let spans = line_spans(line);
+        for (range, attrs) in &spans {
+            job.append(&line.text()[range.clone()], 0.0, format(attrs.clone()));
+        }
+        // The line break is styled like the end of the line it ends
+        if i + 1 < buf.lines.len() {
+            let attrs = spans
+                .last()
+                .map_or(line.attrs_list().defaults(), |x| x.1.clone());
+            job.append("\n", 0.0, format(attrs));
+        }
+    }
+    job
+}
+
+/// The spans of `job` with cosmic-text attributes, e.g. for
+/// [`CosmicEdit::set_text`](crate::widget::CosmicEdit::set_text), so the app can style text for
+/// both from one [`LayoutJob`].
+///
+/// Colors equal to `default_color` are left to the widget's default.
+pub fn layout_job_spans(
+    job: &LayoutJob,
+    default_color: Color32,
+    pixels_per_point: f32,
+) -> impl Iterator<Item = (&str, Attrs<'_>)> {
+    job.sections.iter().map(move |section| {
+        let format = &section.format;
+        let font_size = format.font_id.size;
+        let line_height = format.line_height.unwrap_or(font_size);
+        let mut attrs = Attrs::new()
+            .family(cosmic_family(&format.font_id.family))
+            .metrics(Metrics::new(
+                font_size * pixels_per_point,
+                line_height * pixels_per_point,
+            ));
+        if format.color != default_color {
+            let [r, g, b, a] = format.color.to_array();
+            attrs = attrs.color(Color::rgba(r, g, b, a));
+        }
+        if format.italics {
+            attrs = attrs.style(Style::Italic);
+        }
+        if format.extra_letter_spacing != 0.0 {
+            attrs = attrs.letter_spacing(format.extra_letter_spacing / font_size);
+        }
+        (&job.text[section.byte_range.clone()], attrs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout_job::{buffer_to_layout_job, egui_family, layout_job_spans};
+    use crate::test_util;
+    use cosmic_text::{
+        Attrs, AttrsList, BufferLine, Color, Family, LineEnding, Metrics, Shaping, Style,
+    };
+    use egui::{Color32, FontFamily};
+    use std::ops::Range;
+
+    #[test]
+    fn styling_survives_the_round_trip() {
+        let mut font_system = test_util::font_system();
+        let mut buf = test_util::buffer(&mut font_system, "", None);
+        let line = |text: &str, spans: &[(Range<usize>, Attrs)]| {
+            let mut attrs_list = AttrsList::new(&Attrs::new());
+            for (range, attrs) in spans {
+                attrs_list.add_span(range.clone(), attrs);
+            }
+            BufferLine::new(text, LineEnding::Lf, attrs_list, Shaping::Advanced)
+        };
+        // Not shaped, the test fonts have no monospace one
+        buf.lines = vec![
+            line(
+                "plain red",
+                &[(6..9, Attrs::new().color(Color::rgb(255, 0, 0)))],
+            ),
+            line(
+                "mono big italic",
+                &[
+                    (0..4, Attrs::new().family(Family::Monospace)),
+                    (
+                        4..15,
+                        Attrs::new()
+                            .style(Style::Italic)
+                            .metrics(Metrics::new(28.0, 30.0)),
+                    ),
+                ],
+            ),
+        ];
+
+        let job = buffer_to_layout_job(&buf, Color32::WHITE, 2.0, egui_family);
+        assert_eq!(job.text, "plain red\nmono big italic");
+        let red = Color32::from_rgb(255, 0, 0);
+        let sections = job
+            .sections
+            .iter()
+            .map(|x| {
+                let format = &x.format;
+                let font_id = &format.font_id;
+                (&job.text[x.byte_range.clone()], font_id.size, format.color)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sections,
+            [
+                ("plain ", 7.0, Color32::WHITE),
+                ("red", 7.0, red),
+                ("\n", 7.0, red),
+                ("mono", 7.0, Color32::WHITE),
+                (" big italic", 14.0, Color32::WHITE),
+            ]
+        );
+        assert_eq!(job.sections[3].format.font_id.family, FontFamily::Monospace);
+        assert!(job.sections[4].format.italics);
+        assert_eq!(job.sections[4].format.line_height, Some(15.0));
+
+        let spans = layout_job_spans(&job, Color32::WHITE, 2.0).collect::<Vec<_>>();
+        assert_eq!(spans.len(), 5);
+        let (text, attrs) = &spans[1];
+        assert_eq!(
+            (*text, attrs.color_opt),
+            ("red", Some(Color::rgb(255, 0, 0)))
+        );
+        assert_eq!(spans[0].1.color_opt, None);
+        assert_eq!(spans[3].1.family, Family::Monospace);
+        let (text, attrs) = &spans[4];
+        assert_eq!(*text, " big italic");
+        assert_eq!(attrs.style, Style::Italic);
+        assert_eq!(
+            attrs.metrics_opt.map(Metrics::from),
+            Some(Metrics::new(28.0, 30.0))
+        );
+    }
+}
@@ -1,8 +1,34 @@
 #![forbid(unsafe_code)]
 
+#[cfg(not(any(feature = "egui_028", feature = "egui_031", feature = "egui_032")))]
+compile_error!("one of the `egui_028`, `egui_031` or `egui_032` features has to be enabled");
+#[cfg(any(
+    all(feature = "egui_028", any(feature = "egui_031", feature = "egui_032")),
+    all(feature = "egui_031", feature = "egui_032")
+))]
+compile_error!(
+    "only one of the `egui_028`, `egui_031` or `egui_032` features can be enabled, \
+     disable the default features to use a newer egui"
+);
+
+#[cfg(feature = "egui_031")]
+extern crate egui_031 as egui;
+#[cfg(feature = "egui_032")]
+extern crate egui_032 as egui;
+
 pub mod atlas;
+mod compat;
 pub mod cursor;
+#[cfg(feature = "debug-tools")]
+pub mod debug;
 pub mod draw;
+pub mod history;
+pub mod layout_job;
+pub mod resources;
+pub mod spacing;
+#[cfg(test)]
+mod test_util;
+pub mod units;
 pub mod util;
 #[cfg(feature = "widget")]
 pub mod widget;
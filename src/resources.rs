@@ -0,0 +1,51 @@
+use cosmic_text::{FontSystem, SwashCache};
+use egui::Color32;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use crate::atlas::TextureAtlas;
+
+/// Everything text is shaped and rasterized with, meant to be shared by all widgets.
+///
+/// Each [`TextureAtlas`] owns its own texture, so widgets should share one set of resources
+/// instead of creating their own.
+pub struct TextResources<S: BuildHasher + Default = RandomState> {
+    pub font_system: FontSystem,
+    pub swash_cache: SwashCache,
+    pub atlas: TextureAtlas<S>,
+}
+
+impl<S: BuildHasher + Default> TextResources<S> {
+    /// How many [`Self::end_frame`] calls shaped runs are kept around for without being used.
+    #[cfg(feature = "shape-run-cache")]
+    pub const SHAPE_RUN_CACHE_AGES: u64 = 60;
+
+    /// Loads the system fonts, see [`FontSystem::new`].
+    pub fn new(ctx: egui::Context, default_color: Color32) -> Self {
+        Self::from_font_system(FontSystem::new(), ctx, default_color)
+    }
+
+    /// Use [`FontSystem::new_with_locale_and_db`] to start from a custom `fontdb`.
+    pub fn from_font_system(
+        font_system: FontSystem,
+        ctx: egui::Context,
+        default_color: Color32,
+    ) -> Self {
+        Self {
+            font_system,
+            swash_cache: SwashCache::new(),
+            atlas: TextureAtlas::new(ctx, default_color),
+        }
+    }
+
+    /// Housekeeping to run once at the end of every frame.
+    ///
+    /// The atlas keeps track of frames by itself, so this only ages out the shape run cache when
+    /// the `shape-run-cache` feature is enabled.
+    pub fn end_frame(&mut self) {
+        #[cfg(feature = "shape-run-cache")]
+        self.font_system
+            .shape_run_cache
+            .trim(Self::SHAPE_RUN_CACHE_AGES);
+    }
+}
@@ -0,0 +1,143 @@
+//! Letter and word spacing on top of cosmic-text's layout.
+//!
+//! cosmic-text only lays out letter spacing per span and has no word spacing, so glyphs are moved
+//! to the right after layout. Everything reading glyph positions (drawing, measuring, hit testing, the caret and
+//! selections) has to go through the same [`Spacing`] to stay consistent.
+//!
+//! Line wrapping doesn't know about the extra space, so spaced lines can overflow the wrap width.
+
+use cosmic_text::{Buffer, LayoutGlyph};
+
+use crate::units::Phys;
+
+/// Extra space between glyphs, in the same units as the buffer's font size.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spacing {
+    /// Added between every pair of glyphs
+    pub letter: f32,
+    /// Added after whitespace glyphs, on top of `letter`
+    pub word: f32,
+}
+
+impl Spacing {
+    pub fn is_zero(&self) -> bool {
+        self.letter == 0.0 && self.word == 0.0
+    }
+
+    /// The space added after `glyph`, `text` is the text of the buffer line it belongs to.
+    fn after(self, glyph: &LayoutGlyph, text: &str) -> f32 {
+        let whitespace = text
+            .get(glyph.start..glyph.end)
+            .is_some_and(|x| x.chars().all(char::is_whitespace));
+        match whitespace {
+            true => self.letter + self.word,
+            false => self.letter,
+        }
+    }
+
+    /// How far each glyph of a layout line is moved to the right.
+    pub fn offsets<'a>(
+        self,
+        glyphs: &'a [LayoutGlyph],
+        text: &'a str,
+    ) -> impl Iterator<Item = f32> + 'a {
+        glyphs.iter().scan(0.0, move |offset, glyph| {
+            let curr = *offset;
+            *offset += self.after(glyph, text);
+            Some(curr)
+        })
+    }
+
+    /// How much wider a layout line gets, there's no spacing after the last glyph.
+    pub fn extra_width(self, glyphs: &[LayoutGlyph], text: &str) -> f32 {
+        self.offsets(glyphs, text).last().unwrap_or_default()
+    }
+
+    /// Maps `x` from the laid out line onto the spaced one.
+    ///
+    /// Where one glyph ends and the next starts, `leading` picks the next glyph's offset.
+    pub fn space_x(self, glyphs: &[LayoutGlyph], text: &str, x: f32, leading: bool) -> f32 {
+        let starts_before = |glyph: &LayoutGlyph| match leading {
+            true => glyph.x <= x,
+            false => glyph.x < x,
+        };
+        x + glyphs
+            .iter()
+            .zip(self.offsets(glyphs, text))
+            .take_while(|(glyph, _)| starts_before(glyph))
+            .last()
+            .map(|(_, offset)| offset)
+            .unwrap_or_default()
+    }
+
+    /// Maps `x` from the spaced line back onto the laid out one, e.g. for hit testing.
+    ///
+    /// Positions within the spacing snap to the boundary between the two glyphs.
+    pub fn unspace_x(self, glyphs: &[LayoutGlyph], text: &str, x: f32) -> f32 {
+        let mut last_offset = 0.0;
+        for (glyph, offset) in glyphs.iter().zip(self.offsets(glyphs, text)) {
+            let start = glyph.x + offset;
+            if x < start {
+                return match offset == 0.0 {
+                    true => x,
+                    false => glyph.x,
+                };
+            }
+            if x <= start + glyph.w {
+                return x - offset;
+            }
+            last_offset = offset;
+        }
+        x - last_offset
+    }
+
+    /// The width of the widest spaced layout line.
+    pub fn measure_width(self, buf: &Buffer) -> Phys<f32> {
+        Phys(
+            buf.lines
+                .iter()
+                .filter_map(|line| Some((line.text(), line.layout_opt()?)))
+                .flat_map(|(text, layout_lines)| {
+                    layout_lines
+                        .iter()
+                        .map(move |x| x.w + self.extra_width(&x.glyphs, text))
+                })
+                .fold(0.0, f32::max),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacing::Spacing;
+    use crate::test_util;
+
+    #[test]
+    fn space_and_unspace_round_trip() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "ab cd", None);
+        let run = buf.layout_runs().next().unwrap();
+        let spacing = Spacing {
+            letter: 2.0,
+            word: 5.0,
+        };
+
+        let offsets = spacing.offsets(run.glyphs, run.text).collect::<Vec<_>>();
+        assert_eq!(offsets, [0.0, 2.0, 4.0, 11.0, 13.0]);
+        assert_eq!(spacing.extra_width(run.glyphs, run.text), 13.0);
+
+        for (glyph, offset) in run.glyphs.iter().zip(offsets) {
+            let mid = glyph.x + glyph.w / 2.0;
+            let spaced = spacing.space_x(run.glyphs, run.text, mid, true);
+            assert_eq!(spaced, mid + offset);
+            let unspaced = spacing.unspace_x(run.glyphs, run.text, spaced);
+            assert!((unspaced - mid).abs() < 1e-4);
+        }
+
+        // Inside the gap after "c"
+        let [c, d] = [&run.glyphs[3], &run.glyphs[4]];
+        let gap = c.x + c.w + 11.0 + 1.0;
+        assert_eq!(spacing.unspace_x(run.glyphs, run.text, gap), d.x);
+    }
+}
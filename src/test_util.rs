@@ -0,0 +1,143 @@
+use cosmic_text::{fontdb, Attrs, Buffer, FontSystem, Metrics, Shaping};
+
+/// A font system that only knows about the demo font, so tests don't depend on system fonts.
+pub fn font_system() -> FontSystem {
+    let mut db = fontdb::Database::new();
+    db.load_font_data(include_bytes!("../demo/resources/Ubuntu-Light.ttf").to_vec());
+    FontSystem::new_with_locale_and_db("en-US".to_string(), db)
+}
+
+pub fn buffer(font_system: &mut FontSystem, text: &str, width: Option<f32>) -> Buffer {
+    let mut buf = Buffer::new(font_system, Metrics::new(14.0, 21.0));
+    buf.set_size(font_system, width, None);
+    buf.set_text(font_system, text, &Attrs::new(), Shaping::Advanced);
+    buf.shape_until_scroll(font_system, false);
+    buf
+}
+
+#[cfg(feature = "widget")]
+pub use harness::Harness;
+
+#[cfg(feature = "widget")]
+mod harness {
+    use crate::atlas::TextureAtlas;
+    use crate::widget::{
+        CosmicEdit, CosmicEditOutput, DefaultContextMenu, LayoutMode, NoContextMenu,
+    };
+    use cosmic_text::{FontSystem, SwashCache};
+    use egui::{
+        pos2, vec2, CentralPanel, Event, Modifiers, RawInput, Rect, Response, ScrollArea, Vec2,
+        ViewportId,
+    };
+    use std::time::Duration;
+
+    /// Runs widgets through real egui frames without a renderer.
+    pub struct Harness {
+        pub ctx: egui::Context,
+        pub font_system: FontSystem,
+        pub swash_cache: SwashCache,
+        pub atlas: TextureAtlas,
+        pub time: f64,
+        /// Held during the following frames
+        pub modifiers: Modifiers,
+        pub output: egui::PlatformOutput,
+        /// How long egui would wait before the next frame after the last one
+        pub repaint_delay: Duration,
+        /// Puts the widget in a vertical `ScrollArea` this high
+        pub scroll_height: Option<f32>,
+        /// The `ScrollArea`'s offset after the last frame
+        pub scroll_offset: Vec2,
+        /// Shows the `DefaultContextMenu` without a clipboard instead of none
+        pub context_menu: bool,
+        /// The widget's output from the last frame
+        pub edit_output: Option<CosmicEditOutput>,
+        /// What the last frame painted
+        pub shapes: Vec<egui::epaint::ClippedShape>,
+    }
+
+    impl Default for Harness {
+        fn default() -> Self {
+            let ctx = egui::Context::default();
+            Self {
+                atlas: TextureAtlas::new(ctx.clone(), egui::Color32::WHITE),
+                ctx,
+                font_system: super::font_system(),
+                swash_cache: SwashCache::new(),
+                time: 0.0,
+                modifiers: Modifiers::NONE,
+                output: Default::default(),
+                repaint_delay: Duration::MAX,
+                scroll_height: None,
+                scroll_offset: Vec2::ZERO,
+                context_menu: false,
+                edit_output: None,
+                shapes: Vec::new(),
+            }
+        }
+    }
+
+    impl Harness {
+        pub fn frame<L: LayoutMode>(
+            &mut self,
+            edit: &mut CosmicEdit<L>,
+            events: Vec<Event>,
+        ) -> Response {
+            self.time += 1.0 / 60.0;
+            let input = RawInput {
+                screen_rect: Some(Rect::from_min_size(pos2(0.0, 0.0), vec2(800.0, 600.0))),
+                time: Some(self.time),
+                modifiers: self.modifiers,
+                events,
+                ..Default::default()
+            };
+            let mut edit_output = None;
+            let output = self.ctx.run(input, |ctx| {
+                CentralPanel::default().show(ctx, |ui| {
+                    let mut add = |ui: &mut egui::Ui| {
+                        let (font_system, swash_cache, atlas) = (
+                            &mut self.font_system,
+                            &mut self.swash_cache,
+                            &mut self.atlas,
+                        );
+                        edit_output = Some(match self.context_menu {
+                            true => edit.show(
+                                ui,
+                                font_system,
+                                swash_cache,
+                                atlas,
+                                DefaultContextMenu {
+                                    read_clipboard_text: || None,
+                                },
+                            ),
+                            false => edit.show(ui, font_system, swash_cache, atlas, NoContextMenu),
+                        });
+                    };
+                    match self.scroll_height {
+                        Some(height) => {
+                            let output = ScrollArea::vertical().max_height(height).show(ui, add);
+                            self.scroll_offset = output.state.offset;
+                        }
+                        None => add(ui),
+                    }
+                });
+            });
+            self.output = output.platform_output;
+            self.shapes = output.shapes;
+            self.repaint_delay = output.viewport_output[&ViewportId::ROOT].repaint_delay;
+            self.edit_output = edit_output;
+            self.edit_output.as_ref().unwrap().response.clone()
+        }
+
+        /// The text the last frame put on the clipboard
+        pub fn copied_text(&self) -> String {
+            crate::compat::copied_text(&self.output)
+        }
+
+        /// Runs a frame and focuses the widget for the following ones
+        pub fn focus<L: LayoutMode>(&mut self, edit: &mut CosmicEdit<L>) -> Response {
+            let resp = self.frame(edit, vec![]);
+            resp.request_focus();
+            resp
+        }
+    }
+}
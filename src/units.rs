@@ -0,0 +1,79 @@
+//! Newtypes telling **physical pixels** and **logical pixels** (egui points) apart.
+//!
+//! cosmic-text lays out and rasterizes in physical pixels while egui works in logical pixels,
+//! so the public APIs wrap their values in [`Phys`] or [`Logical`] to make mixing them up a type error.
+//! Both deref to the wrapped value for reading.
+
+use std::ops::{Deref, Div, Mul};
+
+/// A value in **physical pixels**.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Phys<T>(pub T);
+
+/// A value in **logical pixels**, which is what egui uses.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Logical<T>(pub T);
+
+impl<T> Phys<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Mul<f32, Output = T>> Phys<T> {
+    pub fn from_logical(logical: Logical<T>, pixels_per_point: f32) -> Self {
+        Self(logical.0 * pixels_per_point)
+    }
+}
+
+impl<T: Div<f32, Output = T>> Phys<T> {
+    pub fn to_logical(self, pixels_per_point: f32) -> Logical<T> {
+        Logical(self.0 / pixels_per_point)
+    }
+}
+
+impl<T> Logical<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Div<f32, Output = T>> Logical<T> {
+    pub fn from_phys(phys: Phys<T>, pixels_per_point: f32) -> Self {
+        Self(phys.0 / pixels_per_point)
+    }
+}
+
+impl<T: Mul<f32, Output = T>> Logical<T> {
+    pub fn to_phys(self, pixels_per_point: f32) -> Phys<T> {
+        Phys(self.0 * pixels_per_point)
+    }
+}
+
+impl<T> Deref for Phys<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Deref for Logical<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Phys<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> From<T> for Logical<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
@@ -1,61 +1,933 @@
-use cosmic_text::{Buffer, Cursor, LayoutLine};
-use egui::{pos2, vec2, Rect};
+use cosmic_text::{
+    Affinity, Attrs, AttrsList, Buffer, BufferLine, Cursor, FontSystem, LayoutLine, LayoutRun,
+    LineEnding, Shaping,
+};
+use egui::{pos2, vec2, Pos2, Rangef, Rect, Vec2};
+use std::borrow::Cow;
+use std::ops::{Div, Mul, Range};
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::cursor;
 use crate::cursor::LineSelection;
+use crate::spacing::Spacing;
+use crate::units::{Logical, Phys};
 
 pub fn layout_lines_iter(buf: &Buffer) -> impl Iterator<Item = &LayoutLine> {
-    buf.lines
+    buf.lines.iter().filter_map(|x| x.layout_opt()).flatten()
+}
+
+/// Whether any font was loaded at all.
+///
+/// cosmic-text panics when shaping text without fonts, so nothing can be laid out in that case.
+pub fn has_fonts(font_system: &FontSystem) -> bool {
+    !font_system.db().is_empty()
+}
+
+/// Runs `f` with the buffer's lines swapped out for a single empty one, so nothing gets shaped.
+///
+/// Lets buffer settings like metrics be changed while there are no fonts to shape with.
+pub fn with_unshaped_lines(buf: &mut Buffer, f: impl FnOnce(&mut Buffer)) {
+    let empty = BufferLine::new(
+        "",
+        LineEnding::default(),
+        AttrsList::new(&Attrs::new()),
+        Shaping::Basic,
+    );
+    let lines = std::mem::replace(&mut buf.lines, vec![empty]);
+    f(buf);
+    buf.lines = lines;
+}
+
+/// Runs `f` with only the lines that have already been shaped, the rest are put back afterwards.
+///
+/// cosmic-text shapes every line when the size, metrics or wrap change, this keeps those changes
+/// from shaping lines that are left for later frames.
+pub fn with_shaped_lines_only<R>(buf: &mut Buffer, f: impl FnOnce(&mut Buffer) -> R) -> R {
+    let mut unshaped = Vec::new();
+    buf.lines = std::mem::take(&mut buf.lines)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, line)| match line.shape_opt() {
+            Some(_) => Some(line),
+            None => {
+                unshaped.push((i, line));
+                None
+            }
+        })
+        .collect();
+    let res = f(buf);
+    let len = buf.lines.len() + unshaped.len();
+    let mut shaped = std::mem::take(&mut buf.lines).into_iter();
+    let mut unshaped = unshaped.into_iter().peekable();
+    buf.lines = (0..len)
+        .filter_map(|i| match unshaped.next_if(|(line_i, _)| *line_i == i) {
+            Some((_, line)) => Some(line),
+            None => shaped.next(),
+        })
+        .collect();
+    res
+}
+
+/// The height lines that haven't been laid out yet are assumed to take up, one row each.
+pub fn unshaped_height(buf: &Buffer) -> Phys<f32> {
+    let unshaped = buf
+        .lines
         .iter()
-        .filter_map(|x| x.layout_opt().as_ref())
-        .flatten()
+        .filter(|line| line.layout_opt().is_none())
+        .count();
+    Phys(unshaped as f32 * buf.metrics().line_height)
+}
+
+/// A rough size for text that couldn't be laid out, from its character count and the metrics.
+pub fn estimate_size(buf: &Buffer) -> Phys<Vec2> {
+    let metrics = buf.metrics();
+    let longest_line = buf
+        .lines
+        .iter()
+        .map(|line| line.text().chars().count())
+        .max()
+        .unwrap_or_default();
+    Phys(vec2(
+        (longest_line as f32 * metrics.font_size * 0.5).max(1.0),
+        buf.lines.len().max(1) as f32 * metrics.line_height,
+    ))
 }
 
 /// Measures the maximum height of the runs that have been laid out.
-///
-/// **This is in physical pixels.**
-pub fn measure_height(buf: &Buffer) -> f32 {
-    layout_lines_iter(buf)
-        .map(|x| x.line_height_opt.unwrap_or(buf.metrics().line_height))
-        .sum()
+pub fn measure_height(buf: &Buffer) -> Phys<f32> {
+    Phys(
+        layout_lines_iter(buf)
+            .map(|x| x.line_height_opt.unwrap_or(buf.metrics().line_height))
+            .sum(),
+    )
 }
 
 /// Measures the maximum width and maximum height of the runs that have been laid out.
-///
-/// **This is in physical pixels.**
-pub fn measure_width_and_height(buf: &Buffer) -> (f32, f32) {
+pub fn measure_width_and_height(buf: &Buffer) -> Phys<Vec2> {
     let base_line_height = buf.metrics().line_height;
-    layout_lines_iter(buf).fold((0.0, 0.0), |(width, height), line| {
-        (
-            line.w.max(width),
-            height + line.line_height_opt.unwrap_or(base_line_height),
+    Phys(layout_lines_iter(buf).fold(Vec2::ZERO, |size, line| {
+        vec2(
+            line.w.max(size.x),
+            size.y + line.line_height_opt.unwrap_or(base_line_height),
         )
-    })
+    }))
 }
 
 /// Attempts to retrieve the cursor's rect from inside the buffer.
 /// This has to be translated to the widget's rect and is relative to the buffer, starting from `0.0, 0.0`
+pub fn cursor_rect(buf: &Buffer, cursor: Cursor, spacing: Spacing) -> Option<Phys<Rect>> {
+    cursor::cursor_pos(buf, cursor, spacing)
+}
+
+/// The caret widened over the character after `cursor`, or half a line height wide where there's
+/// none on its row, e.g. at the end of a line.
+pub fn block_cursor_rect(buf: &Buffer, cursor: Cursor, spacing: Spacing) -> Option<Phys<Rect>> {
+    let rect = cursor_rect(buf, cursor, spacing)?;
+    let next_right = buf
+        .lines
+        .get(cursor.line)
+        .and_then(|line| line.text().get(cursor.index..)?.chars().next())
+        .and_then(|c| {
+            cursor_rect(
+                buf,
+                Cursor::new(cursor.line, cursor.index + c.len_utf8()),
+                spacing,
+            )
+        })
+        // Wrapped onto the next row or right-to-left text
+        .filter(|next| next.min.y == rect.min.y && next.min.x > rect.min.x)
+        .map(|next| next.min.x);
+    let right = next_right.unwrap_or(rect.min.x + *extra_width(Phys(rect.height())));
+    Some(Phys(Rect::from_min_max(rect.min, pos2(right, rect.max.y))))
+}
+
+/// Converts egui's points to the physical pixels cosmic-text lays out and rasterizes in.
+///
+/// The crate converts everything this way, so code drawing next to it lines up exactly.
+pub fn to_physical<T: Mul<f32, Output = T>>(logical: Logical<T>, pixels_per_point: f32) -> Phys<T> {
+    logical.to_phys(pixels_per_point)
+}
+
+/// Converts physical pixels back to egui's points, see [`to_physical`].
+pub fn to_logical<T: Div<f32, Output = T>>(phys: Phys<T>, pixels_per_point: f32) -> Logical<T> {
+    phys.to_logical(pixels_per_point)
+}
+
+/// Values [`round`] can snap to the physical pixel grid.
+pub trait Round {
+    fn round(self) -> Self;
+}
+
+impl Round for f32 {
+    fn round(self) -> Self {
+        f32::round(self)
+    }
+}
+
+impl Round for Vec2 {
+    fn round(self) -> Self {
+        Vec2::round(self)
+    }
+}
+
+impl Round for Pos2 {
+    fn round(self) -> Self {
+        Pos2::round(self)
+    }
+}
+
+impl Round for Rect {
+    fn round(self) -> Self {
+        Rect::from_min_max(self.min.round(), self.max.round())
+    }
+}
+
+/// Rounds to the nearest physical pixel, which is where the crate puts everything it draws.
+pub fn round<T: Round>(phys: Phys<T>) -> Phys<T> {
+    Phys(phys.0.round())
+}
+
+/// Rounds a size down to the whole physical pixels a texture of it gets, like the crate's own
+/// caret and selection textures.
+pub fn floor_size(size: Phys<Vec2>) -> [usize; 2] {
+    [size.x, size.y].map(|x| x.floor() as usize)
+}
+
+/// Moves a position onto the physical pixel grid, keeping it in logical pixels.
+pub fn snap_pos(pos: Logical<Pos2>, pixels_per_point: f32) -> Logical<Pos2> {
+    to_logical(round(to_physical(pos, pixels_per_point)), pixels_per_point)
+}
+
+/// Translates a rect by `min_pos` and converts it to logical pixels,
+/// snapping its edges to the physical pixel grid.
+///
+/// Glyphs, the cursor and selection rects all go through this so they line up at any `pixels_per_point`.
+pub fn snap_to_logical(
+    rect: Phys<Rect>,
+    min_pos: Logical<Pos2>,
+    pixels_per_point: f32,
+) -> Logical<Rect> {
+    let offset = to_physical(Logical(min_pos.to_vec2()), pixels_per_point);
+    let rect = round(Phys(rect.translate(*offset)));
+    to_logical(rect, pixels_per_point)
+}
+
+/// The word, run of whitespace or punctuation mark `cursor` is on, by its logical indices so
+/// right-to-left text snaps the same way. A cursor hit at the end of a glyph takes the one before.
+pub fn word_bounds(buf: &Buffer, cursor: Cursor) -> (Cursor, Cursor) {
+    let Some(text) = buf.lines.get(cursor.line).map(|line| line.text()) else {
+        return (cursor, cursor);
+    };
+    let before = cursor.affinity == Affinity::Before && cursor.index > 0;
+    let mut words = text
+        .split_word_bound_indices()
+        .map(|(start, word)| (start, start + word.len()));
+    let (start, end) = words
+        .clone()
+        .find(|&(start, end)| match before {
+            true => start < cursor.index && cursor.index <= end,
+            false => start <= cursor.index && cursor.index < end,
+        })
+        .or_else(|| words.next_back())
+        .unwrap_or_default();
+    (
+        Cursor::new(cursor.line, start),
+        Cursor::new(cursor.line, end),
+    )
+}
+
+/// The whole line `cursor` is on, up to the start of the next one so its line break is included.
+pub fn line_bounds(buf: &Buffer, cursor: Cursor) -> (Cursor, Cursor) {
+    let end = match buf.lines.get(cursor.line) {
+        _ if cursor.line + 1 < buf.lines.len() => Cursor::new(cursor.line + 1, 0),
+        Some(line) => Cursor::new(cursor.line, line.text().len()),
+        None => cursor,
+    };
+    (Cursor::new(cursor.line, 0), end)
+}
+
+/// The cursor `byte_offset` bytes into the buffer's text, each line break counting as one byte,
+/// e.g. for ranges from a regex or a language server. Offsets inside a char go to its start,
+/// ones past the end of the text are `None`.
+pub fn offset_to_cursor(buf: &Buffer, byte_offset: usize) -> Option<Cursor> {
+    let mut offset = byte_offset;
+    for (i, line) in buf.lines.iter().enumerate() {
+        let text = line.text();
+        if offset <= text.len() {
+            let index = (0..=offset).rev().find(|&x| text.is_char_boundary(x));
+            return Some(Cursor::new(i, index.unwrap_or(0)));
+        }
+        offset -= text.len() + 1;
+    }
+    (byte_offset == 0).then(Cursor::default)
+}
+
+/// Where the buffer's text ends.
+pub fn end_cursor(buf: &Buffer) -> Cursor {
+    match buf.lines.last() {
+        Some(line) => Cursor::new(buf.lines.len() - 1, line.text().len()),
+        None => Cursor::default(),
+    }
+}
+
+/// How many bytes into the buffer's text `cursor` is, the reverse of [`offset_to_cursor`].
+pub fn cursor_to_offset(buf: &Buffer, cursor: Cursor) -> usize {
+    let before = buf.lines.iter().take(cursor.line);
+    before.map(|x| x.text().len() + 1).sum::<usize>() + cursor.index
+}
+
+/// A change to a buffer's text, see [`diff_buffers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextDelta {
+    Insert {
+        at: Cursor,
+        text: String,
+    },
+    Delete {
+        start: Cursor,
+        end: Cursor,
+    },
+    Replace {
+        start: Cursor,
+        end: Cursor,
+        text: String,
+    },
+}
+
+impl TextDelta {
+    /// The range of text that's replaced, empty for insertions.
+    pub fn range(&self) -> (Cursor, Cursor) {
+        match *self {
+            TextDelta::Insert { at, .. } => (at, at),
+            TextDelta::Delete { start, end } | TextDelta::Replace { start, end, .. } => {
+                (start, end)
+            }
+        }
+    }
+
+    /// The text the range is replaced with, empty for deletions.
+    pub fn text(&self) -> &str {
+        match self {
+            TextDelta::Insert { text, .. } | TextDelta::Replace { text, .. } => text,
+            TextDelta::Delete { .. } => "",
+        }
+    }
+}
+
+/// The ranges of `a` and `b` that differ, in order, from a shortest edit script (Myers' diff).
+fn diff_ranges<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(Range<usize>, Range<usize>)> {
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (a.len() as isize, b.len() as isize);
+
+    // The furthest x reached on each diagonal k = x - y, for every number of edits
+    let offset = n + m + 1;
+    let mut v = vec![0_isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    'search: for d in 0..=n + m {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = match k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                true => v[i + 1],
+                false => v[i - 1] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walks the edits back, collecting where the texts match
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let (d, k) = (d as isize, x - y);
+        let i = (k + offset) as usize;
+        let prev_k = match k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = match d {
+            0 => 0,
+            _ => v[(prev_k + offset) as usize],
+        };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x.max(0) && y > prev_y.max(0) {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x.max(0);
+        y = prev_y.max(0);
+    }
+    matches.reverse();
+
+    let (mut a_start, mut b_start) = (0, 0);
+    let mut ranges = Vec::new();
+    for (a_end, b_end) in matches.into_iter().chain([(a.len(), b.len())]) {
+        if a_start < a_end || b_start < b_end {
+            ranges.push((
+                prefix + a_start..prefix + a_end,
+                prefix + b_start..prefix + b_end,
+            ));
+        }
+        (a_start, b_start) = (a_end + 1, b_end + 1);
+    }
+    ranges
+}
+
+fn buffer_lines(buf: &Buffer) -> Vec<&str> {
+    match buf.lines.is_empty() {
+        true => vec![""],
+        false => buf.lines.iter().map(|line| line.text()).collect(),
+    }
+}
+
+/// The deltas that turn the text of `a` into the text of `b`, attributes aren't compared.
 ///
-/// **This is in physical pixels.**
-pub fn cursor_rect(buf: &Buffer, cursor: Cursor) -> Option<Rect> {
-    cursor::cursor_pos(buf, cursor)
+/// Lines are diffed first, then the chars of the lines that changed. The deltas go from the end
+/// of the text to its start, so positions in `a` still hold when they're applied in order.
+pub fn diff_buffers(a: &Buffer, b: &Buffer) -> Vec<TextDelta> {
+    diff_lines(&buffer_lines(a), &buffer_lines(b))
+}
+
+/// [`diff_buffers`] with the new text as a string, its lines separated by `\n`.
+pub fn diff_text(buf: &Buffer, text: &str) -> Vec<TextDelta> {
+    diff_lines(&buffer_lines(buf), &text.split('\n').collect::<Vec<_>>())
+}
+
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<TextDelta> {
+    let line_end = |line: usize| Cursor::new(line, a[line].len());
+
+    let mut deltas = Vec::new();
+    for (a_range, b_range) in diff_ranges(a, b).into_iter().rev() {
+        let b_text = b[b_range.clone()].join("\n");
+        if a_range.is_empty() {
+            deltas.push(match a_range.start < a.len() {
+                true => TextDelta::Insert {
+                    at: Cursor::new(a_range.start, 0),
+                    text: b_text + "\n",
+                },
+                false => TextDelta::Insert {
+                    at: line_end(a.len() - 1),
+                    text: "\n".to_string() + &b_text,
+                },
+            });
+        } else if b_range.is_empty() {
+            deltas.push(match (a_range.end < a.len(), a_range.start) {
+                (true, _) => TextDelta::Delete {
+                    start: Cursor::new(a_range.start, 0),
+                    end: Cursor::new(a_range.end, 0),
+                },
+                (false, 0) => TextDelta::Delete {
+                    start: Cursor::new(0, 0),
+                    end: line_end(a.len() - 1),
+                },
+                (false, start) => TextDelta::Delete {
+                    start: line_end(start - 1),
+                    end: line_end(a.len() - 1),
+                },
+            });
+        } else {
+            let a_text = a[a_range.clone()].join("\n");
+            let a_chars = a_text.char_indices().collect::<Vec<_>>();
+            let b_chars = b_text.char_indices().collect::<Vec<_>>();
+            let byte = |chars: &[(usize, char)], text: &str, i: usize| {
+                chars.get(i).map_or(text.len(), |&(byte, _)| byte)
+            };
+            // Where the byte offset into the joined lines is in the buffer
+            let cursor = |offset: usize| {
+                let before = &a_text[..offset];
+                match before.rfind('\n') {
+                    Some(i) => {
+                        Cursor::new(a_range.start + before.matches('\n').count(), offset - i - 1)
+                    }
+                    None => Cursor::new(a_range.start, offset),
+                }
+            };
+            let a_keys = a_chars.iter().map(|&(_, c)| c).collect::<Vec<_>>();
+            let b_keys = b_chars.iter().map(|&(_, c)| c).collect::<Vec<_>>();
+            for (chars_a, chars_b) in diff_ranges(&a_keys, &b_keys).into_iter().rev() {
+                let start = cursor(byte(&a_chars, &a_text, chars_a.start));
+                let end = cursor(byte(&a_chars, &a_text, chars_a.end));
+                let text = b_text
+                    [byte(&b_chars, &b_text, chars_b.start)..byte(&b_chars, &b_text, chars_b.end)]
+                    .to_string();
+                deltas.push(match (chars_a.is_empty(), chars_b.is_empty()) {
+                    (true, _) => TextDelta::Insert { at: start, text },
+                    (_, true) => TextDelta::Delete { start, end },
+                    _ => TextDelta::Replace { start, end, text },
+                });
+            }
+        }
+    }
+    deltas
 }
 
-/// **This is in physical pixels.**
-pub fn extra_width(line_height: f32) -> f32 {
+pub fn extra_width(line_height: Phys<f32>) -> Phys<f32> {
     // https://github.com/emilk/egui/blob/b8048572e8cc47ef9410b3516456da2a320fcdd2/crates/egui/src/text_selection/visuals.rs#L36
-    line_height / 2.0
+    Phys(*line_height / 2.0)
 }
 
-/// **This is in physical pixels.**
-pub fn selection_rect(line_selection: LineSelection, last: bool) -> Rect {
+pub fn selection_rect(line_selection: LineSelection, last: bool) -> Phys<Rect> {
     let extra_width = extra_width(line_selection.line_height());
-    let (x_left, mut x_width) = line_selection.x_left_and_width();
+    let Phys((x_left, mut x_width)) = line_selection.x_left_and_width();
     if !last && line_selection.end_of_line_included() {
-        x_width += extra_width;
+        x_width += *extra_width;
+    }
+    Phys(Rect::from_min_size(
+        pos2(x_left, *line_selection.line_top()),
+        vec2(x_width, *line_selection.line_height()),
+    ))
+}
+
+/// Where `run`'s visible glyphs are, relative to the buffer. Whitespace doesn't count, so empty
+/// and whitespace-only runs have none. Glyphs are compared by position rather than order, which
+/// covers aligned and right-to-left runs.
+pub fn ink_x_range(run: &LayoutRun, spacing: Spacing) -> Option<Phys<Rangef>> {
+    run.glyphs
+        .iter()
+        .zip(spacing.offsets(run.glyphs, run.text))
+        .filter(|(glyph, _)| {
+            !run.text
+                .get(glyph.start..glyph.end)
+                .is_some_and(|x| x.chars().all(char::is_whitespace))
+        })
+        .map(|(glyph, offset)| Rangef::new(glyph.x + offset, glyph.x + offset + glyph.w))
+        .reduce(|a, b| Rangef::new(a.min.min(b.min), a.max.max(b.max)))
+        .map(Phys)
+}
+
+/// The invisible characters that reorder the text around them, by their abbreviations.
+const BIDI_CONTROLS: [(char, &str); 12] = [
+    ('\u{061C}', "ALM"),
+    ('\u{200E}', "LRM"),
+    ('\u{200F}', "RLM"),
+    ('\u{202A}', "LRE"),
+    ('\u{202B}', "RLE"),
+    ('\u{202C}', "PDF"),
+    ('\u{202D}', "LRO"),
+    ('\u{202E}', "RLO"),
+    ('\u{2066}', "LRI"),
+    ('\u{2067}', "RLI"),
+    ('\u{2068}', "FSI"),
+    ('\u{2069}', "PDI"),
+];
+
+/// Control characters other than tabs and line feeds, and bidi controls, which can make text
+/// look like something it isn't. Zero-width joiners aren't, emoji and many scripts need them.
+pub fn is_hidden_control(c: char) -> bool {
+    (c.is_control() && c != '\t' && c != '\n') || BIDI_CONTROLS.iter().any(|&(x, _)| x == c)
+}
+
+/// Removes every [hidden control](is_hidden_control).
+pub fn strip_controls(text: &str) -> Cow<'_, str> {
+    match text.contains(is_hidden_control) {
+        true => Cow::Owned(text.chars().filter(|&c| !is_hidden_control(c)).collect()),
+        false => Cow::Borrowed(text),
+    }
+}
+
+/// Replaces every [hidden control](is_hidden_control) with a visible placeholder: its control
+/// picture like ␀, its abbreviation like ⟨RLO⟩ or its code point like ⟨U+0085⟩. Also returns
+/// the byte ranges of the placeholders in the escaped text.
+///
+/// [`unescape_controls`] turns them back.
+pub fn escape_controls(text: &str) -> (Cow<'_, str>, Vec<Range<usize>>) {
+    if !text.contains(is_hidden_control) {
+        return (Cow::Borrowed(text), Vec::new());
+    }
+    let mut escaped = String::with_capacity(text.len());
+    let mut placeholders = Vec::new();
+    for c in text.chars() {
+        if !is_hidden_control(c) {
+            escaped.push(c);
+            continue;
+        }
+        let start = escaped.len();
+        match (c, BIDI_CONTROLS.iter().find(|&&(x, _)| x == c)) {
+            // The control pictures are in the same order
+            ('\0'..='\x1F', _) => escaped.extend(char::from_u32(0x2400 + c as u32)),
+            ('\x7F', _) => escaped.push('␡'),
+            (_, Some((_, name))) => escaped.push_str(&format!("⟨{name}⟩")),
+            _ => escaped.push_str(&format!("⟨U+{:04X}⟩", c as u32)),
+        }
+        placeholders.push(start..escaped.len());
+    }
+    (Cow::Owned(escaped), placeholders)
+}
+
+/// The control an [escaped](escape_controls) placeholder at the start of `text` stands for, and
+/// the placeholder's length.
+fn escaped_control(text: &str) -> Option<(char, usize)> {
+    let c = text.chars().next()?;
+    let control = match c {
+        '␀'..='␟' => char::from_u32(c as u32 - 0x2400),
+        '␡' => Some('\x7F'),
+        '⟨' => {
+            let (name, _) = text['⟨'.len_utf8()..].split_once('⟩')?;
+            let control = match name.strip_prefix("U+") {
+                Some(hex) if hex.len() == 4 => {
+                    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                }
+                _ => BIDI_CONTROLS
+                    .iter()
+                    .find(|&&(_, x)| x == name)
+                    .map(|&(x, _)| x),
+            };
+            let len = '⟨'.len_utf8() + name.len() + '⟩'.len_utf8();
+            return control
+                .filter(|&x| is_hidden_control(x) && !x.is_ascii())
+                .map(|x| (x, len));
+        }
+        _ => None,
+    };
+    control
+        .filter(|&x| is_hidden_control(x))
+        .map(|x| (x, c.len_utf8()))
+}
+
+/// The control `placeholder` stands for if it's exactly one [escaped](escape_controls)
+/// placeholder.
+pub fn placeholder_control(placeholder: &str) -> Option<char> {
+    escaped_control(placeholder)
+        .filter(|&(_, len)| len == placeholder.len())
+        .map(|(c, _)| c)
+}
+
+/// Turns the placeholders at the sorted byte ranges `placeholders`, e.g. from
+/// [`escape_controls`], back into the controls they stand for. Text that only looks like one,
+/// e.g. a typed ⟨RLO⟩, is left as it is, and so are ranges that aren't a whole placeholder.
+pub fn unescape_controls<'a>(text: &'a str, placeholders: &[Range<usize>]) -> Cow<'a, str> {
+    let mut controls = placeholders
+        .iter()
+        .filter_map(|range| {
+            let control = placeholder_control(text.get(range.clone())?)?;
+            Some((range.clone(), control))
+        })
+        .peekable();
+    if controls.peek().is_none() {
+        return Cow::Borrowed(text);
+    }
+    let mut unescaped = String::with_capacity(text.len());
+    let mut end = 0;
+    for (range, control) in controls {
+        // Overlapping ranges can't both be placeholders
+        if range.start < end {
+            continue;
+        }
+        unescaped.push_str(&text[end..range.start]);
+        unescaped.push(control);
+        end = range.end;
+    }
+    unescaped.push_str(&text[end..]);
+    Cow::Owned(unescaped)
+}
+
+/// How many chars the buffer's text has, each line break counts as one.
+pub fn char_count(buf: &Buffer) -> usize {
+    let chars = buf
+        .lines
+        .iter()
+        .map(|x| x.text().chars().count())
+        .sum::<usize>();
+    chars + buf.lines.len().saturating_sub(1)
+}
+
+/// The longest start of `text` with at most `max_chars` chars that doesn't split a grapheme.
+pub fn truncate_graphemes(text: &str, max_chars: usize) -> &str {
+    let mut chars = 0;
+    let end = text.grapheme_indices(true).find_map(|(i, grapheme)| {
+        chars += grapheme.chars().count();
+        (chars > max_chars).then_some(i)
+    });
+    &text[..end.unwrap_or(text.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cursor::LineSelection;
+    use crate::spacing::Spacing;
+    use crate::test_util;
+    use crate::units::{Logical, Phys};
+    use crate::util::{
+        block_cursor_rect, char_count, cursor_rect, cursor_to_offset, diff_buffers, end_cursor,
+        escape_controls, floor_size, ink_x_range, offset_to_cursor, round, selection_rect,
+        snap_pos, snap_to_logical, strip_controls, to_physical, truncate_graphemes,
+        unescape_controls, word_bounds, TextDelta,
+    };
+    use cosmic_text::{Affinity, Align, Buffer, Cursor};
+    use egui::{pos2, vec2, Rect};
+    use std::borrow::Cow;
+
+    #[test]
+    fn cursor_and_selection_snap_together() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "Snapping at fractional scales", None);
+        let start = Cursor::new(0, 9);
+        let end = Cursor::new(0, 19);
+
+        for pixels_per_point in [1.0, 1.25, 1.5, 2.0] {
+            let min_pos = Logical(pos2(10.3, 7.7));
+            let caret = snap_to_logical(
+                cursor_rect(&buf, start, Spacing::default()).unwrap(),
+                min_pos,
+                pixels_per_point,
+            );
+            let run = buf.layout_runs().next().unwrap();
+            let selection = snap_to_logical(
+                selection_rect(
+                    LineSelection::new(&run, (start, end), Spacing::default()).unwrap(),
+                    true,
+                ),
+                min_pos,
+                pixels_per_point,
+            );
+
+            for x in [caret.min, caret.max, selection.min, selection.max] {
+                let physical = x * pixels_per_point;
+                assert!((physical - physical.round()).length() < 1e-3);
+            }
+            assert_eq!(caret.left(), selection.left());
+            assert_eq!(caret.y_range(), selection.y_range());
+        }
+    }
+
+    #[test]
+    fn pixel_helpers_match_the_snapping() {
+        for pixels_per_point in [1.0, 1.25, 1.5, 2.0] {
+            let min_pos = Logical(pos2(10.3, 7.7));
+            let snapped = snap_pos(min_pos, pixels_per_point);
+            let physical = to_physical(snapped, pixels_per_point);
+            assert_eq!(round(physical), physical);
+            assert_eq!(
+                snap_to_logical(Phys(Rect::ZERO), min_pos, pixels_per_point).min,
+                *snapped
+            );
+        }
+        assert_eq!(floor_size(Phys(vec2(1.0, 20.9))), [1, 20]);
+    }
+
+    #[test]
+    fn controls_are_stripped_or_escaped_reversibly() {
+        let text = "a\u{202E}b\0\tc\u{7f}\u{85}\u{200d}\n";
+        assert_eq!(strip_controls(text), "ab\tc\u{200d}\n");
+        let (escaped, placeholders) = escape_controls(text);
+        assert_eq!(escaped, "a⟨RLO⟩b␀\tc␡⟨U+0085⟩\u{200d}\n");
+        assert_eq!(placeholders.len(), 4);
+        assert_eq!(unescape_controls(&escaped, &placeholders), text);
+        assert!(matches!(escape_controls("plain").0, Cow::Borrowed(_)));
+
+        // Only the given placeholders are turned back, not text that looks like one
+        let text = "⟨RLO⟩ ⟨RLO ⟨U+ZZ⟩ ⟨LRM⟩";
+        assert_eq!(unescape_controls(text, &[]), text);
+        let lrm = text.rfind('⟨').unwrap()..text.len();
+        assert_eq!(
+            unescape_controls(text, &[0..3, lrm]),
+            "⟨RLO⟩ ⟨RLO ⟨U+ZZ⟩ \u{200e}"
+        );
+    }
+
+    #[test]
+    fn offsets_and_cursors_convert_both_ways() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "hé\n\nlo", None);
+        for (offset, cursor) in [
+            (0, Cursor::new(0, 0)),
+            (3, Cursor::new(0, 3)),
+            (4, Cursor::new(1, 0)),
+            (5, Cursor::new(2, 0)),
+            (7, Cursor::new(2, 2)),
+        ] {
+            assert_eq!(offset_to_cursor(&buf, offset), Some(cursor));
+            assert_eq!(cursor_to_offset(&buf, cursor), offset);
+        }
+        // Inside é, and past the end
+        assert_eq!(offset_to_cursor(&buf, 2), Some(Cursor::new(0, 1)));
+        assert_eq!(offset_to_cursor(&buf, 8), None);
+        assert_eq!(end_cursor(&buf), Cursor::new(2, 2));
+    }
+
+    #[test]
+    fn truncation_keeps_graphemes_whole() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "ab\n\ncé", None);
+        assert_eq!(char_count(&buf), 6);
+
+        // e and a combining acute accent
+        let text = "ae\u{301}b";
+        assert_eq!(truncate_graphemes(text, 4), text);
+        assert_eq!(truncate_graphemes(text, 3), "ae\u{301}");
+        assert_eq!(truncate_graphemes(text, 2), "a");
+        assert_eq!(truncate_graphemes(text, 0), "");
+    }
+
+    #[test]
+    fn ink_covers_the_visible_glyphs_wherever_they_are() {
+        let mut font_system = test_util::font_system();
+        let mut buf = test_util::buffer(&mut font_system, "  ink  \n\n   ", Some(400.0));
+        buf.lines[0].set_align(Some(Align::Right));
+        buf.shape_until_scroll(&mut font_system, false);
+
+        let runs = buf.layout_runs().collect::<Vec<_>>();
+        let ink = *ink_x_range(&runs[0], Spacing::default()).unwrap();
+        let glyphs = &runs[0].glyphs[2..5];
+        assert_eq!(ink.min, glyphs[0].x);
+        assert_eq!(ink.max, glyphs[2].x + glyphs[2].w);
+        // Far from the start of the line, and short of the trailing spaces
+        assert!(ink.min > 300.0);
+        assert!(ink.max < runs[0].glyphs[6].x + runs[0].glyphs[6].w);
+
+        let spacing = Spacing {
+            letter: 2.0,
+            word: 0.0,
+        };
+        let spaced = *ink_x_range(&runs[0], spacing).unwrap();
+        assert_eq!(spaced.min, ink.min + 4.0);
+        assert_eq!(spaced.max, ink.max + 8.0);
+
+        // Empty and whitespace-only lines
+        assert!(ink_x_range(&runs[1], Spacing::default()).is_none());
+        assert!(ink_x_range(&runs[2], Spacing::default()).is_none());
+    }
+
+    #[test]
+    fn cursor_follows_glyph_positions() {
+        let mut font_system = test_util::font_system();
+        let mut buf = test_util::buffer(&mut font_system, "a\tbc\td ef", Some(400.0));
+        // Centering offsets every glyph, so their widths no longer add up to their positions
+        buf.lines[0].set_align(Some(Align::Center));
+        buf.shape_until_scroll(&mut font_system, false);
+
+        let run = buf.layout_runs().next().unwrap();
+        let accumulated: f32 = run.glyphs.iter().take(3).map(|glyph| glyph.w).sum();
+        let glyph = &run.glyphs[3];
+        assert_eq!(glyph.start, 3);
+        assert!((accumulated - glyph.x).abs() > 1.0);
+
+        let caret = cursor_rect(&buf, Cursor::new(0, 3), Spacing::default()).unwrap();
+        assert_eq!(caret.left(), glyph.x);
+
+        let last = run.glyphs.last().unwrap();
+        let caret = cursor_rect(&buf, Cursor::new(0, last.end), Spacing::default()).unwrap();
+        assert_eq!(caret.left(), last.x + last.w);
+    }
+
+    #[test]
+    fn block_cursor_covers_the_next_char() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "ab", None);
+        let next = |index| cursor_rect(&buf, Cursor::new(0, index), Spacing::default()).unwrap();
+
+        let block = block_cursor_rect(&buf, Cursor::new(0, 0), Spacing::default()).unwrap();
+        assert_eq!(block.left(), next(0).left());
+        assert_eq!(block.right(), next(1).left());
+        assert_eq!(block.y_range(), next(0).y_range());
+
+        let block = block_cursor_rect(&buf, Cursor::new(0, 2), Spacing::default()).unwrap();
+        assert_eq!(block.width(), block.height() / 2.0);
+    }
+
+    #[test]
+    fn word_bounds_split_at_punctuation() {
+        let mut font_system = test_util::font_system();
+        let buf = test_util::buffer(&mut font_system, "foo(bar)  baz", None);
+        let bounds = |index, affinity| {
+            let (start, end) = word_bounds(&buf, Cursor::new_with_affinity(0, index, affinity));
+            (start.index, end.index)
+        };
+
+        assert_eq!(bounds(1, Affinity::After), (0, 3));
+        assert_eq!(bounds(3, Affinity::Before), (0, 3));
+        assert_eq!(bounds(3, Affinity::After), (3, 4));
+        assert_eq!(bounds(4, Affinity::After), (4, 7));
+        assert_eq!(bounds(8, Affinity::Before), (7, 8));
+        assert_eq!(bounds(8, Affinity::After), (8, 10));
+        assert_eq!(bounds(13, Affinity::After), (10, 13));
+        assert_eq!(bounds(0, Affinity::Before), (0, 3));
+    }
+
+    #[test]
+    fn diffs_turn_one_buffer_into_the_other() {
+        let mut font_system = test_util::font_system();
+        let apply = |text: &str, deltas: &[TextDelta]| {
+            let mut text = text.to_string();
+            for delta in deltas {
+                let offset = |cursor: Cursor| {
+                    let lines = text.split('\n').take(cursor.line);
+                    lines.map(|line| line.len() + 1).sum::<usize>() + cursor.index
+                };
+                let (start, end) = delta.range();
+                text.replace_range(offset(start)..offset(end), delta.text());
+            }
+            text
+        };
+
+        let mut pairs = [
+            ("", ""),
+            ("", "one\ntwo"),
+            ("one\ntwo", ""),
+            ("one\ntwo", "one\ntwo\nthree"),
+            ("one\ntwo\nthree", "one\ntwo"),
+            ("one\ntwo\nthree", "two\nthree"),
+            ("one\nthree", "one\ntwo\nthree"),
+            ("one\n\n", "one"),
+            ("h\u{e9}llo w\u{f6}rld", "hallo w\u{f6}rld!"),
+        ]
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .to_vec();
+        // Small alphabets so there's plenty to match
+        let mut seed = 7_u32;
+        let mut random_text = || {
+            (0..12)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    ['a', 'b', '\n', '\u{e9}'][(seed >> 16) as usize % 4]
+                })
+                .collect::<String>()
+        };
+        for _ in 0..100 {
+            pairs.push((random_text(), random_text()));
+        }
+
+        for (a, b) in pairs {
+            let (a, b) = (
+                test_util::buffer(&mut font_system, &a, None),
+                test_util::buffer(&mut font_system, &b, None),
+            );
+            // A trailing line break doesn't leave an empty line behind
+            let text = |buf: &Buffer| {
+                let lines = buf.lines.iter().map(|line| line.text());
+                lines.collect::<Vec<_>>().join("\n")
+            };
+            let deltas = diff_buffers(&a, &b);
+            assert_eq!(apply(&text(&a), &deltas), text(&b), "{deltas:?}");
+        }
+
+        let deltas = diff_buffers(
+            &test_util::buffer(&mut font_system, "one\ntwo\nthree", None),
+            &test_util::buffer(&mut font_system, "one\ntoo\nthree", None),
+        );
+        assert_eq!(
+            deltas,
+            [TextDelta::Replace {
+                start: Cursor::new(1, 1),
+                end: Cursor::new(1, 2),
+                text: "o".to_string()
+            }]
+        );
     }
-    Rect::from_min_size(
-        pos2(x_left, line_selection.line_top()),
-        vec2(x_width, line_selection.line_height()),
-    )
 }
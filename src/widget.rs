@@ -1,19 +1,41 @@
-use std::hash::BuildHasher;
+#[cfg(feature = "debug-tools")]
+use cosmic_text::CacheKey;
 use cosmic_text::{
-    Action, Attrs, Buffer, Change, Cursor, Edit, Editor, FontSystem, LayoutGlyph, Metrics, Motion,
-    Selection, Shaping, SwashCache,
+    Action, Affinity, Attrs, AttrsList, AttrsOwned, Buffer, BufferLine, Change, ChangeItem, Cursor,
+    Edit, Editor, Family, FontFeatures, FontSystem, LayoutRun, LineEnding, Metrics, Motion,
+    Selection, Shaping, SwashCache, Wrap,
 };
-use cosmic_undo_2::{ActionIter, Commands};
+use cosmic_undo_2::{ActionIter, Commands, Merge};
+use egui::text_selection::LabelSelectionState;
 use egui::{
-    pos2, vec2, Color32, ColorImage, CursorIcon, Event, EventFilter, Key, NumExt, Painter, Pos2,
-    Rect, Response, Sense, TextureHandle, TextureId, TextureOptions, Ui, Vec2,
+    pos2, vec2, Align, Align2, Button, Color32, CursorIcon, DragAndDrop, Event, EventFilter, Id,
+    Key, LayerId, Modifiers, NumExt, Order, Painter, PointerButton, Pos2, Rangef, Rect, Response,
+    Sense, Stroke, TextStyle, TextureHandle, TextureId, TextureOptions, Ui, Vec2, Visuals,
 };
+use std::borrow::Cow;
+use std::hash::BuildHasher;
+use std::ops::{ControlFlow, Range, RangeInclusive};
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicU64};
+use std::time::{Duration, Instant};
 
 use crate::atlas::TextureAtlas;
+use crate::compat;
 use crate::cursor::LineSelection;
-use crate::draw::{draw_buf, draw_run};
+use crate::draw::{
+    draw_buf, draw_buf_rotated, draw_run, run_glyphs, selection_rects, GlyphInfo, Rot90,
+};
+use crate::history::{self, TextChange};
+use crate::resources::TextResources;
+use crate::spacing::Spacing;
+use crate::units::{Logical, Phys};
 use crate::util::{
-    cursor_rect, extra_width, measure_height, measure_width_and_height, selection_rect,
+    block_cursor_rect, char_count, cursor_rect, cursor_to_offset, diff_text, end_cursor,
+    escape_controls, estimate_size, extra_width, floor_size, has_fonts, ink_x_range,
+    is_hidden_control, line_bounds, measure_height, measure_width_and_height, offset_to_cursor,
+    placeholder_control, snap_pos, snap_to_logical, strip_controls, to_physical,
+    truncate_graphemes, unescape_controls, unshaped_height, with_shaped_lines_only,
+    with_unshaped_lines, word_bounds, TextDelta,
 };
 
 macro_rules! public_enum {
@@ -32,6 +54,7 @@ macro_rules! public_enum {
     ) => {
         $( #[ $main_attr ] )*
         #[derive(Debug, PartialEq, Copy, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum $name {
             $(
                 $( #[ $attr ])*
@@ -53,9 +76,27 @@ macro_rules! public_enum {
 }
 
 public_enum! {
+    /// What the widget reacts to:
+    ///
+    /// | | `Disabled` | `Selection` | `ReadOnly` | `Enabled` |
+    /// |---|---|---|---|---|
+    /// | Hover and click reporting | ✓ | ✓ | ✓ | ✓ |
+    /// | Selecting and copying | | ✓ | ✓ | ✓ |
+    /// | Keyboard navigation, caret and hover caret | | | ✓ | ✓ |
+    /// | Editing | | | | ✓ |
+    ///
+    /// Hover and click reporting is [`CosmicEdit::hovered_cursor`] and
+    /// [`CosmicEdit::clicked_cursor`], e.g. for links or tooltips over spans marked with
+    /// [`Attrs::metadata`](cosmic_text::Attrs::metadata), see [`CosmicEdit::metadata_at`].
+    /// `Disabled` widgets only take clicks while their text has such spans, otherwise they're
+    /// left to what's behind them.
+    /// Rotated widgets don't report anything since hit testing isn't rotated.
     Interactivity {
         /// The widget will respond to input and text selection
         Enabled,
+        /// Like `Enabled` without editing, e.g. to view a file. Keys bound to editing commands,
+        /// typing, pasting and cutting do nothing.
+        ReadOnly,
         /// Widget will only respond to text selection.
         Selection,
         /// Widget is completely disabled and won't react to anything.
@@ -66,9 +107,15 @@ public_enum! {
 }
 
 impl Interactivity {
-    fn sense(&self) -> Sense {
+    /// `has_metadata` is whether the text has [metadata](cosmic_text::Attrs::metadata) spans.
+    fn sense(&self, touch_drag: TouchDrag, has_metadata: bool) -> Sense {
         match self {
+            // Clicks are still reported for links, without any the clicks are left to what's
+            // behind the widget, e.g. a selectable row
+            Self::Disabled if has_metadata => Sense::click(),
             Self::Disabled => Sense::hover(),
+            // Drags are left to a scroll area until they select, then the widget takes them over
+            _ if matches!(touch_drag, TouchDrag::HoldToSelect(_)) => Sense::click(),
             // Click is needed due to the context menu
             // We don't use egui's default drag detection either but this prevents
             // conflicting text selection and drag to scroll in a scroll area.
@@ -76,23 +123,30 @@ impl Interactivity {
         }
     }
 
-    fn input(&self) -> bool {
+    /// Keys are handled and the caret is shown.
+    fn navigation(&self) -> bool {
+        matches!(self, Interactivity::Enabled | Interactivity::ReadOnly)
+    }
+
+    fn mutation(&self) -> bool {
         matches!(self, Interactivity::Enabled)
     }
 
     fn selection(&self) -> bool {
-        matches!(self, Interactivity::Enabled | Interactivity::Selection)
+        matches!(
+            self,
+            Interactivity::Enabled | Interactivity::ReadOnly | Interactivity::Selection
+        )
     }
 }
 
 pub trait LayoutMode {
-    /// Available size is in **physical pixels**
     fn calculate(
         &mut self,
         buf: &mut Buffer,
         font_system: &mut FontSystem,
-        available_size: Vec2,
-    ) -> Vec2;
+        available_size: Phys<Vec2>,
+    ) -> Phys<Vec2>;
 
     /// Some text layouts can't detect whether they should invalidate their cached state.
     /// Therefore you have to invalidate it manually.
@@ -105,12 +159,17 @@ pub trait LayoutMode {
 pub struct PureBoundingBox(Option<Vec2>);
 
 impl LayoutMode for PureBoundingBox {
-    fn calculate(&mut self, buf: &mut Buffer, font_system: &mut FontSystem, _: Vec2) -> Vec2 {
+    fn calculate(
+        &mut self,
+        buf: &mut Buffer,
+        font_system: &mut FontSystem,
+        _: Phys<Vec2>,
+    ) -> Phys<Vec2> {
         let sz = self.0.get_or_insert_with(|| {
             buf.set_size(font_system, None, None);
-            measure_width_and_height(buf).into()
+            *measure_width_and_height(buf)
         });
-        *sz
+        Phys(*sz)
     }
 
     fn invalidate(&mut self) {
@@ -129,14 +188,14 @@ impl LayoutMode for FillWidth {
         &mut self,
         buf: &mut Buffer,
         font_system: &mut FontSystem,
-        available_size: Vec2,
-    ) -> Vec2 {
+        available_size: Phys<Vec2>,
+    ) -> Phys<Vec2> {
         if self.curr_width != available_size.x {
             self.curr_width = available_size.x;
             buf.set_size(font_system, self.curr_width.into(), None);
-            self.height = measure_height(buf);
+            self.height = *measure_height(buf);
         }
-        vec2(self.curr_width, self.height)
+        Phys(vec2(self.curr_width, self.height))
     }
 
     fn invalidate(&mut self) {
@@ -152,11 +211,13 @@ impl LayoutMode for FillWidthAndHeight {
         &mut self,
         buf: &mut Buffer,
         font_system: &mut FontSystem,
-        available_size: Vec2,
-    ) -> Vec2 {
-        self.0
-            .calculate(buf, font_system, available_size)
-            .at_least(available_size)
+        available_size: Phys<Vec2>,
+    ) -> Phys<Vec2> {
+        Phys(
+            self.0
+                .calculate(buf, font_system, available_size)
+                .at_least(*available_size),
+        )
     }
 
     fn invalidate(&mut self) {
@@ -164,6 +225,8 @@ impl LayoutMode for FillWidthAndHeight {
     }
 }
 
+/// As wide as the text up to the available width, where it wraps. The text is measured again
+/// whenever it changes, so typing grows and shrinks the widget, see [`CosmicEdit::with_min_width`].
 #[derive(Default)]
 pub struct ShrinkToFit {
     available_width: f32,
@@ -176,16 +239,16 @@ impl LayoutMode for ShrinkToFit {
         &mut self,
         buf: &mut Buffer,
         font_system: &mut FontSystem,
-        available_size: Vec2,
-    ) -> Vec2 {
+        available_size: Phys<Vec2>,
+    ) -> Phys<Vec2> {
         if self.available_width != available_size.x {
             self.available_width = available_size.x;
             buf.set_size(font_system, self.available_width.into(), None);
-            let (width, height) = measure_width_and_height(buf);
-            self.width = width;
-            self.height = height;
+            let Vec2 { x, y } = *measure_width_and_height(buf);
+            self.width = x;
+            self.height = y;
         }
-        vec2(self.width, self.height)
+        Phys(vec2(self.width, self.height))
     }
 
     fn invalidate(&mut self) {
@@ -198,8 +261,8 @@ impl LayoutMode for Box<dyn LayoutMode> {
         &mut self,
         buf: &mut Buffer,
         font_system: &mut FontSystem,
-        available_size: Vec2,
-    ) -> Vec2 {
+        available_size: Phys<Vec2>,
+    ) -> Phys<Vec2> {
         (**self).calculate(buf, font_system, available_size)
     }
 
@@ -211,6 +274,7 @@ impl LayoutMode for Box<dyn LayoutMode> {
 #[derive(Clone)]
 pub struct CursorTexture {
     line_height: f32,
+    color: Color32,
     texture: TextureHandle,
 }
 
@@ -218,11 +282,12 @@ impl CursorTexture {
     pub fn new(ctx: &egui::Context, line_height: f32, color: Color32) -> Self {
         let texture = ctx.load_texture(
             "egui cosmic text cursor",
-            ColorImage::new([1.0, line_height].map(|x| x as usize), color),
+            compat::filled_color_image(floor_size(Phys(vec2(1.0, line_height))), color),
             TextureOptions::NEAREST,
         );
         Self {
             line_height,
+            color,
             texture,
         }
     }
@@ -231,11 +296,68 @@ impl CursorTexture {
         self.line_height
     }
 
+    pub fn color(&self) -> Color32 {
+        self.color
+    }
+
     pub fn texture_id(&self) -> TextureId {
         self.texture.id()
     }
 }
 
+/// How a [`CosmicEdit`] looks, as plain data that can be kept in a theme, see
+/// [`CosmicEdit::set_style`].
+///
+/// The widget has no frame, margin, current line highlight, gutter or visible whitespace to style
+/// yet, those are left for when it draws them. Wrap it in an [`egui::Frame`] for a frame and
+/// margin.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CosmicEditStyle {
+    /// The color of text without one of its own, `None` uses the atlas's default color, see
+    /// [`CosmicEdit::set_text_color`]
+    pub text_color: Option<Color32>,
+    /// `None` hides the caret
+    pub caret: Option<Color32>,
+    pub selection: Color32,
+    pub caret_layer: CaretLayer,
+    pub overwrite_caret: OverwriteCaret,
+    /// See [`CosmicEdit::set_hover_caret`]
+    pub hover_caret: bool,
+    /// See [`CosmicEdit::with_content_align`]
+    pub content_align: Align2,
+    pub spacing: Spacing,
+}
+
+impl CosmicEditStyle {
+    /// The text, caret and selection colors of egui's own text edits.
+    pub fn from_visuals(visuals: &Visuals) -> Self {
+        Self {
+            text_color: Some(visuals.text_color()),
+            caret: Some(visuals.text_cursor.stroke.color),
+            selection: visuals.selection.bg_fill,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for CosmicEditStyle {
+    /// What [`CosmicEdit::new`] starts out with, a white caret and a dark gray selection. See
+    /// [`Self::from_visuals`] to match egui's text edits instead.
+    fn default() -> Self {
+        Self {
+            text_color: None,
+            caret: Some(Color32::WHITE),
+            selection: Color32::DARK_GRAY,
+            caret_layer: CaretLayer::default(),
+            overwrite_caret: OverwriteCaret::default(),
+            hover_caret: false,
+            content_align: Align2::LEFT_TOP,
+            spacing: Spacing::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LineHeight {
     Absolute(f32),
@@ -301,8 +423,8 @@ impl SelectionTexture {
             SelectionTexture::Default(x) => {
                 *self = Self::Texture(ctx.load_texture(
                     "egui cosmic text selection",
-                    ColorImage::new(
-                        [extra_width(line_height), line_height].map(|x| x as usize),
+                    compat::filled_color_image(
+                        floor_size(Phys(vec2(*extra_width(Phys(line_height)), line_height))),
                         *x,
                     ),
                     TextureOptions::NEAREST,
@@ -336,10 +458,18 @@ impl ClickType {
         }
     }
 
-    /// Takes a logical position
-    fn as_action(self, pos: Pos2, pixels_per_point: f32) -> Action {
-        // logical -> physical
-        let Pos2 { x, y } = (pos * pixels_per_point).round();
+    /// Takes a physical position, see [`CosmicEdit::hit_pos`]
+    fn drag_action(pos: Pos2) -> Action {
+        let Pos2 { x, y } = pos.round();
+        Action::Drag {
+            x: x as i32,
+            y: y as i32,
+        }
+    }
+
+    /// Takes a physical position, see [`CosmicEdit::hit_pos`]
+    fn as_action(self, pos: Pos2) -> Action {
+        let Pos2 { x, y } = pos.round();
         let [x, y] = [x as i32, y as i32];
         match self {
             ClickType::Single => Action::Click { x, y },
@@ -347,75 +477,955 @@ impl ClickType {
             ClickType::Triple => Action::TripleClick { x, y },
         }
     }
+
+    /// The word or line around `cursor` that double and triple clicks select and drag by.
+    fn unit(self, buf: &Buffer, cursor: Cursor) -> Option<(Cursor, Cursor)> {
+        match self {
+            ClickType::Single => None,
+            ClickType::Double => Some(word_bounds(buf, cursor)),
+            ClickType::Triple => Some(line_bounds(buf, cursor)),
+        }
+    }
+}
+
+/// Thresholds for telling clicks, multi clicks and drags apart.
+///
+/// Fields left as `None` follow egui's own values, see [`CosmicEdit::with_click_metrics`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ClickMetrics {
+    /// Max seconds between clicks for them to count as a double or triple click
+    pub max_multi_click_interval: Option<f64>,
+    /// Max distance in points between clicks for them to count as a double or triple click
+    pub max_click_dist: Option<f32>,
+    /// Distance in points the pointer has to move after a press before it starts selecting.
+    /// Defaults to the click distance.
+    pub drag_threshold: Option<f32>,
+}
+
+impl ClickMetrics {
+    fn max_multi_click_interval(&self, ctx: &egui::Context) -> f64 {
+        self.max_multi_click_interval
+            .unwrap_or_else(|| compat::max_multi_click_interval(ctx))
+    }
+
+    fn max_click_dist(&self, ctx: &egui::Context) -> f32 {
+        self.max_click_dist
+            .unwrap_or_else(|| compat::max_click_dist(ctx))
+    }
+
+    fn drag_threshold(&self, ctx: &egui::Context) -> f32 {
+        self.drag_threshold
+            .unwrap_or_else(|| self.max_click_dist(ctx))
+    }
+}
+
+/// What a touch pressed on a selectable widget does, see [`CosmicEdit::with_touch_drag`].
+///
+/// The mouse always selects right away.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum TouchDrag {
+    /// Selects right away like the mouse, swiping can't scroll a surrounding `ScrollArea`.
+    #[default]
+    Select,
+    /// Only selects once the touch was held still for this many seconds or long pressed, a
+    /// swipe before that scrolls a surrounding `ScrollArea`. Taps still place the caret.
+    HoldToSelect(f64),
+}
+
+/// Typed chars undone together, see [`CosmicEdit::set_undo_group_window`].
+#[derive(Clone, Copy)]
+struct TypingGroup {
+    /// Where the last char typed ends
+    cursor: Cursor,
+    time: f64,
+    /// The history's length with the group as its last step
+    commands: usize,
 }
 
 struct LastClick {
     time: f64,
     pos: Pos2,
     ty: ClickType,
+    /// The word or line a double or triple click started on, dragging extends from it
+    unit: Option<(Cursor, Cursor)>,
 }
 
-macro_rules! egui_key_to_cosmic_action {
-    ($name:ident; $( $key:pat => $action:expr ),* $(,)?) => {
-        fn $name(key: Key) -> Option<Action> {
-            match key {
-                $(
-                    $key => Some($action),
-                )*
-                _ => None
+/// See [`EditorCommand::Custom`].
+pub type CustomCommand<L> = Rc<dyn Fn(&mut CosmicEdit<L>, &mut FontSystem)>;
+
+/// What a key does in a [`CosmicEdit`], see [`Keymap`].
+pub enum EditorCommand<L: LayoutMode> {
+    /// Moves the cursor, extending the selection while Shift is held.
+    ///
+    /// Up, Down, PageUp and PageDown keep the cursor's x, Up and Down are left for the app in
+    /// [single-line](CosmicEdit::with_single_line) widgets. Home and End follow [`LineMotions`].
+    /// The word motions are visual, so they follow the arrow's direction in right-to-left text too.
+    Motion(Motion),
+    /// Deletes the selection or the character before the cursor.
+    DeleteBackward,
+    /// Deletes the selection or the character after the cursor.
+    DeleteForward,
+    /// Deletes the selection or back to the previous word boundary.
+    DeleteWordBackward,
+    /// Deletes the selection or up to the next word boundary.
+    DeleteWordForward,
+    /// Deletes the selection or the rest of the line, or the line break at its end, like Emacs'
+    /// Ctrl+K.
+    DeleteToEndOfLine,
+    /// Replaces the selection with a paragraph break, auto-indented. Single-line widgets are
+    /// [submitted](CosmicEdit::submitted) instead.
+    Newline,
+    /// Replaces the selection with a [soft break](CosmicEdit::with_soft_break). Single-line
+    /// widgets are submitted instead.
+    SoftBreak,
+    /// Replaces the selection with a tab with [`TabBehavior::Insert`], with Shift held it does
+    /// nothing.
+    InsertTab,
+    /// See [`CosmicEdit::duplicate_line`].
+    DuplicateLine,
+    /// See [`CosmicEdit::delete_line`].
+    DeleteLine,
+    /// Moves the lines with the cursor or selection one line up.
+    MoveLinesUp,
+    /// Moves the lines with the cursor or selection one line down.
+    MoveLinesDown,
+    /// See [`CosmicEdit::set_overwrite`].
+    ToggleOverwrite,
+    /// Opens the context menu at the caret for keyboard users. Tab moves through its items,
+    /// Escape closes it without touching the selection and the widget gets its focus back.
+    OpenContextMenu,
+    ClearSelection,
+    SelectAll,
+    Undo,
+    Redo,
+    /// The app's own command. Edits should go through [`CosmicEdit::change`] to be undone as one
+    /// step, the layout is invalidated afterwards.
+    Custom(CustomCommand<L>),
+}
+
+impl<L: LayoutMode> EditorCommand<L> {
+    /// Whether it can change the text, so it's skipped in [`Interactivity::ReadOnly`] widgets.
+    /// The app's own commands are left to check [`CosmicEdit::interactivity`] themselves.
+    fn mutates(&self) -> bool {
+        !matches!(
+            self,
+            Self::Motion(_)
+                | Self::OpenContextMenu
+                | Self::ClearSelection
+                | Self::SelectAll
+                | Self::Custom(_)
+        )
+    }
+}
+
+impl<L: LayoutMode> Clone for EditorCommand<L> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Motion(motion) => Self::Motion(*motion),
+            Self::DeleteBackward => Self::DeleteBackward,
+            Self::DeleteForward => Self::DeleteForward,
+            Self::DeleteWordBackward => Self::DeleteWordBackward,
+            Self::DeleteWordForward => Self::DeleteWordForward,
+            Self::DeleteToEndOfLine => Self::DeleteToEndOfLine,
+            Self::Newline => Self::Newline,
+            Self::SoftBreak => Self::SoftBreak,
+            Self::InsertTab => Self::InsertTab,
+            Self::DuplicateLine => Self::DuplicateLine,
+            Self::DeleteLine => Self::DeleteLine,
+            Self::MoveLinesUp => Self::MoveLinesUp,
+            Self::MoveLinesDown => Self::MoveLinesDown,
+            Self::ToggleOverwrite => Self::ToggleOverwrite,
+            Self::OpenContextMenu => Self::OpenContextMenu,
+            Self::ClearSelection => Self::ClearSelection,
+            Self::SelectAll => Self::SelectAll,
+            Self::Undo => Self::Undo,
+            Self::Redo => Self::Redo,
+            Self::Custom(command) => Self::Custom(Rc::clone(command)),
+        }
+    }
+}
+
+/// Maps keys to the [`EditorCommand`]s they run, see [`CosmicEdit::keymap_mut`].
+///
+/// Bindings match like egui's [`Modifiers::matches_logically`], so Shift and Alt may be held on
+/// top of a binding's modifiers. The binding asking for the most modifiers wins, then the one
+/// bound last, e.g. `Modifiers::CTRL` over `Modifiers::COMMAND` outside of macOS.
+pub struct Keymap<L: LayoutMode> {
+    bindings: Vec<(Key, Modifiers, EditorCommand<L>)>,
+}
+
+impl<L: LayoutMode> Keymap<L> {
+    /// A keymap without any bindings, typed text and clipboard shortcuts still work.
+    pub fn empty() -> Self {
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Binds `key` with exactly `modifiers`, replacing what was bound to them before.
+    pub fn bind(&mut self, key: Key, modifiers: Modifiers, command: EditorCommand<L>) -> &mut Self {
+        self.unbind(key, modifiers);
+        self.bindings.push((key, modifiers, command));
+        self
+    }
+
+    pub fn unbind(&mut self, key: Key, modifiers: Modifiers) -> &mut Self {
+        self.bindings.retain(|(bound_key, bound_modifiers, _)| {
+            (*bound_key, *bound_modifiers) != (key, modifiers)
+        });
+        self
+    }
+
+    /// What pressing `key` while holding `modifiers` runs.
+    pub fn command(&self, key: Key, modifiers: Modifiers) -> Option<&EditorCommand<L>> {
+        self.bindings
+            .iter()
+            .filter(|(bound_key, bound_modifiers, _)| {
+                *bound_key == key && modifiers.matches_logically(*bound_modifiers)
+            })
+            .max_by_key(|(_, bound_modifiers, _)| {
+                let Modifiers {
+                    alt,
+                    ctrl,
+                    shift,
+                    mac_cmd,
+                    command,
+                } = *bound_modifiers;
+                [alt, shift, ctrl || mac_cmd || command]
+                    .into_iter()
+                    .filter(|held| *held)
+                    .count()
+            })
+            .map(|(_, _, command)| command)
+    }
+}
+
+impl<L: LayoutMode> Default for Keymap<L> {
+    /// The arrows, Home, End, PageUp and PageDown move the cursor, Command doesn't change them
+    /// except for Home and End going to the start and end of the text. Ctrl (Option on macOS)
+    /// makes Left, Right, Backspace and Delete work on words like in egui's `TextEdit`, Alt+Up
+    /// and Alt+Down move lines, Command+Shift+D and Command+Shift+K duplicate and delete them.
+    /// Then there's Enter, Shift+Enter, Tab, Insert, Escape, Shift+F10 to open the context menu,
+    /// Command+A to select all, Command+Z to undo and Command+Shift+Z to redo. egui 0.28 doesn't
+    /// report the Menu key, so it can't be bound.
+    fn default() -> Self {
+        let motions = [
+            (Key::ArrowLeft, Motion::Left),
+            (Key::ArrowRight, Motion::Right),
+            (Key::ArrowUp, Motion::Up),
+            (Key::ArrowDown, Motion::Down),
+            (Key::Home, Motion::Home),
+            (Key::End, Motion::End),
+            (Key::PageUp, Motion::PageUp),
+            (Key::PageDown, Motion::PageDown),
+        ];
+        let mut keymap = Self::empty();
+        for (key, motion) in motions {
+            keymap
+                .bind(key, Modifiers::NONE, EditorCommand::Motion(motion))
+                .bind(key, Modifiers::COMMAND, EditorCommand::Motion(motion));
+        }
+        keymap
+            .bind(
+                Key::Backspace,
+                Modifiers::NONE,
+                EditorCommand::DeleteBackward,
+            )
+            .bind(
+                Key::Backspace,
+                Modifiers::COMMAND,
+                EditorCommand::DeleteBackward,
+            )
+            .bind(Key::Delete, Modifiers::NONE, EditorCommand::DeleteForward)
+            .bind(
+                Key::Delete,
+                Modifiers::COMMAND,
+                EditorCommand::DeleteForward,
+            );
+        for word_modifier in [Modifiers::CTRL, Modifiers::ALT] {
+            keymap
+                .bind(
+                    Key::ArrowLeft,
+                    word_modifier,
+                    EditorCommand::Motion(Motion::LeftWord),
+                )
+                .bind(
+                    Key::ArrowRight,
+                    word_modifier,
+                    EditorCommand::Motion(Motion::RightWord),
+                )
+                .bind(
+                    Key::Backspace,
+                    word_modifier,
+                    EditorCommand::DeleteWordBackward,
+                )
+                .bind(Key::Delete, word_modifier, EditorCommand::DeleteWordForward);
+        }
+        keymap
+            .bind(
+                Key::Home,
+                Modifiers::COMMAND,
+                EditorCommand::Motion(Motion::BufferStart),
+            )
+            .bind(
+                Key::End,
+                Modifiers::COMMAND,
+                EditorCommand::Motion(Motion::BufferEnd),
+            )
+            .bind(Key::ArrowUp, Modifiers::ALT, EditorCommand::MoveLinesUp)
+            .bind(Key::ArrowDown, Modifiers::ALT, EditorCommand::MoveLinesDown)
+            .bind(
+                Key::D,
+                Modifiers::COMMAND | Modifiers::SHIFT,
+                EditorCommand::DuplicateLine,
+            )
+            .bind(
+                Key::K,
+                Modifiers::COMMAND | Modifiers::SHIFT,
+                EditorCommand::DeleteLine,
+            )
+            .bind(Key::Enter, Modifiers::NONE, EditorCommand::Newline)
+            .bind(Key::Enter, Modifiers::COMMAND, EditorCommand::Newline)
+            .bind(Key::Enter, Modifiers::SHIFT, EditorCommand::SoftBreak)
+            .bind(Key::Tab, Modifiers::NONE, EditorCommand::InsertTab)
+            .bind(Key::Insert, Modifiers::NONE, EditorCommand::ToggleOverwrite)
+            .bind(Key::F10, Modifiers::SHIFT, EditorCommand::OpenContextMenu)
+            .bind(Key::Escape, Modifiers::NONE, EditorCommand::ClearSelection)
+            .bind(Key::A, Modifiers::COMMAND, EditorCommand::SelectAll)
+            .bind(Key::Z, Modifiers::COMMAND, EditorCommand::Undo)
+            .bind(
+                Key::Z,
+                Modifiers::COMMAND | Modifiers::SHIFT,
+                EditorCommand::Redo,
+            );
+        keymap
+    }
+}
+
+/// Every widget gets its own id, so its focus doesn't depend on where it's shown in the `Ui`.
+fn next_widget_id() -> Id {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    Id::new((
+        "egui_cosmic_text",
+        NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+    ))
+}
+
+/// [`Ui::allocate_painter`] with a given id instead of the next auto id.
+fn allocate_painter(ui: &mut Ui, id: Id, desired_size: Vec2, sense: Sense) -> (Response, Painter) {
+    let (_, rect) = ui.allocate_space(desired_size);
+    let resp = ui.interact(rect, id, sense);
+    let painter = ui.painter().with_clip_rect(ui.clip_rect().intersect(rect));
+    (resp, painter)
+}
+
+/// The painter's visible rows in physical pixels relative to `min_pos`, where the text starts.
+fn visible_y_range(painter: &Painter, min_pos: Pos2, pixels_per_point: f32) -> Phys<Rangef> {
+    let clip_y_range = painter.clip_rect().y_range();
+    let [min, max] = [clip_y_range.min, clip_y_range.max]
+        .map(|y| *to_physical(Logical(y - min_pos.y), pixels_per_point));
+    Phys(Rangef::new(min, max))
+}
+
+/// Turns `\r\n` and lone `\r` into `\n`, cosmic-text only splits inserted lines on `\n`.
+fn normalize_line_endings(string: String) -> String {
+    match string.contains('\r') {
+        true => string.replace("\r\n", "\n").replace('\r', "\n"),
+        false => string,
+    }
+}
+
+/// Gives `attrs` the `to` features if it uses the `from` features.
+fn replace_font_features<'a>(
+    attrs: Attrs<'a>,
+    from: &FontFeatures,
+    to: &FontFeatures,
+) -> Attrs<'a> {
+    match attrs.font_features == *from {
+        true => attrs.font_features(to.clone()),
+        false => attrs,
+    }
+}
+
+/// [`replace_font_features`] for the defaults and every span of `list`.
+fn replace_list_font_features(
+    list: &AttrsList,
+    from: &FontFeatures,
+    to: &FontFeatures,
+) -> AttrsList {
+    let mut replaced = AttrsList::new(&replace_font_features(list.defaults(), from, to));
+    for (range, attrs) in list.spans_iter() {
+        replaced.add_span(
+            range.clone(),
+            &replace_font_features(attrs.as_attrs(), from, to),
+        );
+    }
+    replaced
+}
+
+/// The family text without a family of its own uses, [`Family::SansSerif`] if the widget has none.
+fn widget_family(name: Option<&str>) -> Family<'_> {
+    name.map_or(Family::SansSerif, Family::Name)
+}
+
+/// Gives `attrs` the `to` family if it uses the `from` family.
+fn replace_family<'a>(attrs: Attrs<'a>, from: Family<'a>, to: Family<'a>) -> Attrs<'a> {
+    match attrs.family == from {
+        true => attrs.family(to),
+        false => attrs,
+    }
+}
+
+/// `attrs` with the widget's `family` and `features` where it uses the defaults.
+fn with_widget_font<'a>(
+    attrs: Attrs<'a>,
+    family: Family<'a>,
+    features: &FontFeatures,
+) -> Attrs<'a> {
+    let attrs = replace_font_features(attrs, &FontFeatures::default(), features);
+    replace_family(attrs, Family::SansSerif, family)
+}
+
+/// [`replace_family`] for the defaults and every span of `list`.
+fn replace_list_family(list: &AttrsList, from: Family, to: Family) -> AttrsList {
+    let mut replaced = AttrsList::new(&replace_family(list.defaults(), from, to));
+    for (range, attrs) in list.spans_iter() {
+        replaced.add_span(range.clone(), &replace_family(attrs.as_attrs(), from, to));
+    }
+    replaced
+}
+
+/// `list` with `f` applied to the attributes of the bytes in `range`, spans are split at its
+/// ends.
+fn map_list_range(list: &AttrsList, range: Range<usize>, f: impl Fn(Attrs) -> Attrs) -> AttrsList {
+    let mut bounds = vec![range.start, range.end];
+    for (span, _) in list.spans_iter() {
+        bounds.extend(
+            [span.start, span.end]
+                .into_iter()
+                .filter(|x| range.contains(x)),
+        );
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut mapped = list.clone();
+    for pair in bounds.windows(2) {
+        mapped.add_span(pair[0]..pair[1], &f(list.get_span(pair[0])));
+    }
+    mapped
+}
+
+/// Joins all lines into the first one with a space in between, for single-line widgets.
+fn join_lines(buf: &mut Buffer) {
+    if buf.lines.len() < 2 {
+        return;
+    }
+    let mut lines = std::mem::take(&mut buf.lines).into_iter();
+    let mut joined = lines.next().unwrap();
+    for line in lines {
+        let space = BufferLine::new(
+            " ",
+            LineEnding::default(),
+            AttrsList::new(&joined.attrs_list().defaults()),
+            Shaping::Advanced,
+        );
+        joined.append(space);
+        joined.append(line);
+    }
+    buf.lines = vec![joined];
+}
+
+/// The text of `lines` with a newline after each one, and their attributes.
+fn lines_text(buf: &Buffer, lines: RangeInclusive<usize>) -> (String, AttrsList) {
+    let lines = &buf.lines[lines];
+    let mut text = String::new();
+    let mut attrs_list = AttrsList::new(&lines[0].attrs_list().defaults());
+    for line in lines {
+        let offset = text.len();
+        text.push_str(line.text());
+        text.push('\n');
+        if line.attrs_list().defaults() != attrs_list.defaults() {
+            attrs_list.add_span(offset..text.len(), &line.attrs_list().defaults());
+        }
+        for (range, attrs) in line.attrs_list().spans_iter() {
+            attrs_list.add_span(range.start + offset..range.end + offset, &attrs.as_attrs());
+        }
+    }
+    (text, attrs_list)
+}
+
+/// Marks the break after `line`.
+///
+/// The line after a soft break is marked with [`LineEnding::None`], which cosmic-text otherwise
+/// only gives the last line. Marking the line after the break keeps it right when lines are
+/// joined, as the joined line keeps the first line's ending.
+fn mark_line_break(buf: &mut Buffer, line: usize, line_break: LineBreak) {
+    if let Some(next) = buf.lines.get_mut(line + 1) {
+        next.set_ending(match line_break {
+            LineBreak::Paragraph => LineEnding::Lf,
+            LineBreak::Soft => LineEnding::None,
+        });
+    }
+}
+
+/// Whether any text is marked with [`Attrs::metadata`], e.g. a link.
+fn has_metadata(buf: &Buffer) -> bool {
+    buf.lines.iter().any(|line| {
+        let attrs_list = line.attrs_list();
+        attrs_list.defaults().metadata != 0
+            || attrs_list
+                .spans_iter()
+                .any(|(_, attrs)| attrs.metadata != 0)
+    })
+}
+
+/// The byte length of each line, to turn change items into [`TextChange`]s.
+fn line_lens(buf: &Buffer) -> Vec<usize> {
+    buf.lines.iter().map(|x| x.text().len()).collect()
+}
+
+fn line_break_after(buf: &Buffer, line: usize) -> Option<LineBreak> {
+    buf.lines.get(line + 1).map(|next| match next.ending() {
+        LineEnding::None => LineBreak::Soft,
+        _ => LineBreak::Paragraph,
+    })
+}
+
+/// Text from cosmic-text leaves the last line without an ending, which would read as a soft break.
+fn unmark_last_line(buf: &mut Buffer) {
+    if let Some(last) = buf
+        .lines
+        .last_mut()
+        .filter(|x| x.ending() == LineEnding::None)
+    {
+        last.set_ending(LineEnding::Lf);
+    }
+}
+
+/// Splits lines at `soft_break`, which is left out, and marks the new lines as soft breaks.
+fn split_soft_breaks(buf: &mut Buffer, soft_break: &str) {
+    if !splits_soft_breaks(soft_break) {
+        return;
+    }
+    let mut line_i = 0;
+    while line_i < buf.lines.len() {
+        let line = &mut buf.lines[line_i];
+        if let Some(index) = line.text().find(soft_break) {
+            let mut rest = line.split_off(index + soft_break.len());
+            line.split_off(index);
+            rest.set_ending(LineEnding::None);
+            buf.lines.insert(line_i + 1, rest);
+        }
+        line_i += 1;
+    }
+}
+
+/// A `\n` soft break can't be told apart from the line breaks in text.
+fn splits_soft_breaks(soft_break: &str) -> bool {
+    !soft_break.is_empty() && soft_break != "\n"
+}
+
+/// Inserts a line break at the cursor.
+fn insert_line_break(editor: &mut Editor, line_break: LineBreak) {
+    editor.insert_string("\n", None);
+    let line = editor.cursor().line - 1;
+    editor.with_buffer_mut(|x| mark_line_break(x, line, line_break));
+}
+
+fn line_texts(buf: &Buffer) -> Vec<String> {
+    buf.lines.iter().map(|x| x.text().to_string()).collect()
+}
+
+/// Escapes the hidden controls in the buffer like [`ControlChars::EscapeVisible`] does in inserted
+/// text, each placeholder taking its control's attributes. Returns where the placeholders are.
+fn escape_lines(buf: &mut Buffer) -> Vec<Range<Cursor>> {
+    let mut escapes = Vec::new();
+    for (line_i, line) in buf.lines.iter_mut().enumerate() {
+        if !line.text().contains(is_hidden_control) {
+            continue;
+        }
+        let attrs = line.attrs_list().clone();
+        let mut escaped = String::with_capacity(line.text().len());
+        let mut escaped_attrs = AttrsList::new(&attrs.defaults());
+        for run in span_runs(line.text(), &attrs) {
+            let start = escaped.len();
+            let (text, placeholders) = escape_controls(&line.text()[run.clone()]);
+            escaped.push_str(&text);
+            escapes.extend(
+                placeholders.into_iter().map(|x| {
+                    Cursor::new(line_i, start + x.start)..Cursor::new(line_i, start + x.end)
+                }),
+            );
+            let span = attrs.get_span(run.start);
+            if span != attrs.defaults() {
+                escaped_attrs.add_span(start..escaped.len(), &span);
             }
         }
-    };
+        let ending = line.ending();
+        line.set_text(escaped, ending, escaped_attrs);
+    }
+    escapes
 }
 
-macro_rules! egui_key_to_motion {
-    ($( $key:pat => $action:expr ),* $(,)?) => {
-        egui_key_to_cosmic_action! {
-            egui_key_to_motion;
-            $(
-                $key => Action::Motion($action)
-            ),*
+/// The ranges of `text` that each have the same attributes in `attrs`. A `\r\n` is kept in one
+/// range so it's still converted to a single line break.
+fn span_runs(text: &str, attrs: &AttrsList) -> Vec<Range<usize>> {
+    let spans = attrs
+        .spans_iter()
+        .flat_map(|(range, _)| [range.start, range.end]);
+    let mut bounds = [0, text.len()]
+        .into_iter()
+        .chain(spans.filter(|&x| x < text.len() && text.is_char_boundary(x)))
+        .map(|x| match text.get(x.saturating_sub(1)..x + 1) {
+            Some("\r\n") => x + 1,
+            _ => x,
+        })
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds.windows(2).map(|x| x[0]..x[1]).collect()
+}
+
+/// Inserts at the cursor, replacing the selection, with each `soft_break` in `text` turned into a
+/// soft line break. Returns where the escaped `placeholders`, byte ranges of `text`, ended up.
+fn insert_with_line_breaks(
+    editor: &mut Editor,
+    text: &str,
+    mut attrs: Option<AttrsList>,
+    placeholders: &[Range<usize>],
+    soft_break: &str,
+    single_line: bool,
+) -> Vec<Range<Cursor>> {
+    editor.delete_selection();
+    let parts = match splits_soft_breaks(soft_break) {
+        true => text.split(soft_break).collect(),
+        false => vec![text],
+    };
+    let mut escapes = Vec::with_capacity(placeholders.len());
+    let mut offset = 0;
+    for (i, part) in parts.into_iter().enumerate() {
+        if i > 0 {
+            attrs = attrs.map(|mut x| x.split_off(soft_break.len()));
+            offset += soft_break.len();
+            match single_line {
+                true => editor.insert_string(" ", None),
+                false => insert_line_break(editor, LineBreak::Soft),
+            }
+        }
+        // The spans are relative to the part they're inserted with
+        let part_attrs = attrs.as_mut().map(|x| {
+            let rest = x.split_off(part.len());
+            std::mem::replace(x, rest)
+        });
+        let part_offset = offset;
+        offset += part.len();
+        if part.is_empty() {
+            continue;
         }
+        let part = match single_line {
+            true => Cow::Owned(part.replace('\n', " ")),
+            false => Cow::Borrowed(part),
+        };
+        // New lines take the ending of the line they're inserted into
+        let start = editor.cursor();
+        editor.insert_string(&part, part_attrs);
+        let end = editor.cursor().line;
+        editor.with_buffer_mut(|x| {
+            (start.line..end).for_each(|line| mark_line_break(x, line, LineBreak::Paragraph))
+        });
+        let part_placeholders = placeholders
+            .iter()
+            .filter(|x| x.start >= part_offset && x.end <= offset)
+            .map(|x| x.start - part_offset..x.end - part_offset)
+            .collect::<Vec<_>>();
+        escapes.extend(placeholder_cursors(start, &part, &part_placeholders));
+    }
+    escapes
+}
+
+/// Like a fresh widget, an editor without a selection gets an empty one at the cursor.
+fn normalize_selection(editor: &mut Editor) {
+    if editor.selection() == Selection::None {
+        editor.set_selection(Selection::Normal(editor.cursor()));
+    }
+}
+
+/// Where the caret and the selection's anchor end up after `items` are applied.
+fn transform_caret((cursor, selection): Caret, items: &[ChangeItem]) -> Caret {
+    let transform = |cursor| {
+        items.iter().fold(cursor, |cursor, item| {
+            history::transform_cursor(cursor, item)
+        })
     };
+    (transform(cursor), map_anchor(selection, transform))
+}
+
+/// Moves the escaped controls along with `items`, see [`CosmicEdit::set_control_chars`].
+fn move_escapes(escapes: &mut [Range<Cursor>], items: &[ChangeItem]) {
+    for escape in escapes {
+        *escape = items.iter().fold(escape.clone(), |range, item| {
+            history::transform_range(range, item)
+        });
+    }
 }
 
-egui_key_to_motion! {
-    Key::ArrowLeft => Motion::Left,
-    Key::ArrowRight => Motion::Right,
-    Key::ArrowUp => Motion::Up,
-    Key::ArrowDown => Motion::Down,
-    Key::Home => Motion::Home,
-    Key::End => Motion::End,
-    // These operations are really slow and idk why
-    //Key::PageUp => Motion::PageUp,
-    //Key::PageDown => Motion::PageDown
+/// Keeps the escaped controls that are still whole placeholders, sorted.
+fn retain_escapes(buf: &Buffer, escapes: &mut Vec<Range<Cursor>>) {
+    escapes.retain(|escape| {
+        escape.start.line == escape.end.line
+            && buf.lines.get(escape.start.line).is_some_and(|line| {
+                line.text()
+                    .get(escape.start.index..escape.end.index)
+                    .and_then(placeholder_control)
+                    .is_some()
+            })
+    });
+    escapes.sort_unstable_by_key(|x| (x.start.line, x.start.index));
+    escapes.dedup();
 }
 
-egui_key_to_cosmic_action! {
-    egui_key_to_non_motion;
-    Key::Escape => Action::Escape,
-    Key::Enter => Action::Enter,
-    Key::Backspace => Action::Backspace,
-    Key::Delete => Action::Delete
+/// The byte ranges of the escaped controls on `line`, relative to `from`.
+fn line_escapes(
+    escapes: &[Range<Cursor>],
+    line: usize,
+    from: usize,
+) -> impl Iterator<Item = Range<usize>> + '_ {
+    let first = escapes.partition_point(|x| x.start.line < line);
+    escapes[first..]
+        .iter()
+        .take_while(move |x| x.start.line == line)
+        .filter(move |x| x.start.index >= from)
+        .map(move |x| x.start.index - from..x.end.index - from)
 }
 
-fn egui_key_to_cosmic_action(key: Key) -> Option<Action> {
-    egui_key_to_motion(key).or_else(|| egui_key_to_non_motion(key))
+/// [`text_between`] with the escaped controls turned back.
+fn unescaped_text_between(
+    buf: &Buffer,
+    escapes: &[Range<Cursor>],
+    start: Cursor,
+    end: Cursor,
+) -> String {
+    let lines = &buf.lines[start.line..=end.line];
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let text = line.text();
+            let from = if i == 0 { start.index } else { 0 };
+            let to = if i == lines.len() - 1 {
+                end.index
+            } else {
+                text.len()
+            };
+            let escapes = line_escapes(escapes, start.line + i, from)
+                .filter(|x| x.end <= to - from)
+                .collect::<Vec<_>>();
+            unescape_controls(&text[from..to], &escapes)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-fn apply_history_action_to_editor(action: cosmic_undo_2::Action<&Change>, editor: &mut Editor) {
-    match action {
-        cosmic_undo_2::Action::Do(x) => {
-            editor.apply_change(x);
+/// Where the placeholders at the sorted byte ranges of `text` end up once it's inserted at `at`.
+fn placeholder_cursors(
+    at: Cursor,
+    text: &str,
+    placeholders: &[Range<usize>],
+) -> Vec<Range<Cursor>> {
+    let (mut cursor, mut index) = (at, 0);
+    let mut cursors = Vec::with_capacity(placeholders.len());
+    for range in placeholders {
+        if range.start < index || text.get(range.clone()).is_none() {
+            continue;
         }
+        cursor = history::insert_end(cursor, &text[index..range.start]);
+        index = range.start;
+        cursors.push(cursor..history::insert_end(cursor, &text[range.clone()]));
+    }
+    cursors
+}
+
+/// The byte ranges of `text`'s chars, with each of the sorted `placeholders` as one, and whether
+/// they're a placeholder.
+fn placeholder_units(text: &str, placeholders: &[Range<usize>]) -> Vec<(Range<usize>, bool)> {
+    let mut placeholders = placeholders.iter().peekable();
+    let mut units = Vec::with_capacity(text.len());
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        let unit = match placeholders.next_if(|x| x.start == index) {
+            Some(placeholder) => (placeholder.clone(), true),
+            None => (index..index + c.len_utf8(), false),
+        };
+        index = unit.0.end;
+        units.push(unit);
+    }
+    units
+}
+
+/// Cuts `text`, the start of text with escaped `placeholders`, short of a placeholder it ends
+/// inside of, and keeps only the placeholders within it.
+fn whole_placeholders<'a>(text: &'a str, placeholders: &mut Vec<Range<usize>>) -> &'a str {
+    let end = placeholders
+        .iter()
+        .find(|x| x.end > text.len())
+        .map_or(text.len(), |x| x.start.min(text.len()));
+    placeholders.retain(|x| x.end <= end);
+    &text[..end]
+}
+
+fn map_anchor(selection: Selection, f: impl FnOnce(Cursor) -> Cursor) -> Selection {
+    match selection {
+        Selection::Normal(anchor) => Selection::Normal(f(anchor)),
+        Selection::Line(anchor) => Selection::Line(f(anchor)),
+        Selection::Word(anchor) => Selection::Word(f(anchor)),
+        Selection::None => Selection::None,
+    }
+}
+
+fn is_valid_cursor(buf: &Buffer, cursor: Cursor) -> bool {
+    buf.lines
+        .get(cursor.line)
+        .is_some_and(|line| line.text().is_char_boundary(cursor.index))
+}
+
+/// The text between two valid cursors, with lines joined like cosmic-text records deletions.
+fn text_between(buf: &Buffer, start: Cursor, end: Cursor) -> String {
+    let lines = &buf.lines[start.line..=end.line];
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let text = line.text();
+            let from = if i == 0 { start.index } else { 0 };
+            let to = if i == lines.len() - 1 {
+                end.index
+            } else {
+                text.len()
+            };
+            &text[from..to]
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether `item` still fits the buffer, cosmic-text panics on out of bounds cursors.
+fn is_valid_change_item(buf: &Buffer, item: &ChangeItem) -> bool {
+    if !is_valid_cursor(buf, item.start) {
+        return false;
+    }
+    item.insert
+        || (is_valid_cursor(buf, item.end)
+            && item.start <= item.end
+            && text_between(buf, item.start, item.end) == item.text)
+}
+
+/// The change `action` applies, reversed for undo.
+fn history_action_change(action: cosmic_undo_2::Action<&Change>) -> Cow<'_, Change> {
+    match action {
+        cosmic_undo_2::Action::Do(x) => Cow::Borrowed(x),
         cosmic_undo_2::Action::Undo(x) => {
             let mut x = x.clone();
             x.reverse();
-            editor.apply_change(&x);
+            Cow::Owned(x)
         }
     }
 }
 
+/// Applies the items one by one, stopping at the first one that doesn't match the buffer.
+///
+/// Returns whether the whole change was applied.
+fn apply_history_action_to_editor(
+    action: cosmic_undo_2::Action<&Change>,
+    editor: &mut Editor,
+) -> bool {
+    history_action_change(action)
+        .items
+        .iter()
+        .all(|item| apply_change_item(editor, item))
+}
+
+/// Applies `item` if it still fits the buffer, recorded if a change was started.
+fn apply_change_item(editor: &mut Editor, item: &ChangeItem) -> bool {
+    if !editor.with_buffer(|x| is_valid_change_item(x, item)) {
+        return false;
+    }
+    if item.insert {
+        let cursor = insert_at(editor, item.start, &item.text, None);
+        editor.set_cursor(cursor);
+        // Which kind of break the inserted ones were isn't recorded
+        let start = item.start.line;
+        editor.with_buffer_mut(|x| {
+            (start..cursor.line).for_each(|line| mark_line_break(x, line, LineBreak::Paragraph))
+        });
+    } else {
+        editor.delete_range(item.start, item.end);
+        editor.set_cursor(item.start);
+    }
+    true
+}
+
+/// Inserts `text` at `cursor` like [`Edit::insert_at`] and returns where it ends.
+///
+/// cosmic-text strips a control char off the end of inserted text, e.g. a lone tab, and records
+/// the insert as ending before it. The char is put back into the line here, [`CosmicEdit::change`]
+/// moves the recorded end past it.
+fn insert_at(editor: &mut Editor, cursor: Cursor, text: &str, attrs: Option<AttrsList>) -> Cursor {
+    let stripped = text.chars().last().filter(|&c| c != '\n' && c.is_control());
+    let stripped_attrs = stripped
+        .zip(attrs.as_ref())
+        .map(|(c, attrs)| AttrsList::new(&attrs.get_span(text.len() - c.len_utf8())));
+    let mut end = editor.insert_at(cursor, text, attrs);
+    if let Some(c) = stripped {
+        editor.with_buffer_mut(|x| {
+            let line = &mut x.lines[end.line];
+            let after = line.split_off(end.index);
+            let attrs = stripped_attrs.unwrap_or_else(|| {
+                AttrsList::new(&line.attrs_list().get_span(end.index.saturating_sub(1)))
+            });
+            let ending = line.ending();
+            line.append(BufferLine::new(
+                c.to_string(),
+                ending,
+                attrs,
+                Shaping::Advanced,
+            ));
+            line.append(after);
+        });
+        end.index += c.len_utf8();
+    }
+    end
+}
+
+/// Applies `delta` if its range fits the buffer, returns the items it was recorded as.
+fn apply_delta(editor: &mut Editor, delta: &TextDelta) -> Option<Vec<ChangeItem>> {
+    let (start, end) = delta.range();
+    let mut items = Vec::new();
+    if start != end {
+        let text = editor.with_buffer(|x| {
+            (is_valid_cursor(x, start) && is_valid_cursor(x, end) && start < end)
+                .then(|| text_between(x, start, end))
+        })?;
+        items.push(ChangeItem {
+            start,
+            end,
+            text,
+            insert: false,
+        });
+    }
+    if !delta.text().is_empty() {
+        items.push(ChangeItem {
+            start,
+            end: history::insert_end(start, delta.text()),
+            text: delta.text().to_string(),
+            insert: true,
+        });
+    }
+    items
+        .iter()
+        .all(|item| apply_change_item(editor, item))
+        .then_some(items)
+}
+
 #[derive(Debug, Default)]
 pub struct EditorActions {
     pub scroll_to_cursor: bool,
@@ -466,14 +1476,14 @@ impl<F: FnOnce() -> Option<String>> ContextMenu for DefaultContextMenu<F> {
         ui.set_min_width(100.0);
         let mut scroll_to_cursor = false;
         let mut focus = false;
-        let input = editor.interactivity().input();
+        let input = editor.interactivity().mutation();
         if input && ui.button("Cut").clicked() && editor.cut(ui, font_system) {
             scroll_to_cursor = true;
             focus = true;
-            ui.close_menu();
+            compat::close_menu(ui);
         }
-        if ui.button("Copy").clicked() && editor.copy(ui) {
-            ui.close_menu();
+        if ui.button("Copy").clicked() && editor.copy(ui).is_some() {
+            compat::close_menu(ui);
         }
         if input {
             if ui.button("Paste").clicked() {
@@ -482,19 +1492,21 @@ impl<F: FnOnce() -> Option<String>> ContextMenu for DefaultContextMenu<F> {
                     editor.insert_string(clipboard_text, font_system);
                     scroll_to_cursor = true;
                     focus = true;
-                    ui.close_menu();
+                    compat::close_menu(ui);
                 }
             }
             ui.separator();
-            if ui.button("Undo").clicked() && editor.undo() {
+            let undo = ui.add_enabled(editor.can_undo(), Button::new("Undo"));
+            if undo.clicked() && editor.undo() {
                 scroll_to_cursor = true;
                 focus = true;
-                ui.close_menu();
+                compat::close_menu(ui);
             }
-            if ui.button("Redo").clicked() && editor.redo() {
+            let redo = ui.add_enabled(editor.can_redo(), Button::new("Redo"));
+            if redo.clicked() && editor.redo() {
                 scroll_to_cursor = true;
                 focus = true;
-                ui.close_menu();
+                compat::close_menu(ui);
             }
         }
         EditorActions {
@@ -510,7 +1522,7 @@ impl<F: FnOnce() -> Option<String>> ContextMenu for DefaultContextMenu<F> {
 
 public_enum! {
     HoverStrategy {
-        /// Shows the hover icon only when hovering the text's bounding box.
+        /// Shows the hover icon only over the text's glyphs, line by line, leaving out whitespace.
         BoundingBox,
         /// Shows the hover icon when hovering on the widget.
         Widget,
@@ -520,38 +1532,417 @@ public_enum! {
 }
 
 impl HoverStrategy {
-    fn calculate_width(&self, glyphs: &[LayoutGlyph]) -> Option<f32> {
+    /// The icon over `run` at `pos`, relative to the buffer.
+    fn hover_icon(&self, run: &LayoutRun, pos: Phys<Pos2>, spacing: Spacing) -> Option<CursorIcon> {
         match self {
-            HoverStrategy::BoundingBox => glyphs.last().map(|x| x.x + x.w),
+            HoverStrategy::BoundingBox => ink_x_range(run, spacing)
+                .filter(|x| x.contains(pos.x))
+                .map(|_| CursorIcon::Text),
             _ => None,
         }
     }
 }
 
-enum ScrollState {
-    Idle,
-    Scrolling,
-    FinishedLastFrame,
+public_enum! {
+    /// What happens to inserted text with chars the [filter](CosmicEdit::set_char_filter)
+    /// rejects.
+    #[derive(Default)]
+    RejectedChars {
+        /// They're left out, the rest is inserted.
+        #[default]
+        Drop,
+        /// Nothing is inserted.
+        RejectInsert
+    }
 }
 
-pub struct CosmicEdit<L: LayoutMode> {
-    editor: Editor<'static>,
-    interactivity: Interactivity,
-    hover_strategy: HoverStrategy,
-    layout_mode: L,
-    cursor_style: CursorStyle,
-    selection_texture: SelectionTexture,
-    commands: Commands<Change>,
-    last_click: Option<LastClick>,
-    scroll_state: ScrollState,
-    dragging: bool,
-    frame_changed: bool,
-    last_updated_time: f64
+public_enum! {
+    /// Where the caret is painted relative to the glyphs.
+    #[derive(Default)]
+    CaretLayer {
+        /// Painted over the glyphs, e.g. covering color emoji.
+        #[default]
+        AboveGlyphs,
+        /// Painted before the glyphs so they are drawn on top of it.
+        BelowGlyphs
+    }
 }
 
-// TODO: Docs
-impl<L: LayoutMode> CosmicEdit<L> {
-    const BLINK_INTERVAL_IN_SECS: f32 = 0.5;
+public_enum! {
+    /// What Home and End move to on wrapped lines.
+    #[derive(Default)]
+    LineMotions {
+        /// The start and end of the visual row, like most platform editors.
+        #[default]
+        VisualRows,
+        /// The start and end of the whole buffer line.
+        Paragraphs
+    }
+}
+
+public_enum! {
+    /// How the caret is drawn in overwrite mode, see [`CosmicEdit::set_overwrite`].
+    #[derive(Default)]
+    OverwriteCaret {
+        /// Covers the character the next keystroke replaces
+        #[default]
+        Block,
+        /// The same caret as when inserting
+        Bar
+    }
+}
+
+public_enum! {
+    /// The kind of break between two buffer lines, see [`CosmicEdit::line_break`].
+    #[derive(Default)]
+    LineBreak {
+        /// Enter, and every line break in text that doesn't come from a soft break.
+        #[default]
+        Paragraph,
+        /// Shift+Enter, see [`CosmicEdit::with_soft_break`].
+        Soft
+    }
+}
+
+public_enum! {
+    /// What Tab does while the widget has focus.
+    #[derive(Default)]
+    TabBehavior {
+        /// Tab and Shift+Tab move focus to the next and previous widget, like egui's `TextEdit`.
+        #[default]
+        MoveFocus,
+        /// Tab inserts `'\t'`, Shift+Tab does nothing so focus stays in the widget.
+        Insert
+    }
+}
+
+public_enum! {
+    /// What happens to [hidden controls](crate::util::is_hidden_control) in text put into the
+    /// widget, see [`CosmicEdit::set_control_chars`].
+    #[derive(Default)]
+    ControlChars {
+        /// Kept as they are. Bidi overrides can make untrusted text look like something else.
+        #[default]
+        Allow,
+        /// Removed.
+        StripControls,
+        /// Shown as placeholders like ␀ or ⟨RLO⟩, copies turn them back into the controls. Only
+        /// placeholders the widget put there turn back, typed or pasted look-alikes stay text.
+        EscapeVisible
+    }
+}
+
+/// Drag and drop payload set when dragging a selection out of a [`CosmicEdit`].
+///
+/// See [`CosmicEdit::with_selection_drag_source`].
+#[derive(Debug, Clone)]
+pub struct SelectedText(pub String);
+
+/// What happened in a [`CosmicEdit::show`] call.
+#[derive(Debug, Clone)]
+pub struct CosmicEditOutput {
+    pub response: Response,
+    /// The text was changed by the user this frame, e.g. by typing, pasting, undoing or the
+    /// context menu, see [`CosmicEdit::changed_this_frame`].
+    pub changed: bool,
+    pub cursor: Cursor,
+    /// The caret in screen points, e.g. to anchor a popup. It isn't rotated with the text.
+    pub cursor_rect: Rect,
+    /// Not empty when set
+    pub selection: Option<(Cursor, Cursor)>,
+    /// See [`CosmicEdit::submitted`].
+    pub submitted: bool,
+    /// See [`CosmicEdit::at_char_limit`].
+    pub at_char_limit: bool,
+}
+
+/// A [`CosmicEdit`] with what it's drawn with, made by [`CosmicEdit::widget`].
+pub struct CosmicEditRef<'a, L: LayoutMode, S: BuildHasher + Default, C: ContextMenu> {
+    edit: &'a mut CosmicEdit<L>,
+    font_system: &'a mut FontSystem,
+    swash_cache: &'a mut SwashCache,
+    atlas: &'a mut TextureAtlas<S>,
+    context_menu: C,
+}
+
+impl<L: LayoutMode, S: BuildHasher + Default, C: ContextMenu> egui::Widget
+    for CosmicEditRef<'_, L, S, C>
+{
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.edit.ui(
+            ui,
+            self.font_system,
+            self.swash_cache,
+            self.atlas,
+            self.context_menu,
+        )
+    }
+}
+
+/// A read-only pane onto another [`CosmicEdit`]'s buffer with its own caret and selection.
+///
+/// Shown with [`CosmicEdit::view_ui`], e.g. for a split view. Put each pane in its own
+/// `ScrollArea` to scroll them independently.
+#[derive(Default)]
+pub struct CosmicEditView {
+    cursor: Cursor,
+    anchor: Option<Cursor>,
+    revision: u64,
+    /// In physical pixels, relative to the buffer
+    selection_rects: Vec<Phys<Rect>>,
+}
+
+impl CosmicEditView {
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    pub fn selection_bounds(&self) -> Option<(Cursor, Cursor)> {
+        let anchor = self.anchor.filter(|&anchor| anchor != self.cursor)?;
+        Some(match anchor < self.cursor {
+            true => (anchor, self.cursor),
+            false => (self.cursor, anchor),
+        })
+    }
+}
+
+/// Another user's caret and selection, drawn in their color.
+///
+/// See [`CosmicEdit::remote_selections_mut`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteSelection {
+    pub cursor: Cursor,
+    /// Where the selection started, `None` for just a caret
+    pub anchor: Option<Cursor>,
+    pub color: Color32,
+}
+
+impl RemoteSelection {
+    /// Cursors past the end of the text are moved back into it.
+    fn bounds(&self, buf: &Buffer) -> Option<(Cursor, Cursor)> {
+        let cursor = clamp_cursor(buf, self.cursor);
+        let anchor = clamp_cursor(buf, self.anchor?);
+        match anchor.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some((anchor, cursor)),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some((cursor, anchor)),
+        }
+    }
+}
+
+/// Identifies another user in a collaborative session, see [`ChangeOrigin`].
+pub type PeerId = u64;
+
+/// Who made a change passed to [`CosmicEdit::apply_change`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeOrigin {
+    /// This user, the change can be undone.
+    Local,
+    /// Another user, undo and redo leave the change alone.
+    Remote(PeerId),
+}
+
+/// Moves `cursor` back inside the buffer after the text it pointed into was removed.
+fn clamp_cursor(buf: &Buffer, cursor: Cursor) -> Cursor {
+    let last_line = buf.lines.len().saturating_sub(1);
+    let line = cursor.line.min(last_line);
+    let text = buf.lines.get(line).map_or("", |line| line.text());
+    // Past the last line is the end of the text
+    let mut index = match cursor.line > last_line {
+        true => text.len(),
+        false => cursor.index.min(text.len()),
+    };
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    // Keeps which row a cursor at a wrap belongs to
+    Cursor {
+        line,
+        index,
+        ..cursor
+    }
+}
+
+/// What the cached selection rects were computed from
+#[derive(PartialEq)]
+struct SelectionRectsKey {
+    bounds: (Cursor, Cursor),
+    revision: u64,
+    buffer_size: (Option<f32>, Option<f32>),
+    visible_y_range: Rangef,
+}
+
+/// The editor's state once a frame's input is handled, read once for the rest of the frame.
+#[derive(Clone, Copy)]
+struct FrameSnapshot {
+    cursor: Cursor,
+    /// `None` if nothing is selected, an empty selection included
+    selection_bounds: Option<(Cursor, Cursor)>,
+    metrics: Metrics,
+}
+
+impl FrameSnapshot {
+    fn new(editor: &Editor) -> Self {
+        Self {
+            cursor: editor.cursor(),
+            selection_bounds: editor
+                .selection_bounds()
+                .filter(|(start, end)| start != end),
+            metrics: editor.with_buffer(|x| x.metrics()),
+        }
+    }
+}
+
+enum ScrollState {
+    Idle,
+    Scrolling,
+    FinishedLastFrame,
+}
+
+/// See [`CosmicEdit::with_hover_icon`].
+type HoverIcon = Box<dyn Fn(&LayoutRun, Pos2) -> Option<CursorIcon>>;
+
+/// See [`CosmicEdit::set_char_filter`].
+type CharFilter = Box<dyn Fn(char) -> bool>;
+
+/// The cursor and selection, put back by undo and redo.
+type Caret = (Cursor, Selection);
+
+/// A recorded change with the caret from before and after it.
+#[derive(Clone)]
+struct HistoryStep {
+    change: Change,
+    before: Caret,
+    after: Caret,
+    /// The escaped controls before and after the change, see [`CosmicEdit::set_control_chars`]
+    escaped_before: Vec<Range<Cursor>>,
+    escaped_after: Vec<Range<Cursor>>,
+    /// [`CosmicEdit::text_replacements`] when it was recorded
+    text_replacements: usize,
+}
+
+pub struct CosmicEdit<L: LayoutMode> {
+    editor: Editor<'static>,
+    id: Id,
+    interactivity: Interactivity,
+    hover_strategy: HoverStrategy,
+    layout_mode: L,
+    cursor_style: CursorStyle,
+    style: CosmicEditStyle,
+    selection_texture: SelectionTexture,
+    commands: Commands<HistoryStep>,
+    last_click: Option<LastClick>,
+    click_metrics: ClickMetrics,
+    touch_drag: TouchDrag,
+    /// A touch press held back by [`TouchDrag::HoldToSelect`]
+    touch_press_pending: bool,
+    scroll_state: ScrollState,
+    rotation: Option<Rot90>,
+    follow_cursor: bool,
+    scroll_to_bottom: bool,
+    scroll_to_cursor: bool,
+    /// Set by [`EditorCommand::OpenContextMenu`] until the menu is opened.
+    open_context_menu: bool,
+    /// The context menu was opened from the keyboard and is still open.
+    keyboard_menu_open: bool,
+    dragging: bool,
+    frame_changed: bool,
+    last_updated_time: f64,
+    last_copy_frame: Option<u64>,
+    selection_drag_modifiers: Option<Modifiers>,
+    dragging_selection: bool,
+    /// Set by a long press on a touch screen, the selection's ends can be dragged by handles
+    showing_touch_handles: bool,
+    /// The end of the selection that stays put while the other one's handle is dragged
+    touch_handle_anchor: Option<Cursor>,
+    event_scratch: Vec<Event>,
+    /// Bumped whenever the text or its layout may have changed
+    revision: u64,
+    selection_rects_key: Option<SelectionRectsKey>,
+    /// In physical pixels, relative to the buffer
+    selection_rects: Vec<Phys<Rect>>,
+    fonts_missing: bool,
+    long_line_threshold: Option<usize>,
+    /// The wrap to restore once there are no long lines left
+    unwrapped_from: Option<Wrap>,
+    long_lines_revision: Option<u64>,
+    /// The revision lines were last shaped for before being measured
+    shaped_revision: Option<u64>,
+    skipped_history_changes: usize,
+    max_recorded_change_bytes: Option<usize>,
+    undo_group_window: Option<Duration>,
+    typing_group: Option<TypingGroup>,
+    history_barriers: usize,
+    /// Set while [`Self::change`] runs, nested calls are part of its step
+    in_change: bool,
+    char_limit: Option<usize>,
+    char_filter: Option<(CharFilter, RejectedChars)>,
+    typing_attrs: Option<AttrsOwned>,
+    /// Edits not taken yet, `None` unless changes are tracked
+    text_changes: Option<Vec<TextChange>>,
+    line_motions: LineMotions,
+    tab_behavior: TabBehavior,
+    keymap: Keymap<L>,
+    /// Typed characters replace the one after the cursor, toggled with Insert
+    overwrite: bool,
+    single_line: bool,
+    min_width: f32,
+    submitted: bool,
+    soft_break: String,
+    remote_selections: Vec<RemoteSelection>,
+    remote_selection_rects: Vec<Phys<Rect>>,
+    /// Top left of each remote caret in screen coordinates
+    remote_name_tags: Vec<Option<Pos2>>,
+    hovered_cursor: Option<Cursor>,
+    /// The app draws something from the hovered cursor, see [`Self::set_hover_repaint`]
+    hover_repaint: bool,
+    pointer_over_selection: bool,
+    clicked_cursor: Option<Cursor>,
+    drop_cursor: Option<Cursor>,
+    /// The x Up and Down aim for, kept while the cursor is where the last vertical motion left it
+    vertical_motion_x: Option<(Cursor, Phys<f32>)>,
+    shaping_budget: Option<Duration>,
+    line_ending: LineEnding,
+    control_chars: ControlChars,
+    /// The placeholders in the buffer that [`ControlChars::EscapeVisible`] put there, sorted.
+    /// Only these are turned back into their controls, typed or pasted text that looks like one
+    /// isn't.
+    escaped_controls: Vec<Range<Cursor>>,
+    /// The items of the change in progress that `escaped_controls` were already moved along with
+    change_items: Vec<ChangeItem>,
+    /// How often the text was replaced without being recorded, undo and redo only bring back the
+    /// escaped controls of steps recorded since
+    text_replacements: usize,
+    font_features: FontFeatures,
+    font_family: Option<String>,
+    /// The frame input was last handled in, later [`CosmicEdit::ui`] calls in it only draw
+    last_ui_frame: Option<u64>,
+    /// Set by [`CosmicEdit::on_shown`], the next frame restarts the blink and scrolls to the caret
+    shown_again: bool,
+    read_primary_selection: Option<Box<dyn FnMut() -> Option<String>>>,
+    hover_icon: Option<HoverIcon>,
+    /// egui's input time of the last change to the text
+    last_change_time: Option<f64>,
+    /// A change was made that `last_change_time` doesn't know about yet
+    unstamped_change: bool,
+    /// [`CosmicEdit::debounced_changed`] hasn't reported the last change yet
+    debounce_pending: bool,
+    /// The glyphs drawn during the last frame, `None` unless they're captured
+    visible_glyphs: Option<Vec<GlyphInfo>>,
+}
+
+// TODO: Docs
+impl<L: LayoutMode> CosmicEdit<L> {
+    const BLINK_INTERVAL_IN_SECS: f32 = 0.5;
+    const DRAG_PREVIEW_CHARS: usize = 30;
+    const HOVER_CARET_OPACITY: f32 = 0.35;
+    /// Keeps the glyph under the block caret readable
+    const BLOCK_CARET_OPACITY: f32 = 0.5;
+    const REMOTE_SELECTION_OPACITY: f32 = 0.35;
+    const TOUCH_HANDLE_RADIUS: f32 = 6.0;
+    /// Repaints per second at most while hover visuals follow the pointer
+    const HOVER_REPAINT_RATE: f32 = 30.0;
+    pub const DEFAULT_LONG_LINE_THRESHOLD: usize = 20_000;
 
     pub fn new(
         font_size: f32,
@@ -561,61 +1952,403 @@ impl<L: LayoutMode> CosmicEdit<L> {
         layout_mode: L,
         font_system: &mut FontSystem,
     ) -> Self {
-        Self {
-            editor: {
-                let mut editor = Editor::new(Buffer::new(
-                    font_system,
-                    Metrics::new(font_size, line_height.into_absolute(font_size)),
-                ));
-                editor.set_selection(Selection::Normal(editor.cursor()));
-                editor
-            },
-            interactivity,
-            hover_strategy,
-            layout_mode,
-            cursor_style: CursorStyle::default(),
-            selection_texture: SelectionTexture::default(),
-            commands: Commands::new(),
-            last_click: None,
-            scroll_state: ScrollState::Idle,
-            dragging: false,
-            frame_changed: false,
-            last_updated_time: 0.0,
-        }
+        let editor = Editor::new(Buffer::new(
+            font_system,
+            Metrics::new(font_size, line_height.into_absolute(font_size)),
+        ));
+        Self::from_editor(editor, interactivity, hover_strategy, layout_mode)
     }
 
+    /// Wraps an existing editor, e.g. one from [`Self::into_editor`], starting with an empty undo
+    /// history. The text is laid out again on the next [`Self::ui`] call.
     pub fn from_editor(
-        editor: Editor<'static>,
+        mut editor: Editor<'static>,
         interactivity: Interactivity,
         hover_strategy: HoverStrategy,
-        layout_mode: L,
+        mut layout_mode: L,
     ) -> Self {
+        normalize_selection(&mut editor);
+        editor.with_buffer_mut(unmark_last_line);
+        // Whatever it cached was measured from another buffer
+        layout_mode.invalidate();
         Self {
             editor,
+            id: next_widget_id(),
             interactivity,
             hover_strategy,
             layout_mode,
             cursor_style: CursorStyle::default(),
+            style: CosmicEditStyle::default(),
             selection_texture: SelectionTexture::default(),
             commands: Commands::new(),
             last_click: None,
+            click_metrics: ClickMetrics::default(),
+            touch_drag: TouchDrag::default(),
+            touch_press_pending: false,
             scroll_state: ScrollState::Idle,
+            rotation: None,
+            follow_cursor: true,
+            scroll_to_bottom: false,
+            scroll_to_cursor: false,
+            open_context_menu: false,
+            keyboard_menu_open: false,
             dragging: false,
             frame_changed: false,
             last_updated_time: 0.0,
+            last_copy_frame: None,
+            selection_drag_modifiers: None,
+            dragging_selection: false,
+            showing_touch_handles: false,
+            touch_handle_anchor: None,
+            event_scratch: Vec::new(),
+            revision: 0,
+            selection_rects_key: None,
+            selection_rects: Vec::new(),
+            fonts_missing: false,
+            long_line_threshold: Some(Self::DEFAULT_LONG_LINE_THRESHOLD),
+            unwrapped_from: None,
+            long_lines_revision: None,
+            shaped_revision: None,
+            skipped_history_changes: 0,
+            max_recorded_change_bytes: None,
+            undo_group_window: Some(Duration::from_millis(500)),
+            typing_group: None,
+            history_barriers: 0,
+            in_change: false,
+            char_limit: None,
+            char_filter: None,
+            typing_attrs: None,
+            text_changes: None,
+            line_motions: LineMotions::default(),
+            tab_behavior: TabBehavior::default(),
+            keymap: Keymap::default(),
+            overwrite: false,
+            single_line: false,
+            min_width: 0.0,
+            submitted: false,
+            soft_break: "\n".to_string(),
+            remote_selections: Vec::new(),
+            remote_selection_rects: Vec::new(),
+            remote_name_tags: Vec::new(),
+            hovered_cursor: None,
+            hover_repaint: false,
+            pointer_over_selection: false,
+            clicked_cursor: None,
+            drop_cursor: None,
+            vertical_motion_x: None,
+            shaping_budget: None,
+            line_ending: LineEnding::default(),
+            control_chars: ControlChars::default(),
+            escaped_controls: Vec::new(),
+            change_items: Vec::new(),
+            text_replacements: 0,
+            font_features: FontFeatures::default(),
+            font_family: None,
+            last_ui_frame: None,
+            shown_again: false,
+            read_primary_selection: None,
+            hover_icon: None,
+            last_change_time: None,
+            unstamped_change: false,
+            debounce_pending: false,
+            visible_glyphs: None,
         }
     }
 
+    /// The id the widget's [`Response`] and focus use, unique per widget by default.
+    ///
+    /// Give widgets that are recreated, e.g. loaded again from saved state, the same id to keep
+    /// them focused.
+    pub fn with_id(mut self, id: Id) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// [`Self::with_id`] with an id made from `id_salt`, e.g. the document's path.
+    pub fn with_id_salt(self, id_salt: impl std::hash::Hash) -> Self {
+        self.with_id(Id::new(id_salt))
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
     pub fn with_cursor_style(mut self, style: CursorStyle) -> Self {
+        self.style.caret = match &style {
+            CursorStyle::None => None,
+            CursorStyle::Default(color) => Some(*color),
+            CursorStyle::Texture(texture) => Some(texture.color),
+        };
         self.cursor_style = style;
         self
     }
 
+    pub fn with_caret_layer(mut self, caret_layer: CaretLayer) -> Self {
+        self.style.caret_layer = caret_layer;
+        self
+    }
+
+    /// A texture of its own isn't part of the [style](Self::style), it's kept until a style with
+    /// another selection color is set.
     pub fn with_selection_texture(mut self, selection_texture: SelectionTexture) -> Self {
+        if let SelectionTexture::Default(color) = selection_texture {
+            self.style.selection = color;
+        }
         self.selection_texture = selection_texture;
         self
     }
 
+    /// See [`Self::set_style`].
+    pub fn with_style(mut self, style: &CosmicEditStyle) -> Self {
+        self.set_style(style);
+        self
+    }
+
+    pub fn with_line_motions(mut self, line_motions: LineMotions) -> Self {
+        self.line_motions = line_motions;
+        self
+    }
+
+    pub fn with_tab_behavior(mut self, tab_behavior: TabBehavior) -> Self {
+        self.tab_behavior = tab_behavior;
+        self
+    }
+
+    /// What keys do while the widget has focus, [`Keymap::default`] unless replaced.
+    pub fn with_keymap(mut self, keymap: Keymap<L>) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    pub fn with_overwrite_caret(mut self, overwrite_caret: OverwriteCaret) -> Self {
+        self.style.overwrite_caret = overwrite_caret;
+        self
+    }
+
+    /// Keeps the text on one line, e.g. for a search box. Enter sets [`Self::submitted`] instead
+    /// of inserting a newline, newlines in set or pasted text become spaces, and Up and Down are
+    /// left for the app, e.g. to pick from suggestions.
+    ///
+    /// Text that's already in the widget is joined into one line.
+    pub fn with_single_line(mut self, single_line: bool) -> Self {
+        self.single_line = single_line;
+        if single_line {
+            self.editor.with_buffer_mut(join_lines);
+            self.invalidate_layout();
+        }
+        self
+    }
+
+    /// What Shift+Enter inserts, `\n` by default. Set it to `"\u{2028}"`, the Unicode line
+    /// separator, or a marker of the app's own to tell line breaks within a paragraph apart from
+    /// paragraph breaks.
+    ///
+    /// cosmic-text doesn't break lines at U+2028, so the buffer gets a new line that's marked as a
+    /// soft break instead, see [`Self::line_break`]. [`Self::text`] writes the soft break back out,
+    /// text that's set or pasted gets it turned into a marked line again.
+    pub fn with_soft_break(mut self, soft_break: impl Into<String>) -> Self {
+        self.soft_break = soft_break.into();
+        self
+    }
+
+    /// Where the text sits when the widget is given more room than it needs, e.g. by
+    /// [`FillWidthAndHeight`] or `ui.centered_and_justified`. Drawing, the caret, selections and
+    /// hit testing all follow it. Defaults to [`Align2::LEFT_TOP`], rotated widgets ignore it.
+    pub fn with_content_align(mut self, content_align: Align2) -> Self {
+        self.style.content_align = content_align;
+        self
+    }
+
+    /// The least width in points the widget takes, 0 by default. With [`ShrinkToFit`] and
+    /// [single-line](Self::with_single_line) text it makes a field that grows with its text, e.g.
+    /// to rename in place, without collapsing once it's emptied. Rotated widgets apply it along
+    /// the text.
+    pub fn with_min_width(mut self, min_width: f32) -> Self {
+        self.min_width = min_width;
+        self
+    }
+
+    pub fn set_min_width(&mut self, min_width: f32) {
+        self.min_width = min_width;
+    }
+
+    fn min_width(&self, pixels_per_point: f32) -> Phys<f32> {
+        to_physical(Logical(self.min_width), pixels_per_point)
+    }
+
+    /// Overrides egui's click and drag thresholds for this editor.
+    pub fn with_click_metrics(mut self, click_metrics: ClickMetrics) -> Self {
+        self.click_metrics = click_metrics;
+        self
+    }
+
+    /// Sets whether dragging a finger across the widget selects text or scrolls, e.g. inside a
+    /// `ScrollArea`.
+    pub fn with_touch_drag(mut self, touch_drag: TouchDrag) -> Self {
+        self.touch_drag = touch_drag;
+        self
+    }
+
+    /// Lets the selection be dragged out of the widget as a [`SelectedText`] drag and drop payload
+    /// when a drag starts on it while `modifiers` are held.
+    ///
+    /// Use [`Modifiers::NONE`] to not require any modifier.
+    pub fn with_selection_drag_source(mut self, modifiers: Modifiers) -> Self {
+        self.selection_drag_modifiers = Some(modifiers);
+        self
+    }
+
+    /// Picks the icon over each line instead of the [`HoverStrategy`], e.g. a pointing hand over
+    /// lines with links. It's called with the run under the pointer and the pointer's position
+    /// relative to the buffer in physical pixels, like the run's glyphs. With
+    /// [`HoverStrategy::Widget`] the rest of the widget keeps the text icon.
+    pub fn with_hover_icon(
+        mut self,
+        hover_icon: impl Fn(&LayoutRun, Pos2) -> Option<CursorIcon> + 'static,
+    ) -> Self {
+        self.hover_icon = Some(Box::new(hover_icon));
+        self
+    }
+
+    /// Pastes the primary selection where the middle mouse button is released, as expected on
+    /// Linux. The crate doesn't own a clipboard, so `read_primary_selection` is asked for the
+    /// text, e.g. through `arboard`'s `LinuxClipboardKind::Primary`.
+    ///
+    /// Only [enabled](Interactivity::Enabled) widgets paste, the paste is undone like typing.
+    pub fn with_primary_paste(
+        mut self,
+        read_primary_selection: impl FnMut() -> Option<String> + 'static,
+    ) -> Self {
+        self.read_primary_selection = Some(Box::new(read_primary_selection));
+        self
+    }
+
+    /// Keeps the glyphs drawn each frame for [`Self::visible_glyphs`], e.g. to lay interactive
+    /// regions over them. Off by default, drawing doesn't collect anything then.
+    pub fn with_glyph_capture(mut self, capture: bool) -> Self {
+        self.set_glyph_capture(capture);
+        self
+    }
+
+    /// Lines longer than `threshold` bytes turn wrapping off for the whole buffer, `None` never
+    /// does. Defaults to [`Self::DEFAULT_LONG_LINE_THRESHOLD`].
+    ///
+    /// Wrapping a huge line (e.g. minified JSON) into thousands of rows on every edit freezes the
+    /// UI, unwrapped it's one row that can be scrolled horizontally instead.
+    /// See [`Self::has_long_lines`].
+    pub fn with_long_line_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.long_line_threshold = threshold;
+        self.long_lines_revision = None;
+        self
+    }
+
+    /// Turns wrapping off while there's a line over the threshold and restores it afterwards.
+    fn update_long_lines(&mut self, font_system: &mut FontSystem) {
+        if self.long_lines_revision == Some(self.revision) {
+            return;
+        }
+        let long_lines = self.long_line_threshold.is_some_and(|threshold| {
+            self.editor
+                .with_buffer(|x| x.lines.iter().any(|line| line.text().len() > threshold))
+        });
+        let wrap = match (long_lines, self.unwrapped_from) {
+            (true, None) => {
+                self.unwrapped_from = Some(self.editor.with_buffer(|x| x.wrap()));
+                Some(Wrap::None)
+            }
+            (false, Some(wrap)) => {
+                self.unwrapped_from = None;
+                Some(wrap)
+            }
+            _ => None,
+        };
+        if let Some(wrap) = wrap {
+            self.with_buffer_budgeted(|x, _| x.set_wrap(font_system, wrap));
+            self.layout_mode.invalidate();
+            self.revision += 1;
+        }
+        self.long_lines_revision = Some(self.revision);
+    }
+
+    /// Caps how long each frame may spend shaping, `None` (the default) shapes everything the
+    /// layout mode needs right away.
+    ///
+    /// With a budget, [`Self::set_text`] doesn't shape anything and lines are shaped from the top
+    /// a bit more every frame, with a repaint requested until they're all done. Lines that aren't
+    /// shaped yet count as one row of the line height and aren't drawn, so a large document shows
+    /// up immediately with a size that refines over a few frames. At least one line is shaped per
+    /// frame, so `Duration::ZERO` shapes one line at a time.
+    pub fn set_shaping_budget(&mut self, budget: Option<Duration>) {
+        self.shaping_budget = budget;
+        self.invalidate_layout();
+    }
+
+    pub fn shaping_budget(&self) -> Option<Duration> {
+        self.shaping_budget
+    }
+
+    /// Runs `f` on the buffer without shaping lines that are left for later frames.
+    fn with_buffer_budgeted<R>(&mut self, f: impl FnOnce(&mut Buffer, &mut L) -> R) -> R {
+        let budgeted = self.shaping_budget.is_some();
+        let layout_mode = &mut self.layout_mode;
+        self.editor.with_buffer_mut(|x| match budgeted {
+            true => with_shaped_lines_only(x, |x| f(x, layout_mode)),
+            false => f(x, layout_mode),
+        })
+    }
+
+    /// Shapes what the editor needs, only the cursor's line with a shaping budget.
+    fn shape_as_needed(&mut self, font_system: &mut FontSystem) {
+        match self.shaping_budget {
+            None => self.editor.shape_as_needed(font_system, false),
+            Some(_) => {
+                let line = self.editor.cursor().line;
+                self.editor.with_buffer_mut(|x| {
+                    x.line_layout(font_system, line);
+                });
+            }
+        }
+    }
+
+    /// Shapes unshaped lines from the top until `budget` runs out, returns whether any are left.
+    ///
+    /// Lines are only drawn up to the first unshaped one, so shaping further down first wouldn't
+    /// show anything sooner.
+    fn shape_within_budget(&mut self, font_system: &mut FontSystem, budget: Duration) -> bool {
+        let start = Instant::now();
+        let mut shaped = 0;
+        let remaining = self.editor.with_buffer_mut(|x| {
+            let mut line_i = 0;
+            while let Some(i) = (line_i..x.lines.len()).find(|&i| x.lines[i].layout_opt().is_none())
+            {
+                if shaped > 0 && start.elapsed() >= budget {
+                    return true;
+                }
+                x.line_layout(font_system, i);
+                shaped += 1;
+                line_i = i + 1;
+            }
+            false
+        });
+        if shaped > 0 {
+            self.invalidate_layout();
+        }
+        remaining
+    }
+
+    /// Without fonts nothing can be shaped, so only an outline of roughly the text's size is drawn.
+    fn placeholder_ui(&mut self, ui: &mut Ui, pixels_per_point: f32) -> Response {
+        let size = self.editor.with_buffer(estimate_size);
+        let (resp, painter) =
+            ui.allocate_painter(*size.to_logical(pixels_per_point), Sense::hover());
+        compat::rect_stroke(
+            &painter,
+            resp.rect.shrink(0.5),
+            Stroke::new(1.0, ui.visuals().weak_text_color()),
+        );
+        resp
+    }
+
     fn line_height(&self) -> f32 {
         self.editor.with_buffer(|x| x.metrics().line_height)
     }
@@ -629,10 +2362,94 @@ impl<L: LayoutMode> CosmicEdit<L> {
     ) where
         T: IntoIterator<Item = (&'a str, Attrs<'b>)>,
     {
+        let line_lens = self.tracked_line_lens();
+        let family = widget_family(self.font_family.as_deref());
+        let replace = |attrs| with_widget_font(attrs, family, &self.font_features);
+        let default_attrs = replace(default_attrs);
+        // Escaped once they're in the buffer, where the placeholders can be found
+        let escape = self.control_chars == ControlChars::EscapeVisible;
+        let spans = spans
+            .into_iter()
+            .map(|(text, attrs)| match escape {
+                true => (Cow::Borrowed(text), replace(attrs)),
+                false => (self.sanitize(text).0, replace(attrs)),
+            })
+            .collect::<Vec<_>>();
+        let spans = spans.iter().map(|(text, attrs)| (&**text, attrs.clone()));
+        let fonts = has_fonts(font_system);
+        let budgeted = self.shaping_budget.is_some();
+        let single_line = self.single_line;
+        let soft_break = self.soft_break.as_str();
         self.editor.with_buffer_mut(|x| {
-            x.set_rich_text(font_system, spans, default_attrs, shaping);
+            if fonts && budgeted {
+                // Nothing past the zero height gets shaped, what was is reset to be shaped in `x`
+                let mut scratch = Buffer::new_empty(x.metrics());
+                scratch.set_size(font_system, None, Some(0.0));
+                scratch.set_rich_text(font_system, spans, &default_attrs, shaping, None);
+                x.lines = std::mem::take(&mut scratch.lines);
+                x.lines.iter_mut().for_each(BufferLine::reset_shaping);
+                x.set_redraw(true);
+            } else if fonts {
+                x.set_rich_text(font_system, spans, &default_attrs, shaping, None);
+            } else {
+                // Keep the text around without shaping it, the span attributes are dropped
+                let text = spans.into_iter().map(|(text, _)| text).collect::<String>();
+                x.lines = normalize_line_endings(text)
+                    .split('\n')
+                    .map(|line| {
+                        BufferLine::new(
+                            line,
+                            LineEnding::default(),
+                            AttrsList::new(&default_attrs),
+                            shaping,
+                        )
+                    })
+                    .collect();
+            }
+            x.lines.iter_mut().for_each(|line| {
+                line.set_ending(LineEnding::Lf);
+            });
+            split_soft_breaks(x, soft_break);
+            if single_line {
+                join_lines(x);
+            }
         });
+        self.escaped_controls = match escape {
+            true => self.editor.with_buffer_mut(escape_lines),
+            false => Vec::new(),
+        };
+        self.text_replacements += 1;
         self.invalidate_layout();
+        self.track_replaced_text(line_lens);
+    }
+
+    /// Empties the text and puts the caret at the start. With `undoable` it's one undo step,
+    /// otherwise the undo history is cleared as it no longer matches the text.
+    ///
+    /// The blink and scroll states start over like after [`Self::on_shown`].
+    pub fn clear(&mut self, undoable: bool, font_system: &mut FontSystem) {
+        let end = self.editor.with_buffer(end_cursor);
+        let delete_all = |editor: &mut Editor| {
+            editor.set_selection(Selection::Normal(Cursor::default()));
+            editor.set_cursor(end);
+            editor.delete_selection();
+        };
+        match undoable {
+            true => self.change(font_system, |_font_system, widget| {
+                delete_all(&mut widget.editor)
+            }),
+            false => {
+                let line_lens = self.tracked_line_lens();
+                delete_all(&mut self.editor);
+                self.commands.clear();
+                self.escaped_controls.clear();
+                self.invalidate_layout();
+                self.track_replaced_text(line_lens);
+            }
+        }
+        self.editor.set_cursor(Cursor::default());
+        self.editor.set_selection(Selection::None);
+        self.on_shown();
     }
 
     pub fn ui<S: BuildHasher + Default>(
@@ -643,43 +2460,294 @@ impl<L: LayoutMode> CosmicEdit<L> {
         atlas: &mut TextureAtlas<S>,
         context_menu: impl ContextMenu,
     ) -> Response {
+        self.sanitize_cursors();
+        let frame_nr = compat::frame_nr(ui.ctx());
+        if self.last_ui_frame == Some(frame_nr) {
+            return self.draw_only_ui(ui, font_system, swash_cache, atlas);
+        }
+        // Skipped a frame, e.g. the tab it's in was switched away from and back
+        if self.last_ui_frame.is_some_and(|last| frame_nr > last + 1) {
+            self.on_shown();
+        }
+        self.last_ui_frame = Some(frame_nr);
+        // Made by the app since the last frame
+        self.stamp_change(ui.input(|i| i.time));
+
         self.frame_changed = false;
+        self.hovered_cursor = None;
+        self.pointer_over_selection = false;
+        self.clicked_cursor = None;
+        self.drop_cursor = None;
+        self.submitted = false;
+        if let Some(glyphs) = &mut self.visible_glyphs {
+            glyphs.clear();
+        }
 
         let pixels_per_point = ui.ctx().pixels_per_point();
 
-        let base_line_height = self.line_height();
+        self.fonts_missing = !has_fonts(font_system);
+        if self.fonts_missing {
+            return self.placeholder_ui(ui, pixels_per_point);
+        }
 
-        // In physical pixels
-        let size = self.editor.with_buffer_mut(|x| {
-            // egui logical pixel -> physical pixel
-            let (available_width, available_height) =
-                (ui.available_size_before_wrap() * pixels_per_point).into();
+        // Hit testing isn't rotated, so rotated widgets can't be interacted with for now
+        let interactivity = match self.rotation {
+            Some(_) => Interactivity::Disabled,
+            // E.g. `ui.add_enabled`, egui only takes the focus away after input is handled
+            None if !ui.is_enabled() => Interactivity::Disabled,
+            None => self.interactivity,
+        };
 
-            let sz =
-                self.layout_mode
-                    .calculate(x, font_system, vec2(available_width, available_height));
-            (sz.x, sz.y)
-        });
+        // Input is handled before laying out and allocating so edits that change the size
+        // (e.g. Enter at the bottom of the text) are reflected in this frame's rect.
+        let id = self.id;
+        let has_focus = ui.memory(|m| m.has_focus(id));
+        if has_focus {
+            // egui's selectable labels would copy their selection over the widget's
+            let mut label_selection = LabelSelectionState::load(ui.ctx());
+            if label_selection.has_selection() {
+                label_selection.clear_selection();
+                label_selection.store(ui.ctx());
+            }
+        }
 
-        let (resp, mut painter) = ui.allocate_painter(
-            // Size is in physical pixels -> logical pixels
-            Vec2::from(size) / pixels_per_point,
-            self.interactivity.sense(),
-        );
+        let shown_again = std::mem::take(&mut self.shown_again);
+        if shown_again {
+            self.last_updated_time = ui.input(|i| i.time);
+        }
+        let mut should_scroll_to_cursor = shown_again && has_focus;
+        if !has_focus {
+            self.typing_group = None;
+        }
 
-        let interact_pos = || {
-            resp.interact_pointer_pos()
-                .map(|pos| pos - resp.rect.min.to_vec2())
-        };
+        if interactivity.navigation() && has_focus && self.keyboard_menu_open {
+            // The menu takes the keys, e.g. Tab to move through its items
+            ui.memory_mut(|m| m.set_focus_lock_filter(id, EventFilter::default()));
+        } else if interactivity.navigation() && has_focus {
+            let tab_inserts = self.tab_behavior == TabBehavior::Insert && interactivity.mutation();
+            ui.memory_mut(|m| {
+                m.set_focus_lock_filter(
+                    id,
+                    EventFilter {
+                        tab: tab_inserts,
+                        horizontal_arrows: true,
+                        vertical_arrows: !self.single_line,
+                        escape: true,
+                    },
+                )
+            });
 
-        if self.interactivity.selection() {
-            if ui.input(|i| i.pointer.primary_released()) {
-                self.dragging = false;
-            } else if resp.is_pointer_button_down_on() && ui.input(|i| i.pointer.primary_pressed())
-            {
-                if !resp.lost_focus() {
-                    resp.request_focus();
-                }
+            // What's visible of the text, e.g. a `ScrollArea`'s viewport
+            let page_height = to_physical(Logical(ui.clip_rect().height()), pixels_per_point);
+            let time = ui.input(|i| i.time);
+
+            if ui.input(|i| i.pointer.any_pressed()) {
+                self.typing_group = None;
+            }
+
+            // Reuses the allocation from previous frames
+            let mut events = std::mem::take(&mut self.event_scratch);
+            ui.input(|i| events.extend(i.events.iter().cloned()));
+            for event in events.drain(..) {
+                match event {
+                    Event::Cut | Event::Paste(_) | Event::Text(_) if !interactivity.mutation() => {}
+                    Event::Cut => {
+                        should_scroll_to_cursor |= self.cut(ui, font_system);
+                    }
+                    Event::Copy => {
+                        self.copy(ui);
+                    }
+                    Event::Paste(text) if !text.is_empty() => {
+                        self.insert_string(text, font_system);
+                        should_scroll_to_cursor = true;
+                    }
+                    Event::Key {
+                        key,
+                        pressed: true,
+                        modifiers,
+                        ..
+                    } => {
+                        let command = self
+                            .keymap
+                            .command(key, modifiers)
+                            .filter(|x| interactivity.mutation() || !x.mutates())
+                            .cloned();
+                        if let Some(command) = command {
+                            should_scroll_to_cursor |= self.run_command(
+                                &command,
+                                modifiers,
+                                font_system,
+                                time,
+                                page_height,
+                            );
+                        }
+                    }
+                    Event::Text(string) => {
+                        let (string, mut placeholders) = self.sanitize(&string);
+                        let string = self
+                            .filter_chars(&string, &mut placeholders)
+                            .unwrap_or_default();
+                        let string = self.fit_char_limit(&string, self.overwrite);
+                        let string = whole_placeholders(string, &mut placeholders).to_string();
+                        for (unit, placeholder) in placeholder_units(&string, &placeholders) {
+                            self.type_text(&string[unit], placeholder, time, font_system);
+                        }
+                        if !string.is_empty() {
+                            // Needs to be shaped to get a cursor pos
+                            should_scroll_to_cursor = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.event_scratch = events;
+        }
+
+        // Before shaping, so edited long lines are never wrapped
+        self.update_long_lines(font_system);
+
+        // New lines have to be shaped to be measured, the app's edits since the last frame too
+        // unless the shaping budget gets to them
+        let edited_by_app =
+            self.shaping_budget.is_none() && self.shaped_revision != Some(self.revision);
+        if self.frame_changed || edited_by_app {
+            self.shape_as_needed(font_system);
+        }
+        self.shaped_revision = Some(self.revision);
+
+        if let Some(budget) = self.shaping_budget {
+            if self.shape_within_budget(font_system, budget) {
+                ui.ctx().request_repaint();
+            }
+        }
+
+        let rotation = self.rotation;
+        let transpose = |size: Vec2| match rotation {
+            Some(_) => Rot90::transpose(size),
+            None => size,
+        };
+
+        let available_size = Phys::from_logical(
+            Logical(transpose(ui.available_size_before_wrap())),
+            pixels_per_point,
+        );
+        let size = self.with_buffer_budgeted(|x, layout_mode| {
+            layout_mode.calculate(x, font_system, available_size)
+        });
+        let size = self.editor.with_buffer(|x| {
+            let size = Phys(vec2(size.x, size.y + *unshaped_height(x)));
+            // Layout modes measure without the spacing and clamp unwrapped lines
+            match self.style.spacing.is_zero() && self.unwrapped_from.is_none() {
+                true => size,
+                false => Phys(vec2(
+                    size.x.max(*self.style.spacing.measure_width(x)),
+                    size.y,
+                )),
+            }
+        });
+        let size = Phys(vec2(size.x.max(*self.min_width(pixels_per_point)), size.y));
+
+        let has_metadata =
+            interactivity == Interactivity::Disabled && self.editor.with_buffer(has_metadata);
+        let (mut resp, mut painter) = allocate_painter(
+            ui,
+            id,
+            transpose(*size.to_logical(pixels_per_point)),
+            interactivity.sense(self.touch_drag, has_metadata),
+        );
+        if self.frame_changed {
+            resp.mark_changed();
+        }
+        // Where the buffer's origin sits, everything below is relative to it
+        let content_rect = self.content_rect(resp.rect, pixels_per_point);
+
+        if self.scroll_to_bottom {
+            self.scroll_to_bottom = false;
+            let bottom = pos2(resp.rect.left(), resp.rect.bottom());
+            ui.scroll_to_rect(Rect::from_min_max(bottom, bottom), Some(Align::BOTTOM));
+        }
+        if self.scroll_to_cursor {
+            self.apply_to_cursor_rect(
+                self.editor.cursor(),
+                Logical(content_rect.min),
+                pixels_per_point,
+                |editor, rect| {
+                    ui.scroll_to_rect(rect, None);
+                    editor.scroll_to_cursor = false;
+                },
+            );
+        }
+
+        // Nothing to interact with or draw, e.g. scrolled out of view inside a `ScrollArea`.
+        // The blink and scroll states are left alone until the widget is visible again.
+        if !has_focus && !ui.is_rect_visible(resp.rect) {
+            return resp;
+        }
+
+        let interact_pos = || {
+            resp.interact_pointer_pos()
+                .map(|pos| pos - content_rect.min.to_vec2())
+        };
+
+        // Hit testing isn't rotated
+        if self.rotation.is_none() {
+            let hit =
+                |pos: Pos2| self.hit(Logical(pos - content_rect.min.to_vec2()), pixels_per_point);
+            let hovered_cursor = resp.hover_pos().and_then(hit);
+            let clicked_cursor = resp
+                .clicked()
+                .then(|| resp.interact_pointer_pos())
+                .flatten()
+                .and_then(hit);
+            // `hover_pos` is `None` while something else is being dragged
+            let dragging_over = DragAndDrop::has_any_payload(ui.ctx())
+                || ui.input(|i| !i.raw.hovered_files.is_empty());
+            let drop_cursor =
+                match interactivity.mutation() && dragging_over && resp.contains_pointer() {
+                    true => ui.ctx().pointer_hover_pos().and_then(hit),
+                    false => None,
+                };
+            let pointer_over_selection = hovered_cursor.is_some_and(|x| self.is_selected(x));
+            self.hovered_cursor = hovered_cursor;
+            self.pointer_over_selection = pointer_over_selection;
+            self.clicked_cursor = clicked_cursor;
+            self.drop_cursor = drop_cursor;
+        }
+
+        if interactivity.selection() {
+            let holding_handle = self.touch_handles_ui(
+                ui,
+                resp.has_focus(),
+                Logical(content_rect.min),
+                pixels_per_point,
+            );
+            if holding_handle && !resp.has_focus() {
+                resp.request_focus();
+            }
+
+            let mut pressed_on =
+                resp.is_pointer_button_down_on() && ui.input(|i| i.pointer.primary_pressed());
+            if let TouchDrag::HoldToSelect(delay) = self.touch_drag {
+                pressed_on = self.hold_touch_press(ui, &resp, delay, pressed_on);
+            }
+            // A held back tap is only pressed once it's released
+            let released = ui.input(|i| i.pointer.primary_released());
+
+            let selection_drag = interact_pos()
+                .filter(|_| pressed_on && !released)
+                .and_then(|pos| self.selection_drag_text(ui, has_focus, pos, pixels_per_point));
+
+            if released && !pressed_on {
+                self.dragging = false;
+                // egui clears the payload itself once it had a chance to be dropped
+                self.dragging_selection = false;
+            } else if let Some(text) = selection_drag {
+                DragAndDrop::set_payload(ui.ctx(), SelectedText(text));
+                self.dragging_selection = true;
+            } else if pressed_on {
+                if !resp.lost_focus() {
+                    resp.request_focus();
+                }
 
                 let interact_pos = interact_pos().unwrap();
 
@@ -687,8 +2755,10 @@ impl<L: LayoutMode> CosmicEdit<L> {
 
                 let click_type = if let Some(ref mut last_click) = self.last_click {
                     let diff_time = curr_time - last_click.time;
-                    // https://github.com/emilk/egui/blob/114f8201709aa822a3f620404a20de2e695725ad/crates/egui/src/input_state.rs#L12
-                    if diff_time < 0.5 && last_click.pos.distance(interact_pos) < 6.0 {
+                    if diff_time < self.click_metrics.max_multi_click_interval(ui.ctx())
+                        && last_click.pos.distance(interact_pos)
+                            < self.click_metrics.max_click_dist(ui.ctx())
+                    {
                         last_click.ty.promote()
                     } else {
                         ClickType::Single
@@ -697,169 +2767,110 @@ impl<L: LayoutMode> CosmicEdit<L> {
                     ClickType::Single
                 };
 
+                let hit_pos = self.hit_pos(interact_pos, pixels_per_point);
+                let extend = ui.input(|i| i.modifiers.shift);
+                let mut unit = None;
+                self.change(font_system, |font_system, widget| {
+                    // Shift+click extends from the selection's anchor
+                    let anchor = match extend {
+                        true => widget.selection_anchor(),
+                        false => {
+                            widget
+                                .editor
+                                .action(font_system, click_type.as_action(hit_pos));
+                            widget.editor.cursor()
+                        }
+                    };
+                    unit = widget
+                        .editor
+                        .with_buffer(|buf| click_type.unit(buf, anchor));
+                    match unit {
+                        Some(unit) => widget.select_units(click_type, unit, hit_pos),
+                        None if extend => widget
+                            .editor
+                            .action(font_system, ClickType::drag_action(hit_pos)),
+                        None => {}
+                    }
+                });
+
                 self.last_click = Some(LastClick {
                     time: curr_time,
                     pos: interact_pos,
                     ty: click_type,
+                    unit,
                 });
-
-                self.change(font_system, |font_system, widget| {
-                    widget.editor.action(
-                        font_system,
-                        click_type.as_action(interact_pos, pixels_per_point),
-                    );
-                });
+                self.typing_attrs = None;
 
                 self.last_updated_time = curr_time;
 
-                self.dragging = true;
+                self.dragging = !released;
             } else if self.dragging && resp.has_focus() && resp.hovered() {
                 let interact_pos = interact_pos().unwrap();
 
                 // Let me know if this causes any problems
-                let is_actual_drag = self
-                    .last_click
-                    .as_ref()
-                    .is_some_and(|last_click| last_click.pos.distance(interact_pos) >= 6.0);
+                let drag_threshold = self.click_metrics.drag_threshold(ui.ctx());
+                let is_actual_drag = self.last_click.as_ref().is_some_and(|last_click| {
+                    last_click.pos.distance(interact_pos) >= drag_threshold
+                });
 
                 if is_actual_drag {
-                    self.change(font_system, |font_system, widget| {
-                        let physical_interact_pos = (interact_pos * pixels_per_point).round();
-
-                        widget.editor.action(
-                            font_system,
-                            Action::Drag {
-                                x: physical_interact_pos.x as i32,
-                                y: physical_interact_pos.y as i32,
-                            },
-                        );
+                    let hit_pos = self.hit_pos(interact_pos, pixels_per_point);
+                    let unit = self
+                        .last_click
+                        .as_ref()
+                        .and_then(|last_click| Some((last_click.ty, last_click.unit?)));
+                    self.change(font_system, |font_system, widget| match unit {
+                        Some((click_type, unit)) => widget.select_units(click_type, unit, hit_pos),
+                        None => widget
+                            .editor
+                            .action(font_system, ClickType::drag_action(hit_pos)),
                     });
 
                     self.last_updated_time = ui.ctx().input(|i| i.time);
                 }
             }
-        }
 
-        let mut should_scroll_to_cursor = false;
-
-        if self.interactivity.input() && resp.has_focus() {
-            ui.memory_mut(|m| {
-                m.set_focus_lock_filter(
-                    resp.id,
-                    EventFilter {
-                        tab: false,
-                        horizontal_arrows: true,
-                        vertical_arrows: true,
-                        escape: true,
-                    },
-                )
-            });
+            if resp.long_touched() {
+                self.touch_press_pending = false;
+                if let Some(pos) = interact_pos() {
+                    self.long_press_select(ui.input(|i| i.time), pos, pixels_per_point);
+                    resp.request_focus();
+                }
+            }
+        }
 
-            let events = ui.input(|i| i.events.clone());
-            for event in events {
-                match event {
-                    Event::Cut => {
-                        if self.cut(ui, font_system) {
-                            should_scroll_to_cursor = true;
-                        }
-                    }
-                    Event::Copy => {
-                        self.copy(ui);
-                    }
-                    Event::Paste(text) if !text.is_empty() => {
-                        self.insert_string(text, font_system);
-                        should_scroll_to_cursor = true;
-                    }
-                    Event::Key {
-                        key: Key::Z,
-                        pressed: true,
-                        modifiers,
-                        ..
-                    } if modifiers.command => {
-                        let scroll_to_cursor = match modifiers.shift {
-                            true => self.redo(),
-                            false => self.undo(),
-                        };
-                        if scroll_to_cursor {
-                            should_scroll_to_cursor = true;
-                        }
-                    }
-                    Event::Key {
-                        key: Key::A,
-                        pressed: true,
-                        modifiers,
-                        ..
-                    } if modifiers.command => {
-                        self.editor.set_cursor(Cursor::default());
-                        let last_cursor = self.editor.with_buffer(|x| {
-                            let line_i = x.lines.len().saturating_sub(1);
-                            x.lines
-                                .last()
-                                .map(|x| x.text().len())
-                                .map(|index| Cursor::new(line_i, index))
-                                .unwrap_or_default()
-                        });
-                        self.editor.set_selection(Selection::Normal(last_cursor));
-                    }
-                    Event::Key {
-                        key,
-                        pressed: true,
-                        modifiers,
-                        ..
-                    } => {
-                        if let Some(action) = egui_key_to_cosmic_action(key) {
-                            self.change(font_system, |font_system, widget| {
-                                if action == Action::Escape {
-                                    widget.editor.set_selection(Selection::None);
-                                } else if matches!(action, Action::Backspace | Action::Delete) {
-                                    widget.editor.action(font_system, action);
-                                    widget.invalidate_layout();
-                                } else {
-                                    if let Action::Motion(_) = action {
-                                        widget.last_updated_time = ui.input(|i| i.time);
-
-                                        match widget.editor.selection() {
-                                            Selection::None if modifiers.shift => {
-                                                widget.editor.set_selection(Selection::Normal(
-                                                    widget.editor.cursor(),
-                                                ));
-                                            }
-                                            _ => {
-                                                if !modifiers.shift {
-                                                    widget.editor.set_selection(Selection::None);
-                                                }
-                                            }
-                                        }
-                                    }
-
-                                    widget.editor.action(font_system, action);
-
-                                    if let Action::Enter = action {
-                                        widget.invalidate_layout();
-                                    }
-                                }
-                                should_scroll_to_cursor = true;
-                            });
-                        }
-                    }
-                    Event::Text(string) => {
-                        string.chars().for_each(|x| {
-                            self.change(font_system, |font_system, widget| {
-                                widget.editor.action(font_system, Action::Insert(x));
-                            });
-                        });
-                        if !string.is_empty() {
-                            self.invalidate_layout();
-                            // Needs to be shaped to get a cursor pos
-                            should_scroll_to_cursor = true;
-                        }
-                    }
-                    _ => {}
+        if interactivity.mutation()
+            && self.rotation.is_none()
+            && resp.clicked_by(PointerButton::Middle)
+        {
+            if let Some(pos) = interact_pos() {
+                should_scroll_to_cursor |=
+                    self.primary_paste(ui, font_system, pos, pixels_per_point);
+                if self.frame_changed {
+                    resp.request_focus();
+                    // The rect was already allocated, lay out the paste next frame
+                    ui.ctx().request_repaint();
                 }
             }
         }
 
-        if context_menu.enabled() {
+        if self.dragging_selection {
+            self.paint_selection_drag_preview(ui, resp.id);
+        }
+
+        let open_context_menu = std::mem::take(&mut self.open_context_menu);
+        if context_menu.enabled() && self.rotation.is_none() {
+            if open_context_menu {
+                let mut pos = resp.rect.left_top();
+                self.apply_to_cursor_rect(
+                    self.editor.cursor(),
+                    Logical(content_rect.min),
+                    pixels_per_point,
+                    |_, rect| pos = rect.left_bottom(),
+                );
+                compat::open_context_menu(&resp, pos);
+                self.keyboard_menu_open = true;
+            }
             resp.context_menu(|ui| {
                 let actions = context_menu.ui(ui, self, font_system);
                 should_scroll_to_cursor |= actions.scroll_to_cursor;
@@ -867,51 +2878,78 @@ impl<L: LayoutMode> CosmicEdit<L> {
                     resp.request_focus();
                 }
             });
+            // The rect was already allocated, lay out the edit from the menu next frame
+            if self.frame_changed {
+                ui.ctx().request_repaint();
+                resp.mark_changed();
+            }
+            if self.keyboard_menu_open && !resp.context_menu_opened() {
+                self.keyboard_menu_open = false;
+                // A press elsewhere closed it and decides where the focus goes
+                if !ui.input(|i| i.pointer.any_pressed()) {
+                    resp.request_focus();
+                }
+            }
         }
-
-        self.editor.shape_as_needed(font_system, false);
-
-        if should_scroll_to_cursor {
-            self.apply_to_cursor_rect(resp.rect.min, pixels_per_point, |editor, cursor| {
-                ui.scroll_to_rect(cursor, None);
-                editor.scroll_state = ScrollState::Scrolling;
-            });
+        self.stamp_change(ui.input(|i| i.time));
+
+        self.shape_as_needed(font_system);
+
+        // Input, clicks and the context menu are done, everything below sees their result
+        let snapshot = FrameSnapshot::new(&self.editor);
+
+        if !self.follow_cursor {
+            self.scroll_state = ScrollState::Idle;
+        } else if should_scroll_to_cursor {
+            self.apply_to_cursor_rect(
+                snapshot.cursor,
+                Logical(content_rect.min),
+                pixels_per_point,
+                |editor, cursor| {
+                    ui.scroll_to_rect(cursor, None);
+                    editor.scroll_state = ScrollState::Scrolling;
+                },
+            );
         } else if let ScrollState::Scrolling = self.scroll_state {
-            self.apply_to_cursor_rect(resp.rect.min, pixels_per_point, |editor, rect| {
-                // This can be borked if the cursor is larger than the view, infinitely scrolling to
-                // the cursor even though it's visible, though not completely.
-                if ui.clip_rect().contains_rect(rect) {
-                    // Sometimes the clip rect can change in the next frame, probably due to how the scroll area and a frame container works.
-                    // E.g: { label, frame { scroll area { this widget } } }
-                    // This is a hack so lmk if you encounter any issues
-                    editor.scroll_state = ScrollState::FinishedLastFrame
-                } else {
-                    ui.scroll_to_rect(rect, None);
-                }
-            });
+            self.apply_to_cursor_rect(
+                snapshot.cursor,
+                Logical(content_rect.min),
+                pixels_per_point,
+                |editor, rect| {
+                    // This can be borked if the cursor is larger than the view, infinitely scrolling to
+                    // the cursor even though it's visible, though not completely.
+                    if ui.clip_rect().contains_rect(rect) {
+                        // Sometimes the clip rect can change in the next frame, probably due to how the scroll area and a frame container works.
+                        // E.g: { label, frame { scroll area { this widget } } }
+                        // This is a hack so lmk if you encounter any issues
+                        editor.scroll_state = ScrollState::FinishedLastFrame
+                    } else {
+                        ui.scroll_to_rect(rect, None);
+                    }
+                },
+            );
         } else if let ScrollState::FinishedLastFrame = self.scroll_state {
             match resp.has_focus() {
                 true => {
-                    self.apply_to_cursor_rect(resp.rect.min, pixels_per_point, |editor, rect| {
-                        if ui.clip_rect().contains_rect(rect) {
-                            editor.scroll_state = ScrollState::Idle
-                        } else {
-                            ui.scroll_to_rect(rect, None);
-                            editor.scroll_state = ScrollState::Scrolling;
-                        }
-                    });
+                    self.apply_to_cursor_rect(
+                        snapshot.cursor,
+                        Logical(content_rect.min),
+                        pixels_per_point,
+                        |editor, rect| {
+                            if ui.clip_rect().contains_rect(rect) {
+                                editor.scroll_state = ScrollState::Idle
+                            } else {
+                                ui.scroll_to_rect(rect, None);
+                                editor.scroll_state = ScrollState::Scrolling;
+                            }
+                        },
+                    );
                 }
                 false => self.scroll_state = ScrollState::Idle,
             }
         }
 
-        let selection_bounds = if resp.has_focus() {
-            self.editor
-                .selection_bounds()
-                .and_then(|x @ (start, end)| (start != end).then_some(x))
-        } else {
-            None
-        };
+        let selection_bounds = snapshot.selection_bounds.filter(|_| resp.has_focus());
 
         if let HoverStrategy::Widget = self.hover_strategy {
             if resp.hover_pos().is_some() {
@@ -919,256 +2957,6601 @@ impl<L: LayoutMode> CosmicEdit<L> {
             }
         }
 
+        // Whether the blinking caret is visible this frame
+        let caret_visible =
+            if interactivity.navigation() && resp.has_focus() && selection_bounds.is_none() {
+                // https://github.com/emilk/egui/blob/9a1e358a144b5d2af9d03a80257c34883f57cf0b/crates/egui/src/widgets/text_edit/builder.rs#L715
+                let now = ui.ctx().input(|i| i.time);
+
+                if self.frame_changed {
+                    self.last_updated_time = now;
+                }
+
+                let time_since_last_update = now - self.last_updated_time;
+
+                // 0.0..=0.5 (on), 0.5..=1.0 (off)
+                // just so i dont forget about how this works
+                let total_duration = Self::BLINK_INTERVAL_IN_SECS * 2.0;
+
+                // value is within 0.0..=1.0
+                let time_in_cycle = (time_since_last_update % total_duration as f64) as f32;
+
+                let on = time_in_cycle <= Self::BLINK_INTERVAL_IN_SECS;
+
+                let time_till_flip = if on {
+                    Self::BLINK_INTERVAL_IN_SECS - time_in_cycle
+                } else {
+                    total_duration - time_in_cycle
+                };
+
+                ui.ctx().request_repaint_after_secs(time_till_flip);
+
+                on
+            } else {
+                false
+            };
+
+        // Where a click would put the caret, never shown while dragging a selection
+        let hover_caret = match self.style.hover_caret
+            && interactivity.navigation()
+            && !self.dragging
+            && !self.dragging_selection
+        {
+            true => self.hovered_cursor,
+            false => None,
+        };
+
+        self.request_hover_repaint(ui, &resp, interactivity);
+
+        if self.style.caret_layer == CaretLayer::BelowGlyphs {
+            self.draw_carets(
+                ui.ctx(),
+                &mut painter,
+                caret_visible.then_some(snapshot.cursor),
+                hover_caret,
+                Logical(content_rect.min),
+            );
+        }
+
+        if let Some(rotation) = self.rotation {
+            self.editor.with_buffer(|x| {
+                draw_buf_rotated(
+                    x,
+                    font_system,
+                    swash_cache,
+                    atlas,
+                    &mut painter,
+                    Logical(content_rect),
+                    rotation,
+                    self.style.spacing,
+                    self.style.text_color,
+                )
+            });
+            return resp;
+        }
+
+        let visible_y_range = visible_y_range(&painter, content_rect.min, pixels_per_point);
+
+        // Beneath the local selection
+        self.draw_remote_selections(&painter, Logical(content_rect.min), visible_y_range);
+
+        if let Some(bounds) = selection_bounds {
+            self.update_selection_rects(bounds, visible_y_range);
+            self.paint_selection_rects(
+                &painter,
+                Logical(content_rect.min),
+                snapshot.metrics.line_height,
+            );
+        }
+
+        let default_color = self.style.text_color.unwrap_or(atlas.default_color());
         self.editor.with_buffer(|x| {
             draw_buf(
                 x,
-                resp.rect.min,
-                painter.clip_rect(),
-                resp.hover_pos(),
-                selection_bounds.map(|(_, end)| end),
+                Logical(content_rect.min),
+                Logical(painter.clip_rect()),
+                resp.hover_pos().map(Logical),
+                None,
                 &mut painter,
-                |x| self.hover_strategy.calculate_width(x),
-                || ui.ctx().set_cursor_icon(CursorIcon::Text),
-                |run| selection_bounds.and_then(|bounds| LineSelection::new(run, bounds)),
-                |selection, last, painter| {
-                    let rect = (selection_rect(selection, last) / pixels_per_point)
-                        .translate(resp.rect.min.to_vec2());
-                    self.selection_texture
-                        .with_texture(ui.ctx(), base_line_height, |texture| {
-                            painter.image(
-                                texture.id(),
-                                rect,
-                                Rect::from_two_pos(Pos2::ZERO, pos2(1.0, 1.0)),
-                                Color32::WHITE,
-                            );
-                        });
+                |run, pos| match &self.hover_icon {
+                    Some(hover_icon) => hover_icon(run, *pos),
+                    None => self.hover_strategy.hover_icon(run, pos, self.style.spacing),
                 },
+                |icon| ui.ctx().set_cursor_icon(icon),
+                // Selections are painted from the cached rects above
+                |_| None::<LineSelection>,
+                |_, _, _| {},
                 |run, painter| {
-                    draw_run(run, font_system, swash_cache, atlas, painter, resp.rect);
+                    draw_run(
+                        run,
+                        font_system,
+                        swash_cache,
+                        atlas,
+                        painter,
+                        Logical(content_rect),
+                        self.style.spacing,
+                        self.style.text_color,
+                    );
+                    if let Some(glyphs) = &mut self.visible_glyphs {
+                        let widget_pos = resp.rect.min.to_vec2();
+                        run_glyphs(
+                            run,
+                            Logical(content_rect),
+                            pixels_per_point,
+                            self.style.spacing,
+                            default_color,
+                            |glyph| {
+                                glyphs.push(GlyphInfo {
+                                    rect: glyph.rect.translate(-widget_pos),
+                                    ..glyph
+                                })
+                            },
+                        );
+                    }
                 },
             )
         });
 
-        if self.interactivity.input() && resp.has_focus() && selection_bounds.is_none() {
-            // https://github.com/emilk/egui/blob/9a1e358a144b5d2af9d03a80257c34883f57cf0b/crates/egui/src/widgets/text_edit/builder.rs#L715
-            let now = ui.ctx().input(|i| i.time);
-
-            if self.frame_changed {
-                self.last_updated_time = now;
-            }
-
-            let time_since_last_update = now - self.last_updated_time;
-
-            // 0.0..=0.5 (on), 0.5..=1.0 (off)
-            // just so i dont forget about how this works
-            let total_duration = Self::BLINK_INTERVAL_IN_SECS * 2.0;
-
-            // value is within 0.0..=1.0
-            let time_in_cycle = (time_since_last_update % total_duration as f64) as f32;
-
-            let time_till_flip = if time_in_cycle <= Self::BLINK_INTERVAL_IN_SECS {
-                self.draw_cursor(ui.ctx(), &mut painter, resp.rect.min, pixels_per_point);
+        self.draw_remote_carets(&painter, Logical(content_rect.min));
 
-                Self::BLINK_INTERVAL_IN_SECS - time_in_cycle
-            } else {
-                total_duration - time_in_cycle
-            };
-
-            ui.ctx().request_repaint_after_secs(time_till_flip)
+        if self.style.caret_layer == CaretLayer::AboveGlyphs {
+            self.draw_carets(
+                ui.ctx(),
+                &mut painter,
+                caret_visible.then_some(snapshot.cursor),
+                hover_caret,
+                Logical(content_rect.min),
+            );
         }
 
+        // They hang below the text, outside of the widget's clip rect
+        self.paint_touch_handles(ui.painter(), Logical(content_rect.min), pixels_per_point);
+
         resp
     }
 
-    fn change<F: FnOnce(&mut FontSystem, &mut Self)>(
+    /// A second [`Self::ui`] call in the same frame, e.g. the widget shown in two panels, only
+    /// draws the text as the first call laid it out. Input, the caret blink and scrolling were
+    /// already handled, doing so again would apply events twice.
+    fn draw_only_ui<S: BuildHasher + Default>(
         &mut self,
+        ui: &mut Ui,
         font_system: &mut FontSystem,
-        f: F,
-    ) {
-        self.editor.start_change();
-
-        f(font_system, self);
+        swash_cache: &mut SwashCache,
+        atlas: &mut TextureAtlas<S>,
+    ) -> Response {
+        let pixels_per_point = ui.ctx().pixels_per_point();
 
-        if let Some(change) = self.editor.finish_change() {
-            if !change.items.is_empty() {
-                self.commands.push(change);
-                self.frame_changed = true;
-            }
+        if self.fonts_missing {
+            return self.placeholder_ui(ui, pixels_per_point);
         }
-    }
 
-    /// Returns whether to scroll to cursor
-    fn apply_history_actions(
-        &mut self,
-        actions: impl FnOnce(&mut Commands<Change>) -> ActionIter<Change>,
-    ) -> bool {
-        let mut changed = false;
-        actions(&mut self.commands).for_each(|x| {
-            apply_history_action_to_editor(x, &mut self.editor);
-            changed = true;
+        let size = self.editor.with_buffer(|x| {
+            let size = *measure_width_and_height(x) + vec2(0.0, *unshaped_height(x));
+            match self.style.spacing.is_zero() {
+                true => size,
+                false => vec2(size.x.max(*self.style.spacing.measure_width(x)), size.y),
+            }
         });
-        if changed {
-            self.invalidate_layout();
-            self.editor.set_selection(Selection::None);
+        let size = vec2(size.x.max(*self.min_width(pixels_per_point)), size.y);
+        let size = match self.rotation {
+            Some(_) => Rot90::transpose(size),
+            None => size,
+        };
+        // The widget's own id belongs to the first call
+        let (resp, mut painter) =
+            ui.allocate_painter(*Phys(size).to_logical(pixels_per_point), Sense::hover());
+
+        if !ui.is_rect_visible(resp.rect) {
+            return resp;
         }
-        changed
-    }
 
-    pub fn undo(&mut self) -> bool {
-        self.apply_history_actions(Commands::undo)
-    }
+        let content_rect = self.content_rect(resp.rect, pixels_per_point);
 
-    pub fn redo(&mut self) -> bool {
-        self.apply_history_actions(Commands::redo)
-    }
+        if let Some(rotation) = self.rotation {
+            self.editor.with_buffer(|x| {
+                draw_buf_rotated(
+                    x,
+                    font_system,
+                    swash_cache,
+                    atlas,
+                    &mut painter,
+                    Logical(content_rect),
+                    rotation,
+                    self.style.spacing,
+                    self.style.text_color,
+                )
+            });
+            return resp;
+        }
 
-    pub fn copy(&mut self, ui: &mut Ui) -> bool {
-        if self
-            .editor
-            .selection_bounds()
-            .is_some_and(|(start, end)| start == end)
-        {
-            return false;
-        }
-        if let Some(string) = self.editor.copy_selection() {
-            ui.output_mut(|x| x.copied_text = string);
-            return true;
+        let selection_bounds = match ui.memory(|m| m.has_focus(self.id)) {
+            true => self
+                .editor
+                .selection_bounds()
+                .filter(|(start, end)| start != end),
+            false => None,
+        };
+        if let Some(bounds) = selection_bounds {
+            let visible_y_range = visible_y_range(&painter, content_rect.min, pixels_per_point);
+            self.update_selection_rects(bounds, visible_y_range);
+            let line_height = self.line_height();
+            self.paint_selection_rects(&painter, Logical(content_rect.min), line_height);
         }
-        false
+
+        self.editor.with_buffer(|x| {
+            draw_buf(
+                x,
+                Logical(content_rect.min),
+                Logical(painter.clip_rect()),
+                None,
+                None,
+                &mut painter,
+                |_, _| None,
+                |_| {},
+                |_| None::<LineSelection>,
+                |_, _, _| {},
+                |run, painter| {
+                    draw_run(
+                        run,
+                        font_system,
+                        swash_cache,
+                        atlas,
+                        painter,
+                        Logical(content_rect),
+                        self.style.spacing,
+                        self.style.text_color,
+                    );
+                },
+            )
+        });
+
+        resp
     }
 
-    pub fn cut(&mut self, ui: &mut Ui, font_system: &mut FontSystem) -> bool {
-        if !self.copy(ui) {
+    fn draw_remote_selections(
+        &mut self,
+        painter: &Painter,
+        min_pos: Logical<Pos2>,
+        visible_y_range: Phys<Rangef>,
+    ) {
+        let pixels_per_point = painter.ctx().pixels_per_point();
+        let CosmicEdit {
+            editor,
+            remote_selections,
+            remote_selection_rects: rects,
+            style: CosmicEditStyle { spacing, .. },
+            ..
+        } = self;
+        editor.with_buffer(|x| {
+            for remote in remote_selections.iter() {
+                let Some(bounds) = remote.bounds(x) else {
+                    continue;
+                };
+                rects.clear();
+                selection_rects(x, visible_y_range, bounds, *spacing, rects);
+                let color = remote.color.gamma_multiply(Self::REMOTE_SELECTION_OPACITY);
+                for &rect in rects.iter() {
+                    painter.rect_filled(
+                        *snap_to_logical(rect, min_pos, pixels_per_point),
+                        0.0,
+                        color,
+                    );
+                }
+            }
+        });
+    }
+
+    fn draw_remote_carets(&mut self, painter: &Painter, min_pos: Logical<Pos2>) {
+        let pixels_per_point = painter.ctx().pixels_per_point();
+        let CosmicEdit {
+            editor,
+            remote_selections,
+            remote_name_tags,
+            style: CosmicEditStyle { spacing, .. },
+            ..
+        } = self;
+        remote_name_tags.clear();
+        editor.with_buffer(|x| {
+            remote_name_tags.extend(remote_selections.iter().map(|remote| {
+                let cursor = clamp_cursor(x, remote.cursor);
+                let rect = cursor_rect(x, cursor, *spacing)?;
+                let rect = *snap_to_logical(rect, min_pos, pixels_per_point);
+                painter.rect_filled(rect, 0.0, remote.color);
+                Some(rect.left_top())
+            }));
+        });
+    }
+
+    /// Holds back a touch press until it's either held still for `delay` seconds or lifted as a
+    /// tap, a touch moving before that is a swipe left to a surrounding scroll area. Returns
+    /// whether to act on the press this frame.
+    ///
+    /// The widget only senses clicks then, so it takes the drag over once it selects.
+    fn hold_touch_press(&mut self, ui: &Ui, resp: &Response, delay: f64, pressed_on: bool) -> bool {
+        if pressed_on && ui.input(|i| i.any_touches()) {
+            self.touch_press_pending = true;
             return false;
         }
-        self.change(font_system, |_font_system, widget| {
-            widget.editor.delete_selection();
-        });
+        // The mouse selects right away
+        if !pressed_on {
+            if !self.touch_press_pending {
+                return false;
+            }
+            if resp.clicked() {
+                self.touch_press_pending = false;
+                return true;
+            }
+            let (time, press_start, origin, pos) = ui.input(|i| {
+                let pointer = &i.pointer;
+                (
+                    i.time,
+                    pointer.press_start_time(),
+                    pointer.press_origin(),
+                    pointer.interact_pos(),
+                )
+            });
+            let drag_threshold = self.click_metrics.drag_threshold(ui.ctx());
+            let still = origin
+                .zip(pos)
+                .is_some_and(|(origin, pos)| origin.distance(pos) < drag_threshold);
+            let held_down = resp.is_pointer_button_down_on() && still;
+            let Some(press_start) = press_start.filter(|_| held_down) else {
+                self.touch_press_pending = false;
+                return false;
+            };
+            let held = time - press_start;
+            if held < delay {
+                ui.ctx().request_repaint_after_secs((delay - held) as f32);
+                return false;
+            }
+            self.touch_press_pending = false;
+        }
+        // The press went to the scroll area, which also took the focus away
+        ui.ctx().set_dragged_id(resp.id);
+        resp.request_focus();
         true
     }
 
-    // Check if string is empty here?
-    pub fn insert_string(&mut self, string: String, font_system: &mut FontSystem) {
-        debug_assert!(!string.is_empty());
-        self.change(font_system, |_font_system, widget| {
-            widget.editor.insert_string(string.as_str(), None);
+    /// Keeps whatever follows the hovered cursor up to date between pointer events, capped at
+    /// [`Self::HOVER_REPAINT_RATE`] and only while the pointer is over the widget.
+    fn request_hover_repaint(&self, ui: &Ui, resp: &Response, interactivity: Interactivity) {
+        let hover_visuals =
+            self.hover_repaint || self.style.hover_caret && interactivity.navigation();
+        if hover_visuals && resp.contains_pointer() {
+            ui.ctx()
+                .request_repaint_after_secs(1.0 / Self::HOVER_REPAINT_RATE);
+        }
+    }
+
+    /// Selects the word under a long press, moving the finger on extends the selection by words
+    /// like a double click drag. `pos` is relative to where the text starts.
+    fn long_press_select(&mut self, time: f64, pos: Pos2, pixels_per_point: f32) {
+        let Some(cursor) = self.hit(Logical(pos), pixels_per_point) else {
+            return;
+        };
+        let unit = self.editor.with_buffer(|x| word_bounds(x, cursor));
+        self.select_units(ClickType::Double, unit, self.hit_pos(pos, pixels_per_point));
+        self.last_click = Some(LastClick {
+            time,
+            pos,
+            ty: ClickType::Double,
+            unit: Some(unit),
         });
-        self.invalidate_layout();
+        self.showing_touch_handles = true;
+        self.last_updated_time = time;
     }
 
-    pub fn invalidate_layout(&mut self) {
-        self.layout_mode.invalidate();
+    /// The carets at the selection's ends and the touch handles hanging below them, while shown.
+    fn touch_handle_rects(
+        &self,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+    ) -> Option<[(Rect, Rect); 2]> {
+        if !self.showing_touch_handles {
+            return None;
+        }
+        let (start, end) = self
+            .editor
+            .selection_bounds()
+            .filter(|(start, end)| start != end)?;
+        self.editor.with_buffer(|x| {
+            let handle = |cursor| {
+                let caret = cursor_rect(x, cursor, self.style.spacing)?;
+                let caret = *snap_to_logical(caret, min_pos, pixels_per_point);
+                let radius = Self::TOUCH_HANDLE_RADIUS;
+                let center = pos2(caret.center().x, caret.bottom() + radius);
+                Some((
+                    caret,
+                    Rect::from_center_size(center, Vec2::splat(radius * 2.0)),
+                ))
+            };
+            Some([handle(start)?, handle(end)?])
+        })
     }
 
-    // Batch with buffer size?
-    pub fn set_font_size(
+    /// Moves the selection's ends by dragging their touch handles, a tap anywhere else or losing
+    /// focus hides them.
+    ///
+    /// Returns whether a handle is held, egui takes the focus away when one outside of the widget
+    /// is pressed.
+    fn touch_handles_ui(
         &mut self,
-        font_size: f32,
-        line_height: LineHeight,
-        font_system: &mut FontSystem,
-    ) {
-        let metrics = Metrics::new(font_size, line_height.into_absolute(font_size));
-        self.editor.with_buffer_mut(|x| {
-            if x.metrics() != metrics {
-                x.set_metrics(font_system, metrics);
-                self.layout_mode.invalidate();
-            }
+        ui: &Ui,
+        has_focus: bool,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+    ) -> bool {
+        let Some(handles) = self.touch_handle_rects(min_pos, pixels_per_point) else {
+            self.showing_touch_handles = false;
+            self.touch_handle_anchor = None;
+            return false;
+        };
+        let resps = [0, 1].map(|i| {
+            // Bigger than drawn so they're easy to hit with a finger
+            let rect = handles[i].1.expand(Self::TOUCH_HANDLE_RADIUS);
+            ui.interact(rect, self.id.with(("touch handle", i)), Sense::drag())
         });
+        let held = resps.iter().any(Response::is_pointer_button_down_on);
+        if !held && (!has_focus || ui.input(|i| i.pointer.any_pressed())) {
+            self.showing_touch_handles = false;
+            self.touch_handle_anchor = None;
+            return false;
+        }
+        let dragged = (0..2).find(|&i| resps[i].dragged());
+        let (Some(i), Some((start, end))) = (dragged, self.editor.selection_bounds()) else {
+            self.touch_handle_anchor = None;
+            return held;
+        };
+        let anchor = *self
+            .touch_handle_anchor
+            .get_or_insert(if i == 0 { end } else { start });
+        let Some(pos) = resps[i].interact_pointer_pos() else {
+            return held;
+        };
+        // The finger holds the handle below the text it moves
+        let (caret, handle) = handles[i];
+        let pos = pos - vec2(0.0, handle.center().y - caret.center().y) - min_pos.to_vec2();
+        if let Some(cursor) = self.hit(Logical(pos), pixels_per_point) {
+            self.editor.set_selection(Selection::Normal(anchor));
+            self.editor.set_cursor(cursor);
+            self.last_updated_time = ui.input(|i| i.time);
+        }
+        held
     }
 
-    pub fn text(&self) -> String {
-        self.editor.with_buffer(|x| {
-            x.lines.iter().fold(String::new(), |mut str, line| {
-                str.push_str(line.text());
-                str.push('\n');
-                str
-            })
-        })
+    fn paint_touch_handles(
+        &self,
+        painter: &Painter,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+    ) {
+        let Some(handles) = self.touch_handle_rects(min_pos, pixels_per_point) else {
+            return;
+        };
+        let color = self.style.caret.unwrap_or(self.style.selection);
+        for (_, handle) in handles {
+            painter.circle_filled(handle.center(), Self::TOUCH_HANDLE_RADIUS, color);
+        }
     }
 
-    pub fn editor(&self) -> &Editor {
-        &self.editor
+    /// The cursor a click at `pos` would place, `pos` is relative to where the text starts.
+    ///
+    /// That's the widget's min pos unless [`Self::with_content_align`] moved the text.
+    pub fn hit(&self, pos: Logical<Pos2>, pixels_per_point: f32) -> Option<Cursor> {
+        let Pos2 { x, y } = self.hit_pos(*pos, pixels_per_point);
+        self.editor.with_buffer(|buf| buf.hit(x, y))
     }
 
-    pub fn into_editor(self) -> Editor<'static> {
-        self.editor
+    /// [`Self::ui`] with the shared [`TextResources`].
+    pub fn ui_with<S: BuildHasher + Default>(
+        &mut self,
+        ui: &mut Ui,
+        resources: &mut TextResources<S>,
+        context_menu: impl ContextMenu,
+    ) -> Response {
+        let TextResources {
+            font_system,
+            swash_cache,
+            atlas,
+        } = resources;
+        self.ui(ui, font_system, swash_cache, atlas, context_menu)
     }
 
-    pub fn interactivity(&self) -> Interactivity {
-        self.interactivity
+    /// [`Self::ui`] with what happened in it, so the widget doesn't have to be queried
+    /// afterwards.
+    pub fn show<S: BuildHasher + Default>(
+        &mut self,
+        ui: &mut Ui,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+        atlas: &mut TextureAtlas<S>,
+        context_menu: impl ContextMenu,
+    ) -> CosmicEditOutput {
+        let response = self.ui(ui, font_system, swash_cache, atlas, context_menu);
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let min_pos = self.content_rect(response.rect, pixels_per_point).min;
+        CosmicEditOutput {
+            changed: self.frame_changed,
+            cursor: self.editor.cursor(),
+            cursor_rect: *self.cursor_rect(Logical(min_pos), pixels_per_point),
+            selection: self.selection(),
+            submitted: self.submitted,
+            at_char_limit: self.at_char_limit(),
+            response,
+        }
     }
 
-    pub fn interactivity_mut(&mut self) -> &mut Interactivity {
-        &mut self.interactivity
+    /// [`Self::show`] with the shared [`TextResources`].
+    pub fn show_with<S: BuildHasher + Default>(
+        &mut self,
+        ui: &mut Ui,
+        resources: &mut TextResources<S>,
+        context_menu: impl ContextMenu,
+    ) -> CosmicEditOutput {
+        let TextResources {
+            font_system,
+            swash_cache,
+            atlas,
+        } = resources;
+        self.show(ui, font_system, swash_cache, atlas, context_menu)
     }
 
-    pub fn hover_strategy(&self) -> HoverStrategy {
-        self.hover_strategy
+    /// [`Self::view_ui`] with the shared [`TextResources`].
+    pub fn view_ui_with<S: BuildHasher + Default>(
+        &mut self,
+        view: &mut CosmicEditView,
+        ui: &mut Ui,
+        resources: &mut TextResources<S>,
+    ) -> Response {
+        let TextResources {
+            font_system,
+            swash_cache,
+            atlas,
+        } = resources;
+        self.view_ui(view, ui, font_system, swash_cache, atlas)
     }
 
-    pub fn hover_strategy_mut(&mut self) -> &mut HoverStrategy {
-        &mut self.hover_strategy
+    /// Borrows what [`Self::ui`] needs as an [`egui::Widget`], e.g. for `ui.add_sized` or
+    /// `ui.add_enabled`.
+    pub fn widget<'a, S: BuildHasher + Default, C: ContextMenu>(
+        &'a mut self,
+        font_system: &'a mut FontSystem,
+        swash_cache: &'a mut SwashCache,
+        atlas: &'a mut TextureAtlas<S>,
+        context_menu: C,
+    ) -> CosmicEditRef<'a, L, S, C> {
+        CosmicEditRef {
+            edit: self,
+            font_system,
+            swash_cache,
+            atlas,
+            context_menu,
+        }
     }
 
-    pub fn set_layout_mode(&mut self, layout_mode: L) {
-        self.layout_mode = layout_mode;
-        self.layout_mode.invalidate();
+    /// [`Self::widget`] with the shared [`TextResources`].
+    pub fn widget_with<'a, S: BuildHasher + Default, C: ContextMenu>(
+        &'a mut self,
+        resources: &'a mut TextResources<S>,
+        context_menu: C,
+    ) -> CosmicEditRef<'a, L, S, C> {
+        let TextResources {
+            font_system,
+            swash_cache,
+            atlas,
+        } = resources;
+        self.widget(font_system, swash_cache, atlas, context_menu)
     }
 
-    /// Was the buffer's text changed this frame through user input?
-    pub fn changed_this_frame(&self) -> bool {
-        self.frame_changed
+    /// The part of the allocated `rect` the text is drawn in, see [`Self::with_content_align`].
+    fn content_rect(&self, rect: Rect, pixels_per_point: f32) -> Rect {
+        if self.style.content_align == Align2::LEFT_TOP || self.rotation.is_some() {
+            return rect;
+        }
+        let size = self.editor.with_buffer(|x| {
+            let Vec2 { x: width, y } = *measure_width_and_height(x);
+            let width = match self.style.spacing.is_zero() {
+                true => width,
+                false => *self.style.spacing.measure_width(x),
+            };
+            Phys(vec2(width, y))
+        });
+        let aligned = self
+            .style
+            .content_align
+            .align_size_within_rect(*size.to_logical(pixels_per_point), rect);
+        // Keep glyphs on the physical pixel grid
+        Rect::from_min_size(
+            *snap_pos(Logical(aligned.min), pixels_per_point),
+            aligned.size(),
+        )
     }
 
-    /// Returns the cursor rect in **logical pixels**
-    pub fn cursor_rect(&self, logical_min_pos: Pos2, pixels_per_point: f32) -> Rect {
-        let cursor = self.editor.cursor();
+    /// Draws the buffer into `view` without touching this widget's caret, selection or scroll.
+    ///
+    /// The view shows the layout of the last [`Self::ui`] call, so it wraps at this widget's width.
+    /// Edits made through this widget move the view's caret back into the text if needed.
+    pub fn view_ui<S: BuildHasher + Default>(
+        &mut self,
+        view: &mut CosmicEditView,
+        ui: &mut Ui,
+        font_system: &mut FontSystem,
+        swash_cache: &mut SwashCache,
+        atlas: &mut TextureAtlas<S>,
+    ) -> Response {
+        let pixels_per_point = ui.ctx().pixels_per_point();
+
+        if !has_fonts(font_system) {
+            return self.placeholder_ui(ui, pixels_per_point);
+        }
+
+        if view.revision != self.revision {
+            view.revision = self.revision;
+            self.editor.with_buffer(|x| {
+                view.cursor = clamp_cursor(x, view.cursor);
+                view.anchor = view.anchor.map(|anchor| clamp_cursor(x, anchor));
+            });
+        }
+
+        // The view may be shown before the widget got to shape this frame's edits
+        self.shape_as_needed(font_system);
+
+        let size = self.editor.with_buffer(|x| {
+            let size = *measure_width_and_height(x) + vec2(0.0, *unshaped_height(x));
+            match self.style.spacing.is_zero() {
+                true => Phys(size),
+                false => Phys(vec2(
+                    size.x.max(*self.style.spacing.measure_width(x)),
+                    size.y,
+                )),
+            }
+        });
+        let (resp, mut painter) =
+            ui.allocate_painter(*size.to_logical(pixels_per_point), Sense::click_and_drag());
+
+        if !resp.has_focus() && !ui.is_rect_visible(resp.rect) {
+            return resp;
+        }
+
+        if resp.hover_pos().is_some() {
+            ui.ctx().set_cursor_icon(CursorIcon::Text);
+        }
+
+        let hit = resp
+            .interact_pointer_pos()
+            .and_then(|pos| self.hit(Logical(pos - resp.rect.min.to_vec2()), pixels_per_point));
+        if let Some(cursor) = hit {
+            if resp.is_pointer_button_down_on() && ui.input(|i| i.pointer.primary_pressed()) {
+                resp.request_focus();
+                view.anchor = Some(cursor);
+                view.cursor = cursor;
+            } else if resp.dragged() {
+                view.cursor = cursor;
+            }
+        }
+
+        if let Some(bounds) = view.selection_bounds() {
+            let visible_y_range = visible_y_range(&painter, resp.rect.min, pixels_per_point);
+            view.selection_rects.clear();
+            self.editor.with_buffer(|x| {
+                selection_rects(
+                    x,
+                    visible_y_range,
+                    bounds,
+                    self.style.spacing,
+                    &mut view.selection_rects,
+                )
+            });
+
+            let base_line_height = self.line_height();
+            self.selection_texture
+                .with_texture(ui.ctx(), base_line_height, |texture| {
+                    view.selection_rects.iter().for_each(|&rect| {
+                        painter.image(
+                            texture.id(),
+                            *snap_to_logical(rect, Logical(resp.rect.min), pixels_per_point),
+                            Rect::from_two_pos(Pos2::ZERO, pos2(1.0, 1.0)),
+                            Color32::WHITE,
+                        );
+                    });
+                });
+        }
+
         self.editor.with_buffer(|x| {
-            (cursor_rect(x, cursor).unwrap() / pixels_per_point)
-                .translate(logical_min_pos.to_vec2())
-        })
+            draw_buf(
+                x,
+                Logical(resp.rect.min),
+                Logical(painter.clip_rect()),
+                None,
+                None,
+                &mut painter,
+                |_, _| None,
+                |_| {},
+                |_| None::<LineSelection>,
+                |_, _, _| {},
+                |run, painter| {
+                    draw_run(
+                        run,
+                        font_system,
+                        swash_cache,
+                        atlas,
+                        painter,
+                        Logical(resp.rect),
+                        self.style.spacing,
+                        self.style.text_color,
+                    );
+                },
+            )
+        });
+
+        // Doesn't blink, the view can't be typed into
+        if resp.has_focus() && view.selection_bounds().is_none() {
+            self.draw_cursor(
+                ui.ctx(),
+                &mut painter,
+                view.cursor,
+                Color32::WHITE,
+                Logical(resp.rect.min),
+                pixels_per_point,
+            );
+        }
+
+        resp
     }
 
-    fn apply_to_cursor_rect(
-        &mut self,
-        logical_min_pos: Pos2,
-        pixels_per_point: f32,
-        f: impl FnOnce(&mut Self, Rect)
-    ) {
+    /// Moves the cursor to the visual row above or below, keeping its x position like platform
+    /// editors do. cosmic-text's own Up and Down keep the glyph index instead, which drifts
+    /// sideways with proportional fonts.
+    ///
+    /// With a `distance`, e.g. a page, it moves to the row that far away instead, stopping at the
+    /// first or last row.
+    fn move_vertically(&mut self, down: bool, distance: Option<Phys<f32>>) {
         let cursor = self.editor.cursor();
-        let cursor_rect = self.editor.with_buffer(|x| {
-            cursor_rect(x, cursor)
+        let spacing = self.style.spacing;
+        let vertical_motion_x = self.vertical_motion_x.filter(|(at, _)| *at == cursor);
+        let moved = self.editor.with_buffer(|buf| {
+            let rect = cursor_rect(buf, cursor, spacing)?;
+            let x = vertical_motion_x.map_or(Phys(rect.min.x), |(_, x)| x);
+            let center_y = rect.center().y;
+            let runs = || buf.layout_runs();
+            let target = match distance {
+                Some(Phys(distance)) => {
+                    let target_y = match down {
+                        true => center_y + distance,
+                        false => center_y - distance,
+                    };
+                    runs()
+                        .find(|run| target_y < run.line_top + run.line_height)
+                        .or_else(|| runs().last())?
+                }
+                None => {
+                    let row = runs().position(|run| center_y < run.line_top + run.line_height)?;
+                    match down {
+                        true => runs().nth(row + 1)?,
+                        false => runs().nth(row.checked_sub(1)?)?,
+                    }
+                }
+            };
+            let unspaced_x = match spacing.is_zero() {
+                true => *x,
+                false => spacing.unspace_x(target.glyphs, target.text, *x),
+            };
+            let cursor = buf.hit(unspaced_x, target.line_top + target.line_height / 2.0)?;
+            Some((cursor, x))
         });
+        if let Some((cursor, x)) = moved {
+            self.editor.set_cursor(cursor);
+            self.vertical_motion_x = Some((cursor, x));
+        }
+    }
 
-        if let Some(cursor_rect) = cursor_rect {
-            let cursor_rect = (cursor_rect / pixels_per_point)
-                .translate(logical_min_pos.to_vec2());
+    /// Converts a logical position relative to the widget to a physical position in the laid
+    /// out buffer, undoing the [spacing](Self::set_letter_spacing).
+    fn hit_pos(&self, pos: Pos2, pixels_per_point: f32) -> Pos2 {
+        let Pos2 { x, y } = *to_physical(Logical(pos), pixels_per_point);
+        if self.style.spacing.is_zero() {
+            return pos2(x, y);
+        }
+        let x = self.editor.with_buffer(|buf| {
+            buf.layout_runs()
+                .find(|run| y < run.line_top + run.line_height)
+                .map(|run| self.style.spacing.unspace_x(run.glyphs, run.text, x))
+                .unwrap_or(x)
+        });
+        pos2(x, y)
+    }
 
-            f(self, cursor_rect)
+    /// `pos` is relative to the widget and in **logical pixels**
+    fn selection_drag_text(
+        &self,
+        ui: &Ui,
+        has_focus: bool,
+        pos: Pos2,
+        pixels_per_point: f32,
+    ) -> Option<String> {
+        let modifiers = self.selection_drag_modifiers?;
+        let held = ui.input(|i| i.modifiers);
+        // The selection isn't shown without focus, Shift+clicks extend it instead
+        if !has_focus || !held.matches_logically(modifiers) || (held.shift && !modifiers.shift) {
+            return None;
         }
+        let cursor = self.hit(Logical(pos), pixels_per_point)?;
+        self.is_selected(cursor)
+            .then(|| self.selection_text())
+            .flatten()
     }
 
-    fn draw_cursor(
+    /// Moves the cursor to a middle click and pastes the primary selection there, returns whether
+    /// anything was pasted.
+    fn primary_paste(
         &mut self,
-        ctx: &egui::Context,
-        painter: &mut Painter,
-        logical_min_pos: Pos2,
+        ui: &Ui,
+        font_system: &mut FontSystem,
+        pos: Pos2,
         pixels_per_point: f32,
+    ) -> bool {
+        let Some(read_primary_selection) = &mut self.read_primary_selection else {
+            return false;
+        };
+        let text = read_primary_selection().filter(|text| !text.is_empty());
+        let hit_pos = self.hit_pos(pos, pixels_per_point);
+        self.change(font_system, |font_system, widget| {
+            widget
+                .editor
+                .action(font_system, ClickType::Single.as_action(hit_pos));
+        });
+        self.last_updated_time = ui.input(|i| i.time);
+        match text {
+            Some(text) => {
+                self.insert_string(text, font_system);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The selection's anchor, or the cursor without a selection.
+    fn selection_anchor(&self) -> Cursor {
+        match self.editor.selection() {
+            Selection::None => self.editor.cursor(),
+            Selection::Normal(anchor) | Selection::Word(anchor) | Selection::Line(anchor) => anchor,
+        }
+    }
+
+    /// Selects whole words or lines from `anchor` to the one at the physical `hit_pos`, backwards
+    /// when that one comes first. Goes by logical order, so right-to-left runs snap the same way.
+    fn select_units(&mut self, click_type: ClickType, anchor: (Cursor, Cursor), hit_pos: Pos2) {
+        let Pos2 { x, y } = hit_pos.round();
+        let Some(hovered) = self
+            .editor
+            .with_buffer(|buf| click_type.unit(buf, buf.hit(x, y)?))
+        else {
+            return;
+        };
+        let key = |cursor: Cursor| (cursor.line, cursor.index);
+        let (from, to) = match key(hovered.0) >= key(anchor.0) {
+            true => {
+                let end = std::cmp::max_by_key(anchor.1, hovered.1, |&x| key(x));
+                (
+                    anchor.0,
+                    Cursor::new_with_affinity(end.line, end.index, Affinity::Before),
+                )
+            }
+            false => (anchor.1, hovered.0),
+        };
+        self.editor.set_selection(Selection::Normal(from));
+        self.editor.set_cursor(to);
+    }
+
+    /// Runs a key's command, returns whether to scroll to the cursor.
+    /// Types `text`, a char or an escaped control's placeholder, as one undo step, or as part of
+    /// the last one while [typing continues](Self::set_undo_group_window).
+    fn type_text(
+        &mut self,
+        text: &str,
+        placeholder: bool,
+        time: f64,
+        font_system: &mut FontSystem,
     ) {
-        self.apply_to_cursor_rect(logical_min_pos, pixels_per_point, |editor, cursor_rect| {
-            // Probably shouldn't render the cursor if it isn't in view.
-            // Shouldn't matter much, it'll be clipped, etc.
-            let cursor_rect = painter.round_rect_to_pixels(cursor_rect);
-            editor.cursor_style
-                .with_texture(ctx, editor.line_height(), |cursor_texture| {
-                    let cursor_texture_id = cursor_texture.texture_id();
-                    painter.image(
-                        cursor_texture_id,
-                        cursor_rect,
-                        Rect::from_two_pos(Pos2::ZERO, pos2(1.0, 1.0)),
-                        Color32::WHITE,
-                    );
-                });
+        let Some(c) = text.chars().next() else {
+            return;
+        };
+        let cursor = self.editor.cursor();
+        let window = self.undo_group_window.map_or(-1.0, |x| x.as_secs_f64());
+        let continues = self.typing_group.is_some_and(|group| {
+            !c.is_whitespace()
+                && self.selection().is_none()
+                && (group.cursor.line, group.cursor.index) == (cursor.line, cursor.index)
+                && group.commands == self.commands.len()
+                && time - group.time <= window
+        });
+        let commands = self.commands.len();
+        self.change(font_system, |font_system, widget| {
+            if widget.overwrite {
+                widget.select_overwritten_char();
+            }
+            let attrs = widget.typing_attrs_list();
+            if placeholder {
+                widget.editor.insert_string(text, attrs);
+                let end = widget.editor.cursor();
+                let start = Cursor::new(end.line, end.index - text.len());
+                widget.add_escapes(vec![start..end]);
+                return;
+            }
+            match attrs {
+                Some(attrs) if !c.is_control() => {
+                    widget
+                        .editor
+                        .insert_string(c.encode_utf8(&mut [0; 4]), Some(attrs));
+                }
+                _ => widget.editor.action(font_system, Action::Insert(c)),
+            }
+        });
+        // Not recorded, e.g. over the char limit
+        if self.commands.len() == commands {
+            self.typing_group = None;
+            return;
+        }
+        if continues {
+            self.commands.merge(|mut it| {
+                let start = it.clone();
+                let step = match (it.next(), it.next()) {
+                    (Some(last), Some(group)) => HistoryStep {
+                        change: Change {
+                            items: [&group.change.items[..], &last.change.items[..]].concat(),
+                        },
+                        before: group.before,
+                        after: last.after,
+                        escaped_before: group.escaped_before.clone(),
+                        escaped_after: last.escaped_after.clone(),
+                        text_replacements: last.text_replacements,
+                    },
+                    _ => return ControlFlow::Break(None),
+                };
+                ControlFlow::Break(Some(Merge {
+                    start,
+                    end: it,
+                    command: Some(step),
+                }))
+            });
+        }
+        self.typing_group = Some(TypingGroup {
+            cursor: self.editor.cursor(),
+            time,
+            commands: self.commands.len(),
         });
     }
+
+    fn run_command(
+        &mut self,
+        command: &EditorCommand<L>,
+        modifiers: Modifiers,
+        font_system: &mut FontSystem,
+        time: f64,
+        page_height: Phys<f32>,
+    ) -> bool {
+        self.typing_group = None;
+        match command {
+            // Left for the app
+            EditorCommand::Motion(Motion::Up | Motion::Down) if self.single_line => {
+                return false;
+            }
+            EditorCommand::Motion(motion) => {
+                self.last_updated_time = time;
+                self.typing_attrs = None;
+                match self.editor.selection() {
+                    Selection::None if modifiers.shift => {
+                        self.editor
+                            .set_selection(Selection::Normal(self.editor.cursor()));
+                    }
+                    _ => {
+                        if !modifiers.shift {
+                            self.editor.set_selection(Selection::None);
+                        }
+                    }
+                }
+                match (motion, self.line_motions) {
+                    (Motion::Up, _) => self.move_vertically(false, None),
+                    (Motion::Down, _) => self.move_vertically(true, None),
+                    (Motion::PageUp, _) => self.move_vertically(false, Some(page_height)),
+                    (Motion::PageDown, _) => self.move_vertically(true, Some(page_height)),
+                    (Motion::Home, LineMotions::Paragraphs) => self
+                        .editor
+                        .action(font_system, Action::Motion(Motion::ParagraphStart)),
+                    (Motion::End, LineMotions::Paragraphs) => self
+                        .editor
+                        .action(font_system, Action::Motion(Motion::ParagraphEnd)),
+                    (motion, _) => self.editor.action(font_system, Action::Motion(*motion)),
+                }
+            }
+            EditorCommand::DeleteBackward
+            | EditorCommand::DeleteForward
+            | EditorCommand::DeleteWordBackward
+            | EditorCommand::DeleteWordForward => {
+                let backwards = matches!(
+                    command,
+                    EditorCommand::DeleteBackward | EditorCommand::DeleteWordBackward
+                );
+                let words = matches!(
+                    command,
+                    EditorCommand::DeleteWordBackward | EditorCommand::DeleteWordForward
+                );
+                self.change(font_system, |font_system, widget| {
+                    let has_selection = widget
+                        .editor
+                        .selection_bounds()
+                        .is_some_and(|(start, end)| start != end);
+                    match (words && !has_selection, backwards) {
+                        (true, _) => widget.delete_word(font_system, backwards),
+                        (false, true) => widget.editor.action(font_system, Action::Backspace),
+                        (false, false) => widget.editor.action(font_system, Action::Delete),
+                    }
+                });
+            }
+            EditorCommand::DeleteToEndOfLine => {
+                self.change(font_system, |font_system, widget| {
+                    widget.delete_to_end_of_line(font_system);
+                });
+            }
+            EditorCommand::Newline | EditorCommand::SoftBreak if self.single_line => {
+                self.submitted = true;
+                return false;
+            }
+            EditorCommand::Newline => {
+                self.change(font_system, |font_system, widget| {
+                    // Replace the selection first so auto-indent is taken from the line the
+                    // newline ends up on, all as a single undo step.
+                    widget.editor.delete_selection();
+                    widget.editor.action(font_system, Action::Enter);
+                    let line = widget.editor.cursor().line - 1;
+                    widget
+                        .editor
+                        .with_buffer_mut(|x| mark_line_break(x, line, LineBreak::Paragraph));
+                });
+            }
+            EditorCommand::SoftBreak => {
+                self.change(font_system, |_font_system, widget| {
+                    widget.editor.delete_selection();
+                    insert_line_break(&mut widget.editor, LineBreak::Soft);
+                });
+            }
+            // Shift+Tab is left for dedenting, it doesn't move focus either
+            EditorCommand::InsertTab
+                if self.tab_behavior != TabBehavior::Insert || modifiers.shift =>
+            {
+                return false;
+            }
+            EditorCommand::InsertTab => {
+                self.change(font_system, |_font_system, widget| widget.insert_tab());
+            }
+            EditorCommand::DuplicateLine => self.duplicate_line(font_system),
+            EditorCommand::DeleteLine => self.delete_line(font_system),
+            EditorCommand::MoveLinesUp => return self.move_lines(font_system, false),
+            EditorCommand::MoveLinesDown => return self.move_lines(font_system, true),
+            EditorCommand::ToggleOverwrite => {
+                self.overwrite = !self.overwrite;
+                // Shows the new caret right away
+                self.last_updated_time = time;
+                return false;
+            }
+            EditorCommand::OpenContextMenu => {
+                self.open_context_menu = true;
+                return false;
+            }
+            EditorCommand::ClearSelection => self.editor.set_selection(Selection::None),
+            EditorCommand::SelectAll => {
+                self.editor.set_cursor(Cursor::default());
+                let last_cursor = self.editor.with_buffer(|x| {
+                    let line_i = x.lines.len().saturating_sub(1);
+                    x.lines
+                        .last()
+                        .map(|x| x.text().len())
+                        .map(|index| Cursor::new(line_i, index))
+                        .unwrap_or_default()
+                });
+                self.editor.set_selection(Selection::Normal(last_cursor));
+                return false;
+            }
+            EditorCommand::Undo => return self.undo(),
+            EditorCommand::Redo => return self.redo(),
+            EditorCommand::Custom(command) => {
+                command(self, font_system);
+                self.invalidate_layout();
+            }
+        }
+        true
+    }
+
+    /// Deletes the selection, or from the cursor to the end of its line, or the line break if the
+    /// cursor is already there.
+    fn delete_to_end_of_line(&mut self, font_system: &mut FontSystem) {
+        let cursor = self.editor.cursor();
+        let line_end = self
+            .editor
+            .with_buffer(|x| Cursor::new(cursor.line, x.lines[cursor.line].text().len()));
+        let has_selection = self
+            .editor
+            .selection_bounds()
+            .is_some_and(|(start, end)| start != end);
+        match has_selection || cursor == line_end {
+            true => self.editor.action(font_system, Action::Delete),
+            false => self.editor.delete_range(cursor, line_end),
+        }
+    }
+
+    /// Deletes from the cursor to the previous or next word boundary, joining lines at their ends.
+    fn delete_word(&mut self, font_system: &mut FontSystem, backwards: bool) {
+        let motion = match backwards {
+            true => Motion::PreviousWord,
+            false => Motion::NextWord,
+        };
+        self.editor
+            .set_selection(Selection::Normal(self.editor.cursor()));
+        self.editor.action(font_system, Action::Motion(motion));
+        self.editor.delete_selection();
+    }
+
+    /// Selects the character after the cursor so inserting replaces it, unless something is
+    /// selected already or the cursor is at the end of the line.
+    fn select_overwritten_char(&mut self) {
+        if self
+            .editor
+            .selection_bounds()
+            .is_some_and(|(start, end)| start != end)
+        {
+            return;
+        }
+        let cursor = self.editor.cursor();
+        let next = self.editor.with_buffer(|x| {
+            let c = x
+                .lines
+                .get(cursor.line)?
+                .text()
+                .get(cursor.index..)?
+                .chars()
+                .next()?;
+            Some(Cursor::new(cursor.line, cursor.index + c.len_utf8()))
+        });
+        if let Some(next) = next {
+            self.editor.set_selection(Selection::Normal(next));
+        }
+    }
+
+    /// The lines with the cursor or selection in them.
+    fn selected_lines(&self) -> RangeInclusive<usize> {
+        let cursor = self.editor.cursor();
+        let (start, end) = self
+            .editor
+            .selection_bounds()
+            .filter(|(start, end)| start != end)
+            .unwrap_or((cursor, cursor));
+        // A selection ending at the start of a line doesn't include it
+        match end.index == 0 && end.line > start.line {
+            true => start.line..=end.line - 1,
+            false => start.line..=end.line,
+        }
+    }
+
+    /// The escaped controls on `lines`, moved to the lines `to` maps them to.
+    fn lines_escapes(
+        &self,
+        lines: RangeInclusive<usize>,
+        to: impl Fn(usize) -> usize,
+    ) -> Vec<Range<Cursor>> {
+        let first = self
+            .escaped_controls
+            .partition_point(|x| x.start.line < *lines.start());
+        self.escaped_controls[first..]
+            .iter()
+            .take_while(|x| lines.contains(&x.start.line))
+            .map(|x| {
+                let line = to(x.start.line);
+                Cursor::new(line, x.start.index)..Cursor::new(line, x.end.index)
+            })
+            .collect()
+    }
+
+    /// Moves the lines with the cursor or selection one line up or down, as one undo step.
+    ///
+    /// Returns whether there was a line to swap with.
+    fn move_lines(&mut self, font_system: &mut FontSystem, down: bool) -> bool {
+        let cursor = self.editor.cursor();
+        let (start, last) = self.selected_lines().into_inner();
+        let start = Cursor::new(start, 0);
+        let line_count = self.editor.with_buffer(|x| x.lines.len());
+        if (down && last + 1 >= line_count) || (!down && start.line == 0) {
+            return false;
+        }
+
+        // Moving the selected lines up is moving the line above them down
+        let (moved, dest) = match down {
+            true => (last + 1..=last + 1, start.line),
+            false => (start.line..=last, start.line - 1),
+        };
+        // The kinds of line breaks stay where they are, the lines move between them
+        let block = match down {
+            true => start.line..=last + 1,
+            false => start.line - 1..=last,
+        };
+        let endings = self.editor.with_buffer(|x| {
+            x.lines[block.clone()]
+                .iter()
+                .map(BufferLine::ending)
+                .collect::<Vec<_>>()
+        });
+        let (text, attrs_list, delete_start, delete_end) = self.editor.with_buffer(|x| {
+            let (text, attrs_list) = lines_text(x, moved.clone());
+            // The newline before the moved lines goes with them
+            let before = moved.start() - 1;
+            (
+                text,
+                attrs_list,
+                Cursor::new(before, x.lines[before].text().len()),
+                Cursor::new(*moved.end(), x.lines[*moved.end()].text().len()),
+            )
+        });
+
+        let shift = |cursor: Cursor| Cursor {
+            line: match down {
+                true => cursor.line + 1,
+                false => cursor.line - 1,
+            },
+            ..cursor
+        };
+        let selection = self.editor.selection();
+        let escapes = self.lines_escapes(moved.clone(), |line| dest + line - moved.start());
+        self.change(font_system, |_font_system, widget| {
+            widget.editor.delete_range(delete_start, delete_end);
+            widget
+                .editor
+                .insert_at(Cursor::new(dest, 0), &text, Some(attrs_list));
+            widget.add_escapes(escapes);
+            widget.editor.with_buffer_mut(|x| {
+                for (line, ending) in x.lines[block].iter_mut().zip(endings) {
+                    line.set_ending(ending);
+                }
+            });
+            widget.editor.set_cursor(shift(cursor));
+            // An anchor at the start of the line after the last one is the end of the text
+            let shift = |anchor| {
+                widget
+                    .editor
+                    .with_buffer(|x| clamp_cursor(x, shift(anchor)))
+            };
+            widget.editor.set_selection(map_anchor(selection, shift));
+        });
+        true
+    }
+
+    /// Inserts a copy of the lines with the cursor or selection in them above them, leaving the
+    /// cursor and selection on the lines below. Does nothing in
+    /// [single-line](Self::with_single_line) widgets.
+    pub fn duplicate_line(&mut self, font_system: &mut FontSystem) {
+        if self.single_line {
+            return;
+        }
+        let lines = self.selected_lines();
+        let (start, last) = lines.clone().into_inner();
+        let (text, attrs_list, endings) = self.editor.with_buffer(|x| {
+            let (text, attrs_list) = lines_text(x, lines.clone());
+            let endings = x.lines[lines.clone()]
+                .iter()
+                .map(BufferLine::ending)
+                .collect::<Vec<_>>();
+            (text, attrs_list, endings)
+        });
+        let count = last - start + 1;
+        let shift = |cursor: Cursor| Cursor {
+            line: cursor.line + count,
+            ..cursor
+        };
+        let cursor = self.editor.cursor();
+        let selection = self.editor.selection();
+        // The copies are where the lines were
+        let escapes = self.lines_escapes(lines, |line| line);
+        self.change(font_system, |_font_system, widget| {
+            widget
+                .editor
+                .insert_at(Cursor::new(start, 0), &text, Some(attrs_list));
+            widget.add_escapes(escapes);
+            widget.editor.with_buffer_mut(|x| {
+                // The copy's breaks are the same as the lines', a paragraph break separates them
+                let copies = x.lines[start..=last + count].iter_mut();
+                for (line, ending) in copies.zip(endings.iter().chain(&endings)) {
+                    line.set_ending(*ending);
+                }
+                mark_line_break(x, last, LineBreak::Paragraph);
+            });
+            widget.editor.set_cursor(shift(cursor));
+            widget.editor.set_selection(map_anchor(selection, shift));
+        });
+    }
+
+    /// Deletes the lines with the cursor or selection in them, the cursor keeps its column on the
+    /// line that ends up where they were.
+    pub fn delete_line(&mut self, font_system: &mut FontSystem) {
+        let (start, last) = self.selected_lines().into_inner();
+        let cursor = self.editor.cursor();
+        let (delete_start, delete_end) = self.editor.with_buffer(|x| {
+            let line_end = |line: usize| Cursor::new(line, x.lines[line].text().len());
+            match (last + 1 < x.lines.len(), start) {
+                (true, _) => (Cursor::new(start, 0), Cursor::new(last + 1, 0)),
+                // The last lines take the line break before them along
+                (false, 1..) => (line_end(start - 1), line_end(last)),
+                (false, 0) => (Cursor::new(0, 0), line_end(last)),
+            }
+        });
+        self.change(font_system, |_font_system, widget| {
+            widget.editor.delete_range(delete_start, delete_end);
+            widget.editor.set_selection(Selection::None);
+            let cursor = widget
+                .editor
+                .with_buffer(|x| clamp_cursor(x, Cursor::new(start, cursor.index)));
+            widget.editor.set_cursor(cursor);
+        });
+    }
+
+    /// Replaces the selection with a tab.
+    fn insert_tab(&mut self) {
+        self.editor.delete_selection();
+        let cursor = self.editor.cursor();
+        let end = insert_at(&mut self.editor, cursor, "\t", None);
+        self.editor.set_cursor(end);
+    }
+
+    /// Whether `cursor` is within the selection, the end of it excluded.
+    fn is_selected(&self, cursor: Cursor) -> bool {
+        self.editor
+            .selection_bounds()
+            .is_some_and(|(start, end)| start <= cursor && cursor < end)
+    }
+
+    fn paint_selection_drag_preview(&self, ui: &Ui, id: Id) {
+        let Some(pointer_pos) = ui.ctx().pointer_latest_pos() else {
+            return;
+        };
+        let Some(payload) = DragAndDrop::payload::<SelectedText>(ui.ctx()) else {
+            return;
+        };
+        let mut preview = payload
+            .0
+            .chars()
+            .take(Self::DRAG_PREVIEW_CHARS)
+            .collect::<String>();
+        if payload.0.chars().nth(Self::DRAG_PREVIEW_CHARS).is_some() {
+            preview.push('…');
+        }
+        ui.ctx()
+            .layer_painter(LayerId::new(Order::Tooltip, id))
+            .text(
+                pointer_pos + vec2(12.0, 12.0),
+                Align2::LEFT_TOP,
+                preview,
+                TextStyle::Body.resolve(ui.style()),
+                ui.visuals().strong_text_color(),
+            );
+    }
+
+    /// Only recomputes the selection rects when the selection, text, layout or visible range changed.
+    fn update_selection_rects(&mut self, bounds: (Cursor, Cursor), visible_y_range: Phys<Rangef>) {
+        let key = SelectionRectsKey {
+            bounds,
+            revision: self.revision,
+            buffer_size: self.editor.with_buffer(|x| x.size()),
+            visible_y_range: *visible_y_range,
+        };
+        if self.selection_rects_key.as_ref() == Some(&key) {
+            return;
+        }
+        self.selection_rects.clear();
+        self.editor.with_buffer(|x| {
+            selection_rects(
+                x,
+                visible_y_range,
+                bounds,
+                self.style.spacing,
+                &mut self.selection_rects,
+            )
+        });
+        self.selection_rects_key = Some(key);
+    }
+
+    fn paint_selection_rects(
+        &mut self,
+        painter: &Painter,
+        min_pos: Logical<Pos2>,
+        base_line_height: f32,
+    ) {
+        let pixels_per_point = painter.ctx().pixels_per_point();
+        let CosmicEdit {
+            selection_texture,
+            selection_rects,
+            ..
+        } = self;
+        selection_texture.with_texture(painter.ctx(), base_line_height, |texture| {
+            selection_rects.iter().for_each(|&rect| {
+                painter.image(
+                    texture.id(),
+                    *snap_to_logical(rect, min_pos, pixels_per_point),
+                    Rect::from_two_pos(Pos2::ZERO, pos2(1.0, 1.0)),
+                    Color32::WHITE,
+                );
+            });
+        });
+    }
+
+    /// Runs `f` as one undo step, everything it does to the editor is undone together. The layout
+    /// is invalidated if it changed the text, moving the cursor alone doesn't.
+    ///
+    /// Edits `f` makes through the widget, e.g. [`Self::insert_string`] for each match of a
+    /// replace all, are part of the step, nested calls are flattened into the outermost one. A
+    /// step that changes nothing isn't recorded.
+    ///
+    /// A step that grows the text past [the limit](Self::set_char_limit) is undone right away,
+    /// without being recorded.
+    pub fn change<F: FnOnce(&mut FontSystem, &mut Self)>(
+        &mut self,
+        font_system: &mut FontSystem,
+        f: F,
+    ) {
+        if self.in_change {
+            f(font_system, self);
+            return;
+        }
+        let chars_before = self.char_limit.map(|_| self.char_count());
+        let (cursor, selection) = (self.editor.cursor(), self.editor.selection());
+        let line_lens = self.tracked_line_lens();
+        let escaped_before = self.escaped_controls.clone();
+        self.editor.start_change();
+
+        self.in_change = true;
+        f(font_system, self);
+        self.move_escapes_along();
+        self.in_change = false;
+
+        if let Some(mut change) = self.editor.finish_change() {
+            change.items = std::mem::take(&mut self.change_items);
+            // Ends short of a control char `insert_at` put back
+            for item in change.items.iter_mut().filter(|x| x.insert) {
+                item.end = history::insert_end(item.start, &item.text);
+            }
+            let chars = chars_before.map(|before| (before, self.char_count()));
+            let over_limit = self
+                .char_limit
+                .zip(chars)
+                .is_some_and(|(limit, (before, after))| after > limit && after > before);
+            if over_limit && !change.items.is_empty() {
+                change.reverse();
+                for item in &change.items {
+                    apply_change_item(&mut self.editor, item);
+                }
+                self.editor.set_cursor(cursor);
+                self.editor.set_selection(selection);
+                self.escaped_controls = escaped_before;
+                self.invalidate_layout();
+                return;
+            }
+            if !change.items.is_empty() {
+                self.track_changes_of(line_lens, &change.items);
+                let bytes = change.items.iter().map(|x| x.text.len()).sum::<usize>();
+                match self.max_recorded_change_bytes {
+                    // Older changes couldn't be undone past this one anyway
+                    Some(max) if bytes > max => {
+                        self.commands.clear();
+                        self.history_barriers += 1;
+                    }
+                    _ => self.commands.push(HistoryStep {
+                        change,
+                        before: (cursor, selection),
+                        after: (self.editor.cursor(), self.editor.selection()),
+                        escaped_before,
+                        escaped_after: self.escaped_controls.clone(),
+                        text_replacements: self.text_replacements,
+                    }),
+                }
+                self.frame_changed = true;
+                self.unstamped_change = true;
+                self.invalidate_layout();
+            }
+        }
+    }
+
+    /// Moves the escaped controls along with what the change in progress did since it started or
+    /// this was last called, so escapes can be added in the text as it is now.
+    fn move_escapes_along(&mut self) {
+        if !self.in_change {
+            return;
+        }
+        if let Some(change) = self.editor.finish_change() {
+            move_escapes(&mut self.escaped_controls, &change.items);
+            self.change_items.extend(change.items);
+        }
+        self.editor.start_change();
+        self.editor
+            .with_buffer(|x| retain_escapes(x, &mut self.escaped_controls));
+    }
+
+    /// Applies a change made outside of the widget with items relative to the current text, e.g.
+    /// one received from another user in a collaborative session.
+    ///
+    /// [`ChangeOrigin::Local`] changes are recorded for undo like typing. Undo and redo skip
+    /// remote ones: the recorded changes are rebased over them, so undo never touches text another
+    /// user inserted. The caret and selection keep their place in the text. Changes that were
+    /// undone can't be redone after a remote change.
+    ///
+    /// Returns whether every item matched the text, applying stops at the first one that doesn't.
+    pub fn apply_change(
+        &mut self,
+        origin: ChangeOrigin,
+        change: &Change,
+        font_system: &mut FontSystem,
+    ) -> bool {
+        let applied = match origin {
+            ChangeOrigin::Local => {
+                let mut applied = false;
+                self.change(font_system, |_font_system, widget| {
+                    applied = apply_history_action_to_editor(
+                        cosmic_undo_2::Action::Do(change),
+                        &mut widget.editor,
+                    );
+                });
+                applied
+            }
+            ChangeOrigin::Remote(_) => {
+                let cursor = self.editor.cursor();
+                let selection = self.editor.selection();
+                let items = change
+                    .items
+                    .iter()
+                    .take_while(|item| apply_change_item(&mut self.editor, item))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                self.move_cursors_along(cursor, selection, &items);
+                move_escapes(&mut self.escaped_controls, &items);
+                self.editor
+                    .with_buffer(|x| retain_escapes(x, &mut self.escaped_controls));
+                self.rebase_history(&items);
+                if !items.is_empty() {
+                    self.unstamped_change = true;
+                    self.invalidate_layout();
+                }
+                items.len() == change.items.len()
+            }
+        };
+        applied
+    }
+
+    /// Rebases the recorded changes over `remote`, which was just applied, so they can still be
+    /// undone one after the other.
+    fn rebase_history(&mut self, remote: &[ChangeItem]) {
+        self.typing_group = None;
+        self.commands.remove_all_undone();
+        let mut remote = remote.to_vec();
+        let mut rebased = self
+            .commands
+            .iter_realized()
+            .filter_map(|step| {
+                // Newest first, as `remote` comes after all of them
+                let mut undo = step.change.clone();
+                undo.reverse();
+                let (items, remote_before) = history::transform(&undo.items, &remote, false);
+                let after = transform_caret(step.after, &remote);
+                let before = transform_caret(step.before, &remote_before);
+                let mut escaped_after = step.escaped_after.clone();
+                move_escapes(&mut escaped_after, &remote);
+                let mut escaped_before = step.escaped_before.clone();
+                move_escapes(&mut escaped_before, &remote_before);
+                remote = remote_before;
+                let mut change = Change { items };
+                change.reverse();
+                // What it did was overwritten by the remote change
+                (!change.items.is_empty()).then_some(HistoryStep {
+                    change,
+                    before,
+                    after,
+                    escaped_before,
+                    escaped_after,
+                    text_replacements: step.text_replacements,
+                })
+            })
+            .collect::<Vec<_>>();
+        rebased.reverse();
+        self.commands = rebased.into_iter().collect();
+    }
+
+    /// Puts the caret and selection back where they were in the text before `items` were applied.
+    fn move_cursors_along(&mut self, cursor: Cursor, selection: Selection, items: &[ChangeItem]) {
+        let (cursor, selection) = transform_caret((cursor, selection), items);
+        self.editor.set_cursor(cursor);
+        self.editor.set_selection(selection);
+    }
+
+    /// Applies `deltas` one after the other as a single undo step, e.g. from
+    /// [`diff_buffers`](crate::util::diff_buffers) to sync the text with a model kept elsewhere
+    /// without [`Self::set_text`] resetting the undo history. The caret and selection keep their
+    /// place in the text.
+    ///
+    /// Returns whether every delta fit the text, applying stops at the first one that doesn't.
+    pub fn apply_deltas(&mut self, deltas: &[TextDelta], font_system: &mut FontSystem) -> bool {
+        let cursor = self.editor.cursor();
+        let selection = self.editor.selection();
+        let mut items = Vec::new();
+        let mut applied = 0;
+        self.change(font_system, |_font_system, widget| {
+            for delta in deltas {
+                match apply_delta(&mut widget.editor, delta) {
+                    Some(delta_items) => items.extend(delta_items),
+                    None => break,
+                }
+                applied += 1;
+            }
+        });
+        self.move_cursors_along(cursor, selection, &items);
+        applied == deltas.len()
+    }
+
+    /// Turns the text into `text` by [applying](Self::apply_deltas) only what differs, e.g. to
+    /// sync it with a model kept elsewhere every frame. Unlike [`Self::set_text`], the caret,
+    /// selection and undo history are kept, and the same text changes nothing.
+    ///
+    /// Line endings, soft breaks and control chars are read like in `set_text`. Inserted text
+    /// takes the attributes around it like typed text does, and its soft breaks become
+    /// paragraph breaks.
+    pub fn sync_text(&mut self, text: &str, font_system: &mut FontSystem) {
+        let mut text = Cow::Borrowed(text);
+        if text.contains('\r') {
+            text = normalize_line_endings(text.into_owned()).into();
+        }
+        if splits_soft_breaks(&self.soft_break) && text.contains(self.soft_break.as_str()) {
+            text = text.replace(self.soft_break.as_str(), "\n").into();
+        }
+        if self.single_line && text.contains('\n') {
+            text = text.replace('\n', " ").into();
+        }
+        let (text, placeholders) = self.sanitize(&text);
+        let deltas = self.editor.with_buffer(|x| diff_text(x, &text));
+        let escapes = placeholder_cursors(Cursor::new(0, 0), &text, &placeholders);
+        if deltas.is_empty() && escapes == self.escaped_controls {
+            return;
+        }
+        // The text is `text` afterwards, with its escapes wherever the diff kept or put them
+        self.change(font_system, |font_system, widget| {
+            widget.apply_deltas(&deltas, font_system);
+            widget.move_escapes_along();
+            widget.escaped_controls = escapes;
+        });
+    }
+
+    /// Puts the caret and selection back where they were before the undone change or after the
+    /// redone one.
+    ///
+    /// Returns whether to scroll to cursor
+    fn apply_history_actions(
+        &mut self,
+        actions: impl FnOnce(&mut Commands<HistoryStep>) -> ActionIter<HistoryStep>,
+    ) -> bool {
+        let mut caret = None;
+        self.typing_group = None;
+        let text_changes = &mut self.text_changes;
+        let escaped_controls = &mut self.escaped_controls;
+        let text_replacements = self.text_replacements;
+        actions(&mut self.commands).for_each(|x| {
+            let (step_caret, step_escapes) = match x {
+                cosmic_undo_2::Action::Do(step) => (step.after, &step.escaped_after),
+                cosmic_undo_2::Action::Undo(step) => (step.before, &step.escaped_before),
+            };
+            let step_replacements = match x {
+                cosmic_undo_2::Action::Do(step) | cosmic_undo_2::Action::Undo(step) => {
+                    step.text_replacements
+                }
+            };
+            caret = Some(step_caret);
+            let change = history_action_change(x.map(|step| &step.change));
+            let mut line_lens = text_changes
+                .is_some()
+                .then(|| self.editor.with_buffer(line_lens));
+            let applied = change
+                .items
+                .iter()
+                .take_while(|item| apply_change_item(&mut self.editor, item))
+                .count();
+            if applied < change.items.len() {
+                self.skipped_history_changes += 1;
+            }
+            match applied == change.items.len() && step_replacements == text_replacements {
+                // Brings back the escapes of text the undone change deleted
+                true => escaped_controls.clone_from(step_escapes),
+                false => move_escapes(escaped_controls, &change.items[..applied]),
+            }
+            self.editor
+                .with_buffer(|x| retain_escapes(x, escaped_controls));
+            if let Some((changes, line_lens)) = text_changes.as_mut().zip(line_lens.as_mut()) {
+                changes.extend(history::text_changes(line_lens, &change.items[..applied]));
+            }
+        });
+        let Some((cursor, selection)) = caret else {
+            return false;
+        };
+        self.frame_changed = true;
+        self.invalidate_layout();
+        self.editor.set_cursor(cursor);
+        self.editor.set_selection(selection);
+        // Skipped changes can leave it outside of the text
+        self.sanitize_cursors();
+        true
+    }
+
+    /// Whether edits and cursor movement scroll the caret into view, `true` by default.
+    ///
+    /// Disable this while the text is rewritten programmatically, e.g. tailing a log, so the view
+    /// stays where the user scrolled to.
+    pub fn set_follow_cursor(&mut self, follow_cursor: bool) {
+        self.follow_cursor = follow_cursor;
+    }
+
+    pub fn follow_cursor(&self) -> bool {
+        self.follow_cursor
+    }
+
+    /// Scrolls the enclosing `ScrollArea` to the end of the text the next time the widget is shown,
+    /// regardless of where the caret is.
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_to_bottom = true;
+    }
+
+    /// Scrolls the enclosing `ScrollArea` to the caret the next time the widget is shown, even
+    /// without focus or [`Self::follow_cursor`], e.g. after [`Self::set_selection_range`].
+    pub fn scroll_to_cursor(&mut self) {
+        self.scroll_to_cursor = true;
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.apply_history_actions(Commands::undo)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.apply_history_actions(Commands::redo)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.commands.current_command_index().is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.commands.is_undoing()
+    }
+
+    /// Forgets every undo and redo step and keeps the text, e.g. once a document is saved or a new
+    /// one is started.
+    pub fn clear_history(&mut self) {
+        self.commands.clear();
+        self.typing_group = None;
+    }
+
+    /// Whether edits are kept for [`Self::take_changes`], `false` by default. Turning it off drops
+    /// the ones not taken yet.
+    pub fn set_track_changes(&mut self, track_changes: bool) {
+        match track_changes {
+            true => {
+                self.text_changes.get_or_insert_with(Vec::new);
+            }
+            false => self.text_changes = None,
+        }
+    }
+
+    pub fn track_changes(&self) -> bool {
+        self.text_changes.is_some()
+    }
+
+    /// The edits made since the last call while [tracking](Self::set_track_changes), in order,
+    /// e.g. to mirror them into a model kept elsewhere. Each one's range is in the text the ones
+    /// before it left, with `\n` line breaks like [`Self::selection_range`]. That's the text
+    /// [`Self::text`] returns only with the default [line ending](Self::set_line_ending) and
+    /// [soft break](Self::with_soft_break), a model kept with others has to convert them itself.
+    ///
+    /// Undo and redo are included. [`Self::set_text`], [`Self::with_editor_mut`] and
+    /// [`Self::replace_editor`] replace the whole text. Changes applied with
+    /// [`ChangeOrigin::Remote`] aren't, they came from the app.
+    pub fn take_changes(&mut self) -> Vec<TextChange> {
+        self.text_changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The line lengths to turn the next edit into [`TextChange`]s with, `None` unless tracking.
+    fn tracked_line_lens(&self) -> Option<Vec<usize>> {
+        self.text_changes.as_ref()?;
+        Some(self.editor.with_buffer(line_lens))
+    }
+
+    fn track_changes_of(&mut self, line_lens: Option<Vec<usize>>, items: &[ChangeItem]) {
+        if let Some((changes, mut line_lens)) = self.text_changes.as_mut().zip(line_lens) {
+            changes.extend(history::text_changes(&mut line_lens, items));
+        }
+    }
+
+    /// Tracks the text as replaced as a whole, `line_lens` are from before it was.
+    fn track_replaced_text(&mut self, line_lens: Option<Vec<usize>>) {
+        if let Some((changes, line_lens)) = self.text_changes.as_mut().zip(line_lens) {
+            let len = line_lens.iter().sum::<usize>() + line_lens.len().saturating_sub(1);
+            let inserted = self.editor.with_buffer(|x| {
+                x.lines
+                    .iter()
+                    .map(|x| x.text())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+            changes.push(TextChange {
+                range: 0..len,
+                inserted,
+            });
+        }
+    }
+
+    /// Changes recording more than `max` bytes of text, e.g. a huge paste, are applied but not
+    /// kept for undo. The history is cleared instead, so nothing before them can be undone.
+    ///
+    /// `None`, the default, records every change.
+    pub fn set_max_recorded_change_bytes(&mut self, max: Option<usize>) {
+        self.max_recorded_change_bytes = max;
+    }
+
+    /// How long after typing a char the next one is still undone together with it, 500ms by
+    /// default. Whitespace, clicks, commands like caret motion, other edits and losing focus start
+    /// a new group. `None` undoes every char on its own.
+    pub fn set_undo_group_window(&mut self, window: Option<Duration>) {
+        self.undo_group_window = window;
+        self.typing_group = None;
+    }
+
+    pub fn undo_group_window(&self) -> Option<Duration> {
+        self.undo_group_window
+    }
+
+    /// How many times the undo history was cleared by a change over
+    /// [the limit](Self::set_max_recorded_change_bytes).
+    pub fn history_barriers(&self) -> usize {
+        self.history_barriers
+    }
+
+    /// How many undo or redo steps didn't match the text anymore and were (partially) skipped
+    /// instead of being applied.
+    ///
+    /// This stays at 0 unless the buffer was edited without going through the widget's history.
+    pub fn skipped_history_changes(&self) -> usize {
+        self.skipped_history_changes
+    }
+
+    /// Copies the selection to egui's clipboard output and returns the copied text,
+    /// e.g. to also hand it to a clipboard crate.
+    ///
+    /// Only one copy is sent to egui per frame, even if both a shortcut and the context menu copy.
+    pub fn copy(&mut self, ui: &mut Ui) -> Option<String> {
+        let string = self.selected_text()?;
+        let frame_nr = compat::frame_nr(ui.ctx());
+        if self.last_copy_frame != Some(frame_nr) {
+            self.last_copy_frame = Some(frame_nr);
+            compat::copy_text(ui.ctx(), string.clone());
+        }
+        Some(string)
+    }
+
+    /// The selection as [`Self::copy`] would copy it, without going through the clipboard.
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection()?;
+        self.selection_text()
+    }
+
+    /// The start and end of the selection, `None` if nothing is selected.
+    pub fn selection(&self) -> Option<(Cursor, Cursor)> {
+        // Only the affinity can differ after selecting back to where it started
+        self.editor
+            .selection_bounds()
+            .filter(|(start, end)| (start.line, start.index) != (end.line, end.index))
+    }
+
+    /// Selects `range`, byte offsets into the text with `\n` line breaks like [`Self::text`]
+    /// returns it by default, e.g. a match found by the app. The caret goes to its end.
+    ///
+    /// Offsets past the end go to the end, ones inside a char to its start.
+    pub fn set_selection_range(&mut self, range: Range<usize>) {
+        let (start, end) = self.editor.with_buffer(|x| {
+            let cursor = |offset| offset_to_cursor(x, offset).unwrap_or_else(|| end_cursor(x));
+            (cursor(range.start), cursor(range.end))
+        });
+        self.editor.set_cursor(end);
+        self.editor.set_selection(Selection::Normal(start));
+        self.typing_attrs = None;
+    }
+
+    /// The selection as byte offsets, see [`Self::set_selection_range`].
+    pub fn selection_range(&self) -> Option<Range<usize>> {
+        let (start, end) = self.selection()?;
+        Some(self.cursor_to_offset(start)..self.cursor_to_offset(end))
+    }
+
+    /// [`offset_to_cursor`] in the widget's text.
+    pub fn offset_to_cursor(&self, byte_offset: usize) -> Option<Cursor> {
+        self.editor
+            .with_buffer(|x| offset_to_cursor(x, byte_offset))
+    }
+
+    /// [`cursor_to_offset`] in the widget's text.
+    pub fn cursor_to_offset(&self, cursor: Cursor) -> usize {
+        self.editor.with_buffer(|x| cursor_to_offset(x, cursor))
+    }
+
+    pub fn cut(&mut self, ui: &mut Ui, font_system: &mut FontSystem) -> bool {
+        if self.copy(ui).is_none() {
+            return false;
+        }
+        self.change(font_system, |_font_system, widget| {
+            widget.editor.delete_selection();
+        });
+        true
+    }
+
+    // Check if string is empty here?
+    /// Inserts at the cursor, replacing the selection, as one undo step. Returns where the text
+    /// ended up, empty if none was inserted, e.g. at the [char limit](Self::set_char_limit).
+    ///
+    /// If the change is larger than [`Self::set_max_recorded_change_bytes`] allows, it isn't
+    /// recorded and the undo history is cleared instead.
+    ///
+    /// Line endings are converted to `\n`, see [`Self::set_line_ending`], and
+    /// [soft breaks](Self::with_soft_break) become soft line breaks. In
+    /// [single-line](Self::with_single_line) widgets they all become spaces.
+    pub fn insert_string(&mut self, string: String, font_system: &mut FontSystem) -> Range<Cursor> {
+        debug_assert!(!string.is_empty());
+        self.insert_text(string, self.typing_attrs_list(), font_system)
+    }
+
+    /// Inserts `text` at `cursor` as one undo step, e.g. generated text, its spans styled by
+    /// `attrs`. The caret and selection keep their place in the text, the selection isn't
+    /// replaced.
+    ///
+    /// The text is converted like in [`Self::insert_string`], which also tells what's returned.
+    pub fn insert_string_at(
+        &mut self,
+        cursor: Cursor,
+        text: &str,
+        attrs: Option<AttrsList>,
+        font_system: &mut FontSystem,
+    ) -> Range<Cursor> {
+        self.replace_text(cursor..cursor, text, attrs, font_system)
+    }
+
+    /// Replaces the text between two cursors with `replacement` as one undo step, e.g. a range
+    /// returned by [`Self::insert_string_at`] or made from byte offsets with
+    /// [`Self::offset_to_cursor`]. Returns where the replacement ended up.
+    ///
+    /// The caret and selection keep their place in the text, where they were inside of the
+    /// range they go to its start. The replacement is converted like in [`Self::insert_string`].
+    pub fn replace_range(
+        &mut self,
+        range: Range<Cursor>,
+        replacement: &str,
+        font_system: &mut FontSystem,
+    ) -> Range<Cursor> {
+        self.replace_text(range, replacement, None, font_system)
+    }
+
+    /// Deletes the text between two cursors as one undo step, see [`Self::replace_range`].
+    pub fn delete_range(&mut self, range: Range<Cursor>, font_system: &mut FontSystem) {
+        self.replace_text(range, "", None, font_system);
+    }
+
+    fn replace_text(
+        &mut self,
+        range: Range<Cursor>,
+        text: &str,
+        attrs: Option<AttrsList>,
+        font_system: &mut FontSystem,
+    ) -> Range<Cursor> {
+        let caret = (self.editor.cursor(), self.editor.selection());
+        let (start, end) = self.editor.with_buffer(|x| {
+            let (a, b) = (clamp_cursor(x, range.start), clamp_cursor(x, range.end));
+            match (a.line, a.index) <= (b.line, b.index) {
+                true => (a, b),
+                false => (b, a),
+            }
+        });
+        let mut inserted = start..start;
+        self.change(font_system, |font_system, widget| {
+            let mut items = Vec::new();
+            if (start.line, start.index) != (end.line, end.index) {
+                let text = widget.editor.with_buffer(|x| text_between(x, start, end));
+                widget.editor.delete_range(start, end);
+                items.push(ChangeItem {
+                    start,
+                    end,
+                    text,
+                    insert: false,
+                });
+            }
+            widget.editor.set_cursor(start);
+            widget.editor.set_selection(Selection::None);
+            if !text.is_empty() {
+                inserted = widget.insert_text(text.to_string(), attrs, font_system);
+                items.push(ChangeItem {
+                    start: inserted.start,
+                    end: inserted.end,
+                    text: widget
+                        .editor
+                        .with_buffer(|x| text_between(x, inserted.start, inserted.end)),
+                    insert: true,
+                });
+            }
+            let (cursor, selection) = transform_caret(caret, &items);
+            widget.editor.set_cursor(cursor);
+            widget.editor.set_selection(selection);
+        });
+        inserted
+    }
+
+    fn insert_text(
+        &mut self,
+        text: String,
+        attrs: Option<AttrsList>,
+        font_system: &mut FontSystem,
+    ) -> Range<Cursor> {
+        let start = self
+            .editor
+            .selection_bounds()
+            .map_or(self.editor.cursor(), |(start, _)| start);
+        let Some((text, attrs, mut placeholders)) = self.convert_text(text, attrs) else {
+            return start..start;
+        };
+        let text = self.fit_char_limit(&text, false);
+        let text = whole_placeholders(text, &mut placeholders);
+        if text.is_empty() {
+            return start..start;
+        }
+        self.change(font_system, |_font_system, widget| {
+            let escapes = insert_with_line_breaks(
+                &mut widget.editor,
+                text,
+                attrs,
+                &placeholders,
+                &widget.soft_break,
+                widget.single_line,
+            );
+            widget.add_escapes(escapes);
+        });
+        start..self.editor.cursor()
+    }
+
+    /// Converts inserted text like [`Self::insert_string`] describes, moving the spans of `attrs`
+    /// along with their chars. `None` if the [char filter](Self::set_char_filter) rejects it.
+    ///
+    /// Also returns the byte ranges of the controls it escaped.
+    fn convert_text(
+        &self,
+        text: String,
+        attrs: Option<AttrsList>,
+    ) -> Option<(String, Option<AttrsList>, Vec<Range<usize>>)> {
+        let convert = |text: String| {
+            let text = normalize_line_endings(text);
+            let (text, mut placeholders) = self.sanitize(&text);
+            let text = self.filter_chars(&text, &mut placeholders)?.into_owned();
+            Some((text, placeholders))
+        };
+        let Some(attrs) = attrs else {
+            return convert(text).map(|(text, placeholders)| (text, None, placeholders));
+        };
+        // The conversions work char by char, so each run can be converted on its own
+        let mut converted = String::with_capacity(text.len());
+        let mut converted_attrs = AttrsList::new(&attrs.defaults());
+        let mut placeholders = Vec::new();
+        for run in span_runs(&text, &attrs) {
+            let start = converted.len();
+            let (run_text, run_placeholders) = convert(text[run.clone()].to_string())?;
+            converted.push_str(&run_text);
+            placeholders.extend(
+                run_placeholders
+                    .into_iter()
+                    .map(|x| x.start + start..x.end + start),
+            );
+            let span = attrs.get_span(run.start);
+            if span != attrs.defaults() {
+                converted_attrs.add_span(start..converted.len(), &span);
+            }
+        }
+        Some((converted, Some(converted_attrs), placeholders))
+    }
+
+    /// Makes the layout mode measure again next frame. Text changes made through the widget,
+    /// including [`Self::change`], already do, this is for edits made directly to the buffer.
+    pub fn invalidate_layout(&mut self) {
+        self.layout_mode.invalidate();
+        self.revision += 1;
+    }
+
+    /// Lets the widget know it's shown again after [`Self::ui`] wasn't called for a while.
+    ///
+    /// Clicks and drags in progress are dropped, the caret starts blinking from the visible phase
+    /// and, if the widget has focus during the next [`Self::ui`] call, gets scrolled into view.
+    /// It's called for you when `ui` notices a frame was skipped, call it yourself when that
+    /// can't be told apart from a frame without repaints.
+    ///
+    /// For tabs, keep one widget per tab and only show the active one. When switching to a tab,
+    /// call this on its widget and, since egui drops the focus of widgets that weren't shown,
+    /// request the focus for its [`Self::id`] again before calling `ui`.
+    pub fn on_shown(&mut self) {
+        self.scroll_state = ScrollState::Idle;
+        self.last_click = None;
+        self.dragging = false;
+        self.dragging_selection = false;
+        self.shown_again = true;
+    }
+
+    // Batch with buffer size?
+    /// Doesn't affect the undo history, see [`Self::set_wrap`].
+    pub fn set_font_size(
+        &mut self,
+        font_size: f32,
+        line_height: LineHeight,
+        font_system: &mut FontSystem,
+    ) {
+        let metrics = Metrics::new(font_size, line_height.into_absolute(font_size));
+        let fonts = has_fonts(font_system);
+        let changed = self.with_buffer_budgeted(|x, _| {
+            let changed = x.metrics() != metrics;
+            match (changed, fonts) {
+                (false, _) => {}
+                (true, true) => x.set_metrics(font_system, metrics),
+                (true, false) => with_unshaped_lines(x, |x| x.set_metrics(font_system, metrics)),
+            }
+            changed
+        });
+        if changed {
+            self.invalidate_layout();
+        }
+    }
+
+    /// OpenType features for all text that doesn't set its own [`Attrs::font_features`], e.g.
+    /// `tnum` for numbers that line up in columns or `liga` set to 0 to turn ligatures off in code.
+    ///
+    /// Also applies to text set later on. Doesn't affect the undo history.
+    pub fn set_font_features(&mut self, font_features: FontFeatures, font_system: &mut FontSystem) {
+        if font_features == self.font_features {
+            return;
+        }
+        let from = std::mem::replace(&mut self.font_features, font_features);
+        let to = &self.font_features;
+        let shape = has_fonts(font_system) && self.shaping_budget.is_none();
+        self.editor.with_buffer_mut(|x| {
+            for line in &mut x.lines {
+                let attrs_list = replace_list_font_features(line.attrs_list(), &from, to);
+                line.set_attrs_list(attrs_list);
+            }
+            if shape {
+                x.shape_until_scroll(font_system, false);
+            }
+        });
+        self.invalidate_layout();
+    }
+
+    pub fn font_features(&self) -> &FontFeatures {
+        &self.font_features
+    }
+
+    /// The font family for all text that uses the default [`Family::SansSerif`], `None` to go
+    /// back to it.
+    ///
+    /// Han characters are shared between Chinese, Japanese and Korean but drawn differently in
+    /// each, e.g. 直 and 值. cosmic-text picks fallback fonts by the [`FontSystem`]'s locale and has
+    /// no per-span language, so a widget showing another locale's text can name that region's
+    /// font here, e.g. `"Noto Sans CJK JP"`.
+    ///
+    /// Also applies to text set later on. Doesn't affect the undo history.
+    pub fn set_font_family(&mut self, font_family: Option<String>, font_system: &mut FontSystem) {
+        if font_family == self.font_family {
+            return;
+        }
+        let from = std::mem::replace(&mut self.font_family, font_family);
+        let from = widget_family(from.as_deref());
+        let to = widget_family(self.font_family.as_deref());
+        let shape = has_fonts(font_system) && self.shaping_budget.is_none();
+        self.editor.with_buffer_mut(|x| {
+            for line in &mut x.lines {
+                let attrs_list = replace_list_family(line.attrs_list(), from, to);
+                line.set_attrs_list(attrs_list);
+            }
+            if shape {
+                x.shape_until_scroll(font_system, false);
+            }
+        });
+        self.invalidate_layout();
+    }
+
+    pub fn font_family(&self) -> Option<&str> {
+        self.font_family.as_deref()
+    }
+
+    /// What typed and pasted text is styled with instead of the attributes of the text before the
+    /// caret, e.g. after a bold button was pressed without a selection. Moving the caret with the
+    /// keyboard, a click, [`Self::set_selection_range`] or [`Self::with_editor_mut`] sets it back
+    /// to `None`, Enter keeps it.
+    ///
+    /// Like [`Self::set_text`], [`Family::SansSerif`] is replaced with
+    /// [the widget's family](Self::set_font_family) and the widget's font features are used
+    /// unless the attributes have their own.
+    pub fn set_typing_attrs(&mut self, attrs: Option<Attrs>) {
+        self.typing_attrs = attrs.map(|x| AttrsOwned::new(&x));
+    }
+
+    /// What the next typed or pasted text is styled with, see [`Self::set_typing_attrs`].
+    pub fn typing_attrs(&self) -> Option<Attrs<'_>> {
+        self.typing_attrs.as_ref().map(AttrsOwned::as_attrs)
+    }
+
+    fn typing_attrs_list(&self) -> Option<AttrsList> {
+        let attrs = self.typing_attrs.as_ref()?.as_attrs();
+        let family = widget_family(self.font_family.as_deref());
+        let attrs = with_widget_font(attrs, family, &self.font_features);
+        Some(AttrsList::new(&attrs))
+    }
+
+    /// Changes the attributes of the selected text with `f`, e.g. to make it bold or color it in
+    /// a rich text editor. Does nothing without a selection.
+    ///
+    /// Doesn't affect the undo history, only the text is restored by undo: deleted text that's
+    /// brought back takes the attributes of the text around it.
+    pub fn apply_attrs_to_selection(
+        &mut self,
+        f: impl Fn(Attrs) -> Attrs,
+        font_system: &mut FontSystem,
+    ) {
+        let Some((start, end)) = self.selection() else {
+            return;
+        };
+        let shape = has_fonts(font_system) && self.shaping_budget.is_none();
+        self.editor.with_buffer_mut(|x| {
+            let lines = x.lines.iter_mut().enumerate();
+            for (line_i, line) in lines.take(end.line + 1).skip(start.line) {
+                let from = if line_i == start.line { start.index } else { 0 };
+                let to = match line_i == end.line {
+                    true => end.index,
+                    false => line.text().len(),
+                };
+                let attrs_list = map_list_range(line.attrs_list(), from..to, &f);
+                line.set_attrs_list(attrs_list);
+            }
+            if shape {
+                x.shape_until_scroll(font_system, false);
+            }
+        });
+        self.invalidate_layout();
+    }
+
+    /// Doesn't affect the undo history, changes are recorded by text index and survive reflowing.
+    ///
+    /// While there are [long lines](Self::with_long_line_threshold) the wrap is applied once they're
+    /// gone.
+    pub fn set_wrap(&mut self, wrap: Wrap, font_system: &mut FontSystem) {
+        if let Some(unwrapped_from) = &mut self.unwrapped_from {
+            *unwrapped_from = wrap;
+            return;
+        }
+        let fonts = has_fonts(font_system);
+        let changed = self.with_buffer_budgeted(|x, _| {
+            let changed = x.wrap() != wrap;
+            match (changed, fonts) {
+                (false, _) => {}
+                (true, true) => x.set_wrap(font_system, wrap),
+                (true, false) => with_unshaped_lines(x, |x| x.set_wrap(font_system, wrap)),
+            }
+            changed
+        });
+        if changed {
+            self.invalidate_layout();
+        }
+    }
+
+    /// The lines joined by the [line ending](Self::set_line_ending), or the
+    /// [soft break](Self::with_soft_break) where that's what ends one. Escaped
+    /// [controls](Self::set_control_chars) are turned back.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.text_into(&mut text);
+        text
+    }
+
+    /// [`Self::text`] written into `text`, which is cleared first, so comparing against the text
+    /// every frame can reuse one allocation.
+    pub fn text_into(&self, text: &mut String) {
+        text.clear();
+        self.editor.with_buffer(|x| {
+            for (line_i, line) in x.lines.iter().enumerate() {
+                let escapes = line_escapes(&self.escaped_controls, line_i, 0).collect::<Vec<_>>();
+                text.push_str(&unescape_controls(line.text(), &escapes));
+                match line_break_after(x, line_i) {
+                    Some(LineBreak::Soft) => text.push_str(&self.soft_break),
+                    Some(LineBreak::Paragraph) => text.push_str(self.line_ending.as_str()),
+                    None => {}
+                }
+            }
+        });
+    }
+
+    /// Which kind of break ends `line`, `None` for the last line.
+    ///
+    /// Breaks brought back by undo and redo are paragraph breaks. cosmic-text leaves the last
+    /// line without an ending, so one of [`Self::from_editor`] or [`Self::replace_editor`] that
+    /// was a soft break becomes a paragraph break too.
+    pub fn line_break(&self, line: usize) -> Option<LineBreak> {
+        self.editor.with_buffer(|x| line_break_after(x, line))
+    }
+
+    /// What lines are joined with in [`Self::text`], copies and selection drags, e.g.
+    /// [`LineEnding::CrLf`] for Windows tooling. Defaults to [`LineEnding::Lf`].
+    ///
+    /// The buffer itself always separates lines with `\n`, pasted text is converted to it.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// The selected text with escaped controls turned back, in the configured line ending.
+    fn selection_text(&self) -> Option<String> {
+        let (start, end) = self.editor.selection_bounds()?;
+        let text = self
+            .editor
+            .with_buffer(|x| unescaped_text_between(x, &self.escaped_controls, start, end));
+        Some(match self.line_ending {
+            LineEnding::Lf => text,
+            ending => text.replace('\n', ending.as_str()),
+        })
+    }
+
+    /// What happens to control characters in text put into the widget by typing, pasting,
+    /// [`Self::insert_string`] and [`Self::set_text`]. Defaults to [`ControlChars::Allow`].
+    ///
+    /// Allowing them is a risk when showing untrusted text: bidi overrides like U+202E reorder the
+    /// text around them, so it reads differently than what gets copied or saved. Text that's
+    /// already in the buffer isn't touched, neither is text synced through
+    /// [`Self::apply_change`] or [`Self::apply_deltas`] so positions keep matching. Controls that
+    /// were escaped keep turning back after switching to another policy.
+    pub fn set_control_chars(&mut self, control_chars: ControlChars) {
+        self.control_chars = control_chars;
+    }
+
+    pub fn control_chars(&self) -> ControlChars {
+        self.control_chars
+    }
+
+    /// The most chars the text can have, line breaks count as one. Typing, pasting and
+    /// [`Self::insert_string`] are cut short at a grapheme boundary, other edits that would go
+    /// over it aren't made, see [`Self::change`]. `None`, the default, has no limit.
+    ///
+    /// It's not applied to text that's already there, [`Self::set_text`] or changes synced
+    /// through [`Self::apply_change`].
+    pub fn set_char_limit(&mut self, char_limit: Option<usize>) {
+        self.char_limit = char_limit;
+    }
+
+    pub fn char_limit(&self) -> Option<usize> {
+        self.char_limit
+    }
+
+    /// Whether the text has as many chars as [the limit](Self::set_char_limit) allows, or more.
+    pub fn at_char_limit(&self) -> bool {
+        self.char_limit
+            .is_some_and(|limit| self.char_count() >= limit)
+    }
+
+    /// Only lets chars `filter` accepts into the text when typing, pasting and through
+    /// [`Self::insert_string`], `rejected` decides what happens to the rest of the text then.
+    /// Rejected chars never make it into the undo history.
+    ///
+    /// Enter and Tab aren't typed text, they run commands from the [keymap](Self::keymap_mut)
+    /// which can be unbound. Like [the char limit](Self::set_char_limit), it's not applied to
+    /// [`Self::set_text`] or synced changes.
+    pub fn set_char_filter(
+        &mut self,
+        filter: impl Fn(char) -> bool + 'static,
+        rejected: RejectedChars,
+    ) {
+        self.char_filter = Some((Box::new(filter), rejected));
+    }
+
+    pub fn clear_char_filter(&mut self) {
+        self.char_filter = None;
+    }
+
+    /// `text` with only the chars the filter accepts, `None` if the whole insert is rejected.
+    ///
+    /// Escaped `placeholders` are kept or dropped as a whole and moved along with their chars.
+    fn filter_chars<'a>(
+        &self,
+        text: &'a str,
+        placeholders: &mut Vec<Range<usize>>,
+    ) -> Option<Cow<'a, str>> {
+        let Some((filter, rejected)) = &self.char_filter else {
+            return Some(Cow::Borrowed(text));
+        };
+        if text.chars().all(filter) {
+            return Some(Cow::Borrowed(text));
+        }
+        match rejected {
+            RejectedChars::Drop => {
+                let mut filtered = String::with_capacity(text.len());
+                let mut kept = Vec::new();
+                for (range, placeholder) in placeholder_units(text, placeholders) {
+                    let unit = &text[range];
+                    if unit.chars().all(filter) {
+                        if placeholder {
+                            kept.push(filtered.len()..filtered.len() + unit.len());
+                        }
+                        filtered.push_str(unit);
+                    }
+                }
+                *placeholders = kept;
+                Some(Cow::Owned(filtered))
+            }
+            RejectedChars::RejectInsert => None,
+        }
+    }
+
+    fn char_count(&self) -> usize {
+        self.editor.with_buffer(char_count)
+    }
+
+    /// The start of `text` that fits within the char limit, replacing the selection. If
+    /// `overwrite`, the rest of the line can be typed over too.
+    fn fit_char_limit<'a>(&self, text: &'a str, overwrite: bool) -> &'a str {
+        let Some(limit) = self.char_limit else {
+            return text;
+        };
+        let cursor = self.editor.cursor();
+        let replaced = match self.editor.copy_selection() {
+            Some(selected) if !selected.is_empty() => selected.chars().count(),
+            _ if overwrite => self
+                .editor
+                .with_buffer(|x| x.lines[cursor.line].text()[cursor.index..].chars().count()),
+            _ => 0,
+        };
+        truncate_graphemes(text, (limit + replaced).saturating_sub(self.char_count()))
+    }
+
+    /// `text` as the [control chars](Self::set_control_chars) policy puts it into the buffer, and
+    /// the byte ranges of the controls it escaped.
+    fn sanitize<'a>(&self, text: &'a str) -> (Cow<'a, str>, Vec<Range<usize>>) {
+        match self.control_chars {
+            ControlChars::Allow => (Cow::Borrowed(text), Vec::new()),
+            ControlChars::StripControls => (strip_controls(text), Vec::new()),
+            ControlChars::EscapeVisible => escape_controls(text),
+        }
+    }
+
+    /// Adds escaped controls in the text as it is now, see [`Self::set_control_chars`].
+    fn add_escapes(&mut self, escapes: Vec<Range<Cursor>>) {
+        if escapes.is_empty() {
+            return;
+        }
+        self.move_escapes_along();
+        self.escaped_controls.extend(escapes);
+        self.editor
+            .with_buffer(|x| retain_escapes(x, &mut self.escaped_controls));
+    }
+
+    pub fn editor(&self) -> &Editor<'static> {
+        &self.editor
+    }
+
+    /// Runs `f` with the editor to change it in ways the widget has no method for.
+    ///
+    /// Edits aren't recorded in the undo history, changes recorded before them that no longer
+    /// apply are skipped. The cursors are [sanitized](Self::sanitize_cursors) and the layout is
+    /// invalidated afterwards. Moving the caret clears the [typing attrs](Self::set_typing_attrs).
+    /// If `f` changes the text, [escaped controls](ControlChars::EscapeVisible) stay as their
+    /// placeholders from then on.
+    pub fn with_editor_mut<R>(&mut self, f: impl FnOnce(&mut Editor<'static>) -> R) -> R {
+        let line_lens = self.tracked_line_lens();
+        let caret = (self.editor.cursor(), self.editor.selection());
+        let lines =
+            (!self.escaped_controls.is_empty()).then(|| self.editor.with_buffer(line_texts));
+        let res = f(&mut self.editor);
+        if (self.editor.cursor(), self.editor.selection()) != caret {
+            self.typing_attrs = None;
+        }
+        if lines.is_some_and(|x| self.editor.with_buffer(|buf| line_texts(buf) != x)) {
+            self.escaped_controls.clear();
+            self.text_replacements += 1;
+        }
+        self.editor.with_buffer_mut(|x| {
+            if x.lines.is_empty() {
+                x.lines.push(BufferLine::new(
+                    "",
+                    LineEnding::default(),
+                    AttrsList::new(&Attrs::new()),
+                    Shaping::Advanced,
+                ));
+            }
+            unmark_last_line(x);
+        });
+        normalize_selection(&mut self.editor);
+        self.sanitize_cursors();
+        self.invalidate_layout();
+        self.track_replaced_text(line_lens);
+        res
+    }
+
+    /// Clamps the cursor and the selection's anchor into the text, e.g. after lines were removed
+    /// from under them. Cursors past the last line go to the end of the text, ones past the end of
+    /// their line or inside a character go back to the closest character boundary.
+    ///
+    /// [`Self::ui`] calls this every frame.
+    pub fn sanitize_cursors(&mut self) {
+        let cursor = self.editor.cursor();
+        let selection = self.editor.selection();
+        let (valid_cursor, valid_selection) = self.editor.with_buffer(|x| {
+            (
+                clamp_cursor(x, cursor),
+                map_anchor(selection, |anchor| clamp_cursor(x, anchor)),
+            )
+        });
+        if valid_cursor != cursor {
+            self.editor.set_cursor(valid_cursor);
+        }
+        if valid_selection != selection {
+            self.editor.set_selection(valid_selection);
+        }
+    }
+
+    pub fn into_editor(self) -> Editor<'static> {
+        self.editor
+    }
+
+    /// Swaps in another editor, e.g. to show a different document, and returns the old one.
+    ///
+    /// The widget's settings are kept, the undo history belonged to the old text and is cleared.
+    /// If [long lines](Self::with_long_line_threshold) turned wrapping off, the old editor is
+    /// returned unwrapped and the new one gets the wrap back.
+    pub fn replace_editor(&mut self, mut editor: Editor<'static>) -> Editor<'static> {
+        normalize_selection(&mut editor);
+        editor.with_buffer_mut(unmark_last_line);
+        let line_lens = self.tracked_line_lens();
+        let old = std::mem::replace(&mut self.editor, editor);
+        self.commands.clear();
+        self.escaped_controls.clear();
+        self.last_click = None;
+        self.scroll_state = ScrollState::Idle;
+        self.dragging = false;
+        self.dragging_selection = false;
+        self.selection_rects_key = None;
+        self.long_lines_revision = None;
+        self.hovered_cursor = None;
+        self.pointer_over_selection = false;
+        self.clicked_cursor = None;
+        self.drop_cursor = None;
+        self.vertical_motion_x = None;
+        self.invalidate_layout();
+        self.track_replaced_text(line_lens);
+        old
+    }
+
+    pub fn interactivity(&self) -> Interactivity {
+        self.interactivity
+    }
+
+    pub fn interactivity_mut(&mut self) -> &mut Interactivity {
+        &mut self.interactivity
+    }
+
+    pub fn hover_strategy(&self) -> HoverStrategy {
+        self.hover_strategy
+    }
+
+    pub fn hover_strategy_mut(&mut self) -> &mut HoverStrategy {
+        &mut self.hover_strategy
+    }
+
+    /// Other users' carets and selections, e.g. for collaborative editing.
+    ///
+    /// Their selections are drawn in order below the local one and their carets above the glyphs.
+    pub fn remote_selections_mut(&mut self) -> &mut Vec<RemoteSelection> {
+        &mut self.remote_selections
+    }
+
+    pub fn remote_selections(&self) -> &[RemoteSelection] {
+        &self.remote_selections
+    }
+
+    /// Where to anchor a name tag for each of the [remote selections](Self::remote_selections_mut)
+    /// in screen coordinates, the top left of their caret. `None` if the caret isn't laid out.
+    ///
+    /// Updated by [`Self::ui`], so it lags a frame behind changes to the remote selections.
+    pub fn remote_name_tags(&self) -> &[Option<Pos2>] {
+        &self.remote_name_tags
+    }
+
+    pub fn content_align(&self) -> Align2 {
+        self.style.content_align
+    }
+
+    /// Replaces how the widget looks, the individual setters change the same style.
+    ///
+    /// Cheap enough to call every frame, e.g. from a theme, the caret and selection textures are
+    /// only made again when their colors change.
+    pub fn set_style(&mut self, style: &CosmicEditStyle) {
+        if style.caret != self.style.caret {
+            self.cursor_style = match style.caret {
+                Some(color) => CursorStyle::Default(color),
+                None => CursorStyle::None,
+            };
+        }
+        if style.selection != self.style.selection {
+            self.selection_texture = SelectionTexture::Default(style.selection);
+        }
+        if style.spacing != self.style.spacing {
+            self.invalidate_layout();
+        }
+        self.style = *style;
+    }
+
+    pub fn style(&self) -> &CosmicEditStyle {
+        &self.style
+    }
+
+    pub fn line_motions(&self) -> LineMotions {
+        self.line_motions
+    }
+
+    pub fn tab_behavior(&self) -> TabBehavior {
+        self.tab_behavior
+    }
+
+    /// Whether typing replaces the character after the cursor instead of inserting before it.
+    /// At the end of a line, or with a selection, it inserts either way.
+    ///
+    /// The Insert key toggles it while the widget has focus.
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn overwrite_caret(&self) -> OverwriteCaret {
+        self.style.overwrite_caret
+    }
+
+    pub fn single_line(&self) -> bool {
+        self.single_line
+    }
+
+    pub fn soft_break(&self) -> &str {
+        &self.soft_break
+    }
+
+    /// Whether Enter was pressed in a [single-line](Self::with_single_line) widget during the
+    /// last [`Self::ui`] call.
+    pub fn submitted(&self) -> bool {
+        self.submitted
+    }
+
+    pub fn tab_behavior_mut(&mut self) -> &mut TabBehavior {
+        &mut self.tab_behavior
+    }
+
+    pub fn keymap(&self) -> &Keymap<L> {
+        &self.keymap
+    }
+
+    /// Rebinds keys, e.g.
+    /// `edit.keymap_mut().bind(Key::K, Modifiers::CTRL, EditorCommand::DeleteToEndOfLine)`.
+    pub fn keymap_mut(&mut self) -> &mut Keymap<L> {
+        &mut self.keymap
+    }
+
+    pub fn caret_layer(&self) -> CaretLayer {
+        self.style.caret_layer
+    }
+
+    pub fn caret_layer_mut(&mut self) -> &mut CaretLayer {
+        &mut self.style.caret_layer
+    }
+
+    /// Extra space between glyphs, in the same units as the font size.
+    ///
+    /// See [`Spacing`] for its limits, it isn't taken into account when wrapping.
+    pub fn set_letter_spacing(&mut self, letter_spacing: f32) {
+        if self.style.spacing.letter != letter_spacing {
+            self.style.spacing.letter = letter_spacing;
+            self.invalidate_layout();
+        }
+    }
+
+    /// Extra space after whitespace, on top of the letter spacing.
+    pub fn set_word_spacing(&mut self, word_spacing: f32) {
+        if self.style.spacing.word != word_spacing {
+            self.style.spacing.word = word_spacing;
+            self.invalidate_layout();
+        }
+    }
+
+    pub fn spacing(&self) -> Spacing {
+        self.style.spacing
+    }
+
+    /// Shows a faint caret where a click would place the cursor while the widget is hovered.
+    ///
+    /// It doesn't touch the real caret, selection or blinking and is hidden while dragging.
+    pub fn set_hover_caret(&mut self, hover_caret: bool) {
+        self.style.hover_caret = hover_caret;
+    }
+
+    pub fn hover_caret(&self) -> bool {
+        self.style.hover_caret
+    }
+
+    /// The color of text without one of its own from [`Attrs::color`], `None` (the default) to
+    /// use the atlas's default color.
+    ///
+    /// Like [`Attrs::color`], it tints the atlas's glyphs, so the text is only drawn in exactly
+    /// this color with a white atlas default color.
+    pub fn set_text_color(&mut self, text_color: Option<Color32>) {
+        self.style.text_color = text_color;
+    }
+
+    pub fn text_color(&self) -> Option<Color32> {
+        self.style.text_color
+    }
+
+    /// Draws the text turned by a quarter, the layout mode is given the transposed available size.
+    ///
+    /// Rotated widgets behave as [`Interactivity::Disabled`] and don't show a context menu.
+    pub fn set_rotation(&mut self, rotation: Option<Rot90>) {
+        if self.rotation != rotation {
+            self.rotation = rotation;
+            self.invalidate_layout();
+        }
+    }
+
+    pub fn rotation(&self) -> Option<Rot90> {
+        self.rotation
+    }
+
+    pub fn set_layout_mode(&mut self, layout_mode: L) {
+        self.layout_mode = layout_mode;
+        self.layout_mode.invalidate();
+    }
+
+    /// Whether the last [`Self::ui`] call found no fonts in the `FontSystem`.
+    ///
+    /// The widget then only draws an outline where the text would be and doesn't react to input.
+    pub fn fonts_missing(&self) -> bool {
+        self.fonts_missing
+    }
+
+    /// Whether wrapping is currently off because of a line over the
+    /// [threshold](Self::with_long_line_threshold).
+    pub fn has_long_lines(&self) -> bool {
+        self.unwrapped_from.is_some()
+    }
+
+    pub fn set_glyph_capture(&mut self, capture: bool) {
+        self.visible_glyphs = capture.then(Vec::new);
+    }
+
+    /// Calls `f` with every glyph drawn during the last frame, with its rect relative to the
+    /// widget's. Runs outside of the clip rect aren't drawn, neither are rotated glyphs captured.
+    ///
+    /// Nothing is captured without [`Self::with_glyph_capture`].
+    pub fn visible_glyphs(&self, f: impl FnMut(GlyphInfo)) {
+        self.visible_glyphs.iter().flatten().cloned().for_each(f)
+    }
+
+    /// The cursor under the pointer during the last [`Self::ui`] call, in every [`Interactivity`].
+    pub fn hovered_cursor(&self) -> Option<Cursor> {
+        self.hovered_cursor
+    }
+
+    /// Keeps repainting at a capped rate while the pointer is over the widget, for tooltips or
+    /// link styling drawn from the [hovered cursor](Self::hovered_cursor). The hover caret
+    /// already does.
+    pub fn set_hover_repaint(&mut self, hover_repaint: bool) {
+        self.hover_repaint = hover_repaint;
+    }
+
+    pub fn hover_repaint(&self) -> bool {
+        self.hover_repaint
+    }
+
+    /// Whether the [hovered cursor](Self::hovered_cursor) is within the selection, e.g. to choose
+    /// between dragging the selection and starting a new one. `false` without a selection.
+    pub fn pointer_over_selection(&self) -> bool {
+        self.pointer_over_selection
+    }
+
+    /// The cursor that was clicked during the last [`Self::ui`] call, in every [`Interactivity`].
+    pub fn clicked_cursor(&self) -> Option<Cursor> {
+        self.clicked_cursor
+    }
+
+    /// Where something dragged over the widget would be dropped, while an egui drag and drop
+    /// payload or files are hovering it. Drawn as a caret in the selection color.
+    ///
+    /// Only editable widgets show it, and the real caret doesn't move.
+    pub fn drop_cursor(&self) -> Option<Cursor> {
+        self.drop_cursor
+    }
+
+    /// The [`Attrs::metadata`] of the glyph on `cursor`'s affinity side, e.g. to find the link
+    /// that was [clicked](Self::clicked_cursor).
+    pub fn metadata_at(&self, cursor: Cursor) -> Option<usize> {
+        let Cursor {
+            line,
+            index,
+            affinity,
+        } = cursor;
+        self.editor.with_buffer(|x| {
+            x.layout_runs()
+                .filter(|run| run.line_i == line)
+                .flat_map(|run| run.glyphs)
+                .find(|glyph| match affinity {
+                    Affinity::Before => glyph.start < index && index <= glyph.end,
+                    Affinity::After => glyph.start <= index && index < glyph.end,
+                })
+                .map(|glyph| glyph.metadata)
+        })
+    }
+
+    /// Was the buffer's text changed this frame through user input? [`Self::ui`] marks its
+    /// response as [changed](Response::changed) then too.
+    pub fn changed_this_frame(&self) -> bool {
+        self.frame_changed
+    }
+
+    fn stamp_change(&mut self, now: f64) {
+        if std::mem::take(&mut self.unstamped_change) {
+            self.last_change_time = Some(now);
+            self.debounce_pending = true;
+        }
+    }
+
+    /// How long ago the text was last changed by an edit that's recorded in the undo history or
+    /// a remote change, in egui's input time. `None` if it never was.
+    pub fn idle_since_change(&self, ctx: &egui::Context) -> Option<Duration> {
+        if self.unstamped_change {
+            return Some(Duration::ZERO);
+        }
+        let idle = ctx.input(|i| i.time) - self.last_change_time?;
+        Some(Duration::from_secs_f64(idle.max(0.0)))
+    }
+
+    /// Whether the text has been left alone for `delay` after changing, e.g. to autosave. It's
+    /// true once per burst of changes, until the text changes again.
+    ///
+    /// Call it every frame, a repaint is requested for when `delay` runs out so it doesn't have
+    /// to be polled.
+    pub fn debounced_changed(&mut self, ctx: &egui::Context, delay: Duration) -> bool {
+        self.stamp_change(ctx.input(|i| i.time));
+        if !self.debounce_pending {
+            return false;
+        }
+        let idle = self.idle_since_change(ctx).unwrap_or_default();
+        match idle >= delay {
+            true => {
+                self.debounce_pending = false;
+                true
+            }
+            false => {
+                ctx.request_repaint_after(delay - idle);
+                false
+            }
+        }
+    }
+
+    /// Returns the cursor rect relative to the text's `min_pos`, see [`Self::hit`]
+    pub fn cursor_rect(&self, min_pos: Logical<Pos2>, pixels_per_point: f32) -> Logical<Rect> {
+        let cursor = self.editor.cursor();
+        self.editor.with_buffer(|x| {
+            let fallback = || {
+                Phys(Rect::from_min_size(
+                    Pos2::ZERO,
+                    vec2(1.0, x.metrics().line_height),
+                ))
+            };
+            snap_to_logical(
+                cursor_rect(x, cursor, self.style.spacing).unwrap_or_else(fallback),
+                min_pos,
+                pixels_per_point,
+            )
+        })
+    }
+
+    /// The [`CacheKey`] of the glyph after the cursor, or the one before it at the end of a line.
+    ///
+    /// Takes the same arguments as [`Self::cursor_rect`] since glyphs are rasterized at the
+    /// widget's position in physical pixels.
+    #[cfg(feature = "debug-tools")]
+    pub fn cursor_glyph(&self, min_pos: Logical<Pos2>, pixels_per_point: f32) -> Option<CacheKey> {
+        let cursor = self.editor.cursor();
+        let offset: (f32, f32) = (*min_pos.to_phys(pixels_per_point)).into();
+        self.editor.with_buffer(|x| {
+            x.layout_runs()
+                .filter(|run| run.line_i == cursor.line)
+                .find_map(|run| {
+                    let glyphs = || {
+                        run.glyphs
+                            .iter()
+                            .zip(self.style.spacing.offsets(run.glyphs, run.text))
+                    };
+                    glyphs()
+                        .find(|(glyph, _)| glyph.start <= cursor.index && cursor.index < glyph.end)
+                        .or_else(|| {
+                            glyphs()
+                                .last()
+                                .filter(|(glyph, _)| glyph.end == cursor.index)
+                        })
+                        .map(|(glyph, spacing)| {
+                            glyph
+                                .physical((offset.0 + spacing, offset.1), 1.0)
+                                .cache_key
+                        })
+                })
+        })
+    }
+
+    fn apply_to_cursor_rect(
+        &mut self,
+        cursor: Cursor,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+        f: impl FnOnce(&mut Self, Rect),
+    ) {
+        let cursor_rect = self
+            .editor
+            .with_buffer(|x| cursor_rect(x, cursor, self.style.spacing));
+
+        if let Some(cursor_rect) = cursor_rect {
+            let cursor_rect = *snap_to_logical(cursor_rect, min_pos, pixels_per_point);
+
+            f(self, cursor_rect)
+        }
+    }
+
+    fn draw_carets(
+        &mut self,
+        ctx: &egui::Context,
+        painter: &mut Painter,
+        caret: Option<Cursor>,
+        hover_caret: Option<Cursor>,
+        min_pos: Logical<Pos2>,
+    ) {
+        let pixels_per_point = ctx.pixels_per_point();
+        // The drop position replaces the hover preview
+        if let Some(cursor) = self.drop_cursor {
+            let tint = ctx.style().visuals.selection.stroke.color;
+            self.draw_cursor(ctx, painter, cursor, tint, min_pos, pixels_per_point);
+        } else if let Some(cursor) = hover_caret {
+            let tint = Color32::WHITE.gamma_multiply(Self::HOVER_CARET_OPACITY);
+            self.draw_cursor(ctx, painter, cursor, tint, min_pos, pixels_per_point);
+        }
+        if let Some(cursor) = caret {
+            match self.overwrite && self.style.overwrite_caret == OverwriteCaret::Block {
+                true => {
+                    let rect = self
+                        .editor
+                        .with_buffer(|x| block_cursor_rect(x, cursor, self.style.spacing));
+                    if let Some(rect) = rect {
+                        let tint = Color32::WHITE.gamma_multiply(Self::BLOCK_CARET_OPACITY);
+                        self.paint_cursor(ctx, painter, rect, tint, min_pos, pixels_per_point);
+                    }
+                }
+                false => self.draw_cursor(
+                    ctx,
+                    painter,
+                    cursor,
+                    Color32::WHITE,
+                    min_pos,
+                    pixels_per_point,
+                ),
+            }
+        }
+    }
+
+    fn draw_cursor(
+        &mut self,
+        ctx: &egui::Context,
+        painter: &mut Painter,
+        cursor: Cursor,
+        tint: Color32,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+    ) {
+        if let Some(rect) = self
+            .editor
+            .with_buffer(|x| cursor_rect(x, cursor, self.style.spacing))
+        {
+            self.paint_cursor(ctx, painter, rect, tint, min_pos, pixels_per_point);
+        }
+    }
+
+    fn paint_cursor(
+        &mut self,
+        ctx: &egui::Context,
+        painter: &mut Painter,
+        rect: Phys<Rect>,
+        tint: Color32,
+        min_pos: Logical<Pos2>,
+        pixels_per_point: f32,
+    ) {
+        let cursor_rect = *snap_to_logical(rect, min_pos, pixels_per_point);
+        // Probably shouldn't render the cursor if it isn't in view.
+        // Shouldn't matter much, it'll be clipped, etc.
+        let line_height = self.line_height();
+        self.cursor_style
+            .with_texture(ctx, line_height, |cursor_texture| {
+                let cursor_texture_id = cursor_texture.texture_id();
+                painter.image(
+                    cursor_texture_id,
+                    cursor_rect,
+                    Rect::from_two_pos(Pos2::ZERO, pos2(1.0, 1.0)),
+                    tint,
+                );
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::draw::Rot90;
+    use crate::history::{self, TextChange};
+    use crate::spacing::Spacing;
+    use crate::test_util;
+    use crate::test_util::Harness;
+    use crate::units::Logical;
+    use crate::util::{cursor_rect, diff_buffers, ink_x_range, TextDelta};
+    use crate::widget::{
+        is_valid_cursor, CaretLayer, ChangeOrigin, ControlChars, CosmicEdit, CosmicEditStyle,
+        CosmicEditView, CursorStyle, EditorCommand, FillWidth, FillWidthAndHeight, HoverStrategy,
+        Interactivity, LayoutMode, LineBreak, LineHeight, LineMotions, NoContextMenu,
+        PureBoundingBox, RejectedChars, RemoteSelection, ScrollState, SelectionTexture,
+        ShrinkToFit, TabBehavior, TouchDrag,
+    };
+    use cosmic_text::{
+        fontdb, Attrs, AttrsList, Cursor, Edit, FontSystem, Selection, Shaping, Wrap,
+    };
+    use cosmic_text::{
+        Affinity, Change, ChangeItem, Color, Family, FamilyOwned, FeatureTag, FontFeatures,
+        LineEnding, Weight,
+    };
+    use cosmic_undo_2::{Action, ActionIter, Commands};
+    use egui::{pos2, Align2, Color32, Event, Key, Modifiers, Pos2};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    fn key(key: Key, modifiers: Modifiers) -> Event {
+        Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers,
+        }
+    }
+
+    fn edit<L: LayoutMode>(harness: &mut Harness, text: &str, layout_mode: L) -> CosmicEdit<L> {
+        let mut edit = CosmicEdit::new(
+            14.0,
+            LineHeight::Relative(1.5),
+            Interactivity::Enabled,
+            HoverStrategy::Widget,
+            layout_mode,
+            &mut harness.font_system,
+        );
+        edit.set_text(
+            [(text, Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        edit
+    }
+
+    #[test]
+    fn enter_grows_rect_in_the_same_frame() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one line", PureBoundingBox::default());
+        let before = harness.focus(&mut edit).rect;
+        let after = harness
+            .frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)])
+            .rect;
+        assert!(after.height() > before.height());
+    }
+
+    #[test]
+    fn copy_returns_selection_and_outputs_once() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "copy me", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(
+            &mut edit,
+            vec![key(Key::A, Modifiers::COMMAND), Event::Copy, Event::Copy],
+        );
+        assert_eq!(harness.copied_text(), "copy me");
+    }
+
+    #[test]
+    fn crlf_is_stored_as_lf_and_output_with_the_line_ending() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(
+            &mut edit,
+            vec![
+                Event::Paste("one\r\ntwo\r\n".to_string()),
+                Event::Text("three".to_string()),
+            ],
+        );
+        let lines = edit.editor().with_buffer(|x| {
+            x.lines
+                .iter()
+                .map(|x| x.text().to_string())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(lines, ["one", "two", "three"]);
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+
+        edit.set_line_ending(LineEnding::CrLf);
+        harness.frame(
+            &mut edit,
+            vec![key(Key::A, Modifiers::COMMAND), Event::Copy],
+        );
+        assert_eq!(harness.copied_text(), "one\r\ntwo\r\nthree");
+        assert_eq!(edit.text(), "one\r\ntwo\r\nthree");
+    }
+
+    #[test]
+    fn font_features_reshape_the_text() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "fi", PureBoundingBox::default());
+        let glyphs = |edit: &CosmicEdit<PureBoundingBox>| {
+            edit.editor().with_buffer(|x| {
+                x.layout_runs()
+                    .flat_map(|run| run.glyphs)
+                    .map(|glyph| glyph.x)
+                    .collect::<Vec<_>>()
+            })
+        };
+        // One ligature glyph
+        assert_eq!(glyphs(&edit).len(), 1);
+
+        let mut features = FontFeatures::new();
+        features.disable(FeatureTag::STANDARD_LIGATURES);
+        edit.set_font_features(features, &mut harness.font_system);
+        let xs = glyphs(&edit);
+        assert_eq!(xs.len(), 2);
+        let caret = edit
+            .editor()
+            .with_buffer(|x| cursor_rect(x, Cursor::new(0, 1), Spacing::default()))
+            .unwrap();
+        assert_eq!(caret.min.x, xs[1]);
+
+        // Text set afterwards and typed text keep the features
+        edit.set_text(
+            [("fi", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![Event::Text("fi".to_string())]);
+        assert_eq!(glyphs(&edit).len(), 4);
+
+        edit.set_font_features(FontFeatures::new(), &mut harness.font_system);
+        assert_eq!(glyphs(&edit).len(), 2);
+    }
+
+    #[test]
+    fn enter_replaces_selection_in_one_undo_step() {
+        let mut harness = Harness::default();
+        let text = "    one\ntwo\nthree";
+        let mut edit = edit(&mut harness, text, PureBoundingBox::default());
+        edit.editor.set_auto_indent(true);
+        harness.focus(&mut edit);
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(0, 6)));
+        edit.editor.set_cursor(Cursor::new(2, 2));
+
+        harness.frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]);
+        assert_eq!(edit.text(), "    on\n    ree");
+
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), format!("{text}"));
+    }
+
+    #[test]
+    fn rotation_transposes_the_allocated_rect() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "y axis", PureBoundingBox::default());
+        let horizontal = harness.frame(&mut edit, vec![]).rect.size();
+        edit.set_rotation(Some(Rot90::Ccw));
+        let vertical = harness.frame(&mut edit, vec![]).rect.size();
+        assert_eq!(vertical, Rot90::transpose(horizontal));
+    }
+
+    #[test]
+    fn selection_rects_are_reused_until_the_text_changes() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "first\nsecond", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![key(Key::A, Modifiers::COMMAND)]);
+        assert_eq!(edit.selection_rects.len(), 2);
+        let revision = edit.revision;
+
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(
+            edit.selection_rects_key.as_ref().unwrap().revision,
+            revision
+        );
+
+        harness.frame(
+            &mut edit,
+            vec![
+                Event::Text("x".to_string()),
+                key(Key::A, Modifiers::COMMAND),
+            ],
+        );
+        assert!(edit.revision > revision);
+        assert_eq!(
+            edit.selection_rects_key.as_ref().unwrap().revision,
+            edit.revision
+        );
+        assert_eq!(edit.selection_rects.len(), 1);
+    }
+
+    #[test]
+    fn no_fonts_draws_a_placeholder_without_panicking() {
+        let mut harness = Harness {
+            font_system: FontSystem::new_with_locale_and_db(
+                "en-US".to_string(),
+                fontdb::Database::new(),
+            ),
+            ..Default::default()
+        };
+        let mut edit = edit(&mut harness, "no\nfonts", PureBoundingBox::default());
+        edit.set_font_size(20.0, LineHeight::Relative(1.5), &mut harness.font_system);
+
+        let resp = harness.focus(&mut edit);
+        assert!(edit.fonts_missing());
+        assert!(resp.rect.width() > 0.0 && resp.rect.height() > 0.0);
+
+        harness.frame(
+            &mut edit,
+            vec![
+                Event::Text("x".to_string()),
+                key(Key::Enter, Modifiers::NONE),
+            ],
+        );
+        edit.insert_string("typed".to_string(), &mut harness.font_system);
+        edit.undo();
+        edit.redo();
+        edit.invalidate_layout();
+        edit.set_rotation(Some(Rot90::Cw));
+        harness.frame(&mut edit, vec![]);
+        edit.cursor_rect(Logical(Pos2::ZERO), 1.0);
+        assert_eq!(edit.text(), "typedno\nfonts");
+    }
+
+    #[test]
+    fn long_lines_turn_wrapping_off_until_they_are_gone() {
+        let mut harness = Harness::default();
+        let long = "word ".repeat(40);
+        let mut edit =
+            edit(&mut harness, &long, FillWidth::default()).with_long_line_threshold(Some(100));
+
+        let resp = harness.focus(&mut edit);
+        assert!(edit.has_long_lines());
+        assert_eq!(edit.editor().with_buffer(|x| x.wrap()), Wrap::None);
+        // Sized to the whole line so it can be scrolled horizontally
+        assert_eq!(edit.editor().with_buffer(|x| x.layout_runs().count()), 1);
+        assert!(resp.rect.width() > harness.ctx.screen_rect().width());
+
+        edit.set_text(
+            [("short", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        harness.frame(&mut edit, vec![]);
+        assert!(!edit.has_long_lines());
+        assert_eq!(edit.editor().with_buffer(|x| x.wrap()), Wrap::WordOrGlyph);
+    }
+
+    /// Typing into a 2 MB single line document, e.g. minified JSON.
+    ///
+    /// Only meaningful in release: `cargo test --release -- --ignored latency`. This is still far
+    /// from 16 ms per keystroke (about 1.8 s here) since cosmic-text shapes a `BufferLine` as a
+    /// whole after every edit, so the bounds only catch regressions.
+    #[test]
+    #[ignore = "timing, run in release"]
+    fn typing_latency_in_a_2mb_line() {
+        let mut harness = Harness::default();
+        let json = r#"{"key":"value","list":[1,2,3]},"#.repeat(2_000_000 / 31);
+        let mut edit = edit(&mut harness, &json, FillWidth::default());
+        harness.focus(&mut edit);
+        assert!(edit.has_long_lines());
+
+        let mut frame = |events| {
+            let start = std::time::Instant::now();
+            harness.frame(&mut edit, events);
+            start.elapsed()
+        };
+        let typing = (0..3)
+            .map(|_| frame(vec![Event::Text("x".into())]))
+            .max()
+            .unwrap();
+        // Only the visible part of the line is drawn
+        let idle = frame(vec![]);
+        eprintln!("typing: {typing:?}, idle: {idle:?}");
+        assert!(typing < Duration::from_secs(4));
+        assert!(idle < Duration::from_millis(100));
+    }
+
+    /// 500 labels in a `ScrollArea` with about 20 of them visible, against all of them visible.
+    ///
+    /// Only meaningful in release: `cargo test --release -- --ignored frame_time`.
+    #[test]
+    #[ignore = "timing, run in release"]
+    fn frame_time_with_most_labels_clipped() {
+        let mut harness = Harness::default();
+        let mut edits = (0..500)
+            .map(|i| edit(&mut harness, &format!("Label #{i}"), FillWidth::default()))
+            .collect::<Vec<_>>();
+
+        let mut frame_time = |screen_height: f32| {
+            let mut frame = || {
+                let input = egui::RawInput {
+                    screen_rect: Some(egui::Rect::from_min_size(
+                        Pos2::ZERO,
+                        egui::vec2(800.0, screen_height),
+                    )),
+                    ..Default::default()
+                };
+                let start = std::time::Instant::now();
+                let _ = harness.ctx.run(input, |ctx| {
+                    egui::CentralPanel::default().show(ctx, |ui| {
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for edit in &mut edits {
+                                edit.ui(
+                                    ui,
+                                    &mut harness.font_system,
+                                    &mut harness.swash_cache,
+                                    &mut harness.atlas,
+                                    NoContextMenu,
+                                );
+                            }
+                        });
+                    });
+                });
+                start.elapsed()
+            };
+            // Shapes and caches the glyphs
+            frame();
+            (0..10).map(|_| frame()).sum::<Duration>() / 10
+        };
+        // Labels are 21 points high
+        let clipped = frame_time(20.0 * 21.0);
+        let all_visible = frame_time(500.0 * 21.0 + 100.0);
+        eprintln!("20 visible: {clipped:?}, all visible: {all_visible:?}");
+        assert!(clipped * 3 < all_visible);
+    }
+
+    #[test]
+    fn hover_caret_leaves_the_real_cursor_alone() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "hover", PureBoundingBox::default());
+        edit.set_hover_caret(true);
+
+        let resp = harness.focus(&mut edit);
+        let cursor = edit.editor().cursor();
+        let end = resp.rect.right_center() - resp.rect.min.to_vec2();
+        assert_eq!(edit.hit(Logical(end), 1.0), Some(Cursor::new(0, 5)));
+
+        harness.frame(
+            &mut edit,
+            vec![Event::PointerMoved(resp.rect.right_center())],
+        );
+        assert_eq!(edit.editor().cursor(), cursor);
+        assert!(!edit.changed_this_frame());
+    }
+
+    #[test]
+    fn view_cursor_stays_in_the_text_after_edits() {
+        let mut harness = Harness::default();
+        let mut edit = edit(
+            &mut harness,
+            "first\nsecond line",
+            PureBoundingBox::default(),
+        );
+        let mut view = CosmicEditView::default();
+        let mut show_view = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events| {
+            let input = egui::RawInput {
+                events,
+                ..Default::default()
+            };
+            let mut resp = None;
+            let _ = harness.ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    resp = Some(edit.view_ui(
+                        &mut view,
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                    ));
+                });
+            });
+            (resp.unwrap(), view.cursor())
+        };
+
+        let (resp, _) = show_view(&mut harness, &mut edit, vec![]);
+        let end = resp.rect.right_bottom() - egui::vec2(1.0, 1.0);
+        let press = |pressed| Event::PointerButton {
+            pos: end,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        show_view(
+            &mut harness,
+            &mut edit,
+            vec![Event::PointerMoved(end), press(true)],
+        );
+        let (_, cursor) = show_view(&mut harness, &mut edit, vec![press(false)]);
+        assert_eq!(cursor, Cursor::new(1, "second line".len()));
+        // The widget's own cursor didn't move
+        assert_eq!(edit.editor().cursor(), Cursor::new(0, 0));
+
+        edit.set_text(
+            [("short", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let (_, cursor) = show_view(&mut harness, &mut edit, vec![]);
+        assert_eq!(cursor, Cursor::new(0, "short".len()));
+    }
+
+    #[test]
+    fn stale_history_is_skipped_instead_of_panicking() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.insert_string("abc\ndef".to_string(), &mut harness.font_system);
+
+        // Edited behind the history's back
+        edit.editor.with_buffer_mut(|x| {
+            x.set_text(
+                &mut harness.font_system,
+                "x",
+                &Attrs::new(),
+                Shaping::Advanced,
+            )
+        });
+        assert!(edit.undo());
+        assert_eq!(edit.skipped_history_changes(), 1);
+        assert_eq!(edit.text(), "x");
+    }
+
+    #[test]
+    fn history_survives_interleaved_size_changes() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", FillWidth::default());
+        edit.set_undo_group_window(None);
+        harness.focus(&mut edit);
+
+        // xorshift, deterministic without pulling in a rng crate
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move |n: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % n
+        };
+
+        // The same history over whole texts, (before, after) each change
+        let mut model = Commands::<(String, String)>::new();
+        let mut text = edit.text();
+        let apply = |text: &mut String, actions: ActionIter<(String, String)>| {
+            for action in actions {
+                *text = match action {
+                    Action::Do((_, after)) => after.clone(),
+                    Action::Undo((before, _)) => before.clone(),
+                };
+            }
+        };
+        for _ in 0..300 {
+            match next(6) {
+                0 | 1 => {
+                    // One character at a time, each is its own undo step
+                    let event = match next(4) {
+                        0 => key(Key::Enter, Modifiers::NONE),
+                        1 => key(Key::Backspace, Modifiers::NONE),
+                        _ => Event::Text(["w", " "][next(2) as usize].to_string()),
+                    };
+                    harness.frame(&mut edit, vec![event]);
+                    if edit.changed_this_frame() {
+                        let after = edit.text();
+                        model.push((std::mem::replace(&mut text, after.clone()), after));
+                    }
+                }
+                2 => {
+                    let font_size = 8.0 + next(40) as f32;
+                    edit.set_font_size(
+                        font_size,
+                        LineHeight::Relative(1.5),
+                        &mut harness.font_system,
+                    );
+                    harness.frame(&mut edit, vec![]);
+                }
+                3 => {
+                    let wrap =
+                        [Wrap::None, Wrap::Glyph, Wrap::Word, Wrap::WordOrGlyph][next(4) as usize];
+                    edit.set_wrap(wrap, &mut harness.font_system);
+                }
+                4 => {
+                    edit.undo();
+                    apply(&mut text, model.undo());
+                }
+                _ => {
+                    edit.redo();
+                    apply(&mut text, model.redo());
+                }
+            }
+            assert_eq!(edit.text(), text);
+        }
+        assert_eq!(edit.skipped_history_changes(), 0);
+    }
+
+    #[test]
+    fn keyboard_selection_follows_visual_rows() {
+        let mut harness = Harness::default();
+        let paragraph = "lorem ipsum dolor sit amet ".repeat(10);
+        let text = format!("{paragraph}\nshort");
+        let mut edit = edit(&mut harness, &text, FillWidth::default());
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![]);
+
+        // (start, end) of every visual row of the paragraph
+        let rows = edit.editor().with_buffer(|x| {
+            x.layout_runs()
+                .filter(|run| run.line_i == 0)
+                .map(|run| (run.glyphs[0].start, run.glyphs.last().unwrap().end))
+                .collect::<Vec<_>>()
+        });
+        assert!(rows.len() > 2, "the paragraph should wrap");
+
+        let mut press = |edit: &mut CosmicEdit<FillWidth>, key_: Key, shift| {
+            let modifiers = match shift {
+                true => Modifiers::SHIFT,
+                false => Modifiers::NONE,
+            };
+            harness.frame(edit, vec![key(key_, modifiers)]);
+            let bounds = edit.editor().selection_bounds();
+            let index = |x: Cursor| (x.line, x.index);
+            (
+                index(edit.editor().cursor()),
+                bounds.map(|(start, end)| (index(start), index(end))),
+            )
+        };
+
+        // Shift+End stops at the end of the first row, not the paragraph
+        assert_eq!(
+            press(&mut edit, Key::End, true),
+            ((0, rows[0].1), Some(((0, 0), (0, rows[0].1))))
+        );
+        // Each Shift+Down extends by exactly one row
+        for row in &rows[1..] {
+            let ((line, index), bounds) = press(&mut edit, Key::ArrowDown, true);
+            assert_eq!(line, 0);
+            assert!(row.0 <= index && index <= row.1, "{index} not in {row:?}");
+            assert_eq!(bounds, Some(((0, 0), (0, index))));
+        }
+        // And the next one leaves the paragraph for the next buffer line
+        let ((line, _), _) = press(&mut edit, Key::ArrowDown, true);
+        assert_eq!(line, 1);
+
+        // Home and End stay on the visual row, without selecting once shift is released
+        press(&mut edit, Key::ArrowUp, false);
+        let last = rows[rows.len() - 1];
+        assert_eq!(press(&mut edit, Key::Home, false), ((0, last.0), None));
+        assert_eq!(press(&mut edit, Key::End, false), ((0, last.1), None));
+        let ((_, index), _) = press(&mut edit, Key::ArrowUp, false);
+        let above = rows[rows.len() - 2];
+        assert!(above.0 <= index && index <= above.1);
+    }
+
+    #[test]
+    fn ctrl_arrows_move_by_words_across_lines() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two\nthree", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let mut press = |k, modifiers| {
+            harness.frame(&mut edit, vec![key(k, modifiers)]);
+            let cursor = edit.editor().cursor();
+            (cursor.line, cursor.index)
+        };
+
+        assert_eq!(press(Key::ArrowRight, Modifiers::CTRL), (0, 3));
+        assert_eq!(press(Key::ArrowRight, Modifiers::CTRL), (0, 7));
+        assert_eq!(press(Key::ArrowRight, Modifiers::CTRL), (1, 0));
+        assert_eq!(press(Key::ArrowLeft, Modifiers::CTRL), (0, 7));
+        // Option on macOS
+        assert_eq!(press(Key::ArrowLeft, Modifiers::ALT), (0, 4));
+        assert_eq!(press(Key::ArrowRight, Modifiers::NONE), (0, 5));
+
+        let shift_ctrl = Modifiers::CTRL | Modifiers::SHIFT;
+        assert_eq!(press(Key::ArrowRight, shift_ctrl), (0, 7));
+        assert_eq!(
+            edit.editor().selection(),
+            Selection::Normal(Cursor::new(0, 5))
+        );
+        assert_eq!(edit.editor().copy_selection().as_deref(), Some("wo"));
+    }
+
+    #[test]
+    fn ctrl_backspace_and_delete_remove_words_one_undo_step_each() {
+        let mut harness = Harness::default();
+        let mut edit = edit(
+            &mut harness,
+            "one two three\nfour",
+            PureBoundingBox::default(),
+        );
+        harness.focus(&mut edit);
+        edit.editor.set_cursor(Cursor::new(0, 13));
+        edit.editor.set_selection(Selection::None);
+
+        harness.frame(&mut edit, vec![key(Key::Backspace, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one two \nfour");
+        harness.frame(&mut edit, vec![key(Key::Backspace, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one \nfour");
+        // Joins the next line once at the end of this one
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::Delete, Modifiers::CTRL),
+                key(Key::Delete, Modifiers::CTRL),
+            ],
+        );
+        assert_eq!(edit.text(), "one ");
+
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one four");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one \nfour");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one two \nfour");
+
+        // Only the selection goes
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(0, 1)));
+        edit.editor.set_cursor(Cursor::new(0, 2));
+        harness.frame(&mut edit, vec![key(Key::Backspace, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "oe two \nfour");
+    }
+
+    #[test]
+    fn page_keys_move_by_the_visible_height() {
+        let mut harness = Harness::default();
+        let text = (0..1000).map(|i| format!("line {i}\n")).collect::<String>();
+        let mut edit = edit(&mut harness, &text, FillWidth::default());
+        harness.focus(&mut edit);
+        let visible_rows = (harness.ctx.screen_rect().height() / 21.0) as usize;
+
+        harness.frame(&mut edit, vec![key(Key::PageDown, Modifiers::NONE)]);
+        let line = edit.editor().cursor().line;
+        assert!(line.abs_diff(visible_rows) <= 1, "{line} {visible_rows}");
+
+        harness.frame(&mut edit, vec![key(Key::PageDown, Modifiers::SHIFT)]);
+        let Selection::Normal(anchor) = edit.editor().selection() else {
+            panic!("Shift+PageDown should select");
+        };
+        assert_eq!((anchor.line, anchor.index), (line, 0));
+        assert!(edit.editor().cursor().line > line);
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::PageUp, Modifiers::NONE),
+                key(Key::PageUp, Modifiers::NONE),
+                key(Key::PageUp, Modifiers::NONE),
+            ],
+        );
+        assert_eq!(edit.editor().cursor().line, 0);
+
+        let page_downs = vec![key(Key::PageDown, Modifiers::NONE); 1000 / visible_rows + 1];
+        harness.frame(&mut edit, page_downs);
+        let last_line = edit.editor().with_buffer(|x| x.lines.len() - 1);
+        assert_eq!(edit.editor().cursor().line, last_line);
+    }
+
+    #[test]
+    fn command_home_and_end_jump_to_the_buffer_ends() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo\nthree", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        edit.editor.set_cursor(Cursor::new(1, 1));
+
+        harness.frame(&mut edit, vec![key(Key::End, Modifiers::COMMAND)]);
+        assert_eq!(edit.editor().cursor(), Cursor::new(2, 5));
+        harness.frame(
+            &mut edit,
+            vec![key(Key::Home, Modifiers::COMMAND | Modifiers::SHIFT)],
+        );
+        assert_eq!(edit.editor().cursor(), Cursor::new(0, 0));
+        assert_eq!(
+            edit.editor().copy_selection().as_deref(),
+            Some("one\ntwo\nthree")
+        );
+    }
+
+    #[test]
+    fn paragraph_line_motions_use_the_whole_line() {
+        let mut harness = Harness::default();
+        let paragraph = "lorem ipsum dolor sit amet ".repeat(10);
+        let mut edit = edit(&mut harness, &paragraph, FillWidth::default())
+            .with_line_motions(LineMotions::Paragraphs);
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![key(Key::End, Modifiers::SHIFT)]);
+        assert_eq!(
+            edit.editor().selection_bounds(),
+            Some((Cursor::new(0, 0), Cursor::new(0, paragraph.len())))
+        );
+    }
+
+    #[test]
+    fn disabled_widgets_still_report_clicks() {
+        let mut harness = Harness::default();
+        let mut edit = CosmicEdit::new(
+            14.0,
+            LineHeight::Relative(1.5),
+            Interactivity::Disabled,
+            HoverStrategy::Widget,
+            PureBoundingBox::default(),
+            &mut harness.font_system,
+        );
+        edit.set_text(
+            [("see ", Attrs::new()), ("link", Attrs::new().metadata(7))],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+
+        let resp = harness.frame(&mut edit, vec![]);
+        let pos = resp.rect.right_center() - egui::vec2(2.0, 0.0);
+        let press = |pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        harness.frame(&mut edit, vec![Event::PointerMoved(pos), press(true)]);
+        let hovered = edit.hovered_cursor().unwrap();
+        assert_eq!(edit.metadata_at(hovered), Some(7));
+
+        harness.frame(&mut edit, vec![press(false)]);
+        let clicked = edit.clicked_cursor().unwrap();
+        assert_eq!(edit.metadata_at(clicked), Some(7));
+        let start = Cursor::new_with_affinity(0, 0, cosmic_text::Affinity::After);
+        assert_eq!(edit.metadata_at(start), Some(0));
+        // Nothing else reacted to the click
+        assert_eq!(
+            edit.editor().selection(),
+            Selection::Normal(Cursor::new(0, 0))
+        );
+    }
+
+    #[test]
+    fn disabled_widgets_without_links_leave_clicks_to_what_is_behind() {
+        let mut harness = Harness::default();
+        let mut edit = CosmicEdit::new(
+            14.0,
+            LineHeight::Relative(1.5),
+            Interactivity::Disabled,
+            HoverStrategy::Widget,
+            PureBoundingBox::default(),
+            &mut harness.font_system,
+        );
+        edit.set_text(
+            [("a selectable row", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+
+        let frame = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events| {
+            let input = egui::RawInput {
+                events,
+                ..Default::default()
+            };
+            let mut resp = None;
+            let mut row_clicked = false;
+            let _ = harness.ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let row =
+                        ui.interact(ui.max_rect(), egui::Id::new("row"), egui::Sense::click());
+                    resp = Some(edit.ui(
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                        NoContextMenu,
+                    ));
+                    row_clicked = row.clicked();
+                });
+            });
+            (resp.unwrap(), row_clicked)
+        };
+        let (resp, _) = frame(&mut harness, &mut edit, vec![]);
+        assert_eq!(resp.sense, egui::Sense::hover());
+        let pos = resp.rect.center();
+        let press = |pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        frame(
+            &mut harness,
+            &mut edit,
+            vec![Event::PointerMoved(pos), press(true)],
+        );
+        let (_, row_clicked) = frame(&mut harness, &mut edit, vec![press(false)]);
+        assert!(row_clicked);
+
+        // Links take the clicks
+        edit.set_text(
+            [("a ", Attrs::new()), ("link", Attrs::new().metadata(1))],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let (resp, _) = frame(&mut harness, &mut edit, vec![]);
+        assert_eq!(resp.sense, egui::Sense::click());
+    }
+
+    #[test]
+    fn drop_cursor_follows_a_drag_over_the_widget() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "drop here", PureBoundingBox::default());
+        let resp = harness.focus(&mut edit);
+        let end = resp.rect.right_center() - egui::vec2(1.0, 0.0);
+
+        harness.frame(&mut edit, vec![Event::PointerMoved(end)]);
+        assert_eq!(edit.drop_cursor(), None);
+
+        egui::DragAndDrop::set_payload(&harness.ctx, "payload");
+        harness.frame(&mut edit, vec![Event::PointerMoved(end)]);
+        assert_eq!(edit.drop_cursor().map(|x| x.index), Some("drop here".len()));
+        assert_eq!(edit.editor().cursor().index, 0);
+
+        egui::DragAndDrop::clear_payload(&harness.ctx);
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(edit.drop_cursor(), None);
+    }
+
+    #[test]
+    fn pointer_over_selection_follows_the_hovered_cursor() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "pick some words", PureBoundingBox::default());
+        let resp = harness.focus(&mut edit);
+        // The middle of the "o" in "some"
+        let glyph_x = edit.editor().with_buffer(|x| {
+            let glyph = &x.layout_runs().next().unwrap().glyphs[6];
+            glyph.x + glyph.w / 2.0
+        });
+        let over_some = pos2(resp.rect.left() + glyph_x, resp.rect.center().y);
+        let past_end = resp.rect.right_center() - egui::vec2(1.0, 0.0);
+
+        harness.frame(&mut edit, vec![Event::PointerMoved(over_some)]);
+        assert!(!edit.pointer_over_selection());
+
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(0, 5)));
+        edit.editor.set_cursor(Cursor::new(0, 9));
+        harness.frame(&mut edit, vec![Event::PointerMoved(over_some)]);
+        assert!(edit.pointer_over_selection());
+
+        harness.frame(&mut edit, vec![Event::PointerMoved(past_end)]);
+        assert!(edit.hovered_cursor().is_some());
+        assert!(!edit.pointer_over_selection());
+
+        harness.frame(&mut edit, vec![Event::PointerGone]);
+        assert!(!edit.pointer_over_selection());
+    }
+
+    #[test]
+    fn oversized_changes_clear_the_history() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_max_recorded_change_bytes(Some(16));
+        assert!(!edit.can_undo());
+
+        edit.insert_string("small".to_string(), &mut harness.font_system);
+        assert!(edit.can_undo());
+
+        edit.insert_string("x".repeat(17), &mut harness.font_system);
+        assert_eq!(edit.history_barriers(), 1);
+        assert!(!edit.can_undo() && !edit.undo());
+        assert_eq!(edit.text(), format!("small{}", "x".repeat(17)));
+
+        // Recording picks up again after the barrier
+        edit.insert_string("!".to_string(), &mut harness.font_system);
+        assert!(edit.undo());
+        assert!(!edit.can_undo() && edit.can_redo());
+        assert_eq!(edit.text(), format!("small{}", "x".repeat(17)));
+    }
+
+    #[test]
+    fn aligned_content_hit_tests_where_it_is_drawn() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "ab", FillWidthAndHeight::default())
+            .with_content_align(Align2::CENTER_CENTER);
+
+        let resp = harness.focus(&mut edit);
+        let center = resp.rect.center();
+        let press = |pressed| Event::PointerButton {
+            pos: center,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        harness.frame(&mut edit, vec![Event::PointerMoved(center), press(true)]);
+        harness.frame(&mut edit, vec![press(false)]);
+        // Between the two glyphs, rather than past the end of the top-left aligned text
+        assert_eq!(edit.editor().cursor().index, 1);
+    }
+
+    #[test]
+    fn remote_selections_report_name_tags() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "shared\ntext", PureBoundingBox::default());
+        edit.remote_selections_mut().extend([
+            RemoteSelection {
+                cursor: Cursor::new(1, 2),
+                anchor: Some(Cursor::new(0, 1)),
+                color: Color32::RED,
+            },
+            // Past the end of the text
+            RemoteSelection {
+                cursor: Cursor::new(5, 0),
+                anchor: None,
+                color: Color32::BLUE,
+            },
+        ]);
+
+        let resp = harness.frame(&mut edit, vec![]);
+        let tags = edit.remote_name_tags();
+        assert_eq!(tags.len(), 2);
+        let [first, second] = [tags[0].unwrap(), tags[1].unwrap()];
+        assert!(resp.rect.contains(first) && resp.rect.contains(second));
+        // Both carets sit on the second line, the clamped one at its end
+        assert_eq!(first.y, second.y);
+        assert!(first.x < second.x);
+    }
+
+    #[test]
+    fn shaping_budget_estimates_and_refines_over_frames() {
+        let mut harness = Harness::default();
+        let mut edit = CosmicEdit::new(
+            14.0,
+            LineHeight::Relative(1.5),
+            Interactivity::Enabled,
+            HoverStrategy::Widget,
+            FillWidth::default(),
+            &mut harness.font_system,
+        );
+        edit.set_shaping_budget(Some(Duration::ZERO));
+        let text = "line\n".repeat(19) + "line";
+        edit.set_text(
+            [(text.as_str(), Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let unshaped = |edit: &CosmicEdit<FillWidth>| {
+            edit.editor().with_buffer(|x| {
+                x.lines
+                    .iter()
+                    .filter(|line| line.layout_opt().is_none())
+                    .count()
+            })
+        };
+        assert_eq!(unshaped(&edit), 20);
+
+        // One line per frame, the rest is estimated at the line height
+        let estimated = harness.frame(&mut edit, vec![]).rect;
+        assert_eq!(unshaped(&edit), 19);
+        assert!(harness.ctx.has_requested_repaint());
+        assert_eq!(estimated.height(), 20.0 * 21.0);
+
+        let mut frames = 1;
+        while unshaped(&edit) > 0 {
+            harness.frame(&mut edit, vec![]);
+            frames += 1;
+        }
+        assert_eq!(frames, 20);
+        let shaped = harness.frame(&mut edit, vec![]).rect;
+        assert_eq!(shaped, estimated);
+        assert_eq!(edit.editor().with_buffer(|x| x.layout_runs().count()), 20);
+    }
+
+    #[test]
+    fn clicks_past_a_wrapped_row_stay_on_it() {
+        for (text, wrap) in [("word ", Wrap::WordOrGlyph), ("wordy", Wrap::Glyph)] {
+            let mut harness = Harness::default();
+            let mut edit = edit(&mut harness, &text.repeat(60), FillWidth::default());
+            edit.set_wrap(wrap, &mut harness.font_system);
+            let resp = harness.focus(&mut edit);
+            let rows = edit.editor().with_buffer(|x| {
+                x.layout_runs()
+                    .map(|run| (run.line_top, run.line_height))
+                    .collect::<Vec<_>>()
+            });
+            assert!(rows.len() > 1);
+
+            for (line_top, line_height) in rows {
+                let y = resp.rect.top() + line_top + line_height / 2.0;
+                let pos = pos2(resp.rect.right() - 2.0, y);
+                let press = |pressed| Event::PointerButton {
+                    pos,
+                    button: egui::PointerButton::Primary,
+                    pressed,
+                    modifiers: Modifiers::NONE,
+                };
+                harness.frame(&mut edit, vec![Event::PointerMoved(pos), press(true)]);
+                harness.frame(&mut edit, vec![press(false)]);
+                // Far enough apart not to count as a double click
+                harness.time += 1.0;
+
+                // The end of the clicked row rather than the start of the next one
+                let cursor = edit.editor().cursor();
+                assert_eq!(cursor.affinity, Affinity::Before);
+                let caret = edit
+                    .editor()
+                    .with_buffer(|x| cursor_rect(x, cursor, Spacing::default()))
+                    .unwrap();
+                assert_eq!(caret.min.y, line_top);
+            }
+        }
+    }
+
+    #[test]
+    fn second_ui_call_in_a_frame_only_draws() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+
+        let mut resps = Vec::new();
+        let input = egui::RawInput {
+            events: vec![Event::Text("x".to_string())],
+            ..Default::default()
+        };
+        let _ = harness.ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                for _ in 0..2 {
+                    resps.push(edit.ui(
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                        NoContextMenu,
+                    ));
+                }
+            });
+        });
+
+        assert_eq!(edit.text(), "x");
+        assert_eq!(resps[0].id, edit.id());
+        assert_ne!(resps[1].id, edit.id());
+        assert_eq!(resps[0].rect.size(), resps[1].rect.size());
+    }
+
+    #[test]
+    fn focus_follows_the_widget_to_another_place() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+
+        let input = egui::RawInput {
+            events: vec![Event::Text("x".to_string())],
+            ..Default::default()
+        };
+        let _ = harness.ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.label("Takes the auto id the widget had");
+                ui.group(|ui| {
+                    edit.ui(
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                        NoContextMenu,
+                    )
+                });
+            });
+        });
+
+        assert_eq!(edit.text(), "x");
+    }
+
+    #[test]
+    fn tab_inserts_or_moves_focus() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![key(Key::Tab, Modifiers::NONE)]);
+        assert_eq!(edit.text(), "");
+
+        let mut edit = edit.with_tab_behavior(TabBehavior::Insert);
+        edit.set_text(
+            [("ab", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        edit.editor.set_cursor(Cursor::new(0, 1));
+        edit.editor.set_selection(Selection::None);
+        harness.focus(&mut edit);
+        // egui only keeps the focus lock filter of a widget that was focused the frame before,
+        // and reads it at the start of the next one
+        for _ in 0..2 {
+            harness.frame(&mut edit, vec![]);
+        }
+        edit.set_track_changes(true);
+        harness.frame(&mut edit, vec![key(Key::Tab, Modifiers::NONE)]);
+        let resp = harness.frame(&mut edit, vec![key(Key::Tab, Modifiers::SHIFT)]);
+        assert_eq!(edit.text(), "a\tb");
+        assert_eq!(edit.editor.cursor(), Cursor::new(0, 2));
+        assert!(resp.has_focus());
+        let tab = TextChange {
+            range: 1..1,
+            inserted: "\t".to_string(),
+        };
+        assert_eq!(edit.take_changes(), vec![tab]);
+
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "ab");
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+        harness.frame(&mut edit, vec![redo]);
+        assert_eq!(edit.text(), "a\tb");
+        assert_eq!(edit.editor.cursor(), Cursor::new(0, 2));
+    }
+
+    #[test]
+    fn typing_continues_while_siblings_come_and_go() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default()).with_id_salt("notes");
+        assert_eq!(edit.id(), egui::Id::new("notes"));
+        harness.focus(&mut edit);
+
+        for (i, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let input = egui::RawInput {
+                events: vec![Event::Text(text.to_string())],
+                ..Default::default()
+            };
+            let _ = harness.ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    if i % 2 == 0 {
+                        ui.label("Sometimes above the editor");
+                    }
+                    edit.ui(
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                        NoContextMenu,
+                    );
+                });
+            });
+        }
+
+        assert_eq!(edit.text(), "abcd");
+    }
+
+    #[test]
+    fn overwrite_replaces_one_char_per_keystroke() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "abc", PureBoundingBox::default());
+        edit.editor.set_cursor(Cursor::new(0, 1));
+        edit.editor.set_selection(Selection::None);
+        edit.set_undo_group_window(None);
+        harness.focus(&mut edit);
+
+        harness.frame(&mut edit, vec![key(Key::Insert, Modifiers::NONE)]);
+        assert!(edit.overwrite());
+        // Past the end of the line it inserts
+        harness.frame(&mut edit, vec![Event::Text("xyz".to_string())]);
+        assert_eq!(edit.text(), "axyz");
+
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "axy");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "axc");
+
+        harness.frame(&mut edit, vec![key(Key::Insert, Modifiers::NONE)]);
+        assert!(!edit.overwrite());
+    }
+
+    #[test]
+    fn font_family_replaces_the_default_family_only() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_text(
+            [
+                ("直", Attrs::new()),
+                ("值", Attrs::new().family(Family::Monospace)),
+            ],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let families = |edit: &CosmicEdit<_>| {
+            edit.editor.with_buffer(|x| {
+                let attrs_list = x.lines[0].attrs_list();
+                [0, "直".len()].map(|index| FamilyOwned::new(attrs_list.get_span(index).family))
+            })
+        };
+
+        edit.set_font_family(
+            Some("Noto Sans CJK JP".to_string()),
+            &mut harness.font_system,
+        );
+        assert_eq!(
+            families(&edit),
+            [
+                FamilyOwned::Name("Noto Sans CJK JP".into()),
+                FamilyOwned::Monospace
+            ]
+        );
+
+        edit.set_font_family(None, &mut harness.font_system);
+        assert_eq!(
+            families(&edit),
+            [FamilyOwned::SansSerif, FamilyOwned::Monospace]
+        );
+    }
+
+    #[test]
+    fn editors_round_trip_and_swap() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "first line\nsecond", FillWidth::default());
+        let before = harness.focus(&mut edit).rect;
+        harness.frame(&mut edit, vec![Event::Text("!".to_string())]);
+        let rect = harness.frame(&mut edit, vec![]).rect;
+        assert_eq!(rect, before);
+
+        let mut edit = CosmicEdit::from_editor(
+            edit.into_editor(),
+            Interactivity::Enabled,
+            HoverStrategy::Widget,
+            FillWidth::default(),
+        );
+        assert!(!edit.can_undo());
+        assert_eq!(harness.frame(&mut edit, vec![]).rect.size(), rect.size());
+        assert_eq!(edit.text(), "!first line\nsecond");
+
+        let other = edit.editor.clone();
+        edit.set_text(
+            [("a\nthird\nline", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![Event::Text("x".to_string())]);
+        assert!(edit.can_undo());
+        let old = edit.replace_editor(other);
+        assert_eq!(old.with_buffer(|x| x.lines.len()), 3);
+        assert!(!edit.can_undo());
+        assert_eq!(harness.frame(&mut edit, vec![]).rect.size(), rect.size());
+    }
+
+    #[test]
+    fn single_line_submits_on_enter() {
+        let mut harness = Harness::default();
+        let mut edit =
+            edit(&mut harness, "search\nterms", PureBoundingBox::default()).with_single_line(true);
+        assert_eq!(edit.text(), "search terms");
+        harness.focus(&mut edit);
+
+        harness.frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]);
+        assert!(edit.submitted());
+        harness.frame(&mut edit, vec![key(Key::ArrowUp, Modifiers::NONE)]);
+        assert!(!edit.submitted());
+        assert_eq!(edit.text(), "search terms");
+        assert_eq!(edit.editor.cursor().index, 0);
+
+        harness.frame(&mut edit, vec![Event::Paste("a\r\nb\n".to_string())]);
+        assert_eq!(edit.text(), "a b search terms");
+
+        edit.set_text(
+            [("one\ntwo", Attrs::new()), ("\nthree", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        assert_eq!(edit.text(), "one two three");
+    }
+
+    #[test]
+    fn alt_arrows_move_lines() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo\nthree", PureBoundingBox::default());
+        edit.editor.set_cursor(Cursor::new(1, 1));
+        edit.editor.set_selection(Selection::None);
+        harness.focus(&mut edit);
+
+        harness.frame(&mut edit, vec![key(Key::ArrowUp, Modifiers::ALT)]);
+        assert_eq!(edit.text(), "two\none\nthree");
+        assert_eq!(edit.editor.cursor(), Cursor::new(0, 1));
+        // Already at the top
+        harness.frame(&mut edit, vec![key(Key::ArrowUp, Modifiers::ALT)]);
+        assert_eq!(edit.text(), "two\none\nthree");
+
+        harness.frame(&mut edit, vec![key(Key::ArrowDown, Modifiers::ALT)]);
+        harness.frame(&mut edit, vec![key(Key::ArrowDown, Modifiers::ALT)]);
+        assert_eq!(edit.text(), "one\nthree\ntwo");
+        harness.frame(&mut edit, vec![key(Key::ArrowDown, Modifiers::ALT)]);
+        assert_eq!(edit.text(), "one\nthree\ntwo");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+
+        // Partly selected lines move whole, a selection ending at a line's start leaves it
+        edit.editor.set_cursor(Cursor::new(0, 2));
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(2, 0)));
+        harness.frame(&mut edit, vec![key(Key::ArrowDown, Modifiers::ALT)]);
+        assert_eq!(edit.text(), "three\none\ntwo");
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(1, 2), Cursor::new(2, 3)))
+        );
+    }
+
+    #[test]
+    fn shift_enter_inserts_soft_breaks() {
+        let mut harness = Harness::default();
+        let mut edit =
+            edit(&mut harness, "", PureBoundingBox::default()).with_soft_break("\u{2028}");
+        edit.set_text(
+            [("one\u{2028}two\nthree", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let breaks = |edit: &CosmicEdit<_>| (0..4).map(|i| edit.line_break(i)).collect::<Vec<_>>();
+        assert_eq!(
+            breaks(&edit),
+            [
+                Some(LineBreak::Soft),
+                Some(LineBreak::Paragraph),
+                None,
+                None
+            ]
+        );
+        assert_eq!(edit.text(), "one\u{2028}two\nthree");
+
+        edit.editor.set_cursor(Cursor::new(2, 5));
+        edit.editor.set_selection(Selection::None);
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![key(Key::Enter, Modifiers::SHIFT)]);
+        harness.frame(&mut edit, vec![Event::Text("four".to_string())]);
+        assert_eq!(edit.text(), "one\u{2028}two\nthree\u{2028}four");
+        // Shaped as separate lines
+        assert_eq!(edit.editor.with_buffer(|x| x.layout_runs().count()), 4);
+
+        // Joining lines keeps the break after them
+        edit.editor.set_cursor(Cursor::new(1, 0));
+        harness.frame(&mut edit, vec![key(Key::Backspace, Modifiers::NONE)]);
+        assert_eq!(
+            breaks(&edit),
+            [
+                Some(LineBreak::Paragraph),
+                Some(LineBreak::Soft),
+                None,
+                None
+            ]
+        );
+
+        edit.editor.set_cursor(Cursor::new(2, 2));
+        harness.frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]);
+        assert_eq!(edit.text(), "onetwo\nthree\u{2028}fo\nur");
+
+        harness.frame(&mut edit, vec![Event::Paste("a\u{2028}b\nc".to_string())]);
+        assert_eq!(edit.text(), "onetwo\nthree\u{2028}fo\na\u{2028}b\ncur");
+    }
+
+    #[test]
+    fn undo_skips_remote_changes() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one", PureBoundingBox::default());
+        edit.editor.set_cursor(Cursor::new(0, 3));
+        edit.editor.set_selection(Selection::None);
+        edit.insert_string(" two".to_string(), &mut harness.font_system);
+
+        let insert = |line, index, text: &str| {
+            let start = Cursor::new(line, index);
+            Change {
+                items: vec![ChangeItem {
+                    start,
+                    end: history::insert_end(start, text),
+                    text: text.to_string(),
+                    insert: true,
+                }],
+            }
+        };
+        assert!(edit.apply_change(
+            ChangeOrigin::Remote(1),
+            &insert(0, 5, "[remote]"),
+            &mut harness.font_system
+        ));
+        assert_eq!(edit.text(), "one t[remote]wo");
+        // The caret stays after what was typed
+        assert_eq!(edit.editor.cursor(), Cursor::new(0, 15));
+
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "one[remote]");
+        assert!(!edit.can_undo());
+        assert!(edit.redo());
+        assert_eq!(edit.text(), "one t[remote]wo");
+    }
+
+    #[test]
+    fn undo_never_alters_remote_text() {
+        // A xorshift, the same interleavings every run
+        fn random(state: &mut u64, n: usize) -> usize {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            (*state % n as u64) as usize
+        }
+        fn cursor_at(text: &str, offset: usize) -> Cursor {
+            let before = &text[..offset];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            Cursor::new(before.matches('\n').count(), offset - line_start)
+        }
+        // Deleting another user's text is the local user's own edit, undoing an undo redoes it.
+        // Local changes only delete local text so that doesn't count as undo altering it.
+        fn item(
+            text: &str,
+            start: usize,
+            inserted: Option<String>,
+            origin: ChangeOrigin,
+        ) -> Change {
+            let cursor = cursor_at(text, start);
+            let (text, insert) = match inserted {
+                Some(inserted) => (inserted, true),
+                None => (
+                    text[start..]
+                        .chars()
+                        .take(3)
+                        .take_while(|c| origin != ChangeOrigin::Local || !c.is_ascii_uppercase())
+                        .collect(),
+                    false,
+                ),
+            };
+            Change {
+                items: vec![ChangeItem {
+                    start: cursor,
+                    end: history::insert_end(cursor, &text),
+                    text,
+                    insert,
+                }],
+            }
+        }
+        // Local text is lowercase, remote text uppercase
+        let is_upper = |c: char| c.is_ascii_uppercase();
+        let remote_text = |edit: &CosmicEdit<_>| {
+            edit.text()
+                .chars()
+                .filter(|&c| is_upper(c))
+                .collect::<String>()
+        };
+        let is_subsequence = |short: &str, long: &str| {
+            let mut long = long.chars();
+            short.chars().all(|c| long.any(|x| x == c))
+        };
+
+        let mut harness = Harness::default();
+        for seed in 1..=300 {
+            let mut state = seed;
+            let mut edit = edit(&mut harness, "AB\nCD", PureBoundingBox::default());
+            for _ in 0..40 {
+                let text = edit.text();
+                let offset = random(&mut state, text.len() + 1);
+                let insert = |state: &mut u64, letters: &[u8]| {
+                    (0..1 + random(state, 3))
+                        .map(|_| letters[random(state, letters.len())] as char)
+                        .collect::<String>()
+                };
+                match random(&mut state, 7) {
+                    0 | 1 => {
+                        let inserted = insert(&mut state, b"abc\n");
+                        let change = item(&text, offset, Some(inserted), ChangeOrigin::Local);
+                        assert!(edit.apply_change(
+                            ChangeOrigin::Local,
+                            &change,
+                            &mut harness.font_system
+                        ));
+                    }
+                    2 if offset < text.len() && !text[offset..].starts_with(is_upper) => {
+                        let change = item(&text, offset, None, ChangeOrigin::Local);
+                        assert!(edit.apply_change(
+                            ChangeOrigin::Local,
+                            &change,
+                            &mut harness.font_system
+                        ));
+                    }
+                    3 => {
+                        let inserted = insert(&mut state, b"XYZ\n");
+                        let change = item(&text, offset, Some(inserted), ChangeOrigin::Remote(1));
+                        assert!(edit.apply_change(
+                            ChangeOrigin::Remote(1),
+                            &change,
+                            &mut harness.font_system
+                        ));
+                    }
+                    4 if offset < text.len() => {
+                        let change = item(&text, offset, None, ChangeOrigin::Remote(1));
+                        assert!(edit.apply_change(
+                            ChangeOrigin::Remote(1),
+                            &change,
+                            &mut harness.font_system
+                        ));
+                    }
+                    5 => {
+                        let before = remote_text(&edit);
+                        edit.undo();
+                        assert!(is_subsequence(&before, &remote_text(&edit)), "seed {seed}");
+                    }
+                    _ => {
+                        edit.redo();
+                    }
+                }
+                assert_eq!(edit.skipped_history_changes(), 0, "seed {seed}");
+            }
+
+            let before = remote_text(&edit);
+            while edit.can_undo() {
+                edit.undo();
+            }
+            assert_eq!(edit.skipped_history_changes(), 0, "seed {seed}");
+            assert!(is_subsequence(&before, &remote_text(&edit)), "seed {seed}");
+            // Everything typed locally was undone
+            assert!(
+                !edit.text().contains(|c: char| c.is_ascii_lowercase()),
+                "seed {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn keymap_rebinds_keys() {
+        let mut harness = Harness::default();
+        let mut edit = edit(
+            &mut harness,
+            "one two
+three",
+            PureBoundingBox::default(),
+        );
+        edit.editor.set_cursor(Cursor::new(0, 3));
+        edit.editor.set_selection(Selection::None);
+        harness.focus(&mut edit);
+
+        // Ctrl+Z runs undo outside of macOS, the more specific binding wins over Command+Z
+        edit.keymap_mut()
+            .bind(Key::K, Modifiers::CTRL, EditorCommand::DeleteToEndOfLine)
+            .bind(
+                Key::U,
+                Modifiers::CTRL,
+                EditorCommand::Custom(Rc::new(|edit, font_system| {
+                    edit.change(font_system, |_font_system, edit| {
+                        edit.editor.insert_string("!", None);
+                    });
+                })),
+            )
+            .bind(Key::Z, Modifiers::CTRL, EditorCommand::Redo)
+            .bind(Key::Y, Modifiers::CTRL, EditorCommand::Undo);
+
+        harness.frame(&mut edit, vec![key(Key::K, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one\nthree");
+        // At the end of the line it joins the next one
+        harness.frame(&mut edit, vec![key(Key::K, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "onethree");
+        harness.frame(&mut edit, vec![key(Key::U, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one!three");
+
+        harness.frame(&mut edit, vec![key(Key::Y, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "onethree");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one!three");
+
+        edit.keymap_mut()
+            .unbind(Key::Z, Modifiers::CTRL)
+            .unbind(Key::U, Modifiers::CTRL);
+        harness.frame(&mut edit, vec![key(Key::U, Modifiers::CTRL)]);
+        assert_eq!(edit.text(), "one!three");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "onethree");
+        assert!(edit.keymap().command(Key::U, Modifiers::CTRL).is_none());
+    }
+
+    #[test]
+    fn shown_again_restarts_blink_and_scrolls() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo", PureBoundingBox::default());
+        let mut other = self::edit(&mut harness, "other tab", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![]);
+        assert!(!edit.shown_again);
+
+        // The other tab is shown for a while
+        harness.focus(&mut other);
+        harness.time += 10.3;
+        harness.frame(&mut other, vec![]);
+
+        harness.ctx.memory_mut(|m| m.request_focus(edit.id()));
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(edit.last_updated_time, harness.time);
+        assert!(matches!(edit.scroll_state, ScrollState::Scrolling));
+        assert!(!edit.shown_again);
+
+        // Without focus it only restarts the blink
+        harness.frame(&mut other, vec![]);
+        harness.time += 10.3;
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(edit.last_updated_time, harness.time);
+        assert!(matches!(edit.scroll_state, ScrollState::Idle));
+    }
+
+    #[test]
+    fn duplicate_and_delete_lines() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo\nthree", PureBoundingBox::default());
+        edit.editor.set_cursor(Cursor::new(1, 2));
+        edit.editor.set_selection(Selection::None);
+        harness.focus(&mut edit);
+
+        let duplicate = key(Key::D, Modifiers::COMMAND | Modifiers::SHIFT);
+        let delete = key(Key::K, Modifiers::COMMAND | Modifiers::SHIFT);
+        harness.frame(&mut edit, vec![duplicate.clone()]);
+        assert_eq!(edit.text(), "one\ntwo\ntwo\nthree");
+        assert_eq!(edit.editor.cursor(), Cursor::new(2, 2));
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+
+        // The last line, with a selection over two lines
+        edit.editor.set_cursor(Cursor::new(1, 1));
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(2, 5)));
+        harness.frame(&mut edit, vec![duplicate]);
+        assert_eq!(edit.text(), "one\ntwo\nthree\ntwo\nthree");
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(3, 1), Cursor::new(4, 5)))
+        );
+
+        harness.frame(&mut edit, vec![delete.clone()]);
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+        assert_eq!(edit.editor.cursor(), Cursor::new(2, 5));
+        assert_eq!(edit.editor.selection(), Selection::None);
+
+        // The cursor keeps its column on the line that moved up
+        edit.editor.set_cursor(Cursor::new(0, 3));
+        harness.frame(&mut edit, vec![delete.clone()]);
+        assert_eq!(edit.text(), "two\nthree");
+        assert_eq!(edit.editor.cursor(), Cursor::new(0, 3));
+        harness.frame(&mut edit, vec![delete.clone(), delete]);
+        assert_eq!(edit.text(), "");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "three");
+    }
+
+    #[test]
+    fn shift_page_keys_extend_the_selection() {
+        let mut harness = Harness::default();
+        let text = (0..1000).map(|i| format!("line {i}\n")).collect::<String>();
+        let mut edit = edit(&mut harness, &text, FillWidth::default());
+        harness.focus(&mut edit);
+        edit.editor.set_cursor(Cursor::new(500, 2));
+        edit.editor.set_selection(Selection::None);
+        let visible_rows = (harness.ctx.screen_rect().height() / 21.0) as usize;
+
+        harness.frame(&mut edit, vec![key(Key::PageDown, Modifiers::SHIFT)]);
+        let line = edit.editor.cursor().line;
+        assert!(
+            line.abs_diff(500 + visible_rows) <= 1,
+            "{line} {visible_rows}"
+        );
+        // The moving end is scrolled to
+        assert!(matches!(edit.scroll_state, ScrollState::Scrolling));
+        harness.frame(&mut edit, vec![key(Key::PageDown, Modifiers::SHIFT)]);
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(500, 2), edit.editor.cursor()))
+        );
+        assert!(edit.editor.cursor().line > line);
+
+        // Back up past the anchor, which stays put
+        let page_ups = vec![key(Key::PageUp, Modifiers::SHIFT); 3];
+        harness.frame(&mut edit, page_ups);
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((edit.editor.cursor(), Cursor::new(500, 2)))
+        );
+        assert!(edit.editor.cursor().line < 500);
+    }
+
+    #[test]
+    fn external_mutation_never_panics_the_next_frame() {
+        // A xorshift, the same sequences every run
+        fn random(state: &mut u64, n: usize) -> usize {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            (*state % n as u64) as usize
+        }
+        let keys = [
+            key(Key::Backspace, Modifiers::NONE),
+            key(Key::Delete, Modifiers::CTRL),
+            key(Key::ArrowUp, Modifiers::SHIFT),
+            key(Key::End, Modifiers::NONE),
+            key(Key::Enter, Modifiers::NONE),
+            key(Key::D, Modifiers::COMMAND | Modifiers::SHIFT),
+            key(Key::K, Modifiers::COMMAND | Modifiers::SHIFT),
+            key(Key::Z, Modifiers::COMMAND),
+            key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT),
+        ];
+
+        let mut harness = Harness::default();
+        for seed in 1..=100 {
+            let mut state = seed;
+            let mut edit = edit(&mut harness, "aé\n日本\n\nxyz", PureBoundingBox::default());
+            harness.focus(&mut edit);
+            for _ in 0..30 {
+                let line = random(&mut state, 6);
+                let index = random(&mut state, 8);
+                match random(&mut state, 6) {
+                    0 => edit.with_editor_mut(|x| {
+                        x.with_buffer_mut(|x| x.lines.truncate(line));
+                    }),
+                    1 => edit.with_editor_mut(|x| {
+                        x.with_buffer_mut(|x| {
+                            let Some(line) = x.lines.get_mut(line) else {
+                                return;
+                            };
+                            let mut text = line.text().to_string();
+                            let mut index = index.min(text.len());
+                            while !text.is_char_boundary(index) {
+                                index -= 1;
+                            }
+                            text.truncate(index);
+                            let ending = line.ending();
+                            line.set_text(text, ending, AttrsList::new(&Attrs::new()));
+                        })
+                    }),
+                    2 => edit.with_editor_mut(|x| {
+                        x.set_cursor(Cursor::new(line, index));
+                        x.set_selection(Selection::Normal(Cursor::new(index, line)));
+                    }),
+                    3 => {
+                        edit.insert_string("é\nab".to_string(), &mut harness.font_system);
+                    }
+                    4 => {
+                        edit.undo();
+                    }
+                    _ => {
+                        let key = keys[random(&mut state, keys.len())].clone();
+                        harness.frame(&mut edit, vec![key]);
+                    }
+                }
+                harness.frame(&mut edit, vec![]);
+                let cursor = edit.editor.cursor();
+                assert!(
+                    edit.editor.with_buffer(|x| is_valid_cursor(x, cursor)),
+                    "seed {seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shift_click_extends_the_selection() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two three", PureBoundingBox::default());
+        let resp = harness.focus(&mut edit);
+        edit.editor.set_cursor(Cursor::new(0, 1));
+        edit.editor.set_selection(Selection::None);
+
+        let pos = |edit: &CosmicEdit<PureBoundingBox>, index| {
+            let rect = edit
+                .editor
+                .with_buffer(|x| cursor_rect(x, Cursor::new(0, index), Spacing::default()))
+                .unwrap();
+            resp.rect.min + rect.center().to_vec2()
+        };
+        let click = |harness: &mut Harness, edit: &mut CosmicEdit<PureBoundingBox>, pos| {
+            let modifiers = harness.modifiers;
+            let press = |pressed| Event::PointerButton {
+                pos,
+                button: egui::PointerButton::Primary,
+                pressed,
+                modifiers,
+            };
+            harness.frame(edit, vec![Event::PointerMoved(pos), press(true)]);
+            let pressed_at = harness.time;
+            harness.frame(edit, vec![press(false)]);
+            pressed_at
+        };
+
+        let bounds = |edit: &CosmicEdit<PureBoundingBox>| {
+            let (start, end) = edit.editor.selection_bounds()?;
+            Some((start.index, end.index))
+        };
+
+        harness.modifiers = Modifiers::SHIFT;
+        let at = pos(&edit, 9);
+        let pressed_at = click(&mut harness, &mut edit, at);
+        assert_eq!(bounds(&edit), Some((1, 9)));
+        assert_eq!(edit.last_updated_time, pressed_at);
+        // The anchor stays where it was
+        harness.time += 1.0;
+        let at = pos(&edit, 5);
+        click(&mut harness, &mut edit, at);
+        assert_eq!(bounds(&edit), Some((1, 5)));
+
+        // A double click extends by words
+        harness.time += 1.0;
+        let at = pos(&edit, 9);
+        click(&mut harness, &mut edit, at);
+        click(&mut harness, &mut edit, at);
+        assert_eq!(bounds(&edit), Some((0, "one two three".len())));
+
+        harness.modifiers = Modifiers::NONE;
+        harness.time += 1.0;
+        click(&mut harness, &mut edit, at);
+        assert_eq!(bounds(&edit), None);
+        assert_eq!(edit.editor.cursor().index, 9);
+    }
+
+    #[test]
+    fn multi_click_drags_snap_to_words_and_lines() {
+        let mut harness = Harness::default();
+        let text = "alpha, beta (gamma)\n\u{5e9}\u{5dc}\u{5d5}\u{5dd} \u{5e2}\u{5d5}\u{5dc}\u{5dd}\nlast line";
+        let mut edit = edit(&mut harness, text, PureBoundingBox::default());
+        let resp = harness.focus(&mut edit);
+
+        // The middle of the char at `index`, either half hits the same word
+        let pos = |edit: &CosmicEdit<PureBoundingBox>, line, index| {
+            let rect = |index| {
+                edit.editor
+                    .with_buffer(|x| cursor_rect(x, Cursor::new(line, index), Spacing::default()))
+                    .unwrap()
+            };
+            let (start, end) = (rect(index), rect(index + 1));
+            resp.rect.min + ((start.center().to_vec2() + end.center().to_vec2()) / 2.0)
+        };
+        let press = |pos, pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        let drag = |harness: &mut Harness, edit: &mut CosmicEdit<PureBoundingBox>, from, clicks| {
+            harness.time += 1.0;
+            harness.frame(edit, vec![Event::PointerMoved(from)]);
+            for i in 0..clicks {
+                if i > 0 {
+                    harness.frame(edit, vec![press(from, false)]);
+                }
+                harness.frame(edit, vec![press(from, true)]);
+            }
+        };
+        let hover = |harness: &mut Harness, edit: &mut CosmicEdit<PureBoundingBox>, to| {
+            harness.frame(edit, vec![Event::PointerMoved(to)]);
+            let (start, end) = edit.editor.selection_bounds().unwrap();
+            ((start.line, start.index), (end.line, end.index))
+        };
+
+        let from = pos(&edit, 0, 8);
+        drag(&mut harness, &mut edit, from, 2);
+        assert_eq!(
+            edit.editor
+                .selection_bounds()
+                .map(|(s, e)| (s.index, e.index)),
+            Some((7, 11))
+        );
+        let to = pos(&edit, 0, 15);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 7), (0, 18)));
+        assert_eq!(edit.editor.cursor().index, 18);
+        // Punctuation next to a word is a unit of its own
+        let to = pos(&edit, 0, 18);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 7), (0, 19)));
+        let to = pos(&edit, 0, 5);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 5), (0, 11)));
+        // Backwards the anchor word stays selected and the cursor goes to the hovered word's start
+        let to = pos(&edit, 0, 1);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 0), (0, 11)));
+        assert_eq!(edit.editor.cursor().index, 0);
+        harness.frame(&mut edit, vec![press(to, false)]);
+
+        // Right-to-left words snap by their logical order
+        let from = pos(&edit, 1, 2);
+        drag(&mut harness, &mut edit, from, 2);
+        let to = pos(&edit, 1, 13);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((1, 0), (1, 17)));
+        let to = pos(&edit, 1, 4);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((1, 0), (1, 8)));
+        harness.frame(&mut edit, vec![press(to, false)]);
+
+        let from = pos(&edit, 0, 3);
+        drag(&mut harness, &mut edit, from, 3);
+        let to = pos(&edit, 2, 2);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 0), (2, 9)));
+        let to = pos(&edit, 1, 2);
+        assert_eq!(hover(&mut harness, &mut edit, to), ((0, 0), (2, 0)));
+        harness.frame(&mut edit, vec![press(to, false)]);
+
+        // Single click drags still go by chars
+        let from = pos(&edit, 0, 1);
+        drag(&mut harness, &mut edit, from, 1);
+        let to = pos(&edit, 0, 8);
+        let ((_, start), (_, end)) = hover(&mut harness, &mut edit, to);
+        assert!((1..=2).contains(&start) && (8..=9).contains(&end));
+    }
+
+    #[test]
+    fn visible_glyphs_are_captured_while_drawing() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_text(
+            [
+                ("ab\n", Attrs::new()),
+                ("c", Attrs::new().color(cosmic_text::Color::rgb(255, 0, 0))),
+            ],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        let glyphs = |edit: &CosmicEdit<_>| {
+            let mut glyphs = Vec::new();
+            edit.visible_glyphs(|glyph| glyphs.push(glyph));
+            glyphs
+        };
+
+        harness.frame(&mut edit, vec![]);
+        assert!(glyphs(&edit).is_empty());
+
+        edit.set_glyph_capture(true);
+        let resp = harness.frame(&mut edit, vec![]);
+        let glyphs = glyphs(&edit);
+        let ranges = glyphs
+            .iter()
+            .map(|glyph| (glyph.line, glyph.range.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(0, 0..1), (0, 1..2), (1, 0..1)]);
+        assert_eq!(glyphs[0].color, Color32::WHITE);
+        assert_eq!(glyphs[2].color, Color32::RED);
+        // Relative to the widget, side by side on the first row and below it on the second
+        assert_eq!(glyphs[0].rect.min, Pos2::ZERO);
+        assert_eq!(glyphs[1].rect.left(), glyphs[0].rect.right());
+        assert_eq!(glyphs[2].rect.top(), glyphs[0].rect.bottom());
+        for glyph in &glyphs {
+            assert!(
+                egui::Rect::from_min_size(Pos2::ZERO, resp.rect.size()).contains_rect(glyph.rect)
+            );
+        }
+
+        // Only the last frame's glyphs are kept
+        edit.set_text(
+            [("d", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        harness.frame(&mut edit, vec![]);
+        let mut count = 0;
+        edit.visible_glyphs(|_| count += 1);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn focused_widget_clears_egui_label_selections() {
+        use egui::text_selection::LabelSelectionState;
+
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "widget text", PureBoundingBox::default());
+        let frame = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events| {
+            harness.time += 1.0 / 60.0;
+            let input = egui::RawInput {
+                time: Some(harness.time),
+                events,
+                ..Default::default()
+            };
+            let mut rects = None;
+            let output = harness.ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let label = ui.label("Selectable label").rect;
+                    let resp = edit.ui(
+                        ui,
+                        &mut harness.font_system,
+                        &mut harness.swash_cache,
+                        &mut harness.atlas,
+                        NoContextMenu,
+                    );
+                    rects = Some((label, resp));
+                });
+            });
+            harness.output = output.platform_output;
+            rects.unwrap()
+        };
+
+        let (label, _) = frame(&mut harness, &mut edit, vec![]);
+        let press = |pos, pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+        let (from, to) = (label.left_center(), label.right_center());
+        frame(&mut harness, &mut edit, vec![Event::PointerMoved(from)]);
+        frame(&mut harness, &mut edit, vec![press(from, true)]);
+        frame(&mut harness, &mut edit, vec![Event::PointerMoved(to)]);
+        frame(&mut harness, &mut edit, vec![press(to, false)]);
+        assert!(LabelSelectionState::load(&harness.ctx).has_selection());
+
+        // Focused without a click, e.g. by tabbing, so egui keeps the label selected
+        let (_, resp) = frame(&mut harness, &mut edit, vec![]);
+        resp.request_focus();
+        frame(&mut harness, &mut edit, vec![]);
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(0, 0)));
+        edit.editor.set_cursor(Cursor::new(0, 6));
+        frame(&mut harness, &mut edit, vec![Event::Copy]);
+        assert_eq!(harness.copied_text(), "widget");
+        assert!(!LabelSelectionState::load(&harness.ctx).has_selection());
+    }
+
+    #[test]
+    fn triple_click_drags_select_whole_lines_with_their_breaks() {
+        let mut harness = Harness::default();
+        let mut edit = edit(
+            &mut harness,
+            "one\ntwo\nthree\nfour",
+            PureBoundingBox::default(),
+        );
+        let resp = harness.focus(&mut edit);
+        let caret = |edit: &CosmicEdit<PureBoundingBox>, line, index| {
+            edit.editor
+                .with_buffer(|x| cursor_rect(x, Cursor::new(line, index), Spacing::default()))
+                .unwrap()
+        };
+        let pos = |edit: &CosmicEdit<PureBoundingBox>, line| {
+            resp.rect.min + caret(edit, line, 1).center().to_vec2()
+        };
+        let press = |pos, pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        };
+
+        let from = pos(&edit, 2);
+        harness.frame(&mut edit, vec![Event::PointerMoved(from)]);
+        for i in 0..3 {
+            if i > 0 {
+                harness.frame(&mut edit, vec![press(from, false)]);
+            }
+            harness.frame(&mut edit, vec![press(from, true)]);
+        }
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(2, 0), Cursor::new(3, 0)))
+        );
+
+        let to = pos(&edit, 1);
+        harness.frame(&mut edit, vec![Event::PointerMoved(to)]);
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(1, 0), Cursor::new(3, 0)))
+        );
+        assert_eq!(edit.editor.cursor(), Cursor::new(1, 0));
+        harness.frame(&mut edit, vec![Event::Copy]);
+        assert_eq!(harness.copied_text(), "two\nthree\n");
+
+        // Both lines show their selected break the same way
+        let rects = edit.selection_rects.clone();
+        assert!(rects[0].right() > caret(&edit, 1, 3).left());
+        assert!(rects[1].right() > caret(&edit, 2, 5).left());
+        assert_eq!(
+            rects[0].right() - caret(&edit, 1, 3).left(),
+            rects[1].right() - caret(&edit, 2, 5).left()
+        );
+
+        // The last line has no break to take
+        let to = pos(&edit, 3);
+        harness.frame(&mut edit, vec![Event::PointerMoved(to)]);
+        assert_eq!(
+            edit.editor.selection_bounds(),
+            Some((Cursor::new(2, 0), Cursor::new(3, 4)))
+        );
+    }
+
+    #[test]
+    fn applied_diffs_keep_the_caret_and_undo_in_one_step() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo\nthree", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        edit.editor.set_cursor(Cursor::new(2, 3));
+        edit.editor
+            .set_selection(Selection::Normal(Cursor::new(2, 1)));
+
+        let target = test_util::buffer(&mut harness.font_system, "zero\none\n2\nthree!", None);
+        let deltas = edit.editor.with_buffer(|x| diff_buffers(x, &target));
+        assert!(edit.apply_deltas(&deltas, &mut harness.font_system));
+        assert_eq!(edit.text(), "zero\none\n2\nthree!");
+        assert_eq!(edit.editor.cursor(), Cursor::new(3, 3));
+        assert_eq!(
+            edit.editor.selection(),
+            Selection::Normal(Cursor::new(3, 1))
+        );
+        assert!(edit.changed_this_frame());
+
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+        assert!(!edit.can_undo());
+
+        // Deltas that don't fit the text stop applying
+        let out_of_range = TextDelta::Delete {
+            start: Cursor::new(0, 0),
+            end: Cursor::new(7, 0),
+        };
+        assert!(!edit.apply_deltas(&[out_of_range], &mut harness.font_system));
+        assert_eq!(edit.text(), "one\ntwo\nthree");
+    }
+
+    /// A finger pressed down, lifted or moved without either at `pos`, as a touch screen sends it.
+    fn touch(pos: Pos2, pressed: Option<bool>) -> Vec<Event> {
+        use egui::{TouchDeviceId, TouchId, TouchPhase};
+
+        let phase = match pressed {
+            Some(true) => TouchPhase::Start,
+            Some(false) => TouchPhase::End,
+            None => TouchPhase::Move,
+        };
+        let mut events = vec![
+            Event::PointerMoved(pos),
+            Event::Touch {
+                device_id: TouchDeviceId(0),
+                id: TouchId(0),
+                phase,
+                pos,
+                force: None,
+            },
+        ];
+        events.extend(pressed.map(|pressed| Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::NONE,
+        }));
+        // Lifted fingers aren't anywhere
+        if pressed == Some(false) {
+            events.push(Event::PointerGone);
+        }
+        events
+    }
+
+    #[test]
+    fn long_press_selects_a_word_with_draggable_handles() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two three", PureBoundingBox::default());
+        let resp = harness.focus(&mut edit);
+        let caret = |edit: &CosmicEdit<PureBoundingBox>, index| {
+            let rect = edit
+                .editor
+                .with_buffer(|x| cursor_rect(x, Cursor::new(0, index), Spacing::default()))
+                .unwrap();
+            resp.rect.min + rect.center().to_vec2()
+        };
+        let bounds = |edit: &CosmicEdit<PureBoundingBox>| {
+            let (start, end) = edit.editor.selection_bounds()?;
+            Some((start.index, end.index))
+        };
+
+        let at = caret(&edit, 5);
+        harness.frame(&mut edit, touch(at, Some(true)));
+        assert_eq!(bounds(&edit), None);
+        // Held still past the click duration
+        harness.time += 1.0;
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(bounds(&edit), Some((4, 7)));
+        harness.frame(&mut edit, touch(at, Some(false)));
+        assert_eq!(bounds(&edit), Some((4, 7)));
+        let handles = edit
+            .touch_handle_rects(Logical(resp.rect.min), 1.0)
+            .unwrap();
+        assert!(handles[0].1.center().x < handles[1].1.center().x);
+        assert!(handles[1].1.top() >= handles[1].0.bottom());
+
+        // The end's handle moves the end, the start stays
+        let from = handles[1].1.center();
+        let to = from + (caret(&edit, 12) - caret(&edit, 7));
+        harness.time += 1.0;
+        harness.frame(&mut edit, touch(from, Some(true)));
+        harness.frame(&mut edit, touch(from + egui::vec2(10.0, 0.0), None));
+        harness.frame(&mut edit, touch(to, None));
+        harness.frame(&mut edit, touch(to, Some(false)));
+        let (start, end) = bounds(&edit).unwrap();
+        assert_eq!(start, 4);
+        assert!((11..=13).contains(&end), "{end}");
+        assert!(edit.showing_touch_handles);
+
+        // A tap anywhere else hides them
+        harness.time += 1.0;
+        let at = caret(&edit, 1);
+        harness.frame(&mut edit, touch(at, Some(true)));
+        harness.frame(&mut edit, touch(at, Some(false)));
+        assert!(!edit.showing_touch_handles);
+        assert_eq!(bounds(&edit), None);
+    }
+
+    #[test]
+    fn touch_swipes_scroll_until_held_to_select() {
+        let mut harness = Harness {
+            scroll_height: Some(100.0),
+            ..Default::default()
+        };
+        let text = (0..30).map(|i| format!("line {i}\n")).collect::<String>();
+        let mut edit = edit(&mut harness, &text, FillWidth::default())
+            .with_touch_drag(TouchDrag::HoldToSelect(0.3));
+        // The scroll area only knows it can scroll after its first frame
+        harness.frame(&mut edit, vec![]);
+        let resp = harness.frame(&mut edit, vec![]);
+        let selected = |edit: &CosmicEdit<FillWidth>| {
+            edit.editor
+                .selection_bounds()
+                .filter(|(start, end)| start != end)
+        };
+        let at = resp.rect.min + egui::vec2(20.0, 50.0);
+        // Moves the finger `dy` after holding it down for `hold` seconds
+        let swipe = |harness: &mut Harness, edit: &mut CosmicEdit<FillWidth>, hold, dy| {
+            harness.frame(edit, touch(at, Some(true)));
+            harness.time += hold;
+            harness.frame(edit, touch(at, None));
+            for step in 1..=4 {
+                harness.frame(
+                    edit,
+                    touch(at + egui::vec2(0.0, dy * step as f32 / 4.0), None),
+                );
+            }
+            harness.frame(edit, touch(at + egui::vec2(0.0, dy), Some(false)));
+            // Lets the kinetic scrolling settle
+            for _ in 0..60 {
+                harness.frame(edit, vec![]);
+            }
+        };
+
+        swipe(&mut harness, &mut edit, 0.0, -40.0);
+        let scrolled = harness.scroll_offset.y;
+        assert!(scrolled >= 40.0, "{scrolled}");
+        assert_eq!(selected(&edit), None);
+        assert!(!harness.ctx.memory(|m| m.has_focus(edit.id)));
+
+        swipe(&mut harness, &mut edit, 0.5, 20.0);
+        assert_eq!(harness.scroll_offset.y, scrolled);
+        let (start, end) = selected(&edit).unwrap();
+        assert!(start.line < end.line);
+
+        // A tap still places the caret
+        harness.time += 1.0;
+        harness.frame(&mut edit, touch(at, Some(true)));
+        harness.frame(&mut edit, touch(at, Some(false)));
+        assert_eq!(selected(&edit), None);
+        assert!(harness.ctx.memory(|m| m.has_focus(edit.id)));
+
+        // The mouse selects right away
+        harness.time += 1.0;
+        let mouse = |pos, pressed: Option<bool>| {
+            let mut events = vec![Event::PointerMoved(pos)];
+            events.extend(pressed.map(|pressed| Event::PointerButton {
+                pos,
+                button: egui::PointerButton::Primary,
+                pressed,
+                modifiers: Modifiers::NONE,
+            }));
+            events
+        };
+        harness.frame(&mut edit, mouse(at, None));
+        harness.frame(&mut edit, mouse(at, Some(true)));
+        harness.frame(&mut edit, mouse(at + egui::vec2(0.0, 30.0), None));
+        harness.frame(&mut edit, mouse(at + egui::vec2(0.0, 30.0), Some(false)));
+        assert!(selected(&edit).is_some());
+        assert_eq!(harness.scroll_offset.y, scrolled);
+    }
+
+    #[test]
+    fn hover_visuals_repaint_at_a_capped_rate_while_hovered() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "hover me", PureBoundingBox::default());
+        let resp = harness.frame(&mut edit, vec![]);
+        let inside = Event::PointerMoved(resp.rect.center());
+        let outside = Event::PointerMoved(resp.rect.right_bottom() + egui::vec2(50.0, 50.0));
+        // egui takes the time a frame needs off
+        let rate = Duration::from_secs_f32(1.0 / CosmicEdit::<PureBoundingBox>::HOVER_REPAINT_RATE)
+            - Duration::from_secs_f32(harness.ctx.input(|i| i.predicted_dt));
+        // egui repaints a couple more times after any input by itself
+        let delay_after = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events| {
+            harness.frame(edit, events);
+            harness.frame(edit, vec![]);
+            harness.frame(edit, vec![]);
+            harness.repaint_delay
+        };
+
+        // Nothing follows the pointer
+        assert_eq!(
+            delay_after(&mut harness, &mut edit, vec![inside.clone()]),
+            Duration::MAX
+        );
+
+        edit.set_hover_repaint(true);
+        assert_eq!(delay_after(&mut harness, &mut edit, vec![]), rate);
+        assert_eq!(
+            delay_after(&mut harness, &mut edit, vec![outside]),
+            Duration::MAX
+        );
+
+        // The hover caret only needs it while it can be shown
+        edit.set_hover_repaint(false);
+        edit.set_hover_caret(true);
+        assert_eq!(delay_after(&mut harness, &mut edit, vec![inside]), rate);
+        *edit.interactivity_mut() = Interactivity::Selection;
+        assert_eq!(delay_after(&mut harness, &mut edit, vec![]), Duration::MAX);
+    }
+
+    #[test]
+    fn control_chars_are_allowed_stripped_or_escaped() {
+        let mut harness = Harness::default();
+        let pasted = "a\u{202E}b\0c";
+        let buffer_text =
+            |edit: &CosmicEdit<_>| edit.editor().with_buffer(|x| x.lines[0].text().to_string());
+
+        for (control_chars, stored, text) in [
+            (ControlChars::Allow, pasted, pasted),
+            (ControlChars::StripControls, "abc", "abc"),
+            (ControlChars::EscapeVisible, "a⟨RLO⟩b␀c", pasted),
+        ] {
+            let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+            edit.set_control_chars(control_chars);
+            harness.focus(&mut edit);
+            harness.frame(
+                &mut edit,
+                vec![
+                    Event::Paste(pasted.to_string()),
+                    Event::Text("\u{200F}".to_string()),
+                ],
+            );
+            // Typed text goes through the same policy
+            let (typed_stored, typed_text) = match control_chars {
+                ControlChars::Allow => ("\u{200F}", "\u{200F}"),
+                ControlChars::StripControls => ("", ""),
+                ControlChars::EscapeVisible => ("⟨RLM⟩", "\u{200F}"),
+            };
+            assert_eq!(buffer_text(&edit), format!("{stored}{typed_stored}"));
+            assert_eq!(edit.text(), format!("{text}{typed_text}"));
+
+            harness.frame(
+                &mut edit,
+                vec![key(Key::A, Modifiers::COMMAND), Event::Copy],
+            );
+            assert_eq!(harness.copied_text(), edit.text());
+
+            edit.set_text(
+                [(pasted, Attrs::new())],
+                Attrs::new(),
+                Shaping::Advanced,
+                &mut harness.font_system,
+            );
+            assert_eq!(buffer_text(&edit), stored);
+        }
+    }
+
+    #[test]
+    fn only_escaped_controls_turn_back() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_control_chars(ControlChars::EscapeVisible);
+        harness.focus(&mut edit);
+        harness.frame(
+            &mut edit,
+            vec![
+                Event::Text("⟨RLO⟩".to_string()),
+                Event::Paste("\u{202E}".to_string()),
+                Event::Paste("␀".to_string()),
+            ],
+        );
+        assert_eq!(edit.text(), "⟨RLO⟩\u{202E}␀");
+        harness.frame(
+            &mut edit,
+            vec![key(Key::A, Modifiers::COMMAND), Event::Copy],
+        );
+        assert_eq!(harness.copied_text(), "⟨RLO⟩\u{202E}␀");
+
+        // Undo brings back the escape of a deleted placeholder
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::End, Modifiers::NONE),
+                key(Key::ArrowLeft, Modifiers::NONE),
+            ]
+            .into_iter()
+            .chain(std::iter::repeat_n(
+                key(Key::ArrowLeft, Modifiers::SHIFT),
+                5,
+            ))
+            .chain([key(Key::Backspace, Modifiers::NONE)])
+            .collect(),
+        );
+        assert_eq!(edit.text(), "⟨RLO⟩␀");
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "⟨RLO⟩\u{202E}␀");
+
+        // Moved lines take their escapes along
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::Home, Modifiers::NONE),
+                key(Key::Enter, Modifiers::NONE),
+                Event::Text("x".to_string()),
+                key(Key::ArrowUp, Modifiers::ALT),
+            ],
+        );
+        assert_eq!(edit.text(), "x⟨RLO⟩\u{202E}␀\n");
+    }
+
+    #[test]
+    fn shift_f10_opens_the_context_menu_at_the_caret() {
+        let mut harness = Harness {
+            context_menu: true,
+            ..Default::default()
+        };
+        let mut edit = edit(&mut harness, "hello world", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::End, Modifiers::NONE),
+                key(Key::ArrowLeft, Modifiers::SHIFT),
+            ],
+        );
+        let selection = edit.editor().selection();
+        assert_ne!(selection, Selection::None);
+
+        let resp = harness.frame(&mut edit, vec![key(Key::F10, Modifiers::SHIFT)]);
+        assert!(resp.context_menu_opened());
+        let caret = edit
+            .editor()
+            .with_buffer(|x| cursor_rect(x, edit.editor().cursor(), Spacing::default()))
+            .unwrap();
+        let menu_rect = harness.ctx.memory(|m| {
+            let layer = m.areas().top_layer_id(egui::Order::Foreground)?;
+            m.area_rect(layer.id)
+        });
+        let anchor = resp.rect.left_top() + caret.left_bottom().to_vec2();
+        // Areas are rounded to pixels
+        assert!((menu_rect.unwrap().left_top() - anchor).length() < 1.0);
+
+        // Tab moves into the menu, Escape closes it and leaves the selection alone
+        harness.frame(&mut edit, vec![key(Key::Tab, Modifiers::NONE)]);
+        let focused = harness.ctx.memory(|m| m.focused());
+        assert!(focused.is_some_and(|x| x != resp.id));
+        assert_eq!(edit.text(), "hello world");
+        harness.frame(&mut edit, vec![key(Key::Escape, Modifiers::NONE)]);
+        let resp = harness.frame(&mut edit, vec![]);
+        assert!(!resp.context_menu_opened());
+        assert!(resp.has_focus());
+        assert_eq!(edit.editor().selection(), selection);
+
+        // Typing goes to the text again
+        harness.frame(&mut edit, vec![Event::Text("!".to_string())]);
+        assert_eq!(edit.text(), "hello worl!");
+    }
+
+    #[test]
+    fn shrink_to_fit_fields_grow_with_typed_text_from_their_min_width() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", ShrinkToFit::default())
+            .with_single_line(true)
+            .with_min_width(40.0);
+        harness.focus(&mut edit);
+        let width = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events| {
+            harness.frame(edit, events).rect.width()
+        };
+        assert_eq!(width(&mut harness, &mut edit, vec![]), 40.0);
+
+        // Measured again in the frame the text changes
+        let typed = "renamed file.txt".to_string();
+        let grown = width(&mut harness, &mut edit, vec![Event::Text(typed)]);
+        assert!(grown > 40.0);
+        let backspace = key(Key::Backspace, Modifiers::NONE);
+        let shrunk = width(&mut harness, &mut edit, vec![backspace.clone()]);
+        assert!(shrunk < grown);
+
+        let all = vec![key(Key::A, Modifiers::COMMAND), backspace];
+        assert_eq!(width(&mut harness, &mut edit, all), 40.0);
+    }
+
+    #[test]
+    fn every_mutation_path_updates_the_size_on_the_next_frame() {
+        type Step = fn(&mut Harness, &mut CosmicEdit<PureBoundingBox>) -> Vec<Event>;
+        let insert = |at: Cursor, text: &str| Change {
+            items: vec![ChangeItem {
+                start: at,
+                end: history::insert_end(at, text),
+                text: text.to_string(),
+                insert: true,
+            }],
+        };
+        let steps: [(&str, Step); 12] = [
+            ("typing", |_, _| vec![Event::Text("abc".to_string())]),
+            ("paste", |_, _| vec![Event::Paste("defg".to_string())]),
+            ("backspace", |_, _| {
+                vec![key(Key::Backspace, Modifiers::NONE)]
+            }),
+            ("newline", |_, _| vec![key(Key::Enter, Modifiers::NONE)]),
+            ("duplicate", |_, _| {
+                vec![key(Key::D, Modifiers::COMMAND | Modifiers::SHIFT)]
+            }),
+            ("delete line", |_, _| {
+                vec![key(Key::K, Modifiers::COMMAND | Modifiers::SHIFT)]
+            }),
+            ("undo", |_, _| vec![key(Key::Z, Modifiers::COMMAND)]),
+            ("redo", |_, _| {
+                vec![key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT)]
+            }),
+            ("insert_string", |harness, edit| {
+                edit.insert_string("a longer line".to_string(), &mut harness.font_system);
+                vec![]
+            }),
+            ("apply_deltas", |harness, edit| {
+                let at = Cursor::new(0, 0);
+                let deltas = [TextDelta::Insert {
+                    at,
+                    text: "wider than the rest ".to_string(),
+                }];
+                assert!(edit.apply_deltas(&deltas, &mut harness.font_system));
+                vec![]
+            }),
+            ("set_text", |harness, edit| {
+                edit.set_text(
+                    [("set", Attrs::new())],
+                    Attrs::new(),
+                    Shaping::Advanced,
+                    &mut harness.font_system,
+                );
+                vec![]
+            }),
+            ("cut", |_, _| {
+                vec![key(Key::A, Modifiers::COMMAND), Event::Cut]
+            }),
+        ];
+
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let mut size = harness.frame(&mut edit, vec![]).rect.size();
+        let mut check = |harness: &mut Harness, edit: &mut CosmicEdit<_>, events, name| {
+            let resized = harness.frame(edit, events).rect.size();
+            assert_ne!(resized, size, "{name}");
+            size = resized;
+        };
+        for (name, step) in steps {
+            let events = step(&mut harness, &mut edit);
+            check(&mut harness, &mut edit, events, name);
+        }
+        for origin in [ChangeOrigin::Local, ChangeOrigin::Remote(1)] {
+            let change = insert(Cursor::new(0, 0), "changed ");
+            assert!(edit.apply_change(origin, &change, &mut harness.font_system));
+            check(&mut harness, &mut edit, vec![], "apply_change");
+        }
+
+        // Moving the cursor doesn't make the layout mode measure again
+        let revision = edit.revision;
+        edit.change(&mut harness.font_system, |_, widget| {
+            widget.editor.set_cursor(Cursor::new(0, 1));
+        });
+        harness.frame(&mut edit, vec![key(Key::ArrowRight, Modifiers::NONE)]);
+        assert_eq!(edit.revision, revision);
+    }
+
+    #[test]
+    fn hover_icon_is_picked_per_line() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "plain\na link", PureBoundingBox::default())
+            .with_hover_icon(|run, pos| {
+                let over_ink =
+                    ink_x_range(run, Spacing::default()).is_some_and(|x| x.contains(pos.x));
+                (run.text.contains("link") && over_ink).then_some(egui::CursorIcon::PointingHand)
+            });
+        let resp = harness.frame(&mut edit, vec![]);
+        let mut icon_at = |y: f32| {
+            let pos = pos2(resp.rect.left() + 10.0, resp.rect.top() + y);
+            harness.frame(&mut edit, vec![Event::PointerMoved(pos)]);
+            harness.output.cursor_icon
+        };
+        assert_eq!(icon_at(10.0), egui::CursorIcon::Text);
+        assert_eq!(icon_at(31.0), egui::CursorIcon::PointingHand);
+
+        // The bounding box strategy goes through the same path
+        edit.hover_icon = None;
+        *edit.hover_strategy_mut() = HoverStrategy::BoundingBox;
+        let resp = harness.frame(&mut edit, vec![]);
+        let mut icon_at = |x: f32| {
+            let pos = pos2(resp.rect.left() + x, resp.rect.top() + 31.0);
+            harness.frame(&mut edit, vec![Event::PointerMoved(pos)]);
+            harness.output.cursor_icon
+        };
+        assert_eq!(icon_at(10.0), egui::CursorIcon::Text);
+        assert_eq!(icon_at(resp.rect.width() + 5.0), egui::CursorIcon::Default);
+    }
+
+    #[test]
+    fn widget_ref_is_added_like_any_egui_widget() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        let expected = harness.focus(&mut edit).rect;
+        let mut add = |edit: &mut CosmicEdit<PureBoundingBox>, enabled: bool, text: &str| {
+            let Harness {
+                ctx,
+                font_system,
+                swash_cache,
+                atlas,
+                ..
+            } = &mut harness;
+            let input = egui::RawInput {
+                events: vec![Event::Text(text.to_string())],
+                ..Default::default()
+            };
+            let mut resp = None;
+            let _ = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let widget = edit.widget(font_system, swash_cache, atlas, NoContextMenu);
+                    resp = Some(ui.add_enabled(enabled, widget));
+                });
+            });
+            resp.unwrap()
+        };
+
+        let resp = add(&mut edit, true, "a");
+        assert_eq!(resp.rect.min, expected.min);
+        assert!(resp.enabled());
+        assert_eq!(edit.text(), "a");
+
+        // Disabled widgets ignore input, even in the frame they still have the focus
+        let resp = add(&mut edit, false, "b");
+        assert!(!resp.enabled());
+        add(&mut edit, false, "c");
+        assert_eq!(edit.text(), "a");
+    }
+
+    #[test]
+    fn show_reports_what_happened_in_the_frame() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default()).with_single_line(true);
+        harness.focus(&mut edit);
+        let mut frame = |edit: &mut CosmicEdit<_>, events| {
+            harness.frame(edit, events);
+            harness.edit_output.take().unwrap()
+        };
+
+        let typed = frame(&mut edit, vec![Event::Text("ab".to_string())]);
+        assert!(typed.changed && !typed.submitted);
+        assert_eq!(typed.cursor, Cursor::new(0, 2));
+        assert_eq!(typed.selection, None);
+        let caret = typed.cursor_rect;
+        assert!(typed.response.rect.expand(1.0).contains_rect(caret));
+        assert!(caret.left() > typed.response.rect.left() + 5.0);
+
+        let idle = frame(&mut edit, vec![]);
+        assert!(!idle.changed);
+        assert_eq!(idle.cursor_rect, caret);
+
+        let undone = frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert!(undone.changed);
+        assert!(undone.cursor.index < 2);
+
+        frame(&mut edit, vec![Event::Text("cd".to_string())]);
+        let selected = frame(&mut edit, vec![key(Key::A, Modifiers::COMMAND)]);
+        assert!(!selected.changed);
+        let end = Cursor::new(0, edit.text().len());
+        assert_eq!(selected.selection, Some((Cursor::new(0, 0), end)));
+        assert!(frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]).submitted);
+    }
+
+    #[test]
+    fn response_is_marked_changed_only_by_edits() {
+        let mut harness = Harness {
+            context_menu: true,
+            ..Default::default()
+        };
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let mut changed = |edit: &mut CosmicEdit<_>, events| harness.frame(edit, events).changed();
+
+        assert!(changed(&mut edit, vec![Event::Text("hello".to_string())]));
+        assert!(!changed(&mut edit, vec![]));
+        assert!(!changed(
+            &mut edit,
+            vec![key(Key::ArrowLeft, Modifiers::NONE)]
+        ));
+        assert!(!changed(&mut edit, vec![key(Key::Home, Modifiers::SHIFT)]));
+        assert!(changed(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]));
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+        assert!(changed(&mut edit, vec![redo]));
+
+        // Cut from the context menu, which runs after the response was allocated
+        changed(&mut edit, vec![key(Key::Home, Modifiers::SHIFT)]);
+        changed(&mut edit, vec![key(Key::F10, Modifiers::SHIFT)]);
+        // egui 0.32's popups are focusable themselves, Cut is the stop after them
+        for _ in 0..if cfg!(feature = "egui_032") { 2 } else { 1 } {
+            assert!(!changed(&mut edit, vec![key(Key::Tab, Modifiers::NONE)]));
+        }
+        assert!(changed(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]));
+        assert_eq!(edit.text(), "");
+    }
+
+    #[test]
+    fn char_limit_cuts_input_short() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "ab", PureBoundingBox::default());
+        edit.set_char_limit(Some(5));
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![key(Key::End, Modifiers::NONE)]);
+
+        // A paste cut short is still one undo step
+        harness.frame(&mut edit, vec![Event::Paste("cdefgh".to_string())]);
+        assert_eq!(edit.text(), "abcde");
+        assert!(harness.edit_output.as_ref().unwrap().at_char_limit);
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "ab");
+        assert!(!edit.at_char_limit());
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+        harness.frame(&mut edit, vec![redo]);
+        assert_eq!(edit.text(), "abcde");
+
+        // Nothing fits at the limit, the auto-indented newline isn't made either
+        let events = vec![
+            Event::Text("x".to_string()),
+            key(Key::Enter, Modifiers::NONE),
+            Event::Paste("y".to_string()),
+        ];
+        harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "abcde");
+
+        // Selected and overwritten chars make room
+        harness.frame(&mut edit, vec![key(Key::ArrowLeft, Modifiers::SHIFT)]);
+        harness.frame(&mut edit, vec![Event::Text("é😀!".to_string())]);
+        assert_eq!(edit.text(), "abcdé");
+        harness.frame(
+            &mut edit,
+            vec![
+                key(Key::Home, Modifiers::NONE),
+                key(Key::Insert, Modifiers::NONE),
+            ],
+        );
+        harness.frame(&mut edit, vec![Event::Text("xyz".to_string())]);
+        assert_eq!(edit.text(), "xyzdé");
+
+        // Graphemes aren't split
+        edit.set_char_limit(Some(6));
+        harness.frame(&mut edit, vec![key(Key::Insert, Modifiers::NONE)]);
+        harness.frame(&mut edit, vec![Event::Text("e\u{301}".to_string())]);
+        assert_eq!(edit.text(), "xyzdé");
+    }
+
+    #[test]
+    fn char_filter_drops_or_rejects_before_recording() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_char_filter(|x| x.is_ascii_digit(), RejectedChars::Drop);
+        harness.focus(&mut edit);
+
+        harness.frame(&mut edit, vec![Event::Text("a1b2".to_string())]);
+        harness.frame(&mut edit, vec![Event::Paste("3,4\n".to_string())]);
+        assert_eq!(edit.text(), "1234");
+        // Typing nothing that's accepted isn't an undo step
+        harness.frame(&mut edit, vec![Event::Text("x".to_string())]);
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "12");
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+        harness.frame(&mut edit, vec![redo]);
+
+        edit.set_char_filter(|x| x.is_ascii_digit(), RejectedChars::RejectInsert);
+        harness.frame(&mut edit, vec![Event::Paste("5x".to_string())]);
+        assert_eq!(edit.text(), "1234");
+        harness.frame(&mut edit, vec![Event::Paste("56".to_string())]);
+        assert_eq!(edit.text(), "123456");
+
+        // Enter runs a command, it's left to the keymap
+        harness.frame(&mut edit, vec![key(Key::Enter, Modifiers::NONE)]);
+        assert_eq!(edit.text(), "123456\n");
+        edit.clear_char_filter();
+        harness.frame(&mut edit, vec![Event::Text("a".to_string())]);
+        assert_eq!(edit.text(), "123456\na");
+    }
+
+    #[test]
+    fn read_only_navigates_and_copies_without_editing() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "view\nonly", PureBoundingBox::default());
+        *edit.interactivity_mut() = Interactivity::ReadOnly;
+        harness.focus(&mut edit);
+
+        let events = vec![
+            Event::Text("x".to_string()),
+            key(Key::Enter, Modifiers::NONE),
+            key(Key::Tab, Modifiers::NONE),
+            key(Key::Backspace, Modifiers::NONE),
+            key(Key::ArrowDown, Modifiers::NONE),
+            key(Key::End, Modifiers::SHIFT),
+            Event::Copy,
+            Event::Cut,
+            Event::Paste("pasted".to_string()),
+            key(Key::Z, Modifiers::COMMAND),
+        ];
+        let resp = harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "view\nonly");
+        assert!(!resp.changed() && resp.has_focus());
+        assert_eq!(harness.copied_text(), "only");
+        assert_eq!(edit.editor().cursor(), Cursor::new(1, 4));
+
+        // The caret blinks like in an editable widget
+        harness.frame(&mut edit, vec![key(Key::ArrowLeft, Modifiers::NONE)]);
+        harness.frame(&mut edit, vec![]);
+        harness.frame(&mut edit, vec![]);
+        assert!(harness.repaint_delay <= Duration::from_secs_f32(0.5));
+        *edit.interactivity_mut() = Interactivity::Selection;
+        harness.frame(&mut edit, vec![]);
+        harness.frame(&mut edit, vec![]);
+        assert_eq!(harness.repaint_delay, Duration::MAX);
+    }
+
+    #[test]
+    fn sync_text_keeps_the_selection_and_history() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "hello world", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let events = vec![
+            key(Key::End, Modifiers::NONE),
+            Event::Text("!".to_string()),
+            key(Key::ArrowLeft, Modifiers::NONE),
+            key(Key::ArrowLeft, Modifiers::SHIFT | Modifiers::CTRL),
+        ];
+        harness.frame(&mut edit, events);
+        let selection = |edit: &CosmicEdit<_>| {
+            let (start, end) = edit.editor().selection_bounds().unwrap();
+            (start.line, start.index, end.line, end.index)
+        };
+        assert_eq!(selection(&edit), (0, 6, 0, 11));
+
+        edit.sync_text("well, hello world!\r\nbye", &mut harness.font_system);
+        assert_eq!(edit.text(), "well, hello world!\nbye");
+        assert_eq!(selection(&edit), (0, 12, 0, 17));
+
+        // Nothing to do for the same text
+        let revision = edit.revision;
+        edit.sync_text("well, hello world!\nbye", &mut harness.font_system);
+        assert_eq!(edit.revision, revision);
+
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "hello world!");
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "hello world");
+    }
+
+    #[test]
+    fn selected_text_leaves_the_clipboard_alone() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo", PureBoundingBox::default());
+        edit.set_line_ending(LineEnding::CrLf);
+        harness.focus(&mut edit);
+        assert_eq!(edit.selected_text(), None);
+        assert_eq!(edit.selection(), None);
+
+        // An empty selection is none
+        harness.frame(&mut edit, vec![key(Key::ArrowRight, Modifiers::SHIFT)]);
+        harness.frame(&mut edit, vec![key(Key::ArrowLeft, Modifiers::SHIFT)]);
+        assert_ne!(edit.editor().selection(), Selection::None);
+        assert_eq!(edit.selected_text(), None);
+
+        harness.frame(&mut edit, vec![key(Key::A, Modifiers::COMMAND)]);
+        assert_eq!(edit.selected_text().as_deref(), Some("one\r\ntwo"));
+        let (start, end) = edit.selection().unwrap();
+        assert_eq!((start.line, start.index, end.line, end.index), (0, 0, 1, 3));
+        assert_eq!(harness.copied_text(), "");
+    }
+
+    #[test]
+    fn selection_range_round_trips_and_scrolls_into_view() {
+        let mut harness = Harness {
+            scroll_height: Some(60.0),
+            ..Default::default()
+        };
+        let text = (0..30).map(|i| format!("line {i}")).collect::<Vec<_>>();
+        let mut edit = edit(&mut harness, &text.join("\n"), PureBoundingBox::default());
+        harness.frame(&mut edit, vec![]);
+
+        let start = edit.text().find("line 27").unwrap();
+        assert_eq!(edit.offset_to_cursor(start), Some(Cursor::new(27, 0)));
+        assert_eq!(edit.cursor_to_offset(Cursor::new(27, 0)), start);
+        edit.set_selection_range(start..start + 7);
+        assert_eq!(edit.selected_text().as_deref(), Some("line 27"));
+        assert_eq!(edit.selection_range(), Some(start..start + 7));
+        edit.scroll_to_cursor();
+        // egui animates the scrolling
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        assert!(harness.scroll_offset.y > 500.0);
+
+        // Clamped to the end
+        edit.set_selection_range(start..usize::MAX);
+        assert_eq!(
+            edit.selected_text().as_deref(),
+            Some("line 27\nline 28\nline 29")
+        );
+        edit.set_selection_range(3..3);
+        assert_eq!(edit.selection_range(), None);
+    }
+
+    #[test]
+    fn scroll_to_bottom_works_without_following_the_cursor() {
+        let mut harness = Harness {
+            scroll_height: Some(60.0),
+            ..Default::default()
+        };
+        let text = (0..30).map(|i| format!("line {i}")).collect::<Vec<_>>();
+        let mut edit = edit(&mut harness, &text.join("\n"), PureBoundingBox::default());
+        harness.focus(&mut edit);
+        edit.set_follow_cursor(false);
+
+        harness.frame(&mut edit, vec![key(Key::End, Modifiers::COMMAND)]);
+        // egui animates the scrolling
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        assert_eq!(edit.editor().cursor().line, 29);
+        assert_eq!(harness.scroll_offset.y, 0.0);
+
+        edit.scroll_to_bottom();
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        let bottom = harness.scroll_offset.y;
+        assert!(bottom > 500.0);
+
+        // Typing at the top doesn't take the view along
+        edit.set_selection_range(0..0);
+        harness.frame(&mut edit, vec![Event::Text("x".to_string())]);
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        assert_eq!(harness.scroll_offset.y, bottom);
+
+        edit.set_follow_cursor(true);
+        harness.frame(&mut edit, vec![Event::Text("y".to_string())]);
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        assert_eq!(edit.text().lines().next(), Some("xyline 0"));
+        assert_eq!(harness.scroll_offset.y, 0.0);
+    }
+
+    #[test]
+    fn clear_empties_the_text_with_or_without_undo() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo", PureBoundingBox::default());
+        let before = harness.focus(&mut edit).rect;
+        let events = vec![
+            key(Key::End, Modifiers::COMMAND),
+            Event::Text("!".to_string()),
+            key(Key::ArrowLeft, Modifiers::SHIFT),
+        ];
+        harness.frame(&mut edit, events);
+
+        edit.clear(true, &mut harness.font_system);
+        assert_eq!(edit.text(), "");
+        assert_eq!(edit.editor().cursor(), Cursor::default());
+        assert_eq!(edit.selection(), None);
+        let rect = harness.frame(&mut edit, vec![]).rect;
+        assert!(rect.height() < before.height());
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "one\ntwo!");
+
+        edit.clear(false, &mut harness.font_system);
+        assert_eq!(edit.text(), "");
+        assert!(!edit.can_undo());
+        assert_eq!(edit.editor().cursor(), Cursor::default());
+    }
+
+    #[test]
+    fn text_has_no_trailing_line_ending_and_can_be_written_into() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "abc", PureBoundingBox::default());
+        assert_eq!(edit.text(), "abc");
+
+        let mut text = String::with_capacity(64);
+        let capacity = text.capacity();
+        edit.set_line_ending(LineEnding::CrLf);
+        edit.set_text(
+            [("one\ntwo", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        edit.text_into(&mut text);
+        assert_eq!(text, "one\r\ntwo");
+        assert_eq!(text.capacity(), capacity);
+        edit.clear(false, &mut harness.font_system);
+        edit.text_into(&mut text);
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn tracked_changes_mirror_every_edit() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one\ntwo", PureBoundingBox::default());
+        let mut model = edit.text();
+        let mut mirror = |edit: &mut CosmicEdit<_>| {
+            let changes = edit.take_changes();
+            for change in &changes {
+                model.replace_range(change.range.clone(), &change.inserted);
+            }
+            assert_eq!(model, edit.text());
+            changes
+        };
+        harness.focus(&mut edit);
+        edit.set_track_changes(true);
+
+        let events = vec![key(Key::End, Modifiers::NONE), Event::Text("!".to_string())];
+        harness.frame(&mut edit, events);
+        let typed = TextChange {
+            range: 3..3,
+            inserted: "!".to_string(),
+        };
+        assert_eq!(mirror(&mut edit), [typed]);
+
+        let events = vec![
+            key(Key::A, Modifiers::COMMAND),
+            Event::Text("z".to_string()),
+            key(Key::Enter, Modifiers::NONE),
+            key(Key::Z, Modifiers::COMMAND),
+            key(Key::Z, Modifiers::COMMAND),
+        ];
+        harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "one!\ntwo");
+        let replaced = TextChange {
+            range: 0..8,
+            inserted: "z".to_string(),
+        };
+        assert_eq!(mirror(&mut edit)[0], replaced);
+
+        assert!(edit.redo());
+        edit.sync_text("z\n\nthree", &mut harness.font_system);
+        mirror(&mut edit);
+        edit.set_text(
+            [("new", Attrs::new())],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        mirror(&mut edit);
+
+        // The app made remote changes itself
+        let remote = Change {
+            items: vec![ChangeItem {
+                start: Cursor::new(0, 0),
+                end: Cursor::new(0, 1),
+                text: "n".to_string(),
+                insert: false,
+            }],
+        };
+        assert!(edit.apply_change(ChangeOrigin::Remote(1), &remote, &mut harness.font_system));
+        assert!(edit.take_changes().is_empty());
+        edit.set_track_changes(false);
+        edit.clear(false, &mut harness.font_system);
+        assert!(edit.take_changes().is_empty());
+    }
+
+    #[test]
+    fn tracked_changes_count_every_line_break_as_one_byte() {
+        let mut harness = Harness::default();
+        let mut edit =
+            edit(&mut harness, "one\ntwo", PureBoundingBox::default()).with_soft_break("\u{2028}");
+        edit.set_line_ending(LineEnding::CrLf);
+        harness.focus(&mut edit);
+        edit.set_track_changes(true);
+
+        let events = vec![
+            key(Key::End, Modifiers::COMMAND),
+            key(Key::Enter, Modifiers::SHIFT),
+            Event::Text("x".to_string()),
+        ];
+        harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "one\r\ntwo\u{2028}x");
+        // Not offsets into `text`, which has two bytes for the CRLF and three for the soft break
+        let changes = edit.take_changes();
+        let inserted = |at: usize, text: &str| TextChange {
+            range: at..at,
+            inserted: text.to_string(),
+        };
+        assert_eq!(changes, [inserted(7, "\n"), inserted(8, "x")]);
+        // Like the other offsets
+        assert_eq!(edit.cursor_to_offset(edit.editor().cursor()), 9);
+    }
+
+    #[test]
+    fn typing_is_undone_a_word_at_a_time() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let undo = key(Key::Z, Modifiers::COMMAND);
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+
+        for c in "hello world".chars() {
+            harness.frame(&mut edit, vec![Event::Text(c.to_string())]);
+        }
+        harness.frame(&mut edit, vec![undo.clone()]);
+        assert_eq!(edit.text(), "hello");
+        harness.frame(&mut edit, vec![redo.clone()]);
+        assert_eq!(edit.text(), "hello world");
+
+        // Moving the caret starts a new group, even back to where it was
+        let events = vec![
+            key(Key::ArrowLeft, Modifiers::NONE),
+            key(Key::ArrowRight, Modifiers::NONE),
+            Event::Text("s".to_string()),
+            Event::Text("!".to_string()),
+        ];
+        harness.frame(&mut edit, events);
+        harness.frame(&mut edit, vec![undo.clone()]);
+        assert_eq!(edit.text(), "hello world");
+
+        // So does a pause longer than the window
+        harness.frame(&mut edit, vec![Event::Text("s".to_string())]);
+        harness.time += 1.0;
+        harness.frame(&mut edit, vec![Event::Text("!".to_string())]);
+        harness.frame(&mut edit, vec![undo]);
+        assert_eq!(edit.text(), "hello worlds");
+    }
+
+    #[test]
+    fn clear_history_keeps_the_text() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        assert!(!edit.can_undo() && !edit.can_redo());
+
+        let events = vec![key(Key::End, Modifiers::NONE), Event::Text("!".to_string())];
+        harness.frame(&mut edit, events);
+        assert!(edit.can_undo());
+        assert!(edit.undo());
+        assert!(edit.can_redo());
+
+        edit.clear_history();
+        assert!(!edit.can_undo() && !edit.can_redo());
+        assert!(!edit.redo());
+        assert_eq!(edit.text(), "one");
+    }
+
+    #[test]
+    fn undo_and_redo_put_the_caret_and_selection_back() {
+        let mut harness = Harness::default();
+        let text = (0..60).map(|i| format!("line {i}\n")).collect::<String>();
+        let mut edit = edit(&mut harness, &text, PureBoundingBox::default());
+        harness.scroll_height = Some(100.0);
+        harness.focus(&mut edit);
+        let undo = key(Key::Z, Modifiers::COMMAND);
+        let redo = key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+
+        edit.set_selection_range(0..4);
+        harness.frame(&mut edit, vec![Event::Text("row".to_string())]);
+        let typed = edit.editor().cursor();
+        harness.frame(&mut edit, vec![key(Key::End, Modifiers::COMMAND)]);
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        let scrolled = harness.scroll_offset.y;
+        assert!(scrolled > 0.0);
+
+        harness.frame(&mut edit, vec![undo]);
+        assert_eq!(edit.selection_range(), Some(0..4));
+        for _ in 0..60 {
+            harness.frame(&mut edit, vec![]);
+        }
+        assert!(harness.scroll_offset.y < scrolled);
+
+        harness.frame(&mut edit, vec![redo]);
+        assert_eq!(edit.selection(), None);
+        assert_eq!(edit.editor().cursor(), typed);
+    }
+
+    #[test]
+    fn nested_changes_are_one_undo_step() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "a-b-c", PureBoundingBox::default());
+        edit.change(&mut harness.font_system, |font_system, edit| {
+            for start in [4, 2] {
+                edit.set_selection_range(start - 1..start);
+                edit.insert_string("+".to_string(), font_system);
+            }
+            // Inner steps count as part of the outer one
+            edit.change(font_system, |_font_system, edit| {
+                edit.set_selection_range(0..1);
+                edit.editor.delete_selection();
+            });
+        });
+        assert_eq!(edit.text(), "+b+c");
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "a-b-c");
+        assert!(!edit.can_undo());
+
+        edit.change(&mut harness.font_system, |_font_system, edit| {
+            edit.set_selection_range(0..1);
+        });
+        assert!(!edit.can_undo());
+    }
+
+    #[test]
+    fn insert_string_at_keeps_the_selection_in_place() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two", PureBoundingBox::default());
+        edit.set_selection_range(4..7);
+
+        let red = Attrs::new().color(Color::rgb(255, 0, 0));
+        let mut attrs = AttrsList::new(&Attrs::new());
+        attrs.add_span(0..4, &red);
+        let range = edit.insert_string_at(
+            Cursor::new(0, 0),
+            "zero\n",
+            Some(attrs),
+            &mut harness.font_system,
+        );
+        assert_eq!(range, Cursor::new(0, 0)..Cursor::new(1, 0));
+        assert_eq!(edit.text(), "zero\none two");
+        assert_eq!(edit.selection_range(), Some(9..12));
+        let color = edit
+            .editor()
+            .with_buffer(|x| x.lines[0].attrs_list().get_span(1).color_opt);
+        assert_eq!(color, red.color_opt);
+
+        let range = edit.insert_string("2".to_string(), &mut harness.font_system);
+        assert_eq!(range, Cursor::new(1, 4)..Cursor::new(1, 5));
+        assert!(edit.undo() && edit.undo());
+        assert_eq!(edit.text(), "one two");
+        assert_eq!(edit.selection_range(), Some(4..7));
+    }
+
+    #[test]
+    fn inserted_spans_follow_their_chars_through_conversions() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_control_chars(ControlChars::EscapeVisible);
+
+        // One span ends between the \r and \n of a line break, the ␀ is longer than the \0
+        let red = Attrs::new().color(Color::rgb(255, 0, 0));
+        let mut attrs = AttrsList::new(&Attrs::new());
+        attrs.add_span(0..2, &red);
+        attrs.add_span(3..4, &red);
+        attrs.add_span(5..6, &red);
+        edit.insert_string_at(
+            Cursor::new(0, 0),
+            "a\r\nb\0c",
+            Some(attrs),
+            &mut harness.font_system,
+        );
+        assert_eq!(edit.text(), "a\nb\0c");
+        let line = edit.editor().with_buffer(|x| x.lines[1].text().to_string());
+        assert_eq!(line, "b\u{2400}c");
+        let color_at = |line: usize, index| {
+            edit.editor()
+                .with_buffer(|x| x.lines[line].attrs_list().get_span(index).color_opt)
+        };
+        assert_eq!(color_at(0, 0), red.color_opt);
+        assert_eq!(color_at(1, 0), red.color_opt);
+        assert_eq!(color_at(1, 1), None);
+        assert_eq!(color_at(1, 4), red.color_opt);
+    }
+
+    #[test]
+    fn replace_and_delete_ranges_keep_the_selection() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two\nthree", PureBoundingBox::default());
+        edit.set_selection_range(8..13);
+
+        let range = Cursor::new(0, 4)..Cursor::new(0, 7);
+        let replaced = edit.replace_range(range, "2\n2", &mut harness.font_system);
+        assert_eq!(replaced, Cursor::new(0, 4)..Cursor::new(1, 1));
+        assert_eq!(edit.text(), "one 2\n2\nthree");
+        assert_eq!(edit.selected_text().as_deref(), Some("three"));
+
+        // Backwards ranges work too, the selection inside of it collapses to its start
+        let (start, end) = (edit.offset_to_cursor(2).unwrap(), edit.offset_to_cursor(10));
+        edit.delete_range(end.unwrap()..start, &mut harness.font_system);
+        assert_eq!(edit.text(), "onree");
+        assert_eq!(edit.selected_text().as_deref(), Some("ree"));
+
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "one 2\n2\nthree");
+        assert!(edit.undo());
+        assert_eq!(edit.text(), "one two\nthree");
+        assert_eq!(edit.selection_range(), Some(8..13));
+    }
+
+    #[test]
+    fn attrs_are_applied_to_the_selection_only() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "one two\nthree", PureBoundingBox::default());
+        let red = Color::rgb(255, 0, 0);
+        let attrs_at = |edit: &CosmicEdit<_>, offset| {
+            let cursor = edit.offset_to_cursor(offset).unwrap();
+            edit.editor().with_buffer(|x| {
+                let attrs = x.lines[cursor.line].attrs_list().get_span(cursor.index);
+                (attrs.color_opt, attrs.weight)
+            })
+        };
+
+        edit.apply_attrs_to_selection(|x| x.color(red), &mut harness.font_system);
+        assert_eq!(attrs_at(&edit, 0).0, None);
+
+        edit.set_selection_range(4..10);
+        edit.apply_attrs_to_selection(|x| x.color(red), &mut harness.font_system);
+        edit.set_selection_range(2..5);
+        edit.apply_attrs_to_selection(|x| x.weight(Weight::BOLD), &mut harness.font_system);
+        assert_eq!(attrs_at(&edit, 1), (None, Weight::NORMAL));
+        assert_eq!(attrs_at(&edit, 2), (None, Weight::BOLD));
+        assert_eq!(attrs_at(&edit, 4), (Some(red), Weight::BOLD));
+        assert_eq!(attrs_at(&edit, 5), (Some(red), Weight::NORMAL));
+        assert_eq!(attrs_at(&edit, 9), (Some(red), Weight::NORMAL));
+        assert_eq!(attrs_at(&edit, 10), (None, Weight::NORMAL));
+        assert_eq!(edit.text(), "one two\nthree");
+    }
+
+    #[test]
+    fn typing_attrs_style_new_text_until_the_caret_moves() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "ab", PureBoundingBox::default());
+        harness.focus(&mut edit);
+        let weight_at = |edit: &CosmicEdit<_>, line: usize, index| {
+            edit.editor()
+                .with_buffer(|x| x.lines[line].attrs_list().get_span(index).weight)
+        };
+
+        let events = vec![key(Key::End, Modifiers::NONE)];
+        harness.frame(&mut edit, events);
+        edit.set_typing_attrs(Some(Attrs::new().weight(Weight::BOLD)));
+        let events = vec![
+            Event::Text("cd".to_string()),
+            key(Key::Enter, Modifiers::NONE),
+            Event::Paste("ef".to_string()),
+        ];
+        harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "abcd\nef");
+        assert_eq!(weight_at(&edit, 0, 1), Weight::NORMAL);
+        assert_eq!(weight_at(&edit, 0, 2), Weight::BOLD);
+        assert_eq!(weight_at(&edit, 0, 3), Weight::BOLD);
+        assert_eq!(weight_at(&edit, 1, 1), Weight::BOLD);
+        assert!(edit.typing_attrs().is_some());
+
+        harness.frame(&mut edit, vec![key(Key::ArrowLeft, Modifiers::NONE)]);
+        assert!(edit.typing_attrs().is_none());
+        let events = vec![
+            key(Key::ArrowUp, Modifiers::NONE),
+            key(Key::Home, Modifiers::NONE),
+            Event::Text("x".to_string()),
+        ];
+        harness.frame(&mut edit, events);
+        assert_eq!(edit.text(), "xabcd\nef");
+        assert_eq!(weight_at(&edit, 0, 0), Weight::NORMAL);
+
+        // Moved by the app
+        let bold = || Some(Attrs::new().weight(Weight::BOLD));
+        edit.set_typing_attrs(bold());
+        edit.set_selection_range(2..2);
+        assert!(edit.typing_attrs().is_none());
+        edit.set_typing_attrs(bold());
+        edit.with_editor_mut(|x| x.set_cursor(Cursor::new(0, 2)));
+        assert!(edit.typing_attrs().is_some());
+        edit.with_editor_mut(|x| x.set_cursor(Cursor::new(1, 0)));
+        assert!(edit.typing_attrs().is_none());
+    }
+
+    #[test]
+    fn style_is_set_wholesale_and_by_setters() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "styled", PureBoundingBox::default())
+            .with_caret_layer(CaretLayer::BelowGlyphs);
+        edit.set_letter_spacing(2.0);
+        assert_eq!(edit.style().caret_layer, CaretLayer::BelowGlyphs);
+        assert_eq!(edit.style().spacing.letter, 2.0);
+
+        harness.focus(&mut edit);
+        harness.frame(&mut edit, vec![]);
+        let CursorStyle::Texture(texture) = &edit.cursor_style else {
+            panic!("the caret should have been drawn");
+        };
+        let texture_id = texture.texture_id();
+
+        // The same style every frame keeps the texture
+        let style = CosmicEditStyle {
+            hover_caret: true,
+            ..*edit.style()
+        };
+        for _ in 0..3 {
+            edit.set_style(&style);
+            harness.frame(&mut edit, vec![]);
+        }
+        assert!(edit.hover_caret());
+        let CursorStyle::Texture(texture) = &edit.cursor_style else {
+            panic!("the caret texture should be kept");
+        };
+        assert_eq!(texture.texture_id(), texture_id);
+
+        edit.set_style(&CosmicEditStyle {
+            caret: None,
+            ..CosmicEditStyle::from_visuals(&egui::Visuals::light())
+        });
+        assert!(matches!(edit.cursor_style, CursorStyle::None));
+        assert_eq!(edit.caret_layer(), CaretLayer::AboveGlyphs);
+        assert_eq!(edit.spacing(), Spacing::default());
+        assert_eq!(
+            edit.style().selection,
+            egui::Visuals::light().selection.bg_fill
+        );
+    }
+
+    #[test]
+    fn new_widgets_start_with_the_default_style() {
+        let mut harness = Harness::default();
+        let edit = edit(&mut harness, "styled", PureBoundingBox::default());
+        assert_eq!(edit.style(), &CosmicEditStyle::default());
+        assert!(matches!(
+            edit.cursor_style,
+            CursorStyle::Default(color) if Some(color) == edit.style().caret
+        ));
+        assert!(matches!(
+            edit.selection_texture,
+            SelectionTexture::Default(color) if color == edit.style().selection
+        ));
+    }
+
+    #[test]
+    fn text_color_tints_text_without_a_color_of_its_own() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        edit.set_text(
+            [
+                ("a", Attrs::new()),
+                ("b", Attrs::new().color(cosmic_text::Color::rgb(255, 0, 0))),
+            ],
+            Attrs::new(),
+            Shaping::Advanced,
+            &mut harness.font_system,
+        );
+        edit.set_glyph_capture(true);
+        edit.set_style(&CosmicEditStyle {
+            text_color: Some(Color32::BLUE),
+            ..*edit.style()
+        });
+        assert_eq!(edit.text_color(), Some(Color32::BLUE));
+
+        harness.frame(&mut edit, vec![]);
+        let mut colors = Vec::new();
+        edit.visible_glyphs(|glyph| colors.push(glyph.color));
+        assert_eq!(colors, [Color32::BLUE, Color32::RED]);
+
+        let atlas_texture = harness.atlas.atlas_texture();
+        let mut tints = harness
+            .shapes
+            .iter()
+            .filter_map(|x| match &x.shape {
+                egui::Shape::Mesh(mesh) if mesh.texture_id == atlas_texture => {
+                    Some(mesh.vertices[0].color)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        tints.sort_by_key(|x| x.to_array());
+        assert_eq!(tints, [Color32::BLUE, Color32::RED]);
+    }
+
+    #[test]
+    fn middle_click_pastes_the_primary_selection() {
+        let mut harness = Harness::default();
+        let primary = Rc::new(std::cell::RefCell::new(Some("pasted ".to_string())));
+        let mut edit = edit(&mut harness, "one two", PureBoundingBox::default())
+            .with_primary_paste({
+                let primary = Rc::clone(&primary);
+                move || primary.borrow().clone()
+            });
+        let resp = harness.frame(&mut edit, vec![]);
+        let pos = resp.rect.min
+            + edit
+                .editor
+                .with_buffer(|x| cursor_rect(x, Cursor::new(0, 4), Spacing::default()))
+                .unwrap()
+                .center()
+                .to_vec2();
+        let middle_click = |harness: &mut Harness, edit: &mut CosmicEdit<PureBoundingBox>| {
+            let press = |pressed| Event::PointerButton {
+                pos,
+                button: egui::PointerButton::Middle,
+                pressed,
+                modifiers: Modifiers::NONE,
+            };
+            harness.frame(edit, vec![Event::PointerMoved(pos), press(true)]);
+            harness.frame(edit, vec![press(false)])
+        };
+
+        let resp = middle_click(&mut harness, &mut edit);
+        assert_eq!(edit.text(), "one pasted two");
+        assert_eq!(edit.editor.cursor().index, "one pasted ".len());
+        assert!(resp.has_focus());
+        harness.frame(&mut edit, vec![key(Key::Z, Modifiers::COMMAND)]);
+        assert_eq!(edit.text(), "one two");
+
+        // Nothing to paste only moves the cursor
+        *primary.borrow_mut() = None;
+        middle_click(&mut harness, &mut edit);
+        assert_eq!(edit.text(), "one two");
+        assert_eq!(edit.editor.cursor().index, 4);
+    }
+
+    #[test]
+    fn debounced_changed_fires_once_after_the_delay() {
+        let mut harness = Harness::default();
+        let mut edit = edit(&mut harness, "", PureBoundingBox::default());
+        let delay = Duration::from_millis(500);
+        harness.focus(&mut edit);
+        assert_eq!(edit.idle_since_change(&harness.ctx), None);
+
+        let mut fired = 0;
+        let type_at = |harness: &mut Harness, edit: &mut CosmicEdit<_>, time, text: &str| {
+            harness.time = time;
+            harness.frame(edit, vec![Event::Text(text.to_string())]);
+        };
+        let mut idle_until = |harness: &mut Harness, edit: &mut CosmicEdit<_>, time| {
+            while harness.time < time {
+                harness.frame(edit, vec![]);
+                fired += edit.debounced_changed(&harness.ctx, delay) as usize;
+            }
+            fired
+        };
+
+        type_at(&mut harness, &mut edit, 1.0, "a");
+        assert_eq!(edit.idle_since_change(&harness.ctx), Some(Duration::ZERO));
+        // Typing again restarts the delay
+        assert_eq!(idle_until(&mut harness, &mut edit, 1.4), 0);
+        type_at(&mut harness, &mut edit, 1.4, "b");
+        assert_eq!(idle_until(&mut harness, &mut edit, 1.85), 0);
+        assert_eq!(idle_until(&mut harness, &mut edit, 3.0), 1);
+        let idle = edit.idle_since_change(&harness.ctx).unwrap();
+        assert!((idle.as_secs_f64() - 1.6).abs() < 0.05, "{idle:?}");
+
+        // Changes made by the app count too
+        edit.insert_string("c".to_string(), &mut harness.font_system);
+        assert_eq!(idle_until(&mut harness, &mut edit, 3.4), 1);
+        assert_eq!(idle_until(&mut harness, &mut edit, 4.0), 2);
+    }
 }
@@ -0,0 +1,76 @@
+//! Checks that steady state frames don't allocate once every glyph is cached.
+//!
+//! Lives outside the crate since the counting allocator needs `unsafe`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use egui::Color32;
+use egui_cosmic_text::atlas::{SeededState, TextureAtlas};
+use egui_cosmic_text::cosmic_text::{
+    fontdb, Attrs, Buffer, CacheKey, FontSystem, Metrics, Shaping, SwashCache,
+};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_in(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn cached_glyphs_allocate_nothing() {
+    let mut db = fontdb::Database::new();
+    db.load_font_data(include_bytes!("../demo/resources/Ubuntu-Light.ttf").to_vec());
+    let mut font_system = FontSystem::new_with_locale_and_db("en-US".to_string(), db);
+    let mut swash_cache = SwashCache::new();
+
+    let mut buf = Buffer::new(&mut font_system, Metrics::new(14.0, 21.0));
+    buf.set_text(
+        &mut font_system,
+        "Steady state frames",
+        &Attrs::new(),
+        Shaping::Advanced,
+    );
+    buf.shape_until_scroll(&mut font_system, false);
+    let cache_keys = buf
+        .layout_runs()
+        .flat_map(|run| run.glyphs)
+        .map(|glyph| glyph.physical((0.0, 0.0), 1.0).cache_key)
+        .collect::<Vec<CacheKey>>();
+
+    let ctx = egui::Context::default();
+    let mut atlas = TextureAtlas::<SeededState>::new(ctx, Color32::WHITE);
+    let mut frame = |atlas: &mut TextureAtlas<SeededState>| {
+        cache_keys.iter().for_each(|&cache_key| {
+            atlas.alloc(cache_key, &mut font_system, &mut swash_cache);
+        });
+    };
+
+    frame(&mut atlas);
+    assert_eq!(allocations_in(|| frame(&mut atlas)), 0);
+}